@@ -0,0 +1,14 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    {
+        // protox parses the proto in Rust, so no protoc is needed
+        println!("cargo:rerun-if-changed=proto/model_manager.proto");
+        let fds = protox::compile(["proto/model_manager.proto"], ["proto"])
+            .expect("Failed to parse proto/model_manager.proto");
+        tonic_prost_build::configure()
+            .build_client(false)
+            .compile_fds(fds)
+            .expect("Failed to generate gRPC code");
+    }
+}
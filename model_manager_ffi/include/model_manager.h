@@ -0,0 +1,44 @@
+#ifndef MODEL_MANAGER_H
+#define MODEL_MANAGER_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Functions returning int return 0 on success, -1 for a null or non-UTF-8 argument and the
+ * error code of the model manager otherwise. mm_last_error describes the failure. */
+
+typedef struct ModelManager ModelManager;
+
+/* Events are "queued", "start", "bytes", "file_done", "retry", "unpack", "done" and "error".
+ * file is null for events of a whole model, downloaded is the retry attempt for "retry" and
+ * total is 0 if unknown. Called on the threads downloading. */
+typedef void (*mm_progress_callback)(void *user_data, const char *event, const char *model,
+                                     const char *file, uint64_t downloaded, uint64_t total);
+
+/* model_path may be null for the default directory. Returns null on failure. */
+ModelManager *mm_manager_new(const char *model_path);
+void mm_manager_free(ModelManager *manager);
+
+int mm_register_config(ModelManager *manager, const char *path);
+/* json is a model definition like {"version": "1", "source": "hf://org/repo"}. */
+int mm_register_model_json(ModelManager *manager, const char *ident, const char *json);
+int mm_set_progress_callback(ModelManager *manager, mm_progress_callback callback,
+                             void *user_data);
+
+/* Downloads the model if needed, blocking. *path is freed with mm_string_free. */
+int mm_get_model(ModelManager *manager, const char *ident, char **path);
+/* The path of a model that's already downloaded, without downloading. */
+int mm_model_path(ModelManager *manager, const char *ident, char **path);
+void mm_string_free(char *value);
+
+/* Valid until the next call on this thread, null if nothing failed. */
+const char *mm_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,288 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::Arc;
+
+use model_manager::error::Error;
+use model_manager::model_manager::{Model, ModelManager};
+use model_manager::progress::ProgressObserver;
+
+/// Returned for a null pointer or a string that isn't UTF-8, every other failure returns the
+/// [`Error::code`] of the error.
+const INVALID_ARGUMENT: c_int = -1;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Called with the user data, the event, the model, the file or null, the bytes downloaded or
+/// the retry attempt and the total size, 0 if unknown. See `model_manager.h` for the events.
+pub type ProgressCallback = extern "C" fn(
+    user_data: *mut c_void,
+    event: *const c_char,
+    model: *const c_char,
+    file: *const c_char,
+    downloaded: u64,
+    total: u64,
+);
+
+fn fail(code: c_int, message: String) -> c_int {
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|v| *v.borrow_mut() = Some(message));
+    code
+}
+
+fn error(error: Error) -> c_int {
+    fail(c_int::from(error.code()), error.report())
+}
+
+unsafe fn string<'a>(value: *const c_char) -> Result<&'a str, c_int> {
+    if value.is_null() {
+        return Err(fail(INVALID_ARGUMENT, "null argument".to_string()));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| fail(INVALID_ARGUMENT, "argument isn't UTF-8".to_string()))
+}
+
+unsafe fn write_path(path: PathBuf, out: *mut *mut c_char) -> c_int {
+    if out.is_null() {
+        return fail(INVALID_ARGUMENT, "null argument".to_string());
+    }
+    match CString::new(path.to_string_lossy().into_owned()) {
+        Ok(path) => {
+            *out = path.into_raw();
+            0
+        }
+        Err(_) => fail(INVALID_ARGUMENT, "path contains a null byte".to_string()),
+    }
+}
+
+macro_rules! arg {
+    ($value:expr) => {
+        match $value {
+            Ok(v) => v,
+            Err(code) => return code,
+        }
+    };
+}
+
+unsafe fn manager<'a>(manager: *mut ModelManager) -> Result<&'a mut ModelManager, c_int> {
+    manager
+        .as_mut()
+        .ok_or_else(|| fail(INVALID_ARGUMENT, "null manager".to_string()))
+}
+
+/// Creates a manager storing models in `model_path`, or the default directory if it's null.
+/// Returns null on failure, see [`mm_last_error`].
+///
+/// # Safety
+///
+/// `model_path` is null or a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mm_manager_new(model_path: *const c_char) -> *mut ModelManager {
+    let mut builder = ModelManager::builder().quiet(true);
+    if !model_path.is_null() {
+        match string(model_path) {
+            Ok(path) => builder = builder.model_path(path),
+            Err(_) => return ptr::null_mut(),
+        }
+    }
+    match builder.build() {
+        Ok(manager) => Box::into_raw(Box::new(manager)),
+        Err(e) => {
+            error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+///
+/// `manager` is null or was returned by [`mm_manager_new`] and isn't used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn mm_manager_free(manager: *mut ModelManager) {
+    if !manager.is_null() {
+        drop(Box::from_raw(manager));
+    }
+}
+
+/// Registers the models of a registry file.
+///
+/// # Safety
+///
+/// `manager` was returned by [`mm_manager_new`], `path` is a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mm_register_config(
+    manager: *mut ModelManager,
+    path: *const c_char,
+) -> c_int {
+    let manager = arg!(self::manager(manager));
+    match manager.register_config(arg!(string(path))) {
+        Ok(()) => 0,
+        Err(e) => error(e),
+    }
+}
+
+/// Registers `ident` with a model definition in JSON as in a JSON registry, like
+/// `{"version": "1", "source": "hf://org/repo"}`.
+///
+/// # Safety
+///
+/// `manager` was returned by [`mm_manager_new`], `ident` and `json` are null-terminated
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn mm_register_model_json(
+    manager: *mut ModelManager,
+    ident: *const c_char,
+    json: *const c_char,
+) -> c_int {
+    let manager = arg!(self::manager(manager));
+    let ident = arg!(string(ident));
+    let mut model = match serde_json::from_str::<Model>(arg!(string(json))) {
+        Ok(model) => model,
+        Err(e) => return error(Error::config(e)),
+    };
+    // like in a registry, the directory defaults to the ident
+    if model.directory.as_os_str().is_empty() {
+        model.directory = PathBuf::from(ident);
+    }
+    manager.register_models(HashMap::from([(ident.to_string(), model)]));
+    0
+}
+
+struct CallbackObserver {
+    callback: ProgressCallback,
+    user_data: *mut c_void,
+}
+
+// the caller of `mm_set_progress_callback` promises the user data may be used from any thread
+unsafe impl Send for CallbackObserver {}
+unsafe impl Sync for CallbackObserver {}
+
+impl CallbackObserver {
+    fn send(&self, event: &str, model: &str, file: Option<&str>, downloaded: u64, total: u64) {
+        let c = |v: &str| CString::new(v.replace('\0', "")).unwrap_or_default();
+        let (event, model, file) = (c(event), c(model), file.map(c));
+        (self.callback)(
+            self.user_data,
+            event.as_ptr(),
+            model.as_ptr(),
+            file.as_ref().map_or(ptr::null(), |v| v.as_ptr()),
+            downloaded,
+            total,
+        );
+    }
+}
+
+impl ProgressObserver for CallbackObserver {
+    fn on_queued(&self, model: &str) {
+        self.send("queued", model, None, 0, 0);
+    }
+
+    fn on_start(&self, model: &str, file: &str, total: Option<u64>) {
+        self.send("start", model, Some(file), 0, total.unwrap_or_default());
+    }
+
+    fn on_bytes(&self, model: &str, file: &str, downloaded: u64) {
+        self.send("bytes", model, Some(file), downloaded, 0);
+    }
+
+    fn on_file_done(&self, model: &str, file: &str) {
+        self.send("file_done", model, Some(file), 0, 0);
+    }
+
+    fn on_retry(&self, model: &str, file: &str, attempt: usize) {
+        self.send("retry", model, Some(file), attempt as u64, 0);
+    }
+
+    fn on_unpack(&self, model: &str) {
+        self.send("unpack", model, None, 0, 0);
+    }
+
+    fn on_model_done(&self, model: &str) {
+        self.send("done", model, None, 0, 0);
+    }
+
+    fn on_error(&self, model: &str, _error: &Error) {
+        self.send("error", model, None, 0, 0);
+    }
+}
+
+/// Reports the progress of downloads to `callback`, on the threads downloading.
+///
+/// # Safety
+///
+/// `manager` was returned by [`mm_manager_new`], `user_data` stays valid and may be used from
+/// any thread as long as the manager downloads.
+#[no_mangle]
+pub unsafe extern "C" fn mm_set_progress_callback(
+    manager: *mut ModelManager,
+    callback: ProgressCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    let manager = arg!(self::manager(manager));
+    manager.set_progress_observer(Arc::new(CallbackObserver {
+        callback,
+        user_data,
+    }));
+    0
+}
+
+/// Downloads the model and its dependencies if needed and writes the path of its directory to
+/// `path`, which is freed with [`mm_string_free`]. Blocks until the download finished.
+///
+/// # Safety
+///
+/// `manager` was returned by [`mm_manager_new`], `ident` is a null-terminated string and `path`
+/// points to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn mm_get_model(
+    manager: *mut ModelManager,
+    ident: *const c_char,
+    path: *mut *mut c_char,
+) -> c_int {
+    let manager = arg!(self::manager(manager));
+    match manager.get_model(arg!(string(ident))) {
+        Ok((dir, _)) => write_path(dir, path),
+        Err(e) => error(e),
+    }
+}
+
+/// Writes the path of a model that's already downloaded to `path`, without downloading
+/// anything. Fails with [`Error::ModelNotFound`]'s code if it isn't.
+///
+/// # Safety
+///
+/// Same as [`mm_get_model`].
+#[no_mangle]
+pub unsafe extern "C" fn mm_model_path(
+    manager: *mut ModelManager,
+    ident: *const c_char,
+    path: *mut *mut c_char,
+) -> c_int {
+    let manager = arg!(self::manager(manager));
+    match manager.get_model_if_cached(arg!(string(ident))) {
+        Some((dir, _)) => write_path(dir, path),
+        None => error(Error::ModelNotFound),
+    }
+}
+
+/// # Safety
+///
+/// `value` is null or a string returned by this library that isn't used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn mm_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+/// Message of the last failure on this thread, null if there was none. Valid until the next
+/// call on this thread.
+#[no_mangle]
+pub extern "C" fn mm_last_error() -> *const c_char {
+    LAST_ERROR.with(|v| v.borrow().as_ref().map_or(ptr::null(), |v| v.as_ptr()))
+}
@@ -0,0 +1,258 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use model_manager::error::Error;
+use model_manager::lockfile::{LockDrift, Lockfile};
+use model_manager::model_manager::ModelManager;
+use model_manager::progress::ProgressObserver;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+create_exception!(model_manager_py, ModelManagerError, PyException);
+
+fn err(error: Error) -> PyErr {
+    ModelManagerError::new_err(error.report())
+}
+
+/// The model manager for Python, sharing its model directory and lockfiles with Rust programs.
+/// Blocking calls release the GIL while they download.
+#[pyclass(name = "ModelManager")]
+struct PyModelManager {
+    inner: ModelManager,
+}
+
+#[pymethods]
+impl PyModelManager {
+    /// Opens `model_path` or the default directory, with the models of the registry file if one
+    /// is given. A registry without `model_path` decides the directory itself.
+    #[new]
+    #[pyo3(signature = (model_path=None, registry=None))]
+    fn new(model_path: Option<PathBuf>, registry: Option<PathBuf>) -> PyResult<Self> {
+        let inner = match (model_path, registry) {
+            (None, Some(registry)) => ModelManager::from_config(registry),
+            (model_path, registry) => {
+                let mut builder = ModelManager::builder();
+                if let Some(path) = model_path {
+                    builder = builder.model_path(path);
+                }
+                builder.build().and_then(|mut manager| {
+                    if let Some(registry) = registry {
+                        manager.register_config(registry)?;
+                    }
+                    Ok(manager)
+                })
+            }
+        };
+        Ok(Self {
+            inner: inner.map_err(err)?,
+        })
+    }
+
+    #[getter]
+    fn model_path(&self) -> PathBuf {
+        self.inner.model_path().to_path_buf()
+    }
+
+    /// Silences the status lines printed while downloading.
+    fn set_quiet(&mut self, quiet: bool) {
+        self.inner.set_quiet(quiet);
+    }
+
+    fn register_config(&mut self, path: PathBuf) -> PyResult<()> {
+        self.inner.register_config(path).map_err(err)
+    }
+
+    /// Calls `callback(event, model, file, downloaded, total)` for the progress of downloads,
+    /// with `None` for what an event doesn't have. Events are `queued`, `start`, `bytes`,
+    /// `file_done`, `retry`, `unpack`, `done` and `error`. The terminal progress bars are
+    /// replaced.
+    fn set_progress_callback(&mut self, callback: Py<PyAny>) {
+        self.inner
+            .set_progress_observer(Arc::new(PyObserver { callback }));
+    }
+
+    /// Downloads the model and its dependencies if needed and returns its directory.
+    fn get_model(&self, py: Python<'_>, ident: &str) -> PyResult<PathBuf> {
+        py.detach(|| self.inner.get_model(ident).map(|v| v.0))
+            .map_err(err)
+    }
+
+    /// `get_model` as an awaitable for asyncio.
+    fn get_model_async<'py>(&self, py: Python<'py>, ident: String) -> PyResult<Bound<'py, PyAny>> {
+        let manager = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            manager
+                .get_model_async(&ident)
+                .await
+                .map(|v| v.0)
+                .map_err(err)
+        })
+    }
+
+    /// Downloads every registered model, `processes` at a time, and returns the idents that
+    /// were downloaded. Fails with the errors of the models that couldn't be.
+    #[pyo3(signature = (processes=4))]
+    fn download_all(&self, py: Python<'_>, processes: usize) -> PyResult<Vec<String>> {
+        let runtime = pyo3_async_runtimes::tokio::get_runtime();
+        py.detach(|| runtime.block_on(download_all(self.inner.clone(), processes)))
+    }
+
+    #[pyo3(signature = (processes=4))]
+    fn download_all_async<'py>(
+        &self,
+        py: Python<'py>,
+        processes: usize,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let manager = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, download_all(manager, processes))
+    }
+
+    fn is_downloaded(&self, ident: &str) -> bool {
+        self.inner.is_downloaded(ident)
+    }
+
+    /// Registered and unregistered models on disk as dicts.
+    fn list<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.inner
+            .list()
+            .map_err(err)?
+            .into_iter()
+            .map(|status| {
+                let dict = PyDict::new(py);
+                dict.set_item("ident", status.ident)?;
+                dict.set_item("registered", status.registered)?;
+                dict.set_item("downloaded", status.downloaded)?;
+                dict.set_item("expected_version", status.expected_version)?;
+                dict.set_item("installed_version", status.installed_version)?;
+                dict.set_item("revision", status.revision)?;
+                dict.set_item("size", status.size)?;
+                let capabilities = status.capabilities.unwrap_or_default();
+                dict.set_item("task", capabilities.task)?;
+                dict.set_item("context_length", capabilities.context_length)?;
+                dict.set_item("parameters", capabilities.parameters)?;
+                dict.set_item("precision", capabilities.precision)?;
+                dict.set_item("vram", capabilities.vram)?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
+    /// Re-hashes the files of a downloaded model against its checksums, returns the files
+    /// checked.
+    fn verify(&self, py: Python<'_>, ident: &str) -> PyResult<usize> {
+        py.detach(|| self.inner.verify(ident)).map_err(err)
+    }
+
+    fn remove(&mut self, ident: &str) -> PyResult<()> {
+        self.inner.remove(ident).map(|_| ()).map_err(err)
+    }
+
+    /// Writes the lockfile at `path` like `model-manager lock`, keeping the entries of an
+    /// existing one unless `update` is set.
+    #[pyo3(signature = (path, update=false))]
+    fn lock(&self, py: Python<'_>, path: PathBuf, update: bool) -> PyResult<()> {
+        py.detach(|| {
+            let previous = match update || !path.exists() {
+                true => None,
+                false => Some(Lockfile::from_file(&path)?),
+            };
+            self.inner.lock(previous.as_ref())?.write(&path)
+        })
+        .map_err(err)
+    }
+
+    /// Models whose definition differs from the lockfile at `path`, as `(ident, reason)`.
+    fn lock_drift(&self, path: PathBuf) -> PyResult<Vec<(String, &'static str)>> {
+        let lockfile = Lockfile::from_file(path).map_err(err)?;
+        let drift = self.inner.lock_drift(&lockfile).map_err(err)?;
+        Ok(drift
+            .into_iter()
+            .map(|drift| match drift {
+                LockDrift::Added(ident) => (ident, "not locked"),
+                LockDrift::Removed(ident) => (ident, "no longer registered"),
+                LockDrift::Changed(ident) => (ident, "changed since locked"),
+            })
+            .collect())
+    }
+}
+
+async fn download_all(manager: ModelManager, processes: usize) -> PyResult<Vec<String>> {
+    let report = manager.download_all(processes).await.map_err(err)?;
+    if !report.is_success() {
+        let failed = report
+            .failed
+            .iter()
+            .map(|(ident, error)| format!("{ident}: {}", error.report()))
+            .collect::<Vec<_>>();
+        return Err(ModelManagerError::new_err(failed.join("\n")));
+    }
+    Ok(report.succeeded)
+}
+
+struct PyObserver {
+    callback: Py<PyAny>,
+}
+
+impl PyObserver {
+    fn send(
+        &self,
+        event: &str,
+        model: &str,
+        file: Option<&str>,
+        downloaded: Option<u64>,
+        total: Option<u64>,
+    ) {
+        Python::attach(|py| {
+            // a failing callback must not fail the download
+            if let Err(error) = self
+                .callback
+                .call1(py, (event, model, file, downloaded, total))
+            {
+                error.print(py);
+            }
+        });
+    }
+}
+
+impl ProgressObserver for PyObserver {
+    fn on_queued(&self, model: &str) {
+        self.send("queued", model, None, None, None);
+    }
+
+    fn on_start(&self, model: &str, file: &str, total: Option<u64>) {
+        self.send("start", model, Some(file), None, total);
+    }
+
+    fn on_bytes(&self, model: &str, file: &str, downloaded: u64) {
+        self.send("bytes", model, Some(file), Some(downloaded), None);
+    }
+
+    fn on_file_done(&self, model: &str, file: &str) {
+        self.send("file_done", model, Some(file), None, None);
+    }
+
+    fn on_retry(&self, model: &str, file: &str, attempt: usize) {
+        self.send("retry", model, Some(file), Some(attempt as u64), None);
+    }
+
+    fn on_unpack(&self, model: &str) {
+        self.send("unpack", model, None, None, None);
+    }
+
+    fn on_model_done(&self, model: &str) {
+        self.send("done", model, None, None, None);
+    }
+
+    fn on_error(&self, model: &str, _error: &Error) {
+        self.send("error", model, None, None, None);
+    }
+}
+
+#[pymodule]
+fn model_manager_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyModelManager>()?;
+    m.add("ModelManagerError", m.py().get_type::<ModelManagerError>())?;
+    Ok(())
+}
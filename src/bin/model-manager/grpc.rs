@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+
+use futures::Stream;
+use model_manager::error::Error;
+use model_manager::events;
+use model_manager::model_manager::{Model, ModelManager, ModelSource, ModelStatus};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status};
+
+use crate::source_label;
+use proto::download_event::Event;
+use proto::model_manager_server::{ModelManager as ModelManagerService, ModelManagerServer};
+
+#[allow(clippy::all)]
+mod proto {
+    tonic::include_proto!("model_manager.v1");
+}
+
+struct Service {
+    manager: Arc<RwLock<ModelManager>>,
+}
+
+fn status(err: Error) -> Status {
+    match err {
+        Error::ModelNotFound => Status::not_found("model not found"),
+        Error::AliasConflict(_) | Error::ConfigError(_) => Status::invalid_argument(err.report()),
+        err => Status::internal(err.report()),
+    }
+}
+
+fn model_info(status: ModelStatus, model: &Model) -> proto::ModelInfo {
+    proto::ModelInfo {
+        name: status.ident,
+        source: source_label(&model.source),
+        downloaded: status.downloaded,
+        expected_version: model.version.to_string(),
+        installed_version: status.installed_version,
+        revision: status.revision,
+        size: status.size,
+        last_used: status.last_used.map(|v| v.to_rfc3339()),
+        groups: model.groups.clone(),
+    }
+}
+
+fn find_model(manager: &ModelManager, ident: &str) -> Result<proto::ModelInfo, Status> {
+    let model = manager.model(ident).ok_or(status(Error::ModelNotFound))?;
+    manager
+        .list()
+        .map_err(status)?
+        .into_iter()
+        // aliases resolve to the same registered model
+        .find(|v| {
+            v.registered
+                && manager
+                    .model(&v.ident)
+                    .is_some_and(|m| std::ptr::eq(m, model))
+        })
+        .map(|v| model_info(v, model))
+        .ok_or(status(Error::ModelNotFound))
+}
+
+fn event(event: events::DownloadEvent) -> proto::DownloadEvent {
+    let (model, event) = match event {
+        events::DownloadEvent::Queued { model } => (model, Event::Queued(proto::Queued {})),
+        events::DownloadEvent::Started { model, file, total } => {
+            (model, Event::Started(proto::Started { file, total }))
+        }
+        events::DownloadEvent::Progress {
+            model,
+            file,
+            downloaded,
+        } => (model, Event::Progress(proto::Progress { file, downloaded })),
+        events::DownloadEvent::Retrying {
+            model,
+            file,
+            attempt,
+        } => (
+            model,
+            Event::Retrying(proto::Retrying {
+                file,
+                attempt: attempt as u64,
+            }),
+        ),
+        events::DownloadEvent::Finished { model } => (model, Event::Finished(proto::Finished {})),
+        events::DownloadEvent::Failed {
+            model,
+            error,
+            kind,
+            code,
+        } => (
+            model,
+            Event::Failed(proto::Failed {
+                error,
+                kind: kind.to_string(),
+                code: code.into(),
+            }),
+        ),
+        events::DownloadEvent::UpdateReady { model, revision } => {
+            (model, Event::UpdateReady(proto::UpdateReady { revision }))
+        }
+    };
+    proto::DownloadEvent {
+        model,
+        event: Some(event),
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<proto::DownloadEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl ModelManagerService for Service {
+    async fn status(
+        &self,
+        _: Request<proto::StatusRequest>,
+    ) -> Result<Response<proto::StatusResponse>, Status> {
+        let manager = self.manager.read().unwrap();
+        let list = manager.list().map_err(status)?;
+        Ok(Response::new(proto::StatusResponse {
+            path: manager.model_path().display().to_string(),
+            registered: list.iter().filter(|v| v.registered).count() as u64,
+            downloaded: list.iter().filter(|v| v.downloaded).count() as u64,
+            size: manager.total_size_on_disk().map_err(status)?,
+        }))
+    }
+
+    async fn list_models(
+        &self,
+        request: Request<proto::ListModelsRequest>,
+    ) -> Result<Response<proto::ListModelsResponse>, Status> {
+        let request = request.into_inner();
+        let manager = self.manager.read().unwrap();
+        let mut models = vec![];
+        for status in manager.list().map_err(status)? {
+            let Some(model) = manager.model(&status.ident).filter(|_| status.registered) else {
+                continue;
+            };
+            if request.downloaded_only && !status.downloaded {
+                continue;
+            }
+            if let Some(group) = &request.group {
+                if !model.groups.contains(group) {
+                    continue;
+                }
+            }
+            models.push(model_info(status, model));
+        }
+        Ok(Response::new(proto::ListModelsResponse { models }))
+    }
+
+    async fn get_model(
+        &self,
+        request: Request<proto::GetModelRequest>,
+    ) -> Result<Response<proto::ModelInfo>, Status> {
+        let manager = self.manager.read().unwrap();
+        find_model(&manager, &request.into_inner().ident).map(Response::new)
+    }
+
+    async fn register_model(
+        &self,
+        request: Request<proto::RegisterModelRequest>,
+    ) -> Result<Response<proto::ModelInfo>, Status> {
+        let request = request.into_inner();
+        let source = ModelSource::parse(&request.source).map_err(status)?;
+        let directory = request.directory.unwrap_or(request.ident.to_string());
+        let mut model = Model::new(PathBuf::from(directory), request.version, source);
+        for group in request.groups {
+            model = model.group(group);
+        }
+        let mut manager = self.manager.write().unwrap();
+        manager.register_models(HashMap::from([(request.ident.to_string(), model)]));
+        find_model(&manager, &request.ident).map(Response::new)
+    }
+
+    async fn delete_model(
+        &self,
+        request: Request<proto::DeleteModelRequest>,
+    ) -> Result<Response<proto::DeleteModelResponse>, Status> {
+        let mut manager = self.manager.write().unwrap();
+        manager
+            .remove(&request.into_inner().ident)
+            .map_err(status)?;
+        Ok(Response::new(proto::DeleteModelResponse {}))
+    }
+
+    type DownloadStream = EventStream;
+
+    async fn download(
+        &self,
+        request: Request<proto::DownloadRequest>,
+    ) -> Result<Response<Self::DownloadStream>, Status> {
+        let ident = request.into_inner().ident;
+        // downloads work on a snapshot so registrations aren't blocked, in-flight tracking is shared
+        let manager = self.manager.read().unwrap().clone();
+        let dependencies = manager
+            .dependencies(&ident)
+            .map_err(status)?
+            .into_iter()
+            .map(|v| v.0.to_string())
+            .collect::<Vec<_>>();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut events = manager.subscribe();
+        tokio::spawn(async move {
+            let target = manager.model(&ident);
+            let relevant = |model: &str| {
+                dependencies.iter().any(|v| v == model)
+                    || manager
+                        .model(model)
+                        .zip(target)
+                        .is_some_and(|(a, b)| std::ptr::eq(a, b))
+            };
+            let send = |e: events::DownloadEvent| {
+                // a client that went away doesn't cancel the download
+                if relevant(e.model()) {
+                    let _ = sender.send(Ok(event(e)));
+                }
+            };
+            let task = manager.get_model_async(&ident);
+            tokio::pin!(task);
+            let result = loop {
+                tokio::select! {
+                    result = &mut task => break result,
+                    e = events.recv() => match e {
+                        Ok(e) => send(e),
+                        Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => break task.await,
+                    },
+                }
+            };
+            while let Ok(e) = events.try_recv() {
+                send(e);
+            }
+            let last = match result {
+                Ok((path, _)) => Ok(proto::DownloadEvent {
+                    model: ident.to_string(),
+                    event: Some(Event::Ready(proto::Ready {
+                        path: path.display().to_string(),
+                    })),
+                }),
+                Err(err) => Err(status(err)),
+            };
+            let _ = sender.send(last);
+        });
+        let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|v| (v, receiver))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Serves the gRPC interface until the process is stopped. Like the REST daemon, registrations
+/// live in memory only.
+pub(crate) async fn run(mut manager: ModelManager, listen: SocketAddr) -> Result<(), Error> {
+    manager.set_quiet(true);
+    let service = Service {
+        manager: Arc::new(RwLock::new(manager)),
+    };
+    tonic::transport::Server::builder()
+        .add_service(ModelManagerServer::new(service))
+        .serve(listen)
+        .await
+        .map_err(|e| Error::new("Server failed", e))
+}
@@ -0,0 +1,1361 @@
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use indicatif::{HumanBytes, HumanDuration};
+use model_manager::error::Error;
+use model_manager::events::DownloadEvent;
+use model_manager::limit::HostLimit;
+use model_manager::lockfile::{LockDrift, Lockfile};
+use model_manager::logfile::LogFile;
+use model_manager::model_manager::{CleanOptions, Model, ModelManager, ModelSource, ModelStatus};
+use model_manager::ndjson::NdjsonObserver;
+use model_manager::progress::Verbosity;
+use model_manager::registry::Registry;
+use model_manager::storage::{Permissions, StorageLayout};
+use model_manager::variants::{default_variant, Variant};
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "serve")]
+mod mirror;
+mod prefetch;
+#[cfg(feature = "serve")]
+mod serve;
+#[cfg(feature = "tui")]
+mod tui;
+
+#[derive(Parser)]
+#[command(
+    name = "model-manager",
+    version,
+    about = "Download and manage models of a registry"
+)]
+struct Cli {
+    /// Registry file (TOML, or JSON/YAML with the matching features).
+    #[arg(
+        long,
+        short,
+        env = "MODEL_MANAGER_CONFIG",
+        default_value = "models.toml"
+    )]
+    config: PathBuf,
+    /// Directory models are stored in, overrides the registry's `path`.
+    #[arg(long, env = "MODEL_MANAGER_HOME")]
+    models_dir: Option<PathBuf>,
+    /// Further directory models spill over to once the earlier ones are full, in order.
+    #[arg(long)]
+    storage_root: Vec<PathBuf>,
+    /// Tenant whose separate directory inside the models directory is used.
+    #[arg(long, env = "MODEL_MANAGER_NAMESPACE", global = true)]
+    namespace: Option<String>,
+    /// Directory downloads are written to before they're moved into the models directory.
+    #[arg(long, env = "MODEL_MANAGER_TEMP_DIR")]
+    temp_dir: Option<PathBuf>,
+    /// Octal mode like `644` given to every downloaded file.
+    #[arg(long, value_parser = parse_mode)]
+    file_mode: Option<u32>,
+    /// Octal mode like `755` given to every directory of a downloaded model.
+    #[arg(long, value_parser = parse_mode)]
+    dir_mode: Option<u32>,
+    /// Owner like `1000:1000` or `1000` given to downloaded models, usually needs root.
+    #[arg(long, value_parser = parse_owner)]
+    owner: Option<(Option<u32>, Option<u32>)>,
+    /// Storage layout like `versioned:2` or `huggingface-hub`, overrides the registry's `layout`.
+    #[arg(long, env = "MODEL_MANAGER_LAYOUT")]
+    layout: Option<StorageLayout>,
+    /// Channel like `beta` the models follow, overrides the registry's `channel`.
+    #[arg(long, env = "MODEL_MANAGER_CHANNEL", global = true)]
+    channel: Option<String>,
+    /// Print results as JSON and download progress as NDJSON events instead of text.
+    #[arg(long, global = true)]
+    json: bool,
+    /// Write download progress as NDJSON lines to stderr instead of drawing bars.
+    #[arg(long, global = true)]
+    progress_ndjson: bool,
+    /// `silent`, `summary` for only the line batch downloads finish with, or `verbose` for
+    /// progress bars too. `--json` implies `silent`.
+    #[arg(
+        long,
+        env = "MODEL_MANAGER_VERBOSITY",
+        default_value = "verbose",
+        global = true
+    )]
+    verbosity: Verbosity,
+    /// Print status lines without emoji, for minimal terminals and log scrapers.
+    #[arg(long, env = "MODEL_MANAGER_NO_EMOJI", global = true)]
+    no_emoji: bool,
+    /// Serve models from disk only, never writing to the models directory. Also set by
+    /// `MODEL_MANAGER_READ_ONLY=1`.
+    #[arg(long, global = true)]
+    read_only: bool,
+    /// Append every download step to this file, rotated at 10 MiB.
+    #[arg(long, env = "MODEL_MANAGER_LOG_FILE", global = true)]
+    log_file: Option<PathBuf>,
+    /// POST a JSON notification to this URL whenever a model download finishes or fails.
+    #[arg(long, env = "MODEL_MANAGER_WEBHOOK", global = true)]
+    webhook: Vec<String>,
+    /// Bandwidth like `10MB` shared by all model-manager processes on this machine that set it.
+    #[arg(long, env = "MODEL_MANAGER_LIMIT_RATE", value_parser = parse_size, global = true)]
+    limit_rate: Option<u64>,
+    /// Connections shared by all model-manager processes on this machine that set it.
+    #[arg(long, env = "MODEL_MANAGER_MAX_CONNECTIONS", global = true)]
+    max_connections: Option<usize>,
+    /// Mirror on the LAN, started with `mirror`, preferred for the files it has with the same
+    /// sha256.
+    #[arg(long, global = true)]
+    peer: Vec<String>,
+    /// Look for mirrors on the LAN this long like `2s` before downloading and prefer them.
+    #[cfg(feature = "mdns")]
+    #[arg(long, value_parser = parse_duration, global = true)]
+    discover_peers: Option<Duration>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Download the given models and their dependencies, every model if none are given. Sources
+    /// like `hf://org/repo` that aren't registered are downloaded as they are, asking which
+    /// variant to get if the repo offers several quantizations or precisions.
+    Download {
+        idents: Vec<String>,
+        /// Take the default variant instead of asking, implied without a terminal or with `--json`.
+        #[arg(long)]
+        non_interactive: bool,
+        /// Give up on a model after this long like `90s` or `10m`, keeping what was downloaded.
+        #[arg(long, value_parser = parse_duration)]
+        deadline: Option<Duration>,
+    },
+    /// List registered and unregistered models on disk.
+    List {
+        #[arg(long)]
+        downloaded_only: bool,
+        /// Only models of this group.
+        #[arg(long)]
+        group: Option<String>,
+    },
+    /// Delete a model from disk.
+    Remove { ident: String },
+    /// Re-hash the files of downloaded models against their checksums and the lockfile. Exits
+    /// with 1 on a mismatch or a model that isn't downloaded and with 2 if a model has no
+    /// checksums to check its files against.
+    Verify {
+        idents: Vec<String>,
+        /// Defaults to the registry file with a `.lock` extension, if there is one.
+        #[arg(long)]
+        lockfile: Option<PathBuf>,
+    },
+    /// Evict expired and least recently used models.
+    Gc {
+        /// Size budget like `200GB` or `512MiB`, evicting least recently used models beyond it.
+        #[arg(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+        /// Only print what would be evicted.
+        #[arg(long, conflicts_with = "yes")]
+        dry_run: bool,
+        /// Evict without asking.
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Move everything that isn't a registered model out of the model directory into a backup
+    /// next to it.
+    Clean {
+        /// Backups to keep, this one included. Older ones are deleted after asking.
+        #[arg(long, default_value_t = 1)]
+        keep_backups: usize,
+        /// Delete old backups without asking.
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Summary of the model directory.
+    Status,
+    /// Check that the model directory is writable and every source reachable with the configured
+    /// token, failing otherwise.
+    Health,
+    /// Resume or clean up downloads and updates a crashed process left unfinished.
+    Recover,
+    /// Move the downloaded models onto the versions of a channel, activating the ones still
+    /// installed and downloading the others. Without a channel back to the registered versions.
+    /// Later commands follow it with `--channel` or the registry's `channel`.
+    SwitchChannel {
+        #[arg(value_name = "CHANNEL")]
+        name: Option<String>,
+    },
+    /// Manage the download queue kept in the model directory across restarts.
+    Queue {
+        #[command(subcommand)]
+        action: QueueAction,
+    },
+    /// Finished downloads, oldest first.
+    History {
+        /// Totals per host and model instead, to spot slow mirrors.
+        #[arg(long)]
+        stats: bool,
+    },
+    /// Disk usage per model and file, largest first.
+    Du {
+        /// Files listed per model, 0 for all.
+        #[arg(long, default_value_t = 5)]
+        files: usize,
+    },
+    /// Resolve every model to a revision and its files and write them to the lockfile.
+    Lock {
+        /// Only fail if the registry and the lockfile drifted apart, without resolving anything.
+        #[arg(long, conflicts_with = "update")]
+        check: bool,
+        /// Resolve unchanged models again instead of keeping their locked revisions.
+        #[arg(long)]
+        update: bool,
+        /// Defaults to the registry file with a `.lock` extension.
+        #[arg(long)]
+        lockfile: Option<PathBuf>,
+    },
+    /// Download every model of a manifest into a directory with the manifest and a lockfile, which
+    /// an air-gapped machine opens with `ModelManager::from_offline_bundle`.
+    OfflineBundle {
+        /// Registry file listing the models, used instead of `--config`.
+        manifest: PathBuf,
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+    /// Place every model of the registry into a project directory with a lockfile, hard-linked
+    /// from the models directory. `ModelManager::from_offline_bundle` opens it.
+    Vendor {
+        output: PathBuf,
+        /// Copy the files instead of hard-linking them.
+        #[arg(long)]
+        copy: bool,
+    },
+    /// Check for new upstream revisions periodically and stage them next to the installed
+    /// versions.
+    Watch {
+        /// Time between checks like `30m`, `1h` or `1d`.
+        #[arg(long, default_value = "1h", value_parser = parse_duration)]
+        interval: Duration,
+        /// Swap staged updates in right away instead of leaving them for the application.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Download every model of a manifest, for build stages and cache warm-up jobs. Exits with
+    /// 2 for an invalid manifest, 3 for network errors, 4 for auth errors, 5 for checksum or
+    /// signature mismatches, 6 for disk errors and 1 for anything else.
+    Prefetch {
+        /// Registry file listing the models, used instead of `--config`.
+        manifest: PathBuf,
+        /// Models downloaded in parallel, defaults to the manager's concurrency.
+        #[arg(long)]
+        processes: Option<usize>,
+    },
+    /// Pack a downloaded model with its definition and checksums into a bundle.
+    #[cfg(feature = "bundle")]
+    Export {
+        ident: String,
+        /// Bundle file, `-` writes it to stdout.
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+    /// Install a model from a bundle written by `export`.
+    #[cfg(feature = "bundle")]
+    Import {
+        /// Bundle file, `-` reads it from stdin.
+        bundle: PathBuf,
+    },
+    /// Run a daemon managing the model directory through a local REST API.
+    #[cfg(feature = "serve")]
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:7450")]
+        listen: std::net::SocketAddr,
+    },
+    /// Serve the downloaded models read-only to other machines, which set their Huggingface
+    /// endpoint to this host to download from it instead of the internet.
+    #[cfg(feature = "serve")]
+    Mirror {
+        #[arg(long, default_value = "0.0.0.0:7452")]
+        listen: std::net::SocketAddr,
+    },
+    /// Run a daemon offering the REST API's operations over gRPC, with streamed progress.
+    #[cfg(feature = "grpc")]
+    Grpc {
+        #[arg(long, default_value = "127.0.0.1:7451")]
+        listen: std::net::SocketAddr,
+    },
+    /// Interactive dashboard to download, pause, cancel and retry models.
+    #[cfg(feature = "tui")]
+    Tui,
+}
+
+#[derive(Subcommand)]
+enum QueueAction {
+    /// Queue models and their dependencies for download.
+    Add {
+        idents: Vec<String>,
+    },
+    /// Show the queued models, oldest first.
+    List,
+    Remove {
+        ident: String,
+    },
+    /// Download the queued models, removing each once downloaded.
+    Run {
+        /// Keep trying failed models at this interval like `30s` or `5m` until the queue is empty.
+        #[arg(long, value_parser = parse_duration)]
+        retry: Option<Duration>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let json = cli.json;
+    match run(cli).await {
+        Ok(code) => code,
+        Err(err) if json => {
+            println!(
+                "{}",
+                json!({ "error": err.report(), "kind": err.kind(), "error_code": err.code() })
+            );
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            eprintln!("error: {}", err.report());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn manager(cli: &Cli, config: &Path) -> Result<ModelManager, Error> {
+    let registry = Registry::from_file(config)?;
+    let mut builder = ModelManager::builder();
+    if let Some(path) = cli.models_dir.as_ref().or(registry.path.as_ref()) {
+        builder = builder.model_path(path);
+    }
+    let mut permissions = Permissions::default();
+    if let Some(mode) = cli.file_mode {
+        permissions = permissions.file_mode(mode);
+    }
+    if let Some(mode) = cli.dir_mode {
+        permissions = permissions.dir_mode(mode);
+    }
+    if let Some((uid, gid)) = cli.owner {
+        permissions = permissions.owner(uid, gid);
+    }
+    builder = builder.permissions(permissions);
+    if let Some(dir) = &cli.temp_dir {
+        builder = builder.temp_dir(dir);
+    }
+    for root in &cli.storage_root {
+        builder = builder.storage_root(root);
+    }
+    if let Some(layout) = cli.layout.as_ref().or(registry.layout.as_ref()) {
+        builder = builder.storage_layout(layout.clone());
+    }
+    if let Some(channel) = cli.channel.as_ref().or(registry.channel.as_ref()) {
+        builder = builder.channel(channel);
+    }
+    if cli.limit_rate.is_some() || cli.max_connections.is_some() {
+        builder = builder.host_limit(HostLimit {
+            bytes_per_sec: cli.limit_rate,
+            connections: cli.max_connections,
+            ..HostLimit::default()
+        });
+    }
+    if cli.progress_ndjson {
+        builder = builder.progress_observer(Arc::new(NdjsonObserver::new(std::io::stderr())));
+    }
+    if let Some(path) = &cli.log_file {
+        builder = builder.log_file(LogFile::new(path));
+    }
+    for url in &cli.webhook {
+        builder = builder.webhook(url);
+    }
+    for url in &cli.peer {
+        builder = builder.peer(url);
+    }
+    if cli.read_only {
+        builder = builder.read_only(true);
+    }
+    let verbosity = match cli.json {
+        true => Verbosity::Silent,
+        false => cli.verbosity,
+    };
+    let mut manager = builder
+        .verbosity(verbosity)
+        .emoji(!cli.no_emoji)
+        .handle_ctrl_c(true)
+        .build()?;
+    if let Some(namespace) = &cli.namespace {
+        manager = manager.namespace(namespace)?;
+    }
+    #[cfg(feature = "mdns")]
+    if let Some(timeout) = cli.discover_peers {
+        let found = manager.discover_peers(timeout)?;
+        // the ones given explicitly are tried first
+        let mut peers = cli.peer.clone();
+        peers.extend(found.into_iter().map(|v| v.url));
+        manager.set_peers(peers);
+    }
+    manager.register_registry(registry)?;
+    Ok(manager)
+}
+
+async fn run(cli: Cli) -> Result<ExitCode, Error> {
+    if let Command::Prefetch {
+        manifest,
+        processes,
+    } = &cli.command
+    {
+        return Ok(prefetch::run(&cli, manifest, *processes).await);
+    }
+    let config = match &cli.command {
+        Command::OfflineBundle { manifest, .. } => manifest,
+        _ => &cli.config,
+    };
+    let mut manager = manager(&cli, config)?;
+    match cli.command {
+        Command::Download { idents, .. } if idents.is_empty() => {
+            manager.set_continue_on_error(true);
+            let events = cli.json.then(|| manager.subscribe());
+            let report = with_events(events, manager.download_all(manager.concurrency())).await?;
+            if cli.json {
+                let failed = report
+                    .failed
+                    .iter()
+                    .map(|(ident, err)| {
+                        json!({
+                            "model": ident,
+                            "error": err.report(),
+                            "kind": err.kind(),
+                            "error_code": err.code(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                println!(
+                    "{}",
+                    json!({
+                        "event": "report",
+                        "succeeded": report.succeeded,
+                        "failed": failed,
+                        "skipped": report.skipped,
+                        "bytes": report.bytes,
+                        "retries": report.retries,
+                        "elapsed_ms": report.elapsed.as_millis() as u64,
+                    })
+                );
+            }
+            for (ident, err) in report.failed.iter().filter(|_| !cli.json) {
+                eprintln!("{ident}: {}", err.report());
+            }
+            if !report.is_success() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Download {
+            idents,
+            non_interactive,
+            deadline,
+        } => {
+            let interactive = !non_interactive && !cli.json && std::io::stdin().is_terminal();
+            for ident in idents {
+                let ident = match manager.model(&ident) {
+                    Some(_) => ident,
+                    None => register_source(&mut manager, &ident, interactive).await?,
+                };
+                let events = cli.json.then(|| manager.subscribe());
+                let (path, _) = match deadline {
+                    Some(deadline) => {
+                        let download = manager.get_model_async_with_deadline(&ident, deadline);
+                        with_events(events, download).await?
+                    }
+                    None => with_events(events, manager.get_model_async(&ident)).await?,
+                };
+                match cli.json {
+                    true => println!(
+                        "{}",
+                        json!({ "event": "ready", "model": ident, "path": path })
+                    ),
+                    false => println!("{ident}: {}", path.display()),
+                }
+            }
+        }
+        Command::List {
+            downloaded_only,
+            group,
+        } => {
+            let mut rows = vec![[
+                "NAME",
+                "SOURCE",
+                "VERSION",
+                "DOWNLOADED",
+                "SIZE",
+                "LAST USED",
+            ]
+            .map(String::from)];
+            let mut entries = vec![];
+            for status in manager.list()? {
+                let model = manager.model(&status.ident);
+                if downloaded_only && !status.downloaded {
+                    continue;
+                }
+                if let Some(group) = &group {
+                    if !model.is_some_and(|v| v.groups.contains(group)) {
+                        continue;
+                    }
+                }
+                if cli.json {
+                    entries.push(status_json(&status, model));
+                    continue;
+                }
+                let downloaded = match (status.registered, status.downloaded) {
+                    (false, _) => "unregistered",
+                    (true, true) => "yes",
+                    (true, false) => "no",
+                };
+                rows.push([
+                    status.ident,
+                    model.map_or("-".to_string(), |v| source_label(&v.source)),
+                    status
+                        .installed_version
+                        .or(status.expected_version)
+                        .unwrap_or("-".to_string()),
+                    downloaded.to_string(),
+                    HumanBytes(status.size).to_string(),
+                    status
+                        .last_used
+                        .map_or("-".to_string(), |v| v.format("%Y-%m-%d %H:%M").to_string()),
+                ]);
+            }
+            match cli.json {
+                true => println!("{}", Value::Array(entries)),
+                false => print_table(&rows),
+            }
+        }
+        Command::Remove { ident } => {
+            manager.remove(&ident)?;
+            match cli.json {
+                true => println!("{}", json!({ "removed": ident })),
+                false => println!("removed {ident}"),
+            }
+        }
+        Command::Verify { idents, lockfile } => {
+            let lockfile = match lockfile {
+                Some(path) => Some(Lockfile::from_file(path)?),
+                None => {
+                    let path = cli.config.with_extension("lock");
+                    match path.is_file() {
+                        true => Some(Lockfile::from_file(path)?),
+                        false => None,
+                    }
+                }
+            };
+            let idents = match idents.is_empty() {
+                true => manager
+                    .list()?
+                    .into_iter()
+                    .filter(|v| v.downloaded)
+                    .map(|v| v.ident)
+                    .collect(),
+                false => idents,
+            };
+            let mut failed = false;
+            let mut unchecked = false;
+            let mut results = vec![];
+            for ident in idents {
+                let result = match &lockfile {
+                    Some(lockfile) => manager.verify_locked(&ident, lockfile),
+                    None => manager.verify(&ident),
+                };
+                failed |= result.is_err();
+                unchecked |= matches!(result, Ok(0));
+                match (&result, cli.json) {
+                    (Ok(checked), true) => results
+                        .push(json!({ "model": ident, "ok": *checked > 0, "checked": checked })),
+                    (Err(err), true) => results.push(json!({
+                        "model": ident,
+                        "ok": false,
+                        "error": err.report(),
+                        "kind": err.kind(),
+                        "error_code": err.code(),
+                    })),
+                    (Ok(0), false) => println!("{ident}: no checksums recorded"),
+                    (Ok(checked), false) => println!("{ident}: ok, {checked} files"),
+                    (Err(err), false) => eprintln!("{ident}: {}", err.report()),
+                }
+            }
+            if cli.json {
+                println!("{}", Value::Array(results));
+            }
+            if failed {
+                return Ok(ExitCode::FAILURE);
+            }
+            // a health check passing without checking anything would be misleading
+            if unchecked {
+                return Ok(ExitCode::from(2));
+            }
+        }
+        Command::Gc {
+            max_size,
+            dry_run,
+            yes,
+        } => {
+            if max_size.is_some() {
+                manager.set_max_size(max_size);
+            }
+            let plan = manager.gc_plan()?;
+            if cli.json {
+                if !dry_run && !yes && !plan.is_empty() {
+                    return Err(Error::config("--json needs --yes or --dry-run to evict"));
+                }
+                // measured before evicting, the directories are gone afterwards
+                let sizes = plan
+                    .iter()
+                    .map(|ident| {
+                        (
+                            ident.clone(),
+                            manager.size_on_disk(ident).unwrap_or_default(),
+                        )
+                    })
+                    .collect::<HashMap<_, _>>();
+                let evicted = match dry_run {
+                    true => plan,
+                    false => manager.gc()?,
+                };
+                let evicted = evicted
+                    .iter()
+                    .map(|ident| {
+                        json!({ "model": ident, "size": sizes.get(ident).copied().unwrap_or_default() })
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", json!({ "dry_run": dry_run, "evicted": evicted }));
+                return Ok(ExitCode::SUCCESS);
+            }
+            if plan.is_empty() {
+                println!("nothing to evict");
+                return Ok(ExitCode::SUCCESS);
+            }
+            for ident in &plan {
+                let size = manager.size_on_disk(ident).unwrap_or_default();
+                println!("{ident}\t{}", HumanBytes(size));
+            }
+            if dry_run {
+                return Ok(ExitCode::SUCCESS);
+            }
+            if !yes && !confirm(&format!("Evict {} models?", plan.len())) {
+                return Ok(ExitCode::FAILURE);
+            }
+            for ident in manager.gc()? {
+                println!("evicted {ident}");
+            }
+        }
+        Command::Clean { keep_backups, yes } => {
+            // this run adds a backup, unless it's empty
+            let excess = (manager.backups()?.len() + 1).saturating_sub(keep_backups);
+            let confirm_delete = yes
+                || excess == 0
+                || (!cli.json
+                    && std::io::stdin().is_terminal()
+                    && confirm(&format!(
+                        "Delete old backups beyond the newest {keep_backups}?"
+                    )));
+            let report = manager.clean_directory_with(&CleanOptions {
+                keep_backups,
+                confirm_delete,
+            })?;
+            if cli.json {
+                println!(
+                    "{}",
+                    json!({
+                        "backup": report.backup,
+                        "removed": report.removed,
+                        "deleted": report.deleted,
+                        "unconfirmed": report.unconfirmed,
+                    })
+                );
+                return Ok(ExitCode::SUCCESS);
+            }
+            for file in &report.removed {
+                println!("removed {}", file.display());
+            }
+            match &report.backup {
+                Some(backup) => println!("backup in {}", backup.display()),
+                None if report.removed.is_empty() => println!("nothing to remove"),
+                None => {}
+            }
+            for backup in &report.deleted {
+                println!("deleted {}", backup.display());
+            }
+            for backup in &report.unconfirmed {
+                println!("kept {}, delete it with --yes", backup.display());
+            }
+        }
+        Command::Health => {
+            let report = manager.health_check_async().await;
+            match cli.json {
+                true => println!("{}", json!(report)),
+                false => {
+                    let disk = match (&report.disk.writable, &report.disk.error) {
+                        (true, _) => "writable".to_string(),
+                        (false, error) => {
+                            format!("not writable: {}", error.as_deref().unwrap_or("-"))
+                        }
+                    };
+                    println!("{}: {disk}", report.disk.path.display());
+                    let mut rows = vec![["NAME", "ENDPOINT", "STATUS", "ERROR"].map(String::from)];
+                    for source in &report.sources {
+                        rows.push([
+                            source.ident.to_string(),
+                            source.endpoint.to_string(),
+                            json!(source.status)
+                                .as_str()
+                                .unwrap_or_default()
+                                .to_string(),
+                            source.error.clone().unwrap_or("-".to_string()),
+                        ]);
+                    }
+                    print_table(&rows);
+                }
+            }
+            if !report.is_healthy() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        #[cfg(feature = "bundle")]
+        Command::Export { ident, output } if output == Path::new("-") => {
+            manager.export(&ident, std::io::stdout().lock())?;
+        }
+        #[cfg(feature = "bundle")]
+        Command::Export { ident, output } => {
+            manager.export_bundle(&ident, &output)?;
+            match cli.json {
+                true => println!("{}", json!({ "exported": ident, "bundle": output })),
+                false => println!("exported {ident} to {}", output.display()),
+            }
+        }
+        #[cfg(feature = "bundle")]
+        Command::Import { bundle } => {
+            let ident = match bundle == Path::new("-") {
+                true => manager.import(std::io::stdin().lock())?,
+                false => manager.import_bundle(&bundle)?,
+            };
+            match cli.json {
+                true => println!("{}", json!({ "imported": ident })),
+                false => println!("imported {ident}"),
+            }
+        }
+        #[cfg(feature = "grpc")]
+        Command::Grpc { listen } => grpc::run(manager, listen).await?,
+        #[cfg(feature = "serve")]
+        Command::Serve { listen } => serve::run(manager, listen).await?,
+        #[cfg(feature = "serve")]
+        Command::Mirror { listen } => mirror::run(manager, listen).await?,
+        #[cfg(feature = "tui")]
+        Command::Tui => tui::run(manager).await?,
+        Command::Lock {
+            check,
+            update,
+            lockfile,
+        } => {
+            let path = lockfile.unwrap_or_else(|| cli.config.with_extension("lock"));
+            if check {
+                let drift = manager.lock_drift(&Lockfile::from_file(&path)?)?;
+                let describe = |drift: &LockDrift| match drift {
+                    LockDrift::Added(ident) => (ident.to_string(), "not locked"),
+                    LockDrift::Removed(ident) => (ident.to_string(), "no longer registered"),
+                    LockDrift::Changed(ident) => (ident.to_string(), "changed since locked"),
+                };
+                match cli.json {
+                    true => {
+                        let entries = drift
+                            .iter()
+                            .map(describe)
+                            .map(|(ident, reason)| json!({ "model": ident, "drift": reason }))
+                            .collect::<Vec<_>>();
+                        println!(
+                            "{}",
+                            json!({ "up_to_date": drift.is_empty(), "drift": entries })
+                        );
+                    }
+                    false => {
+                        for (ident, reason) in drift.iter().map(describe) {
+                            eprintln!("{ident}: {reason}");
+                        }
+                    }
+                }
+                if !drift.is_empty() {
+                    return Ok(ExitCode::FAILURE);
+                }
+                return Ok(ExitCode::SUCCESS);
+            }
+            let previous = match update {
+                true => None,
+                // a missing lockfile is created, an unreadable one shouldn't be overwritten
+                false if !path.exists() => None,
+                false => Some(Lockfile::from_file(&path)?),
+            };
+            let lockfile = manager.lock_async(previous.as_ref()).await?;
+            lockfile.write(&path)?;
+            match cli.json {
+                true => println!(
+                    "{}",
+                    json!({ "lockfile": path, "models": lockfile.models.len() })
+                ),
+                false => println!(
+                    "locked {} models in {}",
+                    lockfile.models.len(),
+                    path.display()
+                ),
+            }
+        }
+        Command::OfflineBundle { output, .. } => {
+            let lockfile = manager.write_offline_bundle_async(&output).await?;
+            match cli.json {
+                true => println!(
+                    "{}",
+                    json!({ "bundle": output, "models": lockfile.models.len() })
+                ),
+                false => println!(
+                    "bundled {} models in {}",
+                    lockfile.models.len(),
+                    output.display()
+                ),
+            }
+        }
+        Command::Vendor { output, copy } => {
+            let lockfile = manager.vendor_async(&output, !copy).await?;
+            match cli.json {
+                true => println!(
+                    "{}",
+                    json!({ "vendored": output, "models": lockfile.models.len() })
+                ),
+                false => println!(
+                    "vendored {} models into {}",
+                    lockfile.models.len(),
+                    output.display()
+                ),
+            }
+        }
+        Command::Watch { interval, apply } => {
+            manager.set_continue_on_error(true);
+            let mut events = manager.subscribe();
+            let watcher = manager.auto_update(interval);
+            tokio::pin!(watcher);
+            loop {
+                let event = tokio::select! {
+                    _ = &mut watcher => break,
+                    event = events.recv() => match event {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    },
+                };
+                if cli.json {
+                    if let Ok(line) = serde_json::to_string(&event) {
+                        println!("{line}");
+                    }
+                }
+                let DownloadEvent::UpdateReady { model, revision } = event else {
+                    continue;
+                };
+                if !cli.json {
+                    println!("{model}: staged {revision}");
+                }
+                if apply {
+                    let path = manager.apply_update_async(&model).await?;
+                    match cli.json {
+                        true => println!(
+                            "{}",
+                            json!({ "event": "applied", "model": model, "path": path })
+                        ),
+                        false => println!("{model}: applied, {}", path.display()),
+                    }
+                }
+            }
+        }
+        Command::Prefetch { .. } => unreachable!("prefetch loads its own manifest"),
+        Command::Queue { action } => match action {
+            QueueAction::Add { idents } => {
+                for ident in &idents {
+                    manager.enqueue(ident)?;
+                }
+                match cli.json {
+                    true => println!("{}", json!({ "queued": idents })),
+                    false => println!("queued {}", idents.join(", ")),
+                }
+            }
+            QueueAction::List => {
+                let queued = manager.queue()?;
+                match cli.json {
+                    true => {
+                        let entries = queued
+                            .iter()
+                            .map(|v| json!({ "model": v.ident, "enqueued": v.enqueued }))
+                            .collect::<Vec<_>>();
+                        println!("{}", Value::Array(entries));
+                    }
+                    false => {
+                        let mut rows = vec![["NAME", "ENQUEUED"].map(String::from)];
+                        for entry in queued {
+                            let enqueued = chrono::DateTime::from_timestamp(entry.enqueued, 0)
+                                .map_or("-".to_string(), |v| {
+                                    v.format("%Y-%m-%d %H:%M").to_string()
+                                });
+                            rows.push([entry.ident, enqueued]);
+                        }
+                        print_table(&rows);
+                    }
+                }
+            }
+            QueueAction::Remove { ident } => {
+                if !manager.dequeue(&ident)? {
+                    return Err(Error::ModelNotFound);
+                }
+                match cli.json {
+                    true => println!("{}", json!({ "dequeued": ident })),
+                    false => println!("dequeued {ident}"),
+                }
+            }
+            QueueAction::Run { retry } => {
+                let events = cli.json.then(|| manager.subscribe());
+                let report = match retry {
+                    Some(retry) => with_events(events, manager.start_queue(retry)?.wait()).await?,
+                    None => with_events(events, manager.process_queue_async()).await?,
+                };
+                for ident in &report.succeeded {
+                    match cli.json {
+                        true => println!("{}", json!({ "event": "ready", "model": ident })),
+                        false => println!("{ident}: downloaded"),
+                    }
+                }
+                for (ident, err) in &report.failed {
+                    match cli.json {
+                        true => println!(
+                            "{}",
+                            json!({
+                                "event": "failed",
+                                "model": ident,
+                                "error": err.report(),
+                                "kind": err.kind(),
+                                "error_code": err.code(),
+                            })
+                        ),
+                        false => eprintln!("{ident}: {}", err.report()),
+                    }
+                }
+                if !report.is_success() {
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+        },
+        Command::Du { files } => {
+            let usage = manager.disk_usage()?;
+            let limit = |n: usize| match files {
+                0 => n,
+                files => files.min(n),
+            };
+            if cli.json {
+                let entries = usage
+                    .iter()
+                    .map(|v| {
+                        let files = v.files[..limit(v.files.len())]
+                            .iter()
+                            .map(|f| json!({ "path": f.path, "size": f.size }))
+                            .collect::<Vec<_>>();
+                        json!({
+                            "name": v.ident,
+                            "registered": v.registered,
+                            "size": v.size,
+                            "apparent_size": v.apparent_size,
+                            "dedup_savings": v.dedup_savings(),
+                            "files": files,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", Value::Array(entries));
+                return Ok(ExitCode::SUCCESS);
+            }
+            for v in &usage {
+                let unregistered = if v.registered { "" } else { " (unregistered)" };
+                match v.dedup_savings() {
+                    0 => println!("{}  {}{unregistered}", HumanBytes(v.size), v.ident),
+                    saved => println!(
+                        "{}  {}{unregistered}, {} saved by dedup",
+                        HumanBytes(v.size),
+                        v.ident,
+                        HumanBytes(saved)
+                    ),
+                }
+                for f in &v.files[..limit(v.files.len())] {
+                    println!("    {}  {}", HumanBytes(f.size), f.path.display());
+                }
+            }
+            let total = usage.iter().map(|v| v.size).sum::<u64>();
+            let saved = usage.iter().map(|v| v.dedup_savings()).sum::<u64>();
+            println!(
+                "total {}, {} saved by dedup",
+                HumanBytes(total),
+                HumanBytes(saved)
+            );
+        }
+        Command::History { stats: false } => {
+            let history = manager.history()?;
+            if cli.json {
+                println!("{}", json!(history));
+                return Ok(ExitCode::SUCCESS);
+            }
+            let mut rows = vec![[
+                "FINISHED", "NAME", "HOST", "SIZE", "TIME", "SPEED", "RETRIES",
+            ]
+            .map(String::from)];
+            for record in history {
+                let finished = chrono::DateTime::from_timestamp(record.finished, 0)
+                    .map_or("-".to_string(), |v| v.format("%Y-%m-%d %H:%M").to_string());
+                rows.push([
+                    finished,
+                    record.ident.clone(),
+                    record.host.clone(),
+                    HumanBytes(record.bytes).to_string(),
+                    HumanDuration(Duration::from_millis(record.duration_ms)).to_string(),
+                    format!("{}/s", HumanBytes(record.speed())),
+                    record.retries.to_string(),
+                ]);
+            }
+            print_table(&rows);
+        }
+        Command::History { stats: true } => {
+            let stats = manager.history_stats()?;
+            if cli.json {
+                println!("{}", json!(stats));
+                return Ok(ExitCode::SUCCESS);
+            }
+            for (title, groups) in [("HOST", stats.hosts), ("NAME", stats.models)] {
+                let mut rows =
+                    vec![[title, "DOWNLOADS", "SIZE", "AVG TIME", "SPEED", "RETRIES"]
+                        .map(String::from)];
+                for v in groups {
+                    rows.push([
+                        v.key.clone(),
+                        v.downloads.to_string(),
+                        HumanBytes(v.bytes).to_string(),
+                        HumanDuration(Duration::from_millis(v.average_duration_ms())).to_string(),
+                        format!("{}/s", HumanBytes(v.speed())),
+                        v.retries.to_string(),
+                    ]);
+                }
+                print_table(&rows);
+                println!();
+            }
+        }
+        Command::Recover => {
+            let recovered = manager.recover_async().await?;
+            if cli.json {
+                let entries = recovered
+                    .iter()
+                    .map(|v| json!({ "model": v.ident, "operation": v.operation, "target": v.target }))
+                    .collect::<Vec<_>>();
+                println!("{}", Value::Array(entries));
+                return Ok(ExitCode::SUCCESS);
+            }
+            for entry in &recovered {
+                println!(
+                    "{}: recovered interrupted {:?} of {}",
+                    entry.ident,
+                    entry.operation,
+                    entry.target.display()
+                );
+            }
+            if recovered.is_empty() {
+                println!("nothing to recover");
+            }
+        }
+        Command::SwitchChannel { name } => {
+            let switched = manager.switch_channel_async(name.as_deref()).await?;
+            if cli.json {
+                println!("{}", json!(switched));
+                return Ok(ExitCode::SUCCESS);
+            }
+            for ident in &switched {
+                let version = manager
+                    .get_model_if_cached(ident)
+                    .map(|v| v.1.version.clone());
+                println!("{ident}: switched to {}", version.unwrap_or_default());
+            }
+            if switched.is_empty() {
+                println!("nothing to switch");
+            }
+        }
+        Command::Status => {
+            let list = manager.list()?;
+            let registered = list.iter().filter(|v| v.registered).count();
+            let downloaded = list.iter().filter(|v| v.downloaded).count();
+            let size = manager.total_size_on_disk()?;
+            if cli.json {
+                println!(
+                    "{}",
+                    json!({
+                        "config": cli.config,
+                        "registered": registered,
+                        "downloaded": downloaded,
+                        "size": size,
+                    })
+                );
+                return Ok(ExitCode::SUCCESS);
+            }
+            println!("config: {}", cli.config.display());
+            println!("models: {downloaded}/{registered} downloaded");
+            println!("size on disk: {}", HumanBytes(size));
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs `task` while printing every event received on `events` as one JSON line.
+async fn with_events<T>(
+    events: Option<Receiver<DownloadEvent>>,
+    task: impl std::future::Future<Output = T>,
+) -> T {
+    let Some(mut events) = events else {
+        return task.await;
+    };
+    let print = |event: DownloadEvent| {
+        if let Ok(line) = serde_json::to_string(&event) {
+            println!("{line}");
+        }
+    };
+    tokio::pin!(task);
+    let result = loop {
+        tokio::select! {
+            result = &mut task => break result,
+            event = events.recv() => match event {
+                Ok(event) => print(event),
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break task.await,
+            },
+        }
+    };
+    while let Ok(event) = events.try_recv() {
+        print(event);
+    }
+    result
+}
+
+fn status_json(status: &ModelStatus, model: Option<&Model>) -> Value {
+    json!({
+        "name": status.ident,
+        "source": model.map(|v| source_label(&v.source)),
+        "registered": status.registered,
+        "downloaded": status.downloaded,
+        "expected_version": status.expected_version,
+        "installed_version": status.installed_version,
+        "revision": status.revision,
+        "size": status.size,
+        "last_used": status.last_used.map(|v| v.to_rfc3339()),
+        "metadata": status.metadata.as_ref().map(|v| json!({
+            "source": v.source,
+            "downloaded": v.downloaded.map(|v| v.to_rfc3339()),
+            "tags": v.tags,
+        })),
+        "capabilities": status.capabilities,
+    })
+}
+
+fn source_label(source: &ModelSource) -> String {
+    match source {
+        ModelSource::Huggingface(v) => match v.commit.as_ref().or(v.revision.as_ref()) {
+            Some(revision) => format!("hf://{}@{revision}", v.repo),
+            None => format!("hf://{}", v.repo),
+        },
+        ModelSource::Zip(url) => url.to_string(),
+    }
+}
+
+fn print_table<const N: usize>(rows: &[[String; N]]) {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in rows {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size {value}"))?;
+    let multiplier: u64 = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" | "K" => 1_000,
+        "MB" | "M" => 1_000_000,
+        "GB" | "G" => 1_000_000_000,
+        "TB" | "T" => 1_000_000_000_000,
+        "KIB" => 1 << 10,
+        "MIB" => 1 << 20,
+        "GIB" => 1 << 30,
+        "TIB" => 1 << 40,
+        unit => return Err(format!("unknown size unit {unit}")),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Registers an unregistered source like `hf://org/repo` for this run, restricted to the variant
+/// picked if the repo offers several, and returns its ident.
+async fn register_source(
+    manager: &mut ModelManager,
+    uri: &str,
+    interactive: bool,
+) -> Result<String, Error> {
+    if !uri.contains("://") {
+        return Err(Error::ModelNotFound);
+    }
+    let ModelSource::Huggingface(links) = ModelSource::parse(uri)? else {
+        return Err(Error::config(
+            "Only Huggingface sources can be downloaded without registering them",
+        ));
+    };
+    let ident = links.repo.to_string();
+    let variants = match links.files.is_empty() && links.allow_patterns.is_empty() {
+        true => manager.variants_async(&links).await?,
+        false => vec![],
+    };
+    let model = match pick_variant(&links.repo, &variants, interactive) {
+        // the variant is the version so switching between them downloads again
+        Some(variant) => Model::new(&ident, &variant.name, variant.apply(links).into()),
+        None => Model::new(&ident, "1", links.into()),
+    };
+    manager.register_models(HashMap::from([(ident.to_string(), model)]));
+    Ok(ident)
+}
+
+fn pick_variant<'a>(repo: &str, variants: &'a [Variant], interactive: bool) -> Option<&'a Variant> {
+    let default = default_variant(variants)?;
+    if !interactive {
+        return Some(default);
+    }
+    eprintln!("{repo} offers {} variants:", variants.len());
+    for (i, variant) in variants.iter().enumerate() {
+        let marker = if std::ptr::eq(variant, default) {
+            " (default)"
+        } else {
+            ""
+        };
+        eprintln!(
+            "  {:>2}) {}  {}{marker}",
+            i + 1,
+            variant.name,
+            HumanBytes(variant.size)
+        );
+    }
+    loop {
+        eprint!("variant [name or number, enter for the default] ");
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer).unwrap_or(0) == 0 {
+            return Some(default);
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Some(default);
+        }
+        let picked = match answer.parse::<usize>() {
+            Ok(i) => variants.get(i.wrapping_sub(1)),
+            Err(_) => variants
+                .iter()
+                .find(|v| v.name.eq_ignore_ascii_case(answer)),
+        };
+        match picked {
+            Some(variant) => return Some(variant),
+            None => eprintln!("no variant {answer}"),
+        }
+    }
+}
+
+fn parse_mode(value: &str) -> Result<u32, String> {
+    let digits = value.trim().trim_start_matches("0o");
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|v| *v <= 0o7777)
+        .ok_or_else(|| format!("invalid mode {value}"))
+}
+
+fn parse_owner(value: &str) -> Result<(Option<u32>, Option<u32>), String> {
+    let id = |v: &str| match v {
+        "" => Ok(None),
+        v => v
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid owner {value}")),
+    };
+    match value.split_once(':') {
+        Some((uid, gid)) => Ok((id(uid)?, id(gid)?)),
+        None => Ok((id(value)?, None)),
+    }
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| format!("invalid duration {value}"))?;
+    let seconds = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        unit => return Err(format!("unknown duration unit {unit}")),
+    };
+    let seconds = number
+        .checked_mul(seconds)
+        .ok_or_else(|| format!("duration {value} is too long"))?;
+    match seconds {
+        0 => Err("the interval must not be zero".to_string()),
+        v => Ok(Duration::from_secs(v)),
+    }
+}
+
+fn confirm(question: &str) -> bool {
+    eprint!("{question} [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok()
+        && matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(
+            parse_duration("2d"),
+            Ok(Duration::from_secs(2 * 24 * 60 * 60))
+        );
+        assert!(parse_duration("0h").is_err());
+        assert!(parse_duration("1w").is_err());
+        assert!(parse_duration("999999999999999999d").is_err());
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::net::SocketAddr;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use bytes::BytesMut;
+use model_manager::checksum::sha256_file;
+use model_manager::error::Error;
+use model_manager::manifest::{Manifest, ManifestFile};
+use model_manager::model_manager::{ModelManager, ModelSource};
+use serde_json::{json, Map, Value};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+const CHUNK: usize = 1 << 16;
+
+/// A downloaded model, the files of its manifest are what's served of it.
+struct Snapshot {
+    ident: String,
+    repo: Option<String>,
+    dir: PathBuf,
+    manifest: Manifest,
+}
+
+/// Sha256 of the files the model definitions have no checksum of, so peers can verify them.
+/// A file that changed since is hashed again.
+#[derive(Default)]
+struct Hashes(Mutex<HashMap<PathBuf, Hashed>>);
+
+struct Hashed {
+    size: u64,
+    modified: Option<SystemTime>,
+    sha256: String,
+}
+
+impl Hashes {
+    fn get(&self, path: &FsPath) -> Option<String> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let hashes = self.0.lock().unwrap();
+        let hashed = hashes.get(path)?;
+        (hashed.size == metadata.len() && hashed.modified == metadata.modified().ok())
+            .then(|| hashed.sha256.clone())
+    }
+
+    fn hash(&self, path: &FsPath) -> Result<String, Error> {
+        if let Some(sha256) = self.get(path) {
+            return Ok(sha256);
+        }
+        let metadata = std::fs::metadata(path).map_err(Error::open_path(path))?;
+        let sha256 = sha256_file(path)?;
+        let hashed = Hashed {
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            sha256: sha256.clone(),
+        };
+        self.0.lock().unwrap().insert(path.to_path_buf(), hashed);
+        Ok(sha256)
+    }
+}
+
+struct Mirror {
+    manager: ModelManager,
+    hashes: Hashes,
+}
+
+impl Mirror {
+    /// The sha256 of a file if it's known without hashing it.
+    fn sha256(&self, snapshot: &Snapshot, file: &ManifestFile) -> Option<String> {
+        let path = snapshot.dir.join(&file.name);
+        file.sha256.clone().or_else(|| self.hashes.get(&path))
+    }
+}
+
+struct MirrorError(StatusCode, &'static str, String);
+
+impl From<Error> for MirrorError {
+    fn from(err: Error) -> Self {
+        let status = match err {
+            Error::ModelNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        MirrorError(status, "", err.report())
+    }
+}
+
+impl IntoResponse for MirrorError {
+    fn into_response(self) -> Response {
+        let mut res = (self.0, Json(json!({ "error": self.2 }))).into_response();
+        // peers map these like the answers of the hub
+        if !self.1.is_empty() {
+            let code = HeaderValue::from_static(self.1);
+            res.headers_mut().insert("x-error-code", code);
+        }
+        res
+    }
+}
+
+fn repo_not_found(repo: &str) -> MirrorError {
+    MirrorError(
+        StatusCode::NOT_FOUND,
+        "RepoNotFound",
+        format!("{repo} isn't downloaded at this revision"),
+    )
+}
+
+fn entry_not_found(file: &str) -> MirrorError {
+    MirrorError(
+        StatusCode::NOT_FOUND,
+        "EntryNotFound",
+        format!("{file} isn't downloaded"),
+    )
+}
+
+/// Serves the downloaded models of `manager` read-only, so other machines can download them
+/// from this host instead of the internet. Huggingface models are served under the hub's own
+/// routes: a peer setting its endpoint (`HF_ENDPOINT`) to this host downloads them unchanged,
+/// checksums and resumed transfers included. Branches and tags resolve to the revision
+/// downloaded here, commits have to match it.
+///
+/// Every model is also served by ident, with its manifest and the sha256 of its files. Files
+/// the model definition has no checksum of are hashed in the background, until then they are
+/// served without an ETag and peers download them from the internet. With the `mdns` feature
+/// the mirror announces itself on the LAN.
+pub(crate) async fn run(manager: ModelManager, listen: SocketAddr) -> Result<(), Error> {
+    let mirror = Arc::new(Mirror {
+        manager,
+        hashes: Hashes::default(),
+    });
+    let hashing = mirror.clone();
+    tokio::task::spawn_blocking(move || {
+        for snapshot in snapshots(&hashing.manager).unwrap_or_default() {
+            for file in snapshot
+                .manifest
+                .files
+                .iter()
+                .filter(|v| v.sha256.is_none())
+            {
+                let _ = hashing.hashes.hash(&snapshot.dir.join(&file.name));
+            }
+        }
+    });
+    let app = Router::new()
+        .route(
+            "/api/models/{org}/{name}/revision/{revision}",
+            get(revision),
+        )
+        .route("/api/models/{org}/{name}/tree/{revision}", get(tree))
+        .route("/{org}/{name}/resolve/{revision}/{*file}", get(resolve))
+        .route("/mirror/models", get(list))
+        .route("/mirror/models/{ident}/manifest", get(manifest))
+        .route("/mirror/models/{ident}/sha256", get(hashes))
+        .route("/mirror/models/{ident}/files/{*file}", get(file))
+        .with_state(mirror);
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| Error::new("Failed to bind", e))?;
+    #[cfg(feature = "mdns")]
+    let _announcement = {
+        let port = listener
+            .local_addr()
+            .map_err(|e| Error::new("Failed to bind", e))?
+            .port();
+        model_manager::peers::Announcement::new(&host_name(), port)?
+    };
+    axum::serve(listener, app)
+        .await
+        .map_err(|e| Error::new("Server failed", e))
+}
+
+#[cfg(feature = "mdns")]
+fn host_name() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .map(|v| v.trim().to_string())
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "model-manager".to_string())
+}
+
+fn snapshots(manager: &ModelManager) -> Result<Vec<Snapshot>, Error> {
+    let mut snapshots = vec![];
+    for status in manager.list()?.into_iter().filter(|v| v.downloaded) {
+        let Some((dir, model)) = manager.get_model_if_cached(&status.ident) else {
+            continue;
+        };
+        let repo = match &model.source {
+            ModelSource::Huggingface(hf) => Some(hf.repo.clone()),
+            ModelSource::Zip(_) => None,
+        };
+        // versions installed before manifests were written don't list their files
+        if let Some(manifest) = manager.manifest(&status.ident)? {
+            snapshots.push(Snapshot {
+                ident: status.ident,
+                repo,
+                dir,
+                manifest,
+            });
+        }
+    }
+    Ok(snapshots)
+}
+
+fn is_commit(revision: &str) -> bool {
+    revision.len() == 40 && revision.bytes().all(|v| v.is_ascii_hexdigit())
+}
+
+/// Snapshots of `repo` at `revision`, all at the same commit.
+fn repo_snapshots(
+    manager: &ModelManager,
+    repo: &str,
+    revision: &str,
+) -> Result<Vec<Snapshot>, MirrorError> {
+    let mut snapshots = snapshots(manager)?
+        .into_iter()
+        .filter(|v| v.repo.as_deref() == Some(repo) && v.manifest.revision.is_some())
+        .collect::<Vec<_>>();
+    let commit = match is_commit(revision) {
+        true => revision.to_string(),
+        false => snapshots
+            .first()
+            .and_then(|v| v.manifest.revision.clone())
+            .ok_or_else(|| repo_not_found(repo))?,
+    };
+    snapshots.retain(|v| v.manifest.revision.as_deref() == Some(commit.as_str()));
+    match snapshots.is_empty() {
+        true => Err(repo_not_found(repo)),
+        false => Ok(snapshots),
+    }
+}
+
+async fn revision(
+    State(mirror): State<Arc<Mirror>>,
+    Path((org, name, revision)): Path<(String, String, String)>,
+) -> Result<Json<Value>, MirrorError> {
+    let repo = format!("{org}/{name}");
+    let snapshots = repo_snapshots(&mirror.manager, &repo, &revision)?;
+    let mut files = snapshots
+        .iter()
+        .flat_map(|v| v.manifest.files.iter().map(|v| v.name.as_str()))
+        .collect::<Vec<_>>();
+    files.sort();
+    files.dedup();
+    Ok(Json(json!({
+        "id": repo,
+        "sha": snapshots[0].manifest.revision,
+        "siblings": files.iter().map(|v| json!({ "rfilename": v })).collect::<Vec<_>>(),
+    })))
+}
+
+/// Every downloaded file of the repo, the listing is always recursive and unpaginated.
+async fn tree(
+    State(mirror): State<Arc<Mirror>>,
+    Path((org, name, revision)): Path<(String, String, String)>,
+) -> Result<Json<Value>, MirrorError> {
+    let snapshots = repo_snapshots(&mirror.manager, &format!("{org}/{name}"), &revision)?;
+    let mut files = snapshots
+        .iter()
+        .flat_map(|v| v.manifest.files.iter())
+        .map(|v| (v.name.as_str(), v.size))
+        .collect::<Vec<_>>();
+    files.sort();
+    files.dedup();
+    Ok(Json(Value::Array(
+        files
+            .into_iter()
+            .map(|(path, size)| json!({ "type": "file", "path": path, "size": size }))
+            .collect(),
+    )))
+}
+
+async fn resolve(
+    State(mirror): State<Arc<Mirror>>,
+    Path((org, name, revision, file)): Path<(String, String, String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, MirrorError> {
+    let snapshots = repo_snapshots(&mirror.manager, &format!("{org}/{name}"), &revision)?;
+    let (snapshot, entry) = snapshots
+        .iter()
+        .find_map(|snapshot| {
+            let entry = snapshot.manifest.files.iter().find(|v| v.name == file)?;
+            Some((snapshot, entry))
+        })
+        .ok_or_else(|| entry_not_found(&file))?;
+    let sha256 = mirror.sha256(snapshot, entry);
+    let mut res = serve_file(snapshot, &file, sha256, &headers).await?;
+    if let Some(commit) = snapshot
+        .manifest
+        .revision
+        .as_deref()
+        .and_then(|v| HeaderValue::from_str(v).ok())
+    {
+        res.headers_mut().insert("x-repo-commit", commit);
+    }
+    Ok(res)
+}
+
+async fn list(State(mirror): State<Arc<Mirror>>) -> Result<Json<Value>, MirrorError> {
+    let models = snapshots(&mirror.manager)?
+        .into_iter()
+        .map(|v| {
+            json!({
+                "ident": v.ident,
+                "repo": v.repo,
+                "version": v.manifest.version,
+                "revision": v.manifest.revision,
+                "size": v.manifest.size(),
+            })
+        })
+        .collect();
+    Ok(Json(Value::Array(models)))
+}
+
+fn snapshot(manager: &ModelManager, ident: &str) -> Result<Snapshot, MirrorError> {
+    snapshots(manager)?
+        .into_iter()
+        .find(|v| v.ident == ident)
+        .ok_or(MirrorError::from(Error::ModelNotFound))
+}
+
+async fn manifest(
+    State(mirror): State<Arc<Mirror>>,
+    Path(ident): Path<String>,
+) -> Result<Json<Manifest>, MirrorError> {
+    Ok(Json(snapshot(&mirror.manager, &ident)?.manifest))
+}
+
+/// Sha256 of every file, hashed here for files the model definition has no checksum of.
+async fn hashes(
+    State(mirror): State<Arc<Mirror>>,
+    Path(ident): Path<String>,
+) -> Result<Json<Value>, MirrorError> {
+    let snapshot = snapshot(&mirror.manager, &ident)?;
+    let hashes = tokio::task::spawn_blocking(move || {
+        let mut hashes = Map::new();
+        for file in &snapshot.manifest.files {
+            let sha256 = match &file.sha256 {
+                Some(v) => v.clone(),
+                None => mirror.hashes.hash(&snapshot.dir.join(&file.name))?,
+            };
+            hashes.insert(file.name.clone(), Value::String(sha256));
+        }
+        Ok::<_, Error>(hashes)
+    })
+    .await
+    .map_err(|e| Error::new("Hashing failed", e))??;
+    Ok(Json(Value::Object(hashes)))
+}
+
+async fn file(
+    State(mirror): State<Arc<Mirror>>,
+    Path((ident, file)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, MirrorError> {
+    let snapshot = snapshot(&mirror.manager, &ident)?;
+    let entry = snapshot.manifest.files.iter().find(|v| v.name == file);
+    match entry.map(|v| mirror.sha256(&snapshot, v)) {
+        Some(sha256) => serve_file(&snapshot, &file, sha256, &headers).await,
+        None => Err(entry_not_found(&file)),
+    }
+}
+
+/// `bytes=<start>-` and `bytes=<start>-<end>`, the ranges the downloaders request.
+fn parse_range(headers: &HeaderMap, size: u64) -> Option<(u64, u64)> {
+    let range = headers.get(header::RANGE)?.to_str().ok()?;
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let start = start.parse::<u64>().ok()?;
+    let end = match end {
+        "" => size.checked_sub(1)?,
+        end => end.parse::<u64>().ok()?.min(size.checked_sub(1)?),
+    };
+    (start <= end).then_some((start, end))
+}
+
+/// Streams a file listed in the manifest of `snapshot`, with its sha256 as the ETag if known.
+async fn serve_file(
+    snapshot: &Snapshot,
+    name: &str,
+    sha256: Option<String>,
+    headers: &HeaderMap,
+) -> Result<Response, MirrorError> {
+    let path = snapshot.dir.join(name);
+    let mut file = tokio::fs::File::open(&path)
+        .await
+        .map_err(Error::open_path(&path))?;
+    let size = file
+        .metadata()
+        .await
+        .map_err(Error::open_path(&path))?
+        .len();
+    let (status, start, length) = match parse_range(headers, size) {
+        Some((start, end)) => (StatusCode::PARTIAL_CONTENT, start, end - start + 1),
+        None => (StatusCode::OK, 0, size),
+    };
+    file.seek(SeekFrom::Start(start))
+        .await
+        .map_err(Error::open_path(&path))?;
+    let stream =
+        futures::stream::unfold((file.take(length), false), |(mut file, done)| async move {
+            if done {
+                return None;
+            }
+            let mut buffer = BytesMut::with_capacity(CHUNK);
+            match file.read_buf(&mut buffer).await {
+                Ok(0) => None,
+                Ok(_) => Some((Ok(buffer.freeze()), (file, false))),
+                Err(e) => Some((Err(e), (file, true))),
+            }
+        });
+    let mut res = Response::new(Body::from_stream(stream));
+    *res.status_mut() = status;
+    let headers = res.headers_mut();
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if status == StatusCode::PARTIAL_CONTENT {
+        let range = format!("bytes {start}-{}/{size}", start + length - 1);
+        if let Ok(range) = HeaderValue::from_str(&range) {
+            headers.insert(header::CONTENT_RANGE, range);
+        }
+    }
+    if let Some(etag) = sha256
+        .as_deref()
+        .and_then(|v| HeaderValue::from_str(&format!("\"{v}\"")).ok())
+    {
+        headers.insert(header::ETAG, etag);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(value: &str, size: u64) -> Option<(u64, u64)> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_str(value).unwrap());
+        parse_range(&headers, size)
+    }
+
+    #[test]
+    fn ranges_are_clamped_to_the_file() {
+        assert_eq!(range("bytes=0-", 100), Some((0, 99)));
+        assert_eq!(range("bytes=10-19", 100), Some((10, 19)));
+        assert_eq!(range("bytes=90-200", 100), Some((90, 99)));
+        assert_eq!(range("bytes=99-", 100), Some((99, 99)));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges_serve_the_whole_file() {
+        assert_eq!(parse_range(&HeaderMap::new(), 100), None);
+        assert_eq!(range("bytes=100-", 100), None);
+        assert_eq!(range("bytes=20-10", 100), None);
+        assert_eq!(range("bytes=0-", 0), None);
+        // suffix ranges and multiple ranges aren't requested by the downloaders
+        assert_eq!(range("bytes=-10", 100), None);
+        assert_eq!(range("bytes=0-1,5-6", 100), None);
+        assert_eq!(range("items=0-10", 100), None);
+    }
+}
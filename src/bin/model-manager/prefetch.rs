@@ -0,0 +1,100 @@
+use std::path::Path;
+use std::process::ExitCode;
+
+use model_manager::error::{Error, ErrorKind};
+use serde_json::json;
+
+use crate::{manager, with_events, Cli};
+
+/// Exit codes of `prefetch`, ordered by precedence when models fail for different reasons.
+const CONFIG: u8 = 2;
+const AUTH: u8 = 4;
+const INTEGRITY: u8 = 5;
+const DISK: u8 = 6;
+const NETWORK: u8 = 3;
+const OTHER: u8 = 1;
+const PRECEDENCE: [u8; 6] = [CONFIG, AUTH, INTEGRITY, DISK, NETWORK, OTHER];
+
+fn exit_code(err: &Error) -> u8 {
+    match err.kind() {
+        ErrorKind::Config => CONFIG,
+        // a missing repo is usually a private one
+        ErrorKind::Auth | ErrorKind::NotFound => AUTH,
+        ErrorKind::Integrity => INTEGRITY,
+        ErrorKind::Disk => DISK,
+        ErrorKind::Network => NETWORK,
+        _ => OTHER,
+    }
+}
+
+/// Downloads every model of `manifest`, the exit code is the class of the most severe failure.
+pub(crate) async fn run(cli: &Cli, manifest: &Path, processes: Option<usize>) -> ExitCode {
+    let failed = match download(cli, manifest, processes).await {
+        Ok(failed) => failed,
+        Err(err) => vec![("".to_string(), err)],
+    };
+    let code = PRECEDENCE
+        .into_iter()
+        .find(|code| failed.iter().any(|(_, err)| exit_code(err) == *code))
+        .unwrap_or(0);
+    for (ident, err) in &failed {
+        let code = exit_code(err);
+        match (cli.json, ident.is_empty()) {
+            (true, true) => println!(
+                "{}",
+                json!({
+                    "error": err.report(),
+                    "kind": err.kind(),
+                    "error_code": err.code(),
+                    "code": code,
+                })
+            ),
+            (true, false) => println!(
+                "{}",
+                json!({
+                    "model": ident,
+                    "error": err.report(),
+                    "kind": err.kind(),
+                    "error_code": err.code(),
+                    "code": code,
+                })
+            ),
+            (false, true) => eprintln!("error: {}", err.report()),
+            (false, false) => eprintln!("{ident}: {}", err.report()),
+        }
+    }
+    ExitCode::from(code)
+}
+
+async fn download(
+    cli: &Cli,
+    manifest: &Path,
+    processes: Option<usize>,
+) -> Result<Vec<(String, Error)>, Error> {
+    // an unreadable manifest is as much a config error as an invalid one
+    let mut manager = manager(cli, manifest).map_err(|err| match err {
+        Error::OpenFileError { source, .. } => {
+            Error::config(format!("{}: {source}", manifest.display()))
+        }
+        err => err,
+    })?;
+    manager.set_continue_on_error(true);
+    let processes = processes.unwrap_or(manager.concurrency()).max(1);
+    let events = cli.json.then(|| manager.subscribe());
+    let report = with_events(events, manager.download_all(processes)).await?;
+    if cli.json {
+        println!(
+            "{}",
+            json!({
+                "event": "report",
+                "succeeded": report.succeeded,
+                "failed": report.failed.iter().map(|v| &v.0).collect::<Vec<_>>(),
+                "skipped": report.skipped,
+                "bytes": report.bytes,
+                "retries": report.retries,
+                "elapsed_ms": report.elapsed.as_millis() as u64,
+            })
+        );
+    }
+    Ok(report.failed)
+}
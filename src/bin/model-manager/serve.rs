@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Component, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use model_manager::error::Error;
+use model_manager::model_manager::{Model, ModelManager, ModelStatus};
+use serde_json::{json, Value};
+
+use crate::status_json;
+
+/// Downloads started through the API, keyed by model.
+#[derive(Clone)]
+enum Job {
+    Running,
+    Done,
+    Failed(String),
+}
+
+#[derive(Clone)]
+struct AppState {
+    manager: Arc<RwLock<ModelManager>>,
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+}
+
+struct ApiError(StatusCode, String);
+
+impl From<Error> for ApiError {
+    fn from(err: Error) -> Self {
+        let status = match err {
+            Error::ModelNotFound => StatusCode::NOT_FOUND,
+            Error::AliasConflict(_) | Error::ConfigError(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        ApiError(status, err.report())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// Serves the model directory of `manager` until the process is stopped. Models registered
+/// through the API live in memory only, the registry file isn't rewritten.
+pub(crate) async fn run(manager: ModelManager, listen: SocketAddr) -> Result<(), Error> {
+    let mut manager = manager;
+    manager.set_quiet(true);
+    let state = AppState {
+        manager: Arc::new(RwLock::new(manager)),
+        jobs: Arc::default(),
+    };
+    let app = Router::new();
+    #[cfg(feature = "metrics")]
+    let app = app.route("/metrics", get(metrics));
+    let app = app
+        .route("/status", get(status))
+        .route("/health", get(health))
+        .route("/models", get(list))
+        .route("/models/{ident}", get(model).put(register).delete(remove))
+        .route("/models/{ident}/download", post(download))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| Error::new("Failed to bind", e))?;
+    axum::serve(listener, app)
+        .await
+        .map_err(|e| Error::new("Server failed", e))
+}
+
+/// The status of the registered model `ident` names directly or as an alias, jobs are keyed by
+/// its ident.
+fn registered_status(manager: &ModelManager, ident: &str) -> Result<ModelStatus, Error> {
+    let model = manager.model(ident).ok_or(Error::ModelNotFound)?;
+    manager
+        .list()?
+        .into_iter()
+        // aliases resolve to the same registered model
+        .find(|v| {
+            v.registered
+                && manager
+                    .model(&v.ident)
+                    .is_some_and(|m| std::ptr::eq(m, model))
+        })
+        .ok_or(Error::ModelNotFound)
+}
+
+fn model_json(state: &AppState, manager: &ModelManager, ident: &str) -> Result<Value, Error> {
+    let status = registered_status(manager, ident)?;
+    let model = manager.model(&status.ident);
+    let mut value = status_json(&status, model);
+    let job = state.jobs.lock().unwrap().get(&status.ident).cloned();
+    value["job"] = match job {
+        None => Value::Null,
+        Some(Job::Running) => json!({ "state": "running" }),
+        Some(Job::Done) => json!({ "state": "done" }),
+        Some(Job::Failed(err)) => json!({ "state": "failed", "error": err }),
+    };
+    Ok(value)
+}
+
+async fn status(State(state): State<AppState>) -> Result<Json<Value>, ApiError> {
+    let manager = state.manager.read().unwrap();
+    let list = manager.list()?;
+    let running = state
+        .jobs
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|v| matches!(v, Job::Running))
+        .count();
+    Ok(Json(json!({
+        "path": manager.model_path(),
+        "registered": list.iter().filter(|v| v.registered).count(),
+        "downloaded": list.iter().filter(|v| v.downloaded).count(),
+        "downloading": running,
+        "size": manager.total_size_on_disk()?,
+    })))
+}
+
+/// Prometheus scrape endpoint.
+#[cfg(feature = "metrics")]
+async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let body = state.manager.read().unwrap().metrics().render();
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4",
+        )],
+        body,
+    )
+}
+
+/// The health report, with 503 when unhealthy so it can back a readiness probe.
+async fn health(State(state): State<AppState>) -> (StatusCode, Json<Value>) {
+    let manager = state.manager.read().unwrap().clone();
+    let report = manager.health_check_async().await;
+    let status = match report.is_healthy() {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(json!(report)))
+}
+
+async fn list(State(state): State<AppState>) -> Result<Json<Value>, ApiError> {
+    let manager = state.manager.read().unwrap();
+    let mut models = vec![];
+    for status in manager.list()?.into_iter().filter(|v| v.registered) {
+        models.push(model_json(&state, &manager, &status.ident)?);
+    }
+    Ok(Json(Value::Array(models)))
+}
+
+async fn model(
+    State(state): State<AppState>,
+    Path(ident): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let manager = state.manager.read().unwrap();
+    Ok(Json(model_json(&state, &manager, &ident)?))
+}
+
+/// Registers or replaces a model, the body is a model definition as in the registry.
+async fn register(
+    State(state): State<AppState>,
+    Path(ident): Path<String>,
+    Json(mut model): Json<Model>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    if model.directory.as_os_str().is_empty() {
+        model.directory = PathBuf::from(&ident);
+    }
+    // anyone reaching the port may register, downloads and removals stay inside the model path
+    if !model
+        .directory
+        .components()
+        .all(|v| matches!(v, Component::Normal(_)))
+    {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid directory: {}", model.directory.display()),
+        ));
+    }
+    let mut manager = state.manager.write().unwrap();
+    manager.register_models(HashMap::from([(ident.to_string(), model)]));
+    Ok((
+        StatusCode::CREATED,
+        Json(model_json(&state, &manager, &ident)?),
+    ))
+}
+
+/// Starts downloading a model and its dependencies in the background, poll the model for the
+/// state of the job.
+async fn download(
+    State(state): State<AppState>,
+    Path(ident): Path<String>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    // downloads work on a snapshot so registrations aren't blocked, in-flight tracking is shared
+    let manager = state.manager.read().unwrap().clone();
+    let ident = registered_status(&manager, &ident)?.ident;
+    {
+        let mut jobs = state.jobs.lock().unwrap();
+        if matches!(jobs.get(&ident), Some(Job::Running)) {
+            return Ok((StatusCode::ACCEPTED, Json(json!({ "state": "running" }))));
+        }
+        jobs.insert(ident.to_string(), Job::Running);
+    }
+    let jobs = state.jobs.clone();
+    tokio::spawn(async move {
+        let job = match manager.get_model_async(&ident).await {
+            Ok(_) => Job::Done,
+            Err(err) => Job::Failed(err.report()),
+        };
+        jobs.lock().unwrap().insert(ident, job);
+    });
+    Ok((StatusCode::ACCEPTED, Json(json!({ "state": "running" }))))
+}
+
+/// Deletes a model from disk and unregisters it.
+async fn remove(
+    State(state): State<AppState>,
+    Path(ident): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let ident = registered_status(&state.manager.read().unwrap(), &ident)?.ident;
+    if matches!(state.jobs.lock().unwrap().get(&ident), Some(Job::Running)) {
+        return Err(ApiError(
+            StatusCode::CONFLICT,
+            format!("{ident} is being downloaded"),
+        ));
+    }
+    state.manager.write().unwrap().remove(&ident)?;
+    state.jobs.lock().unwrap().remove(&ident);
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,401 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use indicatif::HumanBytes;
+use model_manager::error::Error;
+use model_manager::events::DownloadEvent;
+use model_manager::model_manager::ModelManager;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::task::JoinHandle;
+
+const REFRESH: Duration = Duration::from_secs(2);
+const TICK: Duration = Duration::from_millis(100);
+
+#[derive(Clone, PartialEq)]
+enum Status {
+    Missing,
+    Downloaded,
+    Queued,
+    Downloading,
+    Retrying(usize),
+    Paused,
+    Cancelled,
+    Failed(String),
+}
+
+impl Status {
+    fn label(&self) -> String {
+        match self {
+            Status::Missing => "missing".to_string(),
+            Status::Downloaded => "downloaded".to_string(),
+            Status::Queued => "queued".to_string(),
+            Status::Downloading => "downloading".to_string(),
+            Status::Retrying(attempt) => format!("retrying ({attempt})"),
+            Status::Paused => "paused".to_string(),
+            Status::Cancelled => "cancelled".to_string(),
+            Status::Failed(_) => "failed".to_string(),
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Status::Downloaded => Color::Green,
+            Status::Downloading | Status::Queued => Color::Cyan,
+            Status::Retrying(_) | Status::Paused => Color::Yellow,
+            Status::Failed(_) => Color::Red,
+            Status::Missing | Status::Cancelled => Color::Gray,
+        }
+    }
+
+    /// Statuses set from the keyboard or a finished task, a refresh doesn't override them.
+    fn is_sticky(&self) -> bool {
+        matches!(self, Status::Paused | Status::Cancelled | Status::Failed(_))
+    }
+}
+
+struct ModelRow {
+    ident: String,
+    status: Status,
+    /// `(downloaded, total)` of files in flight.
+    files: BTreeMap<String, (u64, Option<u64>)>,
+    size: u64,
+}
+
+impl ModelRow {
+    fn progress(&self) -> String {
+        let downloaded: u64 = self.files.values().map(|v| v.0).sum();
+        let total: u64 = self.files.values().filter_map(|v| v.1).sum();
+        match total {
+            0 if self.files.is_empty() => String::new(),
+            0 => HumanBytes(downloaded).to_string(),
+            total => format!(
+                "{}/{} {:>3}%",
+                HumanBytes(downloaded),
+                HumanBytes(total),
+                downloaded * 100 / total
+            ),
+        }
+    }
+}
+
+struct App {
+    manager: Arc<ModelManager>,
+    rows: Vec<ModelRow>,
+    table: TableState,
+    tasks: HashMap<String, JoinHandle<()>>,
+    done: UnboundedSender<(String, Result<(), String>)>,
+    on_disk: u64,
+    available: Option<u64>,
+}
+
+impl App {
+    fn refresh(&mut self) -> Result<(), Error> {
+        for status in self.manager.list()? {
+            if !status.registered {
+                continue;
+            }
+            let row = match self.rows.iter_mut().find(|v| v.ident == status.ident) {
+                Some(row) => row,
+                None => {
+                    self.rows.push(ModelRow {
+                        ident: status.ident.to_string(),
+                        status: Status::Missing,
+                        files: BTreeMap::new(),
+                        size: 0,
+                    });
+                    self.rows.last_mut().unwrap()
+                }
+            };
+            row.size = status.size;
+            if !self.tasks.contains_key(&row.ident) && !row.status.is_sticky() {
+                row.status = match status.downloaded {
+                    true => Status::Downloaded,
+                    false => Status::Missing,
+                };
+            }
+        }
+        self.rows.sort_by(|a, b| a.ident.cmp(&b.ident));
+        self.on_disk = self.manager.total_size_on_disk()?;
+        self.available = fs4::available_space(self.manager.model_path()).ok();
+        Ok(())
+    }
+
+    fn selected(&self) -> Option<String> {
+        self.table
+            .selected()
+            .and_then(|i| self.rows.get(i))
+            .map(|v| v.ident.to_string())
+    }
+
+    fn row(&mut self, ident: &str) -> Option<&mut ModelRow> {
+        self.rows.iter_mut().find(|v| v.ident == ident)
+    }
+
+    /// Downloads `ident`, `resume` keeps files that are already complete.
+    fn start(&mut self, ident: String, resume: bool) {
+        if self.tasks.get(&ident).is_some_and(|v| !v.is_finished()) {
+            return;
+        }
+        let manager = self.manager.clone();
+        let done = self.done.clone();
+        let id = ident.to_string();
+        let task = tokio::spawn(async move {
+            let result = match resume {
+                true => manager.update_async(&id, true).await.map(|_| ()),
+                false => manager.get_model_async(&id).await.map(|_| ()),
+            };
+            let _ = done.send((id, result.map_err(|e| e.report())));
+        });
+        self.tasks.insert(ident.to_string(), task);
+        if let Some(row) = self.row(&ident) {
+            row.status = Status::Queued;
+            row.files.clear();
+        }
+    }
+
+    /// Aborts the download of `ident`, partially written files are left on disk.
+    fn stop(&mut self, ident: &str, status: Status) {
+        if let Some(task) = self.tasks.remove(ident) {
+            task.abort();
+            if let Some(row) = self.row(ident) {
+                row.status = status;
+                row.files.clear();
+            }
+        }
+    }
+
+    fn key(&mut self, code: KeyCode) -> bool {
+        let selected = self.selected();
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+            KeyCode::Char('a') => {
+                let missing = self
+                    .rows
+                    .iter()
+                    .filter(|v| v.status != Status::Downloaded)
+                    .map(|v| v.ident.to_string())
+                    .collect::<Vec<_>>();
+                for ident in missing {
+                    self.start(ident, false);
+                }
+            }
+            KeyCode::Enter | KeyCode::Char('d') | KeyCode::Char('r') => {
+                if let Some(ident) = selected {
+                    self.start(ident, false);
+                }
+            }
+            KeyCode::Char('p') => {
+                if let Some(ident) = selected {
+                    match self.tasks.contains_key(&ident) {
+                        true => self.stop(&ident, Status::Paused),
+                        false => self.start(ident, true),
+                    }
+                }
+            }
+            KeyCode::Char('c') => {
+                if let Some(ident) = selected {
+                    self.stop(&ident, Status::Cancelled);
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn event(&mut self, event: DownloadEvent) {
+        let ident = event.model().to_string();
+        // events of an aborted download may still be queued
+        if !self.tasks.contains_key(&ident) {
+            return;
+        }
+        let Some(row) = self.row(&ident) else {
+            return;
+        };
+        match event {
+            DownloadEvent::Queued { .. } => row.status = Status::Queued,
+            DownloadEvent::Started { file, total, .. } => {
+                row.status = Status::Downloading;
+                row.files.insert(file, (0, total));
+            }
+            DownloadEvent::Progress {
+                file, downloaded, ..
+            } => {
+                row.status = Status::Downloading;
+                row.files.entry(file).or_default().0 = downloaded;
+            }
+            DownloadEvent::Retrying { attempt, .. } => row.status = Status::Retrying(attempt),
+            DownloadEvent::Finished { .. } => {
+                row.status = Status::Downloaded;
+                row.files.clear();
+            }
+            DownloadEvent::Failed { error, .. } => row.status = Status::Failed(error),
+            // staged updates don't change what's installed
+            DownloadEvent::UpdateReady { .. } => {}
+        }
+    }
+
+    fn finished(&mut self, ident: String, result: Result<(), String>) {
+        self.tasks.remove(&ident);
+        if let Some(row) = self.row(&ident) {
+            row.files.clear();
+            row.status = match result {
+                Ok(()) => Status::Downloaded,
+                Err(err) => Status::Failed(err),
+            };
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, table, details, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(5),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let available = self
+            .available
+            .map_or("?".to_string(), |v| HumanBytes(v).to_string());
+        frame.render_widget(
+            Paragraph::new(format!(
+                " {}  |  on disk: {}  |  free: {}  |  {} downloading",
+                self.manager.model_path().display(),
+                HumanBytes(self.on_disk),
+                available,
+                self.tasks.len()
+            ))
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+            header,
+        );
+
+        let rows = self.rows.iter().map(|row| {
+            Row::new(vec![
+                row.ident.to_string(),
+                row.status.label(),
+                row.progress(),
+                HumanBytes(row.size).to_string(),
+            ])
+            .style(Style::new().fg(row.status.color()))
+        });
+        let widget = Table::new(
+            rows,
+            [
+                Constraint::Fill(2),
+                Constraint::Length(14),
+                Constraint::Fill(2),
+                Constraint::Length(12),
+            ],
+        )
+        .header(
+            Row::new(["MODEL", "STATUS", "PROGRESS", "SIZE"])
+                .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::bordered().title(" Models "))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(widget, table, &mut self.table);
+
+        let lines = match self.table.selected().and_then(|i| self.rows.get(i)) {
+            Some(ModelRow {
+                status: Status::Failed(err),
+                ..
+            }) => vec![err.to_string()],
+            Some(row) => row
+                .files
+                .iter()
+                .map(|(file, (downloaded, total))| match total {
+                    Some(total) => {
+                        format!("{file}  {}/{}", HumanBytes(*downloaded), HumanBytes(*total))
+                    }
+                    None => format!("{file}  {}", HumanBytes(*downloaded)),
+                })
+                .collect(),
+            None => vec![],
+        };
+        frame.render_widget(
+            Paragraph::new(lines.join("\n")).block(Block::bordered().title(" Details ")),
+            details,
+        );
+        frame.render_widget(
+            Paragraph::new(
+                " ↑/↓ select  enter download  a all  p pause/resume  c cancel  r retry  q quit",
+            )
+            .style(Style::new().fg(Color::DarkGray)),
+            footer,
+        );
+    }
+}
+
+pub(crate) async fn run(mut manager: ModelManager) -> Result<(), Error> {
+    manager.set_quiet(true);
+    let manager = Arc::new(manager);
+    let mut events = manager.subscribe();
+    let (done, mut finished) = unbounded_channel();
+    let (keys_tx, mut keys) = unbounded_channel();
+    std::thread::spawn(move || loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                if keys_tx.send(key.code).is_err() {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    });
+
+    let mut app = App {
+        manager,
+        rows: vec![],
+        table: TableState::default().with_selected(Some(0)),
+        tasks: HashMap::new(),
+        done,
+        on_disk: 0,
+        available: None,
+    };
+    app.refresh()?;
+
+    let mut terminal = ratatui::init();
+    let mut tick = tokio::time::interval(TICK);
+    let mut refreshed = Instant::now();
+    let result = loop {
+        tokio::select! {
+            _ = tick.tick() => {
+                if refreshed.elapsed() >= REFRESH {
+                    refreshed = Instant::now();
+                    if let Err(err) = app.refresh() {
+                        break Err(err);
+                    }
+                }
+                if let Err(err) = terminal.draw(|frame| app.draw(frame)) {
+                    break Err(Error::console_clear(err));
+                }
+            }
+            Some(code) = keys.recv() => {
+                if !app.key(code) {
+                    break Ok(());
+                }
+            }
+            Some((ident, result)) = finished.recv() => app.finished(ident, result),
+            event = events.recv() => match event {
+                Ok(event) => app.event(event),
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break Ok(()),
+            },
+        }
+    };
+    ratatui::restore();
+    for task in app.tasks.values() {
+        task.abort();
+    }
+    result
+}
@@ -0,0 +1,132 @@
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+
+use tokio::runtime::{Builder, Handle, Runtime};
+
+use crate::error::Error;
+use crate::health::HealthReport;
+use crate::lockfile::Lockfile;
+use crate::model_manager::{self, Model, ModelManagerBuilder};
+use crate::plan::DownloadPlan;
+use crate::report::DownloadReport;
+
+/// Blocking versions of the downloading APIs for programs without an async runtime, similar to
+/// `reqwest::blocking`. The manager owns its own Tokio runtime, so calls work on any thread that
+/// isn't already running async code. Everything that doesn't download is reachable through
+/// `Deref` on the async manager.
+pub struct ModelManager {
+    inner: model_manager::ModelManager,
+    runtime: Runtime,
+}
+
+impl ModelManager {
+    /// Wraps `manager` and starts the runtime its downloads run on.
+    pub fn new(manager: model_manager::ModelManager) -> Result<Self, Error> {
+        Ok(Self {
+            inner: manager,
+            runtime: runtime()?,
+        })
+    }
+
+    pub fn from_config(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::new(model_manager::ModelManager::from_config(path)?)
+    }
+
+    /// Builds the async manager inside the runtime, so builders that start background work like
+    /// [`ModelManagerBuilder::resume_queue`] have one.
+    pub fn from_builder(builder: ModelManagerBuilder) -> Result<Self, Error> {
+        let runtime = runtime()?;
+        let inner = {
+            let _guard = runtime.enter();
+            builder.build()?
+        };
+        Ok(Self { inner, runtime })
+    }
+
+    pub fn into_inner(self) -> model_manager::ModelManager {
+        self.inner
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> Result<F::Output, Error> {
+        if Handle::try_current().is_ok() {
+            return Err(Error::runtime(
+                "blocking call inside an async runtime, use the async manager",
+            ));
+        }
+        Ok(self.runtime.block_on(future))
+    }
+
+    pub fn get_model(&self, ident: &str) -> Result<(PathBuf, &Model), Error> {
+        self.block_on(self.inner.get_model_async(ident))?
+    }
+
+    pub fn get_model_version(
+        &self,
+        ident: &str,
+        version: &str,
+    ) -> Result<(PathBuf, &Model), Error> {
+        self.block_on(self.inner.get_model_version_async(ident, version))?
+    }
+
+    pub fn update(&self, ident: &str, only_changed: bool) -> Result<(PathBuf, &Model), Error> {
+        self.block_on(self.inner.update_async(ident, only_changed))?
+    }
+
+    pub fn download_all(&self, processes: usize) -> Result<DownloadReport, Error> {
+        self.block_on(self.inner.download_all(processes))?
+    }
+
+    pub fn download_group(&self, group: &str, processes: usize) -> Result<DownloadReport, Error> {
+        self.block_on(self.inner.download_group(group, processes))?
+    }
+
+    pub fn retry_failed(&self) -> Result<DownloadReport, Error> {
+        self.block_on(self.inner.retry_failed())?
+    }
+
+    pub fn process_queue(&self) -> Result<DownloadReport, Error> {
+        self.block_on(self.inner.process_queue_async())?
+    }
+
+    pub fn await_ready(&self, ident: &str) -> Result<PathBuf, Error> {
+        self.block_on(self.inner.await_ready(ident))?
+    }
+
+    pub fn apply_update(&self, ident: &str) -> Result<PathBuf, Error> {
+        self.block_on(self.inner.apply_update_async(ident))?
+    }
+
+    pub fn plan(&self) -> Result<DownloadPlan, Error> {
+        self.block_on(self.inner.plan_async())?
+    }
+
+    pub fn lock(&self, previous: Option<&Lockfile>) -> Result<Lockfile, Error> {
+        self.block_on(self.inner.lock_async(previous))?
+    }
+
+    pub fn health_check(&self) -> Result<HealthReport, Error> {
+        self.block_on(self.inner.health_check_async())
+    }
+}
+
+fn runtime() -> Result<Runtime, Error> {
+    Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(Error::runtime)
+}
+
+impl Deref for ModelManager {
+    type Target = model_manager::ModelManager;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl DerefMut for ModelManager {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
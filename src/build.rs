@@ -0,0 +1,202 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::model_manager::ModelManager;
+use crate::registry::Registry;
+
+/// Overrides the directory models are fetched into, so builds of several crates or clean
+/// builds share one download.
+const CACHE_ENV: &str = "MODEL_MANAGER_BUILD_CACHE";
+
+/// Fetches the models of a manifest from a `build.rs`, so they are guaranteed to be present
+/// when the crate is built. Models already on disk are verified instead of downloaded again.
+///
+/// ```no_run
+/// // build.rs
+/// model_manager::build::BuildModels::new("models.toml").fetch().unwrap();
+/// ```
+///
+/// The crate then finds them under `env!("MODEL_MANAGER_DIR")`.
+#[derive(Clone, Debug)]
+pub struct BuildModels {
+    pub manifest: PathBuf,
+    /// `OUT_DIR/models` unless set here or through `MODEL_MANAGER_BUILD_CACHE`.
+    pub cache_dir: Option<PathBuf>,
+    /// Every model of the manifest if empty.
+    pub models: Vec<String>,
+}
+
+impl BuildModels {
+    /// `manifest` is relative to the crate being built.
+    pub fn new(manifest: impl Into<PathBuf>) -> Self {
+        Self {
+            manifest: manifest.into(),
+            cache_dir: None,
+            models: vec![],
+        }
+    }
+
+    pub fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    pub fn model(mut self, ident: impl ToString) -> Self {
+        self.models.push(ident.to_string());
+        self
+    }
+
+    /// Downloads or verifies the models, prints the `cargo:` directives that rerun the build
+    /// script when the manifest changes and returns the manager holding them.
+    pub fn fetch(self) -> Result<ModelManager, Error> {
+        println!("cargo:rerun-if-changed={}", self.manifest.display());
+        println!("cargo:rerun-if-env-changed={CACHE_ENV}");
+        let dir = self.dir()?;
+        println!("cargo:rustc-env=MODEL_MANAGER_DIR={}", dir.display());
+        // the path of the manifest is for the application, the build has its own directory
+        let registry = Registry::from_file(&self.manifest)?;
+        let mut builder = ModelManager::builder().model_path(&dir).quiet(true);
+        if let Some(layout) = &registry.layout {
+            builder = builder.storage_layout(layout.clone());
+        }
+        let mut manager = builder.build()?;
+        manager.register_registry(registry)?;
+        let idents = match self.models.is_empty() {
+            true => manager
+                .list()?
+                .into_iter()
+                .filter(|v| v.registered)
+                .map(|v| v.ident)
+                .collect(),
+            false => self.models,
+        };
+        for ident in idents {
+            let cached = manager.is_downloaded(&ident);
+            manager.get_model(&ident)?;
+            if cached {
+                manager.verify(&ident)?;
+            }
+        }
+        Ok(manager)
+    }
+
+    fn dir(&self) -> Result<PathBuf, Error> {
+        if let Some(dir) = &self.cache_dir {
+            return Ok(dir.clone());
+        }
+        if let Some(dir) = std::env::var_os(CACHE_ENV) {
+            return Ok(PathBuf::from(dir));
+        }
+        match std::env::var_os("OUT_DIR") {
+            Some(dir) => Ok(Path::new(&dir).join("models")),
+            None => Err(Error::config(
+                "OUT_DIR isn't set, fetch must run in a build script",
+            )),
+        }
+    }
+}
+
+/// Writes `OUT_DIR/models.rs` with an enum named `name` that has a variant for every model of
+/// `manifest`, named in upper camel case, so models are looked up without strings:
+///
+/// ```ignore
+/// include!(concat!(env!("OUT_DIR"), "/models.rs"));
+///
+/// let (path, _) = Models::WhisperLargeV3.get(&manager)?;
+/// ```
+pub fn generate(manifest: impl AsRef<Path>, name: &str) -> Result<PathBuf, Error> {
+    let manifest = manifest.as_ref();
+    println!("cargo:rerun-if-changed={}", manifest.display());
+    let dir = std::env::var_os("OUT_DIR")
+        .ok_or_else(|| Error::config("OUT_DIR isn't set, generate must run in a build script"))?;
+    let mut idents = Registry::from_file(manifest)?
+        .models
+        .into_keys()
+        .collect::<Vec<_>>();
+    idents.sort();
+    let path = Path::new(&dir).join("models.rs");
+    std::fs::write(&path, source(name, &idents)?).map_err(Error::write_path(&path))?;
+    Ok(path)
+}
+
+fn source(name: &str, idents: &[String]) -> Result<String, Error> {
+    let mut variants = Vec::<(String, &str)>::new();
+    for ident in idents {
+        let variant = variant(ident);
+        if let Some((_, other)) = variants.iter().find(|v| v.0 == variant) {
+            return Err(Error::config(format!(
+                "{ident} and {other} are both named {variant} in the generated enum"
+            )));
+        }
+        variants.push((variant, ident));
+    }
+    let list = |f: &dyn Fn(&str, &str) -> String| {
+        variants
+            .iter()
+            .map(|(variant, ident)| f(variant, ident))
+            .collect::<String>()
+    };
+    let manager = "::model_manager::model_manager";
+    Ok(format!(
+        "/// Models of the manifest, generated by `model_manager::build::generate`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum {name} {{
+{variants}}}
+
+#[allow(dead_code)]
+impl {name} {{
+    pub const ALL: &'static [{name}] = &[{all}];
+
+    pub fn ident(self) -> &'static str {{
+        match self {{
+{idents}        }}
+    }}
+
+    pub fn model(self, manager: &{manager}::ModelManager) -> Option<&{manager}::Model> {{
+        manager.model(self.ident())
+    }}
+
+    pub fn get(
+        self,
+        manager: &{manager}::ModelManager,
+    ) -> Result<(::std::path::PathBuf, &{manager}::Model), ::model_manager::error::Error> {{
+        manager.get_model(self.ident())
+    }}
+
+    pub async fn get_async(
+        self,
+        manager: &{manager}::ModelManager,
+    ) -> Result<(::std::path::PathBuf, &{manager}::Model), ::model_manager::error::Error> {{
+        manager.get_model_async(self.ident()).await
+    }}
+}}
+",
+        variants = list(&|variant, ident| format!("    /// `{ident}`\n    {variant},\n")),
+        all = list(&|variant, _| format!("{name}::{variant}, ")),
+        idents = list(&|variant, ident| format!("            {name}::{variant} => {ident:?},\n")),
+    ))
+}
+
+/// `whisper-large-v3` becomes `WhisperLargeV3`.
+fn variant(ident: &str) -> String {
+    let name = ident
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            let mut chars = v.chars();
+            chars
+                .next()
+                .map(|c| c.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect::<String>();
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => name,
+        _ => format!("Model{name}"),
+    }
+}
+
+/// Fetches every model of `manifest` into `OUT_DIR/models`, see [`BuildModels`].
+pub fn fetch(manifest: impl Into<PathBuf>) -> Result<ModelManager, Error> {
+    BuildModels::new(manifest).fetch()
+}
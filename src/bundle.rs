@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::checksum::sha256_file;
+use crate::error::Error;
+use crate::model_manager::{list_files_following, Model};
+use crate::paths;
+
+const MANIFEST: &str = "manifest.toml";
+pub(crate) const FILES: &str = "files";
+const FORMAT: u32 = 1;
+/// Per-machine state that doesn't travel with a bundle.
+const SKIPPED: [&str; 1] = ["last_used"];
+
+/// First entry of a bundle, describing the model and the checksum of every file in it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub format: u32,
+    pub ident: String,
+    pub model: Model,
+    /// Sha256 by path relative to the model directory.
+    pub files: BTreeMap<String, String>,
+}
+
+/// Writes the installed files of `dir` as a zstd compressed tar to `out`. Entries are sorted
+/// and carry no timestamps or owners, so the same model always gives the same bundle.
+pub(crate) fn write_bundle(
+    out: impl Write,
+    ident: &str,
+    model: &Model,
+    dir: &Path,
+) -> Result<(), Error> {
+    let mut files = BTreeMap::new();
+    for file in list_files_following(dir) {
+        let relative = relative_name(&file, dir)?;
+        if !SKIPPED.contains(&relative.as_str()) {
+            files.insert(relative, sha256_file(&file)?);
+        }
+    }
+    let manifest = BundleManifest {
+        format: FORMAT,
+        ident: ident.to_string(),
+        model: model.clone(),
+        files,
+    };
+    let manifest_content = toml::to_string(&manifest).map_err(Error::config)?;
+
+    let encoder = zstd::Encoder::new(out, 0).map_err(Error::write_file)?;
+    let mut builder = tar::Builder::new(encoder);
+    builder
+        .append_data(
+            &mut header(manifest_content.len() as u64),
+            MANIFEST,
+            manifest_content.as_bytes(),
+        )
+        .map_err(Error::write_file)?;
+    for file in manifest.files.keys() {
+        // hub snapshots are symlinks, the tar gets the blob they point at
+        let path = dir.join(paths::relative(file)?);
+        let content = File::open(&path).map_err(Error::open_path(&path))?;
+        let size = content.metadata().map_err(Error::open_path(&path))?.len();
+        builder
+            .append_data(
+                &mut header(size),
+                Path::new(FILES).join(paths::relative(file)?),
+                content,
+            )
+            .map_err(Error::write_file)?;
+    }
+    builder
+        .into_inner()
+        .map_err(Error::write_file)?
+        .finish()
+        .map_err(Error::write_file)?
+        .flush()
+        .map_err(Error::write_file)
+}
+
+fn header(size: u64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_cksum();
+    header
+}
+
+/// Reads a bundle from `reader` in one pass: its manifest first, then its files into `files/` of
+/// the directory `target` returns for the manifest, which are checked against it. Links and
+/// paths leaving the directory are rejected.
+pub(crate) fn unpack_bundle(
+    reader: impl Read,
+    target: impl FnOnce(&BundleManifest) -> Result<PathBuf, Error>,
+) -> Result<BundleManifest, Error> {
+    let decoder = zstd::Decoder::new(reader).map_err(Error::open_file)?;
+    let mut archive = tar::Archive::new(decoder);
+    let mut entries = archive.entries().map_err(Error::open_file)?;
+    let mut entry = entries
+        .next()
+        .ok_or_else(|| Error::config("Empty bundle"))?
+        .map_err(Error::open_file)?;
+    if entry.path().map_err(Error::open_file)?.as_ref() != Path::new(MANIFEST) {
+        return Err(Error::config("Bundle doesn't start with a manifest"));
+    }
+    let mut content = String::new();
+    entry
+        .read_to_string(&mut content)
+        .map_err(Error::open_file)?;
+    let manifest: BundleManifest = toml::from_str(&content).map_err(Error::config)?;
+    if manifest.format != FORMAT {
+        return Err(Error::config(format!(
+            "Unsupported bundle format {}",
+            manifest.format
+        )));
+    }
+    if let Some(file) = manifest
+        .files
+        .keys()
+        .find(|v| !paths::is_contained(Path::new(v)))
+    {
+        return Err(invalid_path(Path::new(file)));
+    }
+    let target = target(&manifest)?;
+    let files = target.join(FILES);
+    std::fs::create_dir_all(&files).map_err(Error::write_path(&files))?;
+    for entry in entries {
+        let mut entry = entry.map_err(Error::open_file)?;
+        let path = entry.path().map_err(Error::open_file)?.into_owned();
+        let Ok(relative) = path.strip_prefix(FILES) else {
+            continue;
+        };
+        let kind = entry.header().entry_type();
+        if !paths::is_contained(relative) || kind.is_symlink() || kind.is_hard_link() {
+            return Err(invalid_path(&path));
+        }
+        // also refuses to write through directories that are links
+        if !entry.unpack_in(&target).map_err(Error::write_file)? {
+            return Err(invalid_path(&path));
+        }
+    }
+    for (file, expected) in &manifest.files {
+        let actual = sha256_file(&files.join(paths::relative(file)?))?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(Error::ChecksumMismatch {
+                file: file.to_string(),
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+    }
+    Ok(manifest)
+}
+
+fn invalid_path(path: &Path) -> Error {
+    Error::config(format!("Invalid path in bundle: {}", path.display()))
+}
+
+fn relative_name(file: &Path, dir: &Path) -> Result<String, Error> {
+    file.strip_prefix(dir)
+        .map_err(Error::pathbuf_custom)
+        .map(paths::slash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_manager::ModelSource;
+
+    fn model() -> Model {
+        Model::new(
+            "model",
+            "1",
+            ModelSource::Zip("https://example.com/model.zip".into()),
+        )
+    }
+
+    /// A bundle with `manifest` followed by `entries`, which are added as is.
+    fn bundle(
+        manifest: &BundleManifest,
+        entries: impl FnOnce(&mut tar::Builder<Vec<u8>>),
+    ) -> Vec<u8> {
+        let content = toml::to_string(manifest).unwrap();
+        let mut builder = tar::Builder::new(vec![]);
+        builder
+            .append_data(
+                &mut header(content.len() as u64),
+                MANIFEST,
+                content.as_bytes(),
+            )
+            .unwrap();
+        entries(&mut builder);
+        zstd::encode_all(builder.into_inner().unwrap().as_slice(), 0).unwrap()
+    }
+
+    fn manifest(files: &[(&str, &str)]) -> BundleManifest {
+        BundleManifest {
+            format: FORMAT,
+            ident: "model".to_string(),
+            model: model(),
+            files: files
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        std::fs::create_dir_all(source.join("sub")).unwrap();
+        std::fs::write(source.join("a.bin"), "a").unwrap();
+        std::fs::write(source.join("sub/b.bin"), "b").unwrap();
+        std::fs::write(source.join("last_used"), "0").unwrap();
+        let mut out = vec![];
+        write_bundle(&mut out, "model", &model(), &source).unwrap();
+        let mut again = vec![];
+        write_bundle(&mut again, "model", &model(), &source).unwrap();
+        assert_eq!(out, again);
+
+        let target = dir.path().join("target");
+        let manifest = unpack_bundle(out.as_slice(), |_| Ok(target.clone())).unwrap();
+        assert_eq!(
+            manifest.files.keys().collect::<Vec<_>>(),
+            ["a.bin", "sub/b.bin"]
+        );
+        let files = target.join(FILES);
+        assert_eq!(
+            std::fs::read_to_string(files.join("sub/b.bin")).unwrap(),
+            "b"
+        );
+        assert!(!files.join("last_used").exists());
+    }
+
+    #[test]
+    fn rejects_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = dir.path().join("outside");
+        std::fs::create_dir_all(&outside).unwrap();
+        let content = bundle(&manifest(&[]), |builder| {
+            let mut link = tar::Header::new_gnu();
+            link.set_entry_type(tar::EntryType::Symlink);
+            link.set_size(0);
+            builder
+                .append_link(&mut link, "files/escape", &outside)
+                .unwrap();
+            builder
+                .append_data(&mut header(1), "files/escape/file", b"x".as_slice())
+                .unwrap();
+        });
+        let target = dir.path().join("target");
+        assert!(unpack_bundle(content.as_slice(), |_| Ok(target.clone())).is_err());
+        assert!(!outside.join("file").exists());
+    }
+
+    #[test]
+    fn rejects_paths_leaving_the_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = manifest(&[("../escape", "00")]);
+        let content = bundle(&manifest, |_| {});
+        let result = unpack_bundle(content.as_slice(), |_| Ok(dir.path().join("target")));
+        assert!(result.is_err());
+        assert!(!dir.path().join("target").exists());
+    }
+}
@@ -0,0 +1,266 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Once;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+use crate::error::Error;
+use crate::sync;
+
+/// Cancels or pauses the downloads running on a manager and its clones. Cancelling resets once
+/// the last of them returned, so later downloads aren't affected.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    paused: AtomicBool,
+    running: AtomicUsize,
+    notify: Notify,
+    /// Pausing it pauses this token too.
+    parent: Option<CancelToken>,
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .field("paused", &self.is_paused())
+            .finish()
+    }
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once [`CancelToken::cancel`] is called.
+    pub async fn cancelled(&self) {
+        self.until(|| self.is_cancelled()).await
+    }
+
+    /// Transfers stop and drop their connections until [`CancelToken::resume`], keeping what
+    /// they received.
+    pub fn pause(&self) {
+        self.inner.paused.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn resume(&self) {
+        self.inner.paused.store(false, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.inner.paused.load(Ordering::SeqCst)
+            || self.inner.parent.as_ref().is_some_and(|v| v.is_paused())
+    }
+
+    /// A token cancelled on its own that follows the pauses of this one, for downloads that
+    /// are cancelled separately but still belong to this token's operations.
+    pub(crate) fn child(&self) -> CancelToken {
+        CancelToken {
+            inner: Arc::new(Inner {
+                parent: Some(self.clone()),
+                ..Inner::default()
+            }),
+        }
+    }
+
+    pub(crate) async fn paused(&self) {
+        self.until(|| self.is_paused()).await
+    }
+
+    pub(crate) async fn resumed(&self) {
+        self.until(|| !self.is_paused()).await
+    }
+
+    async fn until(&self, condition: impl Fn() -> bool) {
+        loop {
+            // parents notify when they're paused or resumed
+            let notified = std::iter::successors(Some(self), |v| v.inner.parent.as_ref())
+                .map(|v| Box::pin(v.inner.notify.notified()))
+                .collect::<Vec<_>>();
+            if condition() {
+                return;
+            }
+            futures::future::select_all(notified).await;
+        }
+    }
+
+    /// Marks an operation as running until the guard is dropped. With `ctrl_c` a Ctrl-C while it
+    /// runs cancels it instead of ending the process.
+    pub(crate) fn enter(&self, ctrl_c: bool) -> RunningGuard {
+        self.inner.running.fetch_add(1, Ordering::SeqCst);
+        if ctrl_c {
+            #[cfg(not(target_arch = "wasm32"))]
+            listen_ctrl_c();
+            sync::lock(&CTRL_C_TOKENS).push(self.clone());
+        }
+        RunningGuard {
+            token: self.clone(),
+            ctrl_c,
+        }
+    }
+}
+
+/// Runs `future`, cancelling `token` once `deadline` elapses or `parent` is cancelled. The
+/// future winds down on its own, so partial files are flushed like for any cancellation.
+pub(crate) async fn within_deadline<T>(
+    future: impl Future<Output = Result<T, Error>>,
+    token: &CancelToken,
+    parent: &CancelToken,
+    deadline: Duration,
+    ident: &str,
+) -> Result<T, Error> {
+    tokio::pin!(future);
+    let expired = tokio::select! {
+        result = &mut future => return result,
+        _ = tokio::time::sleep(deadline) => true,
+        _ = parent.cancelled() => false,
+    };
+    token.cancel();
+    match future.await {
+        Err(Error::Cancelled) if expired => Err(Error::DeadlineExceeded(ident.to_string())),
+        result => result,
+    }
+}
+
+pub(crate) struct RunningGuard {
+    token: CancelToken,
+    ctrl_c: bool,
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        if self.ctrl_c {
+            let mut tokens = sync::lock(&CTRL_C_TOKENS);
+            if let Some(i) = tokens
+                .iter()
+                .position(|v| Arc::ptr_eq(&v.inner, &self.token.inner))
+            {
+                tokens.swap_remove(i);
+            }
+        }
+        if self.token.inner.running.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.token.inner.cancelled.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Tokens of operations that handle Ctrl-C, once per running operation.
+static CTRL_C_TOKENS: Mutex<Vec<CancelToken>> = Mutex::new(vec![]);
+
+/// Tokio keeps the handler for the rest of the process, so outside of downloads a Ctrl-C exits
+/// like it would without one. The listener gets a thread and runtime of its own, the runtime of
+/// a blocking call ends with the call and would take it along.
+#[cfg(not(target_arch = "wasm32"))]
+fn listen_ctrl_c() {
+    static LISTENER: Once = Once::new();
+    LISTENER.call_once(|| {
+        let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        else {
+            return;
+        };
+        let (ready, registered) = std::sync::mpsc::channel();
+        let listener = std::thread::Builder::new()
+            .name("ctrl-c".to_string())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    let mut signal = std::pin::pin!(tokio::signal::ctrl_c());
+                    // the handler is installed on the first poll
+                    let installed = futures::poll!(signal.as_mut()).is_pending();
+                    let _ = ready.send(());
+                    if !installed {
+                        return;
+                    }
+                    while signal.as_mut().await.is_ok() {
+                        let tokens = sync::lock(&CTRL_C_TOKENS).clone();
+                        if tokens.is_empty() {
+                            std::process::exit(130);
+                        }
+                        for token in tokens {
+                            token.cancel();
+                        }
+                        signal.set(tokio::signal::ctrl_c());
+                    }
+                })
+            });
+        // a Ctrl-C right after would still end the process otherwise
+        if listener.is_ok() {
+            let _ = registered.recv();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn child_follows_the_pauses_of_its_parents() {
+        let parent = CancelToken::default();
+        let child = parent.child().child();
+        let waiting = tokio::spawn({
+            let child = child.clone();
+            async move { child.paused().await }
+        });
+        tokio::task::yield_now().await;
+        parent.pause();
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(child.is_paused());
+
+        parent.resume();
+        tokio::time::timeout(Duration::from_secs(1), child.resumed())
+            .await
+            .unwrap();
+        child.pause();
+        assert!(!parent.is_paused());
+    }
+
+    #[test]
+    fn child_is_cancelled_on_its_own() {
+        let parent = CancelToken::default();
+        let child = parent.child();
+        child.cancel();
+        assert!(!parent.is_cancelled());
+        parent.cancel();
+        assert!(!parent.child().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn deadline_cancels_the_token() {
+        let token = CancelToken::default();
+        let future = async {
+            token.cancelled().await;
+            Err::<(), _>(Error::Cancelled)
+        };
+        let result = within_deadline(
+            future,
+            &token,
+            &CancelToken::default(),
+            Duration::from_millis(10),
+            "model",
+        )
+        .await;
+        assert!(matches!(result, Err(Error::DeadlineExceeded(v)) if v == "model"));
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::paths;
+
+const INDEX: &str = "model.safetensors.index.json";
+
+/// A downloaded model in the shape candle loads it, standing in for hf-hub's `ApiRepo`: code
+/// calling `repo.get("config.json")` works unchanged once the repo comes from
+/// [`ModelManager::repo`](crate::model_manager::ModelManager::repo).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelRepo {
+    pub dir: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct ShardIndex {
+    weight_map: HashMap<String, String>,
+}
+
+impl ModelRepo {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Path of a file of the repo, which has to be downloaded.
+    pub fn get(&self, file: &str) -> Result<PathBuf, Error> {
+        let path = self.dir.join(paths::relative(file)?);
+        match path.is_file() {
+            true => Ok(path),
+            false => Err(Error::open_path(&path)(std::io::ErrorKind::NotFound.into())),
+        }
+    }
+
+    pub fn config(&self) -> Result<PathBuf, Error> {
+        self.get("config.json")
+    }
+
+    pub fn tokenizer(&self) -> Result<PathBuf, Error> {
+        self.get("tokenizer.json")
+    }
+
+    /// Safetensors weights in the order to load them: the shards of
+    /// `model.safetensors.index.json`, otherwise `model.safetensors`, otherwise every
+    /// `.safetensors` file at the top of the repo. Sorted by name.
+    pub fn weights(&self) -> Result<Vec<PathBuf>, Error> {
+        if let Ok(index) = self.get(INDEX) {
+            let content = std::fs::read(&index).map_err(Error::open_path(&index))?;
+            let index: ShardIndex = serde_json::from_slice(&content).map_err(Error::config)?;
+            let mut shards = index.weight_map.into_values().collect::<Vec<_>>();
+            shards.sort();
+            shards.dedup();
+            return shards.iter().map(|v| self.get(v)).collect();
+        }
+        if let Ok(single) = self.get("model.safetensors") {
+            return Ok(vec![single]);
+        }
+        let mut weights = std::fs::read_dir(&self.dir)
+            .map_err(Error::open_path(&self.dir))?
+            .flatten()
+            .map(|v| v.path())
+            .filter(|v| v.is_file() && v.extension().is_some_and(|v| v == "safetensors"))
+            .collect::<Vec<_>>();
+        weights.sort();
+        match weights.is_empty() {
+            true => Err(Error::open_path(&self.dir.join(INDEX))(
+                std::io::ErrorKind::NotFound.into(),
+            )),
+            false => Ok(weights),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+
+pub fn sha256_file(path: &Path) -> Result<String, Error> {
+    hash_file(path, Sha256::new())
+}
+
+/// The id git gives the content of `path`, which the hub reports for files outside of LFS.
+pub(crate) fn git_blob_id(path: &Path) -> Result<String, Error> {
+    let size = std::fs::metadata(path).map_err(Error::open_file)?.len();
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {size}\0"));
+    hash_file(path, hasher)
+}
+
+fn hash_file<D: Digest>(path: &Path, mut hasher: D) -> Result<String, Error>
+where
+    sha2::digest::Output<D>: std::fmt::LowerHex,
+{
+    let mut file = File::open(path).map_err(Error::open_file)?;
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = file.read(&mut buffer).map_err(Error::open_file)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn sha256_bytes(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
@@ -1,31 +1,71 @@
 use std::cmp::min;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsStr;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex};
+#[cfg(feature = "xet")]
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-use crate::model_manager::{HuggingfaceModel, ModelSource};
+use bytes::{Bytes, BytesMut};
+use futures::stream;
 use futures_util::StreamExt;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use reqwest::Client;
+use reqwest::StatusCode;
 
+use crate::checksum::{git_blob_id, sha256_file};
 use crate::error::Error;
+use crate::health::SourceStatus;
+#[cfg(feature = "xet")]
+use crate::huggingface::xet::XetBackend;
+use crate::huggingface::{
+    encode_path, encode_segment, file_metadata, gguf_split_set, list_repo_files, resolve_revision,
+    TreeEntry,
+};
+use crate::paths;
+use crate::plan::PlannedFile;
+use crate::progress::ProgressObserver;
+use crate::source::{
+    headers, lock_revision, remote_etag, resolve_files, send, HfContext, HuggingfaceModel,
+    ModelSource,
+};
+pub use crate::source::{stream_source, DownloadOptions, RetryPolicy};
+
+const PEER_TIMEOUT: Duration = Duration::from_secs(3);
+/// Sha256 the hub reported for the LFS files of a download, by name.
+pub(crate) const CHECKSUMS: &str = "checksums.toml";
 
 pub async fn download_file(
     url: &ModelSource,
     model: String,
     version: String,
     path: PathBuf,
-    m: &MultiProgress,
+    observer: &dyn ProgressObserver,
+    options: &DownloadOptions,
+) -> Result<(), Error> {
+    let ident = model.clone();
+    let result = match url {
+        ModelSource::Huggingface(v) => {
+            download_huggingface(v, model, version, path, observer, options).await
+        }
+        ModelSource::Zip(url) => {
+            download_zip_file(url, model, version, path, observer, options).await
+        }
+    };
+    report(observer, &ident, result)
+}
+
+fn report(
+    observer: &dyn ProgressObserver,
+    model: &str,
+    result: Result<(), Error>,
 ) -> Result<(), Error> {
-    match url {
-        ModelSource::Huggingface(v) => download_huggingface(v, model, version, path, m).await,
-        ModelSource::Zip(url) => download_zip_file(url, model, version, path, m).await,
+    match &result {
+        Ok(()) => observer.on_model_done(model),
+        Err(err) => observer.on_error(model, err),
     }
+    result
 }
 
 async fn download_huggingface(
@@ -33,95 +73,672 @@ async fn download_huggingface(
     model: String,
     version: String,
     path: PathBuf,
-    m: &MultiProgress,
+    observer: &dyn ProgressObserver,
+    options: &DownloadOptions,
+) -> Result<(), Error> {
+    let options = &options.for_model(links);
+    create_reference(&path, links)?;
+    let links = resolve_files(lock_revision(links, options).await?, options).await?;
+    let tree = repo_tree(&links, options).await;
+    observer.on_files(
+        &model,
+        links.files.len(),
+        known_size(&links, tree.as_deref()),
+    );
+    let results = stream::iter(links.url_at(options.endpoint()))
+        .map(|(name, url)| async {
+            let result = download_hf_file(
+                name.clone(),
+                url,
+                &model,
+                path.clone(),
+                observer,
+                options,
+                &links,
+            )
+            .await;
+            (name, result)
+        })
+        .buffer_unordered(options.parallel_files.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    partial(&model, results)?;
+    verify_gguf_splits(&path, &links.files)?;
+    write_checksums(&path, &links, tree.as_deref().unwrap_or_default())?;
+    create_revision(&path, &links)?;
+    create_version(&path, version)?;
+    Ok(())
+}
+
+/// Downloads a file of a repo from the first peer reporting the sha256 the endpoint reports for
+/// it, otherwise or if what the peer sent has another sha256 from the endpoint. Files the
+/// endpoint reports no sha256 for, those outside of LFS, always come from it.
+async fn download_hf_file(
+    filename: String,
+    url: String,
+    model: &str,
+    path: PathBuf,
+    observer: &dyn ProgressObserver,
+    options: &DownloadOptions,
+    links: &HuggingfaceModel,
 ) -> Result<(), Error> {
-    for v in links.url() {
-        let v = download_single_file(v.0, &v.1, &model, path.clone(), m, 40).await?;
-        m.remove(&v);
+    let hf = HfContext::new(links, options);
+    if let Some((peer_url, sha256)) = find_peer(&filename, &url, links, options, hf).await {
+        // peers never see the token
+        let name = filename.clone();
+        let result =
+            download_single_file(name, peer_url, model, path.clone(), observer, options, None)
+                .await;
+        let file = path.join(paths::relative(&filename)?);
+        let verified = match result {
+            Err(err @ (Error::Cancelled | Error::DeadlineExceeded(_))) => return Err(err),
+            Err(_) => false,
+            Ok(()) => {
+                let hashed = file.clone();
+                tokio::task::spawn_blocking(move || sha256_file(&hashed))
+                    .await
+                    .is_ok_and(|v| v.is_ok_and(|v| v == sha256))
+            }
+        };
+        if verified {
+            return Ok(());
+        }
+        let _ = std::fs::remove_file(&file);
+        let _ = std::fs::remove_file(paths::with_suffix(&file, ".part"));
     }
+    #[cfg(feature = "xet")]
+    if let Some(options) = xet_options(&url, options, hf).await {
+        let name = filename.clone();
+        let result = download_single_file(
+            name,
+            url.clone(),
+            model,
+            path.clone(),
+            observer,
+            &options,
+            Some(hf),
+        )
+        .await;
+        match result {
+            Err(err @ (Error::Cancelled | Error::DeadlineExceeded(_))) => return Err(err),
+            // the resolve URL continues the `.part` file, it holds the same bytes
+            Err(_) => {}
+            Ok(()) => return Ok(()),
+        }
+    }
+    download_single_file(filename, url, model, path, observer, options, Some(hf)).await
+}
+
+/// `options` with a backend reconstructing the file at `url` from its Xet chunks, `None` if the
+/// hub doesn't store it in Xet.
+#[cfg(feature = "xet")]
+async fn xet_options(
+    url: &str,
+    options: &DownloadOptions,
+    hf: HfContext<'_>,
+) -> Option<DownloadOptions> {
+    let metadata = file_metadata(url, hf.endpoint, hf.repo, hf.token, options)
+        .await
+        .ok()?;
+    let http = XetBackend::new(
+        options.http.clone(),
+        url,
+        metadata.xet?,
+        metadata.size,
+        headers(Some(hf)),
+    );
+    Some(DownloadOptions {
+        http: Arc::new(http),
+        ..options.clone()
+    })
+}
+
+async fn find_peer(
+    filename: &str,
+    url: &str,
+    links: &HuggingfaceModel,
+    options: &DownloadOptions,
+    hf: HfContext<'_>,
+) -> Option<(String, String)> {
+    if options.peers.is_empty() {
+        return None;
+    }
+    let sha256 = file_metadata(url, hf.endpoint, hf.repo, hf.token, options)
+        .await
+        .ok()?
+        .sha256()?
+        .to_string();
+    for peer in &options.peers {
+        let peer_url = format!(
+            "{}/{}/resolve/{}/{}",
+            peer.trim_end_matches('/'),
+            links.repo,
+            encode_segment(links.reference()),
+            encode_path(filename)
+        );
+        let metadata = file_metadata(&peer_url, peer, &links.repo, None, options);
+        // an unreachable peer mustn't hold up the download
+        if let Ok(Ok(metadata)) = tokio::time::timeout(PEER_TIMEOUT, metadata).await {
+            if metadata.etag.as_deref() == Some(sha256.as_str()) {
+                return Some((peer_url, sha256));
+            }
+        }
+    }
+    None
+}
+
+/// The body of a single file of a repo or of a URL, held in memory. Archives aren't unpacked.
+pub(crate) async fn download_bytes(
+    source: &ModelSource,
+    options: &DownloadOptions,
+) -> Result<Bytes, Error> {
+    match source {
+        ModelSource::Huggingface(links) => {
+            let options = &options.for_model(links);
+            let url = match links.url_at(options.endpoint()).as_slice() {
+                [(_, url)] => url.clone(),
+                _ => {
+                    return Err(Error::config(format!(
+                        "{} has to name a single file to download it into memory",
+                        links.repo
+                    )))
+                }
+            };
+            fetch_bytes(&url, options, Some(HfContext::new(links, options))).await
+        }
+        ModelSource::Zip(url) => fetch_bytes(url, options, None).await,
+    }
+}
+
+async fn fetch_bytes(
+    url: &str,
+    options: &DownloadOptions,
+    hf: Option<HfContext<'_>>,
+) -> Result<Bytes, Error> {
+    let mut attempt = 0;
+    loop {
+        let result = async {
+            let res = send(options.http.get(url, &headers(hf)), url, hf).await?;
+            let mut bytes = BytesMut::new();
+            let mut body = res.body;
+            while let Some(chunk) = body.next().await {
+                bytes.extend_from_slice(&chunk?);
+            }
+            Ok::<_, Error>(bytes.freeze())
+        };
+        let result = tokio::select! {
+            result = result => result,
+            _ = options.cancel.cancelled() => return Err(Error::Cancelled),
+        };
+        match result {
+            Err(err) if attempt < options.retry.max_retries && err.is_retryable() => {
+                tokio::time::sleep(options.retry.wait(attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The files of the repo at the revision of `links`, `None` if the tree is unavailable.
+async fn repo_tree(links: &HuggingfaceModel, options: &DownloadOptions) -> Option<Vec<TreeEntry>> {
+    list_repo_files(
+        options.endpoint(),
+        &links.repo,
+        links.reference(),
+        options.token.as_deref(),
+    )
+    .await
+    .ok()
+}
+
+/// Size of every file of `links` from the repo tree, `None` if the tree is unavailable or
+/// misses one of them.
+fn known_size(links: &HuggingfaceModel, tree: Option<&[TreeEntry]>) -> Option<u64> {
+    let tree = tree?;
+    links
+        .files
+        .iter()
+        .map(|name| tree.iter().find(|v| &v.path == name)?.size)
+        .sum()
+}
+
+/// Records the sha256 of the LFS files of `links` next to them, for the manifest and for
+/// telling which files an update changed.
+fn write_checksums(path: &Path, links: &HuggingfaceModel, tree: &[TreeEntry]) -> Result<(), Error> {
+    let checksums = tree
+        .iter()
+        .filter(|v| links.files.contains(&v.path))
+        .filter_map(|v| Some((v.path.clone(), v.sha256()?.to_string())))
+        .collect::<BTreeMap<_, _>>();
+    let file = path.join(CHECKSUMS);
+    if checksums.is_empty() {
+        let _ = std::fs::remove_file(&file);
+        return Ok(());
+    }
+    let content = toml::to_string(&checksums).map_err(Error::config)?;
+    std::fs::write(&file, content).map_err(Error::write_path(&file))
+}
+
+pub(crate) fn read_checksums(path: &Path) -> HashMap<String, String> {
+    std::fs::read_to_string(path.join(CHECKSUMS))
+        .ok()
+        .and_then(|v| toml::from_str(&v).ok())
+        .unwrap_or_default()
+}
+
+/// Fails with [`Error::Partial`] listing every failed file rather than only the first.
+fn partial(model: &str, results: Vec<(String, Result<(), Error>)>) -> Result<(), Error> {
+    let mut succeeded = vec![];
+    let mut failed = vec![];
+    for (file, result) in results {
+        match result {
+            Ok(()) => succeeded.push(file),
+            // every file was stopped the same way
+            Err(err @ (Error::Cancelled | Error::DeadlineExceeded(_))) => return Err(err),
+            Err(err) => failed.push((file, err)),
+        }
+    }
+    // parallel files finish in any order
+    succeeded.sort();
+    failed.sort_by(|a, b| a.0.cmp(&b.0));
+    match failed.is_empty() {
+        true => Ok(()),
+        false => Err(Error::Partial {
+            model: model.to_string(),
+            succeeded,
+            failed,
+        }),
+    }
+}
+
+/// Split GGUF files can't be merged by concatenation, llama.cpp loads them from the first
+/// split, so the set is only checked for completeness.
+fn verify_gguf_splits(path: &Path, files: &[String]) -> Result<(), Error> {
+    for split in files.iter().filter_map(|v| gguf_split_set(v)).flatten() {
+        if !path.join(paths::relative(&split)?).is_file() {
+            return Err(Error::new_option(format!(
+                "Incomplete split GGUF, missing {split}"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Remembers the branch or tag an unpinned repo was resolved from.
+fn create_reference(path: &Path, links: &HuggingfaceModel) -> Result<(), Error> {
+    match links.commit {
+        Some(_) => Ok(()),
+        None => {
+            std::fs::write(path.join("reference"), links.reference()).map_err(Error::write_file)
+        }
+    }
+}
+
+fn create_revision(path: &Path, links: &HuggingfaceModel) -> Result<(), Error> {
+    match &links.commit {
+        Some(commit) => std::fs::write(path.join("revision"), commit).map_err(Error::write_file),
+        None => Ok(()),
+    }
+}
+
+pub async fn download_changed_files(
+    url: &ModelSource,
+    model: String,
+    version: String,
+    path: PathBuf,
+    observer: &dyn ProgressObserver,
+    options: &DownloadOptions,
+) -> Result<(), Error> {
+    let links = match url {
+        ModelSource::Huggingface(v) => v,
+        ModelSource::Zip(_) => {
+            return download_file(url, model, version, path, observer, options).await
+        }
+    };
+    let ident = model.clone();
+    let result = download_changed_huggingface(links, model, version, path, observer, options).await;
+    report(observer, &ident, result)
+}
+
+async fn download_changed_huggingface(
+    links: &HuggingfaceModel,
+    model: String,
+    version: String,
+    path: PathBuf,
+    observer: &dyn ProgressObserver,
+    options: &DownloadOptions,
+) -> Result<(), Error> {
+    let options = &options.for_model(links);
+    create_reference(&path, links)?;
+    let links = resolve_files(lock_revision(links, options).await?, options).await?;
+    let hf = HfContext::new(&links, options);
+    // installed from the same commit, only missing files are fetched
+    let same_commit = links.commit.is_some()
+        && std::fs::read_to_string(path.join("revision")).ok() == links.commit;
+    let tree = repo_tree(&links, options).await.unwrap_or_default();
+    let recorded = read_checksums(&path);
+    let results = stream::iter(links.url_at(options.endpoint()))
+        .map(|(filename, url)| async {
+            let result = async {
+                let file = path.join(paths::relative(&filename)?);
+                if file.is_file() {
+                    let entry = tree.iter().find(|v| v.path == filename);
+                    if same_commit
+                        || is_unchanged(&file, &url, entry, recorded.get(&filename), hf, options)
+                            .await?
+                    {
+                        return Ok(());
+                    }
+                }
+                let name = filename.clone();
+                download_hf_file(name, url, &model, path.clone(), observer, options, &links).await
+            }
+            .await;
+            (filename, result)
+        })
+        .buffer_unordered(options.parallel_files.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    partial(&model, results)?;
+    verify_gguf_splits(&path, &links.files)?;
+    write_checksums(&path, &links, &tree)?;
+    create_revision(&path, &links)?;
     create_version(&path, version)?;
     Ok(())
 }
 
-fn get_progress_style() -> Result<ProgressStyle, Error> {
-    let spinner_color = "33";
-    let proccessed_color = "magenta"; //brighter magenta
-    let coming_color = "white"; //grey
-    let total_bytes_color = "green";
-    let bytes_per_sec_color = "red";
-    let eta_exact_color = "cyan";
-    Ok(ProgressStyle::with_template(&format!(" {{spinner:.{spinner_color}}} {{msg}} {{wide_bar:.{proccessed_color}/{coming_color}}} {{bytes:.{total_bytes_color}}}/{{total_bytes:.{total_bytes_color}}} {{bytes_per_sec:.{bytes_per_sec_color}}} eta {{eta:.{eta_exact_color}}}"))
-        .map_err(Error::console_template)?.progress_chars("━╸━"))
+/// Whether the local `file` is the one the repo has now. Files are compared by the LFS sha256
+/// or the git blob id the hub reports, by size only if it reports neither. `recorded` is the
+/// sha256 the previous download recorded, without it the local file is hashed.
+async fn is_unchanged(
+    file: &Path,
+    url: &str,
+    entry: Option<&TreeEntry>,
+    recorded: Option<&String>,
+    hf: HfContext<'_>,
+    options: &DownloadOptions,
+) -> Result<bool, Error> {
+    let (sha256, blob_id, size) = match entry {
+        Some(v) => (v.sha256().map(str::to_string), v.oid.clone(), v.size),
+        None => {
+            let metadata = file_metadata(url, hf.endpoint, hf.repo, hf.token, options).await?;
+            (metadata.sha256().map(str::to_string), None, metadata.size)
+        }
+    };
+    let hash = |hash: fn(&Path) -> Result<String, Error>| {
+        let file = file.to_path_buf();
+        async move {
+            tokio::task::spawn_blocking(move || hash(&file))
+                .await
+                .map_err(Error::async_thread_join)?
+        }
+    };
+    if let Some(sha256) = sha256 {
+        let local = match recorded {
+            Some(v) => v.clone(),
+            None => hash(sha256_file).await?,
+        };
+        return Ok(local.eq_ignore_ascii_case(&sha256));
+    }
+    if let Some(blob_id) = blob_id {
+        return Ok(hash(git_blob_id).await?.eq_ignore_ascii_case(&blob_id));
+    }
+    let local = std::fs::metadata(file).map_err(Error::open_file)?.len();
+    Ok(size == Some(local))
+}
+
+/// Sizes come from the repo tree where possible, which is one request per model, and from a
+/// HEAD request per file otherwise.
+pub async fn plan_files(
+    source: &ModelSource,
+    options: &DownloadOptions,
+) -> Result<Vec<PlannedFile>, Error> {
+    match source {
+        ModelSource::Huggingface(v) => {
+            let options = &options.for_model(v);
+            let links = resolve_files(v.clone(), options).await?;
+            plan_huggingface(links, options).await
+        }
+        ModelSource::Zip(url) => {
+            let size = remote_size(url, None, options).await?;
+            Ok(vec![PlannedFile {
+                name: "archive".to_string(),
+                url: url.to_string(),
+                size,
+                sha256: None,
+            }])
+        }
+    }
+}
+
+async fn plan_huggingface(
+    links: HuggingfaceModel,
+    options: &DownloadOptions,
+) -> Result<Vec<PlannedFile>, Error> {
+    let hf = HfContext::new(&links, options);
+    let known = list_repo_files(hf.endpoint, hf.repo, links.reference(), hf.token)
+        .await
+        .unwrap_or_default();
+    let mut planned = vec![];
+    for (name, url) in links.url_at(options.endpoint()) {
+        let entry = known.iter().find(|v| v.path == name);
+        let size = match entry.and_then(|v| v.size) {
+            Some(size) => Some(size),
+            None => remote_size(&url, Some(hf), options).await?,
+        };
+        let sha256 = entry.and_then(|v| v.sha256()).map(str::to_string);
+        planned.push(PlannedFile {
+            name,
+            url,
+            size,
+            sha256,
+        });
+    }
+    Ok(planned)
+}
+
+/// Whether the source answers and accepts the token, without downloading anything.
+pub(crate) async fn probe_source(
+    source: &ModelSource,
+    options: &DownloadOptions,
+) -> (SourceStatus, Option<String>) {
+    let result = match source {
+        ModelSource::Huggingface(v) => {
+            let options = options.for_model(v);
+            resolve_revision(
+                options.endpoint(),
+                &v.repo,
+                v.reference(),
+                options.token.as_deref(),
+            )
+            .await
+            .map(|_| ())
+        }
+        ModelSource::Zip(url) => send(options.http.head(url, &vec![]), url, None)
+            .await
+            .map(|_| ()),
+    };
+    match result {
+        Ok(()) => (SourceStatus::Ok, None),
+        Err(err) => (SourceStatus::from_error(&err), Some(err.report())),
+    }
+}
+
+async fn remote_size(
+    url: &str,
+    hf: Option<HfContext<'_>>,
+    options: &DownloadOptions,
+) -> Result<Option<u64>, Error> {
+    if let Some(hf) = hf {
+        // the CDN behind the redirect doesn't always report a length for HEAD requests
+        return Ok(file_metadata(url, hf.endpoint, hf.repo, hf.token, options)
+            .await?
+            .size);
+    }
+    let res = send(options.http.head(url, &headers(hf)), url, hf).await?;
+    Ok(res.content_length())
 }
 
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        skip(path, observer, options, hf),
+        fields(retries = 0, bytes = tracing::field::Empty)
+    )
+)]
 async fn download_single_file(
     filename: String,
-    url: &str,
+    url: String,
     model: &str,
     path: PathBuf,
-    m: &MultiProgress,
-    reload_speed: u64,
-) -> Result<ProgressBar, Error> {
-    let res = Client::new().get(url).send().await.map_err(Error::fetch)?;
-
-    let total_size = res
-        .content_length()
-        .ok_or_else(|| Error::fetch_custom("Failed to get size of request"))?;
-
-    // Indicatif setup downloader
-    let pb = m.add(ProgressBar::new(total_size));
-    let template = get_progress_style()?;
-    pb.set_style(template);
-    pb.set_message(format!("Downloading {}", model));
-
-    // end spinner when download is complete
-    let (sender, receiver) = channel();
-
-    // shared data between threads
-    let progress = Arc::new(Mutex::new(0));
-    let task1_progress: Arc<Mutex<u64>> = progress.clone();
-
-    let task1 = tokio::spawn(async move {
-        // download chunks
-        let p = &path.join(filename);
-        std::fs::create_dir_all(remove_last(p.clone())).map_err(Error::write_file)?;
-        let mut file = File::create(p).map_err(Error::write_file)?;
-        let mut stream = res.bytes_stream();
-
-        while let Some(item) = stream.next().await {
-            let chunk =
-                item.map_err(|_| Error::fetch_custom("Error while downloading file stream"))?;
-            file.write_all(&chunk).map_err(Error::write_file)?;
-            //TODO: wait for instead of unwrap
-            let mut shared_data = task1_progress.lock().unwrap();
-            let new = min(*shared_data + (chunk.len() as u64), total_size);
-
-            *shared_data = new;
-            drop(shared_data);
-        }
-        sender.send(()).map_err(Error::thread_send)
-    });
+    observer: &dyn ProgressObserver,
+    options: &DownloadOptions,
+    hf: Option<HfContext<'_>>,
+) -> Result<(), Error> {
+    #[cfg(feature = "tracing")]
+    let started = std::time::Instant::now();
+    let mut attempt = 0;
+    loop {
+        let result =
+            download_single_file_once(&filename, &url, model, &path, observer, options, hf).await;
+        match result {
+            Err(err) if attempt < options.retry.max_retries && err.is_retryable() => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(error = ?err, attempt, "download failed, retrying");
+                tokio::select! {
+                    _ = tokio::time::sleep(options.retry.wait(attempt)) => {}
+                    _ = options.cancel.cancelled() => return Err(Error::Cancelled),
+                }
+                attempt += 1;
+                #[cfg(feature = "tracing")]
+                tracing::Span::current().record("retries", attempt);
+                observer.on_retry(model, &filename, attempt);
+            }
+            result => {
+                #[cfg(feature = "tracing")]
+                match &result {
+                    Ok(bytes) => {
+                        tracing::Span::current().record("bytes", bytes);
+                        tracing::info!(
+                            bytes,
+                            duration_ms = started.elapsed().as_millis() as u64,
+                            "downloaded file"
+                        )
+                    }
+                    Err(err) => tracing::error!(error = ?err, "download failed"),
+                }
+                return result.map(|_| ());
+            }
+        }
+    }
+}
 
-    let task2_spinner = pb.clone();
+/// Downloads into `<file>.part` and renames it once complete. A `.part` left by an earlier,
+/// cancelled attempt is continued if the server supports range requests, so is one of a paused
+/// transfer, which drops its connection while paused.
+async fn download_single_file_once(
+    filename: &str,
+    url: &str,
+    model: &str,
+    path: &Path,
+    observer: &dyn ProgressObserver,
+    options: &DownloadOptions,
+    hf: Option<HfContext<'_>>,
+) -> Result<u64, Error> {
+    let p = path.join(paths::relative(filename)?);
+    let part = paths::with_suffix(&p, ".part");
+    let dir = remove_last(p.clone());
+    std::fs::create_dir_all(&dir).map_err(Error::write_path(&dir))?;
+    let transfer = |source: Error, start: u64, received: u64| Error::Transfer {
+        model: model.to_string(),
+        file: filename.to_string(),
+        url: url.to_string(),
+        start,
+        received,
+        source: Box::new(source),
+    };
+    let mut started = false;
+    let mut bandwidth = options.host_limit.as_ref().map(|v| v.bandwidth());
+    let downloaded = loop {
+        tokio::select! {
+            _ = options.cancel.resumed() => {}
+            _ = options.cancel.cancelled() => return Err(Error::Cancelled),
+        }
+        let _connection = match &options.host_limit {
+            Some(limit) => limit.connection(&options.cancel).await?,
+            None => None,
+        };
+        let existing = std::fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+        let headers = headers(hf);
+        let request = match existing {
+            0 => options.http.get(url, &headers),
+            _ => options.http.get_range(url, existing, &headers),
+        };
+        let res = tokio::select! {
+            res = send(request, url, hf) => res.map_err(|e| transfer(e, existing, existing))?,
+            _ = options.cancel.cancelled() => return Err(Error::Cancelled),
+        };
+        let resumed = existing > 0 && res.status == StatusCode::PARTIAL_CONTENT;
+        let offset = if resumed { existing } else { 0 };
 
-    let task2 = thread::spawn(move || {
-        while receiver.try_recv().is_err() {
-            let shared_data_t = progress.lock().unwrap();
-            task2_spinner.set_position(*shared_data_t);
-            drop(shared_data_t);
-            thread::sleep(Duration::from_millis(reload_speed));
+        let total_size = offset
+            + res.content_length().ok_or_else(|| {
+                let err = Error::fetch_custom("the response has no Content-Length");
+                transfer(err, existing, offset)
+            })?;
+        if !started {
+            observer.on_start(model, filename, Some(total_size));
+            started = true;
         }
-    });
 
-    task1.await.map_err(Error::async_thread_join)??;
-    task2.join().map_err(Error::thread_join)?;
-    Ok(pb)
+        let mut file = match resumed {
+            true => OpenOptions::new().append(true).open(&part),
+            false => File::create(&part),
+        }
+        .map_err(Error::write_path(&part))?;
+        let mut stream = res.body;
+        let mut downloaded = offset;
+        observer.on_bytes(model, filename, downloaded);
+
+        let paused = loop {
+            let item = tokio::select! {
+                item = stream.next() => item,
+                _ = options.cancel.paused() => break true,
+                _ = options.cancel.cancelled() => {
+                    // keep what arrived for the next attempt
+                    let _ = file.sync_all();
+                    return Err(Error::Cancelled);
+                }
+            };
+            let Some(item) = item else {
+                break false;
+            };
+            let chunk = item.map_err(|e| transfer(e, offset, downloaded))?;
+            file.write_all(&chunk).map_err(Error::write_path(&part))?;
+            downloaded = min(downloaded + (chunk.len() as u64), total_size);
+            observer.on_bytes(model, filename, downloaded);
+            if let Some(bandwidth) = &mut bandwidth {
+                bandwidth
+                    .consume(chunk.len() as u64, &options.cancel)
+                    .await?;
+            }
+        };
+        if !paused {
+            break downloaded;
+        }
+        file.sync_all().map_err(Error::write_path(&part))?;
+    };
+    std::fs::rename(&part, &p).map_err(Error::write_path(&p))?;
+    observer.on_file_done(model, filename);
+    Ok(downloaded)
 }
 
 fn create_version(path: &Path, version: String) -> Result<(), Error> {
-    let mut file = File::create(path.join("version")).map_err(Error::write_file)?;
+    let version_path = path.join("version");
+    let mut file = File::create(&version_path).map_err(Error::write_path(&version_path))?;
     file.write_all(version.as_bytes())
-        .map_err(Error::write_file)?;
+        .map_err(Error::write_path(&version_path))?;
     Ok(())
 }
 
@@ -130,54 +747,37 @@ async fn download_zip_file(
     model: String,
     version: String,
     path: PathBuf,
-    m: &MultiProgress,
+    observer: &dyn ProgressObserver,
+    options: &DownloadOptions,
 ) -> Result<(), Error> {
-    let spinner_color = "33";
     let filename = "archive";
-    let reload_speed = 40;
-    let pb = download_single_file(
+    download_single_file(
         filename.to_string(),
-        url,
+        url.to_string(),
         &model,
         path.clone(),
-        m,
-        reload_speed,
+        observer,
+        options,
+        None,
     )
     .await?;
+    let etag = remote_etag(url, options).await.ok().flatten();
 
-    // setup styling for unzip
-    let spinner2 = ProgressStyle::with_template(&format!(" {{spinner:.{spinner_color}}} {{msg}}"))
-        .map_err(Error::console_template)?;
-    pb.set_style(spinner2);
-    pb.set_message(format!("Unpacking {}", model));
-
-    // end spinner when unzip is complete
-    let (sender, receiver): (Sender<()>, Receiver<()>) = channel();
-
-    let task1_path = path.clone();
+    observer.on_unpack(&model);
     let task1 = thread::spawn(move || {
         zip_extract::extract(
-            File::open(task1_path.join(filename)).map_err(Error::open_file)?,
-            &task1_path,
+            File::open(path.join(filename)).map_err(Error::open_path(&path.join(filename)))?,
+            &path,
             true,
         )
         .map_err(Error::zip_extract)?;
-        std::fs::remove_file(task1_path.join(filename)).map_err(Error::write_file)?;
-        create_version(&task1_path, version)?;
-        sender.send(()).map_err(Error::thread_send)
-    });
-
-    let pb_task2 = pb.clone();
-    let task2 = thread::spawn(move || {
-        while receiver.try_recv().is_err() {
-            pb_task2.inc(1);
-            thread::sleep(Duration::from_millis(reload_speed))
+        std::fs::remove_file(path.join(filename)).map_err(Error::write_file)?;
+        if let Some(etag) = etag {
+            std::fs::write(path.join("etag"), etag).map_err(Error::write_file)?;
         }
+        create_version(&path, version)
     });
-    task1.join().map_err(Error::thread_join)??;
-    task2.join().map_err(Error::thread_join)?;
-    pb.finish_and_clear();
-    Ok(())
+    task1.join().map_err(Error::thread_join)?
 }
 
 fn remove_last(v: PathBuf) -> PathBuf {
@@ -185,3 +785,237 @@ fn remove_last(v: PathBuf) -> PathBuf {
     v.pop();
     PathBuf::from_iter(v)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::checksum::sha256_bytes;
+    #[cfg(feature = "xet")]
+    use crate::http::{BoxFuture, Headers, HttpBackend, HttpResponse, ReqwestBackend};
+    use crate::progress::SilentObserver;
+
+    const COMMIT: &str = "1111111111111111111111111111111111111111";
+
+    fn blob_id(content: &str) -> String {
+        use sha1::{Digest, Sha1};
+        let blob = format!("blob {}\0{content}", content.len());
+        format!("{:x}", Sha1::digest(blob.as_bytes()))
+    }
+
+    /// Serves the revision, tree and files of `org/repo` at [`COMMIT`], `lfs` files with their
+    /// sha256 in the tree. Returns the endpoint and the files downloaded from it.
+    async fn hub(files: &[(&str, &str, bool)]) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let tree = files
+            .iter()
+            .map(|(name, content, lfs)| {
+                let mut entry = serde_json::json!({
+                    "type": "file",
+                    "path": name,
+                    "size": content.len(),
+                    "oid": blob_id(content),
+                });
+                if *lfs {
+                    entry["lfs"] = serde_json::json!({ "oid": sha256_bytes(content.as_bytes()) });
+                }
+                entry
+            })
+            .collect::<Vec<_>>();
+        let mut routes = HashMap::from([
+            (
+                "/api/models/org/repo/revision/main".to_string(),
+                serde_json::json!({ "sha": COMMIT }).to_string(),
+            ),
+            (
+                format!("/api/models/org/repo/tree/{COMMIT}?recursive=true"),
+                serde_json::Value::Array(tree).to_string(),
+            ),
+        ]);
+        for (name, content, _) in files {
+            routes.insert(
+                format!("/org/repo/resolve/{COMMIT}/{name}"),
+                content.to_string(),
+            );
+        }
+        let downloaded = Arc::new(Mutex::new(vec![]));
+        let log = downloaded.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = vec![0; 4096];
+                let len = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..len]).to_string();
+                let mut line = request.split(' ');
+                let (method, path) = (line.next().unwrap(), line.next().unwrap());
+                let response = match routes.get(path) {
+                    Some(body) => {
+                        if method == "GET" && path.contains("/resolve/") {
+                            log.lock()
+                                .unwrap()
+                                .push(path.rsplit('/').next().unwrap().to_string());
+                        }
+                        let body = if method == "HEAD" { "" } else { body };
+                        format!(
+                            "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{body}",
+                            routes[path].len()
+                        )
+                    }
+                    None => {
+                        "HTTP/1.1 404 Not Found\r\nconnection: close\r\ncontent-length: 0\r\n\r\n"
+                            .to_string()
+                    }
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (endpoint, downloaded)
+    }
+
+    #[tokio::test]
+    async fn changed_files_are_found_by_hash() {
+        let (endpoint, downloaded) = hub(&[
+            ("weights.bin", "new!", true),
+            ("tokenizer.json", "same", true),
+            ("config.json", "{\"a\":2}", false),
+            ("generation.json", "{}", false),
+        ])
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        // the old files have the sizes of the new ones
+        std::fs::write(dir.path().join("weights.bin"), "old!").unwrap();
+        std::fs::write(dir.path().join("tokenizer.json"), "same").unwrap();
+        std::fs::write(dir.path().join("config.json"), "{\"a\":1}").unwrap();
+        std::fs::write(dir.path().join("generation.json"), "{}").unwrap();
+        std::fs::write(
+            dir.path().join(CHECKSUMS),
+            format!("\"weights.bin\" = \"{}\"\n", sha256_bytes(b"old!")),
+        )
+        .unwrap();
+        let source = ModelSource::Huggingface(HuggingfaceModel::new("org/repo").files([
+            "weights.bin",
+            "tokenizer.json",
+            "config.json",
+            "generation.json",
+        ]));
+        let options = DownloadOptions {
+            endpoint: Some(endpoint),
+            retry: RetryPolicy::none(),
+            ..DownloadOptions::default()
+        };
+        let update = || {
+            download_changed_files(
+                &source,
+                "model".to_string(),
+                "1".to_string(),
+                dir.path().to_path_buf(),
+                &SilentObserver,
+                &options,
+            )
+        };
+
+        update().await.unwrap();
+        let mut fetched = downloaded.lock().unwrap().clone();
+        fetched.sort();
+        assert_eq!(fetched, ["config.json", "weights.bin"]);
+        let read = |v: &str| std::fs::read_to_string(dir.path().join(v)).unwrap();
+        assert_eq!(read("weights.bin"), "new!");
+        assert_eq!(read("config.json"), "{\"a\":2}");
+        assert_eq!(
+            read_checksums(dir.path()).get("weights.bin"),
+            Some(&sha256_bytes(b"new!"))
+        );
+
+        // nothing moved since
+        downloaded.lock().unwrap().clear();
+        update().await.unwrap();
+        assert!(downloaded.lock().unwrap().is_empty());
+
+        // without the recorded commit and checksums the local files are hashed
+        std::fs::remove_file(dir.path().join("revision")).unwrap();
+        std::fs::remove_file(dir.path().join(CHECKSUMS)).unwrap();
+        update().await.unwrap();
+        assert!(downloaded.lock().unwrap().is_empty());
+    }
+
+    /// Reports every file as stored in Xet, with a refresh route the hub doesn't have.
+    #[cfg(feature = "xet")]
+    #[derive(Default)]
+    struct BrokenXet {
+        inner: ReqwestBackend,
+        requested: Mutex<Vec<String>>,
+    }
+
+    #[cfg(feature = "xet")]
+    impl HttpBackend for BrokenXet {
+        fn get<'a>(
+            &'a self,
+            url: &'a str,
+            headers: &'a Headers,
+        ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+            self.requested.lock().unwrap().push(url.to_string());
+            self.inner.get(url, headers)
+        }
+
+        fn head<'a>(
+            &'a self,
+            url: &'a str,
+            headers: &'a Headers,
+        ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+            self.inner.head(url, headers)
+        }
+
+        fn head_unredirected<'a>(
+            &'a self,
+            url: &'a str,
+            headers: &'a Headers,
+        ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+            Box::pin(async move {
+                let mut res = self.inner.head_unredirected(url, headers).await?;
+                res.headers
+                    .push(("x-xet-hash".to_string(), "hash".to_string()));
+                let route = "/api/models/org/repo/xet-read-token/main".to_string();
+                res.headers.push(("x-xet-refresh-route".to_string(), route));
+                Ok(res)
+            })
+        }
+    }
+
+    #[cfg(feature = "xet")]
+    #[tokio::test]
+    async fn failed_xet_downloads_fall_back_to_the_resolve_url() {
+        let (endpoint, downloaded) = hub(&[("weights.bin", "weights", true)]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let http = Arc::new(BrokenXet::default());
+        let options = DownloadOptions {
+            endpoint: Some(endpoint.clone()),
+            retry: RetryPolicy::none(),
+            http: http.clone(),
+            ..DownloadOptions::default()
+        };
+        let source = HuggingfaceModel::new("org/repo")
+            .files(["weights.bin"])
+            .into();
+        let path = dir.path().to_path_buf();
+        download_file(
+            &source,
+            "model".into(),
+            "1".into(),
+            path,
+            &SilentObserver,
+            &options,
+        )
+        .await
+        .unwrap();
+
+        let route = format!("{endpoint}/api/models/org/repo/xet-read-token/main");
+        assert!(http.requested.lock().unwrap().contains(&route));
+        assert_eq!(*downloaded.lock().unwrap(), ["weights.bin"]);
+        let read = std::fs::read_to_string(dir.path().join("weights.bin")).unwrap();
+        assert_eq!(read, "weights");
+    }
+}
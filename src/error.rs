@@ -1,29 +1,300 @@
+#[cfg(feature = "progress")]
 use indicatif::style::TemplateError;
+use serde::Serialize;
 use std::any::Any;
 use std::convert::Infallible;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use tokio::task::JoinError;
+#[cfg(not(target_arch = "wasm32"))]
 use zip_extract::ZipExtractError;
 
-#[derive(Debug)]
-#[allow(dead_code)]
+use crate::hooks::HookPoint;
+use crate::progress::bytes;
+
+/// Match on [`Error::kind`] or [`Error::code`] rather than on variants, new variants are added
+/// as errors get more specific.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum Error {
-    Fetch(String),
-    ConsoleTemplateError(TemplateError),
-    ConsoleClearError(std::io::Error),
+    /// The request didn't get a response.
+    #[error("request{} failed", to(.url))]
+    Fetch {
+        url: Option<String>,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// The server answered with an error status.
+    #[error("{url} answered with status {status}")]
+    Http { status: u16, url: String },
+    /// A transfer broke off, the bytes before `received` are kept in the `.part` file.
+    #[error(
+        "downloading {file} of {model} from {url} failed at byte {received}, requested from byte {start}"
+    )]
+    Transfer {
+        model: String,
+        file: String,
+        url: String,
+        /// Start of the requested range.
+        start: u64,
+        received: u64,
+        source: Box<Error>,
+    },
+    /// Some files of a model failed, the others were downloaded and are kept.
+    #[error("{} of {} files of {model} failed: {}", .failed.len(), .failed.len() + .succeeded.len(), list(.failed))]
+    Partial {
+        model: String,
+        succeeded: Vec<String>,
+        failed: Vec<(String, Error)>,
+    },
+    #[cfg(feature = "progress")]
+    #[error("invalid progress template")]
+    ConsoleTemplateError(#[source] TemplateError),
+    #[error("failed to clear the terminal")]
+    ConsoleClearError(#[source] std::io::Error),
+    #[error("failed to send to a thread: {0}")]
     ThreadSendError(String),
+    #[error("a worker thread panicked")]
     ThreadJoin,
-    AsyncThreadJoin(JoinError),
-    OpenFileError(std::io::Error),
-    WriteFileError(String),
+    #[error("a background task failed")]
+    AsyncThreadJoin(#[source] JoinError),
+    /// Background work was started outside of a Tokio runtime, or a blocking call couldn't
+    /// start one or was made on a runtime it would block.
+    #[error("no usable Tokio runtime")]
+    Runtime(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("failed to open {}", file(.path))]
+    OpenFileError {
+        path: Option<PathBuf>,
+        source: std::io::Error,
+    },
+    #[error("failed to write {}", file(.path))]
+    WriteFileError {
+        path: Option<PathBuf>,
+        source: std::io::Error,
+    },
+    #[error("{message}: {error}")]
     Custom { message: String, error: String },
+    #[error("{message}")]
     CustomEmpty { message: String },
-    ZipExtractError(ZipExtractError),
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("failed to extract archive")]
+    ZipExtractError(#[source] ZipExtractError),
+    #[error(transparent)]
     PathBufError(Infallible),
+    #[error("invalid path: {0}")]
     PathBufCustomError(String),
+    #[error("model not found")]
     ModelNotFound,
+    #[error("no previous version to roll back to")]
+    NoPreviousVersion,
+    #[error("version not found")]
+    VersionNotFound,
+    #[error("alias {0} is already in use")]
+    AliasConflict(String),
+    #[error("{0} depends on itself")]
+    DependencyCycle(String),
+    #[error("{0} needs a download, but the manager is offline")]
+    Offline(String),
+    #[error("{0} needs a change to the model directory, but the manager is read-only")]
+    ReadOnly(String),
+    #[error("invalid configuration: {0}")]
+    ConfigError(String),
+    #[error("registry signature is invalid")]
+    SignatureInvalid,
+    #[error(
+        "not enough disk space, {} needed but {} available",
+        bytes(*.needed),
+        bytes(*.available)
+    )]
+    InsufficientDiskSpace { needed: u64, available: u64 },
+    /// The repo requires accepting its terms at `url` and a token of an account that did.
+    #[error("{repo} is gated, accept its terms at {url} and use a token of that account")]
+    GatedRepo { repo: String, url: String },
+    /// The token is missing or invalid for this repo.
+    #[error("token is missing or invalid for {0}")]
+    Unauthorized(String),
+    /// The repo doesn't exist or is private.
+    #[error("repo {0} doesn't exist or is private")]
+    RepoNotFound(String),
+    #[error("checksum mismatch for {file}, expected {expected} but got {actual}")]
+    ChecksumMismatch {
+        file: String,
+        expected: String,
+        actual: String,
+    },
+    /// Downloads were cancelled, partial files are kept to resume from.
+    #[error("cancelled")]
+    Cancelled,
+    /// The model wasn't downloaded within its deadline, partial files are kept as well.
+    #[error("{0} wasn't downloaded within its deadline")]
+    DeadlineExceeded(String),
+    #[error("the {point} hook of {model} failed")]
+    Hook {
+        model: String,
+        point: HookPoint,
+        source: Box<Error>,
+    },
+}
+
+fn to(url: &Option<String>) -> String {
+    url.as_ref().map(|v| format!(" to {v}")).unwrap_or_default()
+}
+
+fn list(failed: &[(String, Error)]) -> String {
+    failed
+        .iter()
+        .map(|(file, err)| format!("{file} ({})", err.report()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn file(path: &Option<PathBuf>) -> String {
+    match path {
+        Some(path) => path.display().to_string(),
+        None => "file".to_string(),
+    }
+}
+
+/// Broad class of an [`Error`], for deciding whether to retry and what to tell the user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Connection problems, timeouts and server errors, which may go away on their own.
+    Network,
+    /// Missing or invalid token, or a gated repo whose terms weren't accepted.
+    Auth,
+    NotFound,
+    /// Checksum or signature mismatches.
+    Integrity,
+    Disk,
+    /// Invalid registry or arguments.
+    Config,
+    Cancelled,
+    Other,
+}
+
+impl ErrorKind {
+    /// `network`, `not_found` and so on, as in JSON output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::Network => "network",
+            ErrorKind::Auth => "auth",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::Integrity => "integrity",
+            ErrorKind::Disk => "disk",
+            ErrorKind::Config => "config",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::Other => "other",
+        }
+    }
+
+    /// Stable number of the kind, the thousands of [`Error::code`].
+    pub fn code(&self) -> u16 {
+        match self {
+            ErrorKind::Network => 1,
+            ErrorKind::Auth => 2,
+            ErrorKind::NotFound => 3,
+            ErrorKind::Integrity => 4,
+            ErrorKind::Disk => 5,
+            ErrorKind::Config => 6,
+            ErrorKind::Cancelled => 7,
+            ErrorKind::Other => 9,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 impl Error {
+    /// Stable numeric code, `1000 * kind + error`: `3002` is an HTTP error status meaning not
+    /// found, `1002` one worth retrying. Codes are never reused, new errors get new numbers.
+    pub fn code(&self) -> u16 {
+        let error = match self {
+            Error::Transfer { source, .. } => return source.code(),
+            Error::Fetch { .. } => 1,
+            Error::Http { .. } => 2,
+            Error::Offline(_) => 3,
+            Error::Unauthorized(_) => 4,
+            Error::GatedRepo { .. } => 5,
+            Error::RepoNotFound(_) => 6,
+            Error::ChecksumMismatch { .. } => 7,
+            Error::SignatureInvalid => 8,
+            Error::OpenFileError { .. } => 9,
+            Error::WriteFileError { .. } => 10,
+            Error::InsufficientDiskSpace { .. } => 11,
+            #[cfg(not(target_arch = "wasm32"))]
+            Error::ZipExtractError(_) => 12,
+            Error::ConfigError(_) => 13,
+            Error::ModelNotFound => 14,
+            Error::VersionNotFound => 15,
+            Error::NoPreviousVersion => 16,
+            Error::AliasConflict(_) => 17,
+            Error::DependencyCycle(_) => 18,
+            Error::Cancelled => 19,
+            Error::DeadlineExceeded(_) => 20,
+            Error::Custom { .. } => 21,
+            Error::CustomEmpty { .. } => 22,
+            #[cfg(feature = "progress")]
+            Error::ConsoleTemplateError(_) => 23,
+            Error::ConsoleClearError(_) => 24,
+            Error::ThreadSendError(_) => 25,
+            Error::ThreadJoin => 26,
+            Error::AsyncThreadJoin(_) => 27,
+            Error::PathBufCustomError(_) => 28,
+            Error::PathBufError(_) => 29,
+            Error::Partial { .. } => 30,
+            Error::Runtime(_) => 31,
+            Error::ReadOnly(_) => 32,
+            Error::Hook { .. } => 33,
+        };
+        1000 * self.kind().code() + error
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Fetch { .. } | Error::Offline(_) => ErrorKind::Network,
+            Error::Transfer { source, .. } => source.kind(),
+            // a persistent failure decides, retrying wouldn't fix it
+            Error::Partial { failed, .. } => failed
+                .iter()
+                .map(|v| v.1.kind())
+                .find(|v| *v != ErrorKind::Network)
+                .unwrap_or(ErrorKind::Network),
+            Error::Http { status, .. } => match status {
+                401 | 403 => ErrorKind::Auth,
+                404 | 410 => ErrorKind::NotFound,
+                408 | 429 | 500..=599 => ErrorKind::Network,
+                _ => ErrorKind::Other,
+            },
+            Error::Unauthorized(_) | Error::GatedRepo { .. } => ErrorKind::Auth,
+            Error::RepoNotFound(_) => ErrorKind::NotFound,
+            Error::ChecksumMismatch { .. } | Error::SignatureInvalid => ErrorKind::Integrity,
+            Error::InsufficientDiskSpace { .. }
+            | Error::WriteFileError { .. }
+            | Error::OpenFileError { .. }
+            | Error::ReadOnly(_) => ErrorKind::Disk,
+            #[cfg(not(target_arch = "wasm32"))]
+            Error::ZipExtractError(_) => ErrorKind::Disk,
+            Error::ConfigError(_)
+            | Error::AliasConflict(_)
+            | Error::DependencyCycle(_)
+            | Error::ModelNotFound
+            | Error::VersionNotFound
+            | Error::NoPreviousVersion => ErrorKind::Config,
+            Error::Cancelled | Error::DeadlineExceeded(_) => ErrorKind::Cancelled,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// Transient network errors, worth another attempt. Being offline isn't one.
+    pub fn is_retryable(&self) -> bool {
+        self.kind() == ErrorKind::Network && !matches!(self, Error::Offline(_))
+    }
+
     pub fn new(message: impl ToString, error: impl ToString) -> Self {
         Error::Custom {
             message: message.to_string(),
@@ -44,13 +315,30 @@ impl Error {
         Error::PathBufCustomError(error.to_string())
     }
 
+    pub fn config(error: impl ToString) -> Self {
+        Error::ConfigError(error.to_string())
+    }
+
     pub fn fetch(error: reqwest::Error) -> Self {
-        Error::Fetch(error.to_string())
+        match error.status() {
+            Some(status) => Error::Http {
+                status: status.as_u16(),
+                url: error.url().map(|v| v.to_string()).unwrap_or_default(),
+            },
+            None => Error::Fetch {
+                url: error.url().map(|v| v.to_string()),
+                source: Box::new(error),
+            },
+        }
     }
     pub fn fetch_custom(message: impl ToString) -> Self {
-        Error::Fetch(message.to_string())
+        Error::Fetch {
+            url: None,
+            source: message.to_string().into(),
+        }
     }
 
+    #[cfg(feature = "progress")]
     pub fn console_template(error: TemplateError) -> Self {
         Error::ConsoleTemplateError(error)
     }
@@ -71,19 +359,57 @@ impl Error {
         Error::AsyncThreadJoin(error)
     }
 
+    pub fn runtime(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        Error::Runtime(error.into())
+    }
+
     pub fn write_file(error: std::io::Error) -> Self {
-        Error::WriteFileError(error.to_string())
+        Error::WriteFileError {
+            path: None,
+            source: error,
+        }
+    }
+
+    /// [`Error::write_file`] naming the file, for `map_err(Error::write_path(&path))`.
+    pub fn write_path(path: &Path) -> impl FnOnce(std::io::Error) -> Self + '_ {
+        move |source| Error::WriteFileError {
+            path: Some(path.to_path_buf()),
+            source,
+        }
     }
 
     pub fn write_file_extra(error: fs_extra::error::Error) -> Self {
-        Error::WriteFileError(error.to_string())
+        Error::write_file(std::io::Error::other(error))
     }
 
     pub fn open_file(error: std::io::Error) -> Self {
-        Error::OpenFileError(error)
+        Error::OpenFileError {
+            path: None,
+            source: error,
+        }
     }
 
+    pub fn open_path(path: &Path) -> impl FnOnce(std::io::Error) -> Self + '_ {
+        move |source| Error::OpenFileError {
+            path: Some(path.to_path_buf()),
+            source,
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn zip_extract(error: ZipExtractError) -> Self {
         Error::ZipExtractError(error)
     }
+
+    /// The message followed by those of its sources, like
+    /// `failed to write models/a.bin: No space left on device (os error 28)`.
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(err) = source {
+            report.push_str(&format!(": {err}"));
+            source = err.source();
+        }
+        report
+    }
 }
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::sync::broadcast::Sender;
+
+use crate::error::{Error, ErrorKind};
+use crate::progress::ProgressObserver;
+
+pub(crate) const EVENT_CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DownloadEvent {
+    Queued {
+        model: String,
+    },
+    Started {
+        model: String,
+        file: String,
+        total: Option<u64>,
+    },
+    Progress {
+        model: String,
+        file: String,
+        downloaded: u64,
+    },
+    Retrying {
+        model: String,
+        file: String,
+        attempt: usize,
+    },
+    Finished {
+        model: String,
+    },
+    Failed {
+        model: String,
+        error: String,
+        kind: ErrorKind,
+        code: u16,
+    },
+    /// An update was staged and can be swapped in with `apply_update`.
+    UpdateReady {
+        model: String,
+        revision: String,
+    },
+}
+
+impl DownloadEvent {
+    pub fn model(&self) -> &str {
+        match self {
+            DownloadEvent::Queued { model }
+            | DownloadEvent::Started { model, .. }
+            | DownloadEvent::Progress { model, .. }
+            | DownloadEvent::Retrying { model, .. }
+            | DownloadEvent::Finished { model }
+            | DownloadEvent::Failed { model, .. }
+            | DownloadEvent::UpdateReady { model, .. } => model,
+        }
+    }
+}
+
+/// Forwards everything to `inner` and publishes it to subscribers.
+pub(crate) struct EventObserver {
+    pub(crate) inner: Arc<dyn ProgressObserver>,
+    pub(crate) sender: Sender<DownloadEvent>,
+}
+
+impl EventObserver {
+    fn send(&self, event: DownloadEvent) {
+        // no subscribers is not an error
+        let _ = self.sender.send(event);
+    }
+}
+
+impl ProgressObserver for EventObserver {
+    fn on_batch(&self, models: usize, total: Option<u64>) {
+        self.inner.on_batch(models, total);
+    }
+
+    fn on_files(&self, model: &str, files: usize, total: Option<u64>) {
+        self.inner.on_files(model, files, total);
+    }
+
+    fn on_queued(&self, model: &str) {
+        self.inner.on_queued(model);
+        self.send(DownloadEvent::Queued {
+            model: model.to_string(),
+        });
+    }
+
+    fn on_start(&self, model: &str, file: &str, total: Option<u64>) {
+        self.inner.on_start(model, file, total);
+        self.send(DownloadEvent::Started {
+            model: model.to_string(),
+            file: file.to_string(),
+            total,
+        });
+    }
+
+    fn on_bytes(&self, model: &str, file: &str, downloaded: u64) {
+        self.inner.on_bytes(model, file, downloaded);
+        if self.sender.receiver_count() > 0 {
+            self.send(DownloadEvent::Progress {
+                model: model.to_string(),
+                file: file.to_string(),
+                downloaded,
+            });
+        }
+    }
+
+    fn on_file_done(&self, model: &str, file: &str) {
+        self.inner.on_file_done(model, file);
+    }
+
+    fn on_retry(&self, model: &str, file: &str, attempt: usize) {
+        self.inner.on_retry(model, file, attempt);
+        self.send(DownloadEvent::Retrying {
+            model: model.to_string(),
+            file: file.to_string(),
+            attempt,
+        });
+    }
+
+    fn on_unpack(&self, model: &str) {
+        self.inner.on_unpack(model);
+    }
+
+    fn on_model_done(&self, model: &str) {
+        self.inner.on_model_done(model);
+        self.send(DownloadEvent::Finished {
+            model: model.to_string(),
+        });
+    }
+
+    fn on_error(&self, model: &str, error: &Error) {
+        self.inner.on_error(model, error);
+        self.send(DownloadEvent::Failed {
+            model: model.to_string(),
+            error: error.report(),
+            kind: error.kind(),
+            code: error.code(),
+        });
+    }
+}
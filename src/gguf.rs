@@ -0,0 +1,155 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::downloader::DownloadOptions;
+use crate::error::Error;
+use crate::huggingface::{gguf_split_set, list_repo_files};
+use crate::model_manager::{list_files_following, HuggingfaceModel};
+
+const MAGIC: &[u8; 4] = b"GGUF";
+
+// longer names first so `Q4_K_M` wins over `Q4_K`
+const QUANTIZATIONS: &[&str] = &[
+    "IQ2_XXS", "IQ3_XXS", "IQ2_XS", "IQ3_XS", "IQ4_XS", "IQ4_NL", "Q2_K_S", "Q3_K_S", "Q3_K_M",
+    "Q3_K_L", "Q4_K_S", "Q4_K_M", "Q5_K_S", "Q5_K_M", "IQ1_S", "IQ1_M", "IQ2_S", "IQ2_M", "IQ3_S",
+    "IQ3_M", "Q2_K", "Q3_K", "Q4_0", "Q4_1", "Q4_K", "Q5_0", "Q5_1", "Q5_K", "Q6_K", "Q8_0",
+    "BF16", "F16", "F32",
+];
+
+#[derive(Clone, Debug)]
+pub struct GgufQuantization {
+    pub name: String,
+    /// All files of this quantization, more than one if it is split.
+    pub files: Vec<String>,
+    pub size: u64,
+}
+
+#[derive(Clone, Debug)]
+pub enum QuantizationSelector {
+    /// Case-insensitive name like `Q4_K_M`.
+    Name(String),
+    /// The largest quantization whose files together fit in this many bytes.
+    MaxSize(u64),
+}
+
+/// GGUF quantizations available in a Huggingface repo, smallest first.
+pub async fn list_quantizations(
+    repo: &str,
+    options: &DownloadOptions,
+) -> Result<Vec<GgufQuantization>, Error> {
+    let files = list_repo_files(options.endpoint(), repo, "main", options.token.as_deref()).await?;
+    let mut quantizations: Vec<GgufQuantization> = vec![];
+    for file in files.into_iter().filter(|v| v.path.ends_with(".gguf")) {
+        let Some(name) = quantization_name(&file.path) else {
+            continue;
+        };
+        match quantizations.iter_mut().find(|v| v.name == name) {
+            Some(v) => {
+                v.files.push(file.path);
+                v.size += file.size.unwrap_or_default();
+            }
+            None => quantizations.push(GgufQuantization {
+                name: name.to_string(),
+                files: vec![file.path],
+                size: file.size.unwrap_or_default(),
+            }),
+        }
+    }
+    quantizations.sort_by_key(|v| v.size);
+    Ok(quantizations)
+}
+
+/// Builds a model for the quantization of `repo` matching `selector`.
+pub async fn select_quantization(
+    repo: &str,
+    selector: &QuantizationSelector,
+    options: &DownloadOptions,
+) -> Result<HuggingfaceModel, Error> {
+    let quantizations = list_quantizations(repo, options).await?;
+    let selected = match selector {
+        QuantizationSelector::Name(name) => quantizations
+            .into_iter()
+            .find(|v| v.name.eq_ignore_ascii_case(name)),
+        QuantizationSelector::MaxSize(max) => quantizations.into_iter().rfind(|v| v.size <= *max),
+    }
+    .ok_or_else(|| Error::new_option(format!("No matching quantization in {repo}")))?;
+    Ok(HuggingfaceModel::new(repo).files(selected.files))
+}
+
+pub(crate) fn quantization_name(path: &str) -> Option<&'static str> {
+    let upper = path.to_uppercase();
+    let bounded = |i: usize, len: usize| {
+        let before = upper[..i].chars().next_back();
+        let after = upper[i + len..].chars().next();
+        !before.is_some_and(|c| c.is_ascii_alphanumeric())
+            && !after.is_some_and(|c| c.is_ascii_alphanumeric())
+    };
+    QUANTIZATIONS.iter().copied().find(|name| {
+        upper
+            .match_indices(name)
+            .any(|(i, _)| bounded(i, name.len()))
+    })
+}
+
+/// The GGUF file of a model directory to hand to llama.cpp: the only `.gguf` file, or the first
+/// shard of the only split set, whose other shards llama.cpp finds next to it. Fails if there is
+/// none, more than one or a shard is missing.
+pub fn find_gguf(dir: &Path) -> Result<PathBuf, Error> {
+    let mut files = list_files_following(dir)
+        .into_iter()
+        .filter(|v| v.extension().is_some_and(|v| v == "gguf"))
+        .collect::<Vec<_>>();
+    files.sort();
+    let mut models = Vec::<Vec<PathBuf>>::new();
+    for file in &files {
+        let shards = split_set(file);
+        if !models.contains(&shards) {
+            models.push(shards);
+        }
+    }
+    match models.as_slice() {
+        [] => Err(Error::new_option(format!(
+            "No GGUF file in {}",
+            dir.display()
+        ))),
+        [shards] => match shards.iter().find(|v| !v.is_file()) {
+            Some(missing) => Err(Error::open_path(missing)(
+                std::io::ErrorKind::NotFound.into(),
+            )),
+            None => Ok(shards[0].clone()),
+        },
+        _ => Err(Error::new_option(format!(
+            "{} GGUF models in {}, select one of them with the files of the model",
+            models.len(),
+            dir.display()
+        ))),
+    }
+}
+
+/// Checks that `path` and the other shards of its split set can be memory-mapped by llama.cpp:
+/// regular, readable files starting with the GGUF magic and a known version.
+pub fn check_mmap(path: &Path) -> Result<(), Error> {
+    for shard in split_set(path) {
+        let mut header = [0; 8];
+        let mut file = std::fs::File::open(&shard).map_err(Error::open_path(&shard))?;
+        // files shorter than the header are truncated downloads
+        let complete = file.read_exact(&mut header).is_ok();
+        let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if !complete || &header[..4] != MAGIC || !(1..=3).contains(&version) {
+            return Err(Error::new_option(format!(
+                "{} isn't a GGUF file llama.cpp can load",
+                shard.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Every shard of the split set `path` belongs to, or just `path`.
+fn split_set(path: &Path) -> Vec<PathBuf> {
+    let name = path.file_name().map(|v| v.to_string_lossy().to_string());
+    match name.as_deref().and_then(gguf_split_set) {
+        Some(names) => names.into_iter().map(|v| path.with_file_name(v)).collect(),
+        None => vec![path.to_path_buf()],
+    }
+}
@@ -0,0 +1,69 @@
+use tokio::task::JoinHandle;
+
+use crate::cancel::CancelToken;
+use crate::error::Error;
+
+/// A download running in the background, returned by [`ModelManager::start_download`] and
+/// [`ModelManager::start_download_all`]. Dropping it doesn't stop the download.
+///
+/// [`ModelManager::start_download`]: crate::model_manager::ModelManager::start_download
+/// [`ModelManager::start_download_all`]: crate::model_manager::ModelManager::start_download_all
+pub struct DownloadHandle<T> {
+    control: CancelToken,
+    task: JoinHandle<Result<T, Error>>,
+}
+
+impl<T> DownloadHandle<T> {
+    pub(crate) fn new(control: CancelToken, task: JoinHandle<Result<T, Error>>) -> Self {
+        Self { control, task }
+    }
+
+    /// Stops transferring and closes the connections, the download continues where it stopped
+    /// on [`DownloadHandle::resume`].
+    pub fn pause(&self) {
+        self.control.pause();
+    }
+
+    pub fn resume(&self) {
+        self.control.resume();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.control.is_paused()
+    }
+
+    /// The download returns [`Error::Cancelled`], partial files are kept to resume from.
+    pub fn cancel(&self) {
+        self.control.cancel();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    pub async fn wait(self) -> Result<T, Error> {
+        self.task.await.map_err(Error::async_thread_join)?
+    }
+}
+
+/// Update checks running in the background, returned by [`ModelManager::watch_updates`].
+/// Dropping it stops them.
+///
+/// [`ModelManager::watch_updates`]: crate::model_manager::ModelManager::watch_updates
+pub struct UpdateChecker {
+    task: JoinHandle<()>,
+}
+
+impl UpdateChecker {
+    pub(crate) fn new(task: JoinHandle<()>) -> Self {
+        Self { task }
+    }
+
+    pub fn stop(self) {}
+}
+
+impl Drop for UpdateChecker {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::error::{Error, ErrorKind};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthReport {
+    pub disk: DiskHealth,
+    /// One entry per registered model, sorted by ident.
+    pub sources: Vec<SourceHealth>,
+}
+
+impl HealthReport {
+    /// Models can be downloaded: the directory is writable and every source answered. Offline
+    /// managers only need a writable directory.
+    pub fn is_healthy(&self) -> bool {
+        self.disk.writable
+            && self
+                .sources
+                .iter()
+                .all(|v| matches!(v.status, SourceStatus::Ok | SourceStatus::Offline))
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DiskHealth {
+    pub path: PathBuf,
+    pub writable: bool,
+    /// Free bytes on the filesystem of the model directory.
+    pub available: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SourceHealth {
+    pub ident: String,
+    /// Endpoint of a Huggingface repo or URL of an archive.
+    pub endpoint: String,
+    pub status: SourceStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceStatus {
+    Ok,
+    /// No answer from the endpoint.
+    Unreachable,
+    /// The token is missing or invalid, or access to a gated repo wasn't granted.
+    Unauthorized,
+    NotFound,
+    /// Not probed, the manager is offline.
+    Offline,
+    Error,
+}
+
+impl SourceStatus {
+    pub(crate) fn from_error(err: &Error) -> Self {
+        match err.kind() {
+            ErrorKind::Auth => SourceStatus::Unauthorized,
+            ErrorKind::NotFound => SourceStatus::NotFound,
+            ErrorKind::Network => SourceStatus::Unreachable,
+            _ => SourceStatus::Error,
+        }
+    }
+}
+
+/// Creates and removes a file in `path`.
+pub(crate) fn check_disk(path: &Path) -> DiskHealth {
+    let probe = path.join(format!(".health-{}", std::process::id()));
+    let result = std::fs::create_dir_all(path)
+        .and_then(|_| std::fs::write(&probe, b"ok"))
+        .and_then(|_| std::fs::remove_file(&probe));
+    DiskHealth {
+        path: path.to_path_buf(),
+        writable: result.is_ok(),
+        available: fs4::available_space(path).ok(),
+        error: result.err().map(|e| e.to_string()),
+    }
+}
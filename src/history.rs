@@ -0,0 +1,198 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::progress::ProgressObserver;
+use crate::queue;
+use crate::sync;
+
+/// A finished model download.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadRecord {
+    pub ident: String,
+    pub version: String,
+    /// Endpoint of a Huggingface repo or URL of an archive.
+    pub endpoint: String,
+    /// Host of the endpoint, mirrors are compared by it.
+    pub host: String,
+    /// Bytes received, without those a resumed download already had.
+    pub bytes: u64,
+    pub duration_ms: u64,
+    pub retries: usize,
+    /// Unix timestamp.
+    pub finished: i64,
+}
+
+impl DownloadRecord {
+    /// Average bytes per second.
+    pub fn speed(&self) -> u64 {
+        speed(self.bytes, self.duration_ms)
+    }
+}
+
+/// Totals of the downloads of one host or model.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DownloadStats {
+    pub key: String,
+    pub downloads: usize,
+    pub bytes: u64,
+    pub duration_ms: u64,
+    pub retries: usize,
+}
+
+impl DownloadStats {
+    pub fn speed(&self) -> u64 {
+        speed(self.bytes, self.duration_ms)
+    }
+
+    pub fn average_duration_ms(&self) -> u64 {
+        self.duration_ms / self.downloads.max(1) as u64
+    }
+}
+
+fn speed(bytes: u64, duration_ms: u64) -> u64 {
+    bytes * 1000 / duration_ms.max(1)
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct HistoryStats {
+    /// Sorted by host, the slowest mirrors have the lowest speed.
+    pub hosts: Vec<DownloadStats>,
+    /// Sorted by ident.
+    pub models: Vec<DownloadStats>,
+}
+
+impl HistoryStats {
+    pub fn new(records: &[DownloadRecord]) -> Self {
+        Self {
+            hosts: group(records, |v| &v.host),
+            models: group(records, |v| &v.ident),
+        }
+    }
+}
+
+fn group(records: &[DownloadRecord], key: impl Fn(&DownloadRecord) -> &str) -> Vec<DownloadStats> {
+    let mut groups = BTreeMap::<&str, DownloadStats>::new();
+    for record in records {
+        let stats = groups.entry(key(record)).or_default();
+        stats.downloads += 1;
+        stats.bytes += record.bytes;
+        stats.duration_ms += record.duration_ms;
+        stats.retries += record.retries;
+    }
+    groups
+        .into_iter()
+        .map(|(key, stats)| DownloadStats {
+            key: key.to_string(),
+            ..stats
+        })
+        .collect()
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct HistoryFile {
+    #[serde(default)]
+    downloads: Vec<DownloadRecord>,
+}
+
+pub(crate) fn read(path: &Path) -> Result<Vec<DownloadRecord>, Error> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(toml::from_str::<HistoryFile>(&content)
+            .map_err(Error::config)?
+            .downloads),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(Error::open_path(path)(e)),
+    }
+}
+
+/// Appends `record` as another `[[downloads]]` table, so the history is never rewritten.
+pub(crate) fn append(path: &Path, record: DownloadRecord) -> Result<(), Error> {
+    let content = toml::to_string(&HistoryFile {
+        downloads: vec![record],
+    })
+    .map_err(Error::config)?;
+    let lock = queue::lock(&path.with_extension("lock"))?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(Error::open_path(path))?;
+    file.write_all(format!("\n{content}").as_bytes())
+        .map_err(Error::write_path(path))?;
+    drop(lock);
+    Ok(())
+}
+
+/// Counts the bytes and retries of downloads, then forwards to `inner`.
+pub(crate) struct Tally<'a> {
+    inner: &'a dyn ProgressObserver,
+    pub(crate) bytes: AtomicU64,
+    pub(crate) retries: AtomicUsize,
+    files: Mutex<HashMap<(String, String), u64>>,
+}
+
+impl<'a> Tally<'a> {
+    pub(crate) fn new(inner: &'a dyn ProgressObserver) -> Self {
+        Self {
+            inner,
+            bytes: AtomicU64::default(),
+            retries: AtomicUsize::default(),
+            files: Mutex::default(),
+        }
+    }
+}
+
+impl ProgressObserver for Tally<'_> {
+    fn on_batch(&self, models: usize, total: Option<u64>) {
+        self.inner.on_batch(models, total);
+    }
+
+    fn on_files(&self, model: &str, files: usize, total: Option<u64>) {
+        self.inner.on_files(model, files, total);
+    }
+
+    fn on_queued(&self, model: &str) {
+        self.inner.on_queued(model);
+    }
+
+    fn on_start(&self, model: &str, file: &str, total: Option<u64>) {
+        self.inner.on_start(model, file, total);
+    }
+
+    fn on_bytes(&self, model: &str, file: &str, downloaded: u64) {
+        self.inner.on_bytes(model, file, downloaded);
+        // the first report is what a resumed file already had on disk
+        let key = (model.to_string(), file.to_string());
+        if let Some(counted) = sync::lock(&self.files).insert(key, downloaded) {
+            let received = downloaded.saturating_sub(counted);
+            self.bytes.fetch_add(received, Ordering::Relaxed);
+        }
+    }
+
+    fn on_file_done(&self, model: &str, file: &str) {
+        self.inner.on_file_done(model, file);
+    }
+
+    fn on_retry(&self, model: &str, file: &str, attempt: usize) {
+        self.inner.on_retry(model, file, attempt);
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_unpack(&self, model: &str) {
+        self.inner.on_unpack(model);
+    }
+
+    fn on_model_done(&self, model: &str) {
+        self.inner.on_model_done(model);
+    }
+
+    fn on_error(&self, model: &str, error: &Error) {
+        self.inner.on_error(model, error);
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use crate::error::Error;
+
+/// When a hook registered with [`ModelManager::add_hook`] runs. A failing hook fails the
+/// download and the version isn't activated.
+///
+/// [`ModelManager::add_hook`]: crate::model_manager::ModelManager::add_hook
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HookPoint {
+    /// Before the files are downloaded into the directory, which may not exist yet.
+    BeforeDownload,
+    /// Once the files are downloaded and their checksums verified, before the version is
+    /// activated. Files it adds or changes are part of the installed version.
+    AfterDownload,
+    /// Once an archive is unpacked into the directory, before its checksums are verified.
+    AfterExtract,
+}
+
+impl fmt::Display for HookPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HookPoint::BeforeDownload => "before_download",
+            HookPoint::AfterDownload => "after_download",
+            HookPoint::AfterExtract => "after_extract",
+        })
+    }
+}
+
+/// What a hook runs on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HookContext {
+    pub ident: String,
+    pub version: String,
+    /// Directory the version is downloaded into.
+    pub dir: PathBuf,
+}
+
+type Hook = Arc<dyn Fn(HookContext) -> BoxFuture<'static, Result<(), Error>> + Send + Sync>;
+
+/// Hooks per model, run in the order they were added.
+#[derive(Clone, Default)]
+pub(crate) struct Hooks(HashMap<(String, HookPoint), Vec<Hook>>);
+
+impl Hooks {
+    pub(crate) fn add<F>(
+        &mut self,
+        ident: &str,
+        point: HookPoint,
+        hook: impl Fn(HookContext) -> F + Send + Sync + 'static,
+    ) where
+        F: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let hook: Hook = Arc::new(move |v| hook(v).boxed());
+        self.0
+            .entry((ident.to_string(), point))
+            .or_default()
+            .push(hook);
+    }
+
+    pub(crate) fn remove(&mut self, ident: &str) {
+        self.0.retain(|(v, _), _| v != ident);
+    }
+
+    pub(crate) async fn run(&self, point: HookPoint, context: HookContext) -> Result<(), Error> {
+        let Some(hooks) = self.0.get(&(context.ident.clone(), point)) else {
+            return Ok(());
+        };
+        for hook in hooks {
+            hook(context.clone()).await.map_err(|e| Error::Hook {
+                model: context.ident.clone(),
+                point,
+                source: Box::new(e),
+            })?;
+        }
+        Ok(())
+    }
+}
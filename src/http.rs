@@ -0,0 +1,261 @@
+use std::fmt;
+
+use bytes::Bytes;
+use futures::TryStreamExt;
+use reqwest::header::RANGE;
+#[cfg(not(target_arch = "wasm32"))]
+use reqwest::redirect::Policy;
+use reqwest::{Client, ClientBuilder, RequestBuilder};
+
+use crate::error::Error;
+
+/// `(name, value)` pairs, names are matched case insensitively.
+pub type Headers = Vec<(String, String)>;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub type BoxFuture<'a, T> = futures::future::BoxFuture<'a, T>;
+/// Futures and streams of `fetch` belong to the JavaScript thread, they aren't `Send`.
+#[cfg(target_arch = "wasm32")]
+pub type BoxFuture<'a, T> = futures::future::LocalBoxFuture<'a, T>;
+#[cfg(not(target_arch = "wasm32"))]
+pub type BoxStream<'a, T> = futures::stream::BoxStream<'a, T>;
+#[cfg(target_arch = "wasm32")]
+pub type BoxStream<'a, T> = futures::stream::LocalBoxStream<'a, T>;
+
+/// What file downloads, archive probes and the metadata requests for files of a repo are sent
+/// with, so other HTTP stacks or a mock in tests can replace reqwest. Implementations return
+/// every status as is, the caller decides which are errors. Requests of the Huggingface API
+/// itself always go through reqwest.
+pub trait HttpBackend: Send + Sync {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a Headers,
+    ) -> BoxFuture<'a, Result<HttpResponse, Error>>;
+
+    /// The bytes of `url` from `start` on. Servers without range support answer with the whole
+    /// body and a status other than 206.
+    fn get_range<'a>(
+        &'a self,
+        url: &'a str,
+        start: u64,
+        headers: &'a Headers,
+    ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+        Box::pin(async move {
+            let mut headers = headers.clone();
+            headers.push((RANGE.to_string(), format!("bytes={start}-")));
+            self.get(url, &headers).await
+        })
+    }
+
+    fn head<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a Headers,
+    ) -> BoxFuture<'a, Result<HttpResponse, Error>>;
+
+    /// A HEAD request returning redirects instead of following them, the hub sends the size and
+    /// hash of a file along with its redirect to the CDN. Defaults to [`HttpBackend::head`],
+    /// for backends following redirects that means the CDN's headers are used.
+    fn head_unredirected<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a Headers,
+    ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+        self.head(url, headers)
+    }
+}
+
+impl fmt::Debug for dyn HttpBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HttpBackend")
+    }
+}
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Headers,
+    /// Empty for HEAD requests.
+    pub body: BoxStream<'static, Result<Bytes, Error>>,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|v| v.0.eq_ignore_ascii_case(name))
+            .map(|v| v.1.as_str())
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.header("content-length").and_then(|v| v.parse().ok())
+    }
+}
+
+async fn send(request: RequestBuilder, headers: &Headers) -> Result<HttpResponse, Error> {
+    let request = headers.iter().fold(request, |request, (name, value)| {
+        request.header(name, value)
+    });
+    let res = request.send().await.map_err(Error::fetch)?;
+    Ok(HttpResponse {
+        status: res.status().as_u16(),
+        headers: res
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+        body: Box::pin(res.bytes_stream().map_err(Error::fetch)),
+    })
+}
+
+/// The default backend outside of browsers.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+pub struct ReqwestBackend {
+    client: Client,
+    unredirected: Client,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for ReqwestBackend {
+    /// Panics like [`Client::default`] if the TLS backend can't be initialized.
+    fn default() -> Self {
+        Self::new(Client::builder).expect("default HTTP client")
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ReqwestBackend {
+    /// Builds the clients from `builder`, so proxy, TLS roots, timeouts and user agent apply to
+    /// every request. Requests that mustn't follow redirects get a second client from it with
+    /// redirects turned off.
+    pub fn new(builder: impl Fn() -> ClientBuilder) -> Result<Self, Error> {
+        let build = |v: ClientBuilder| {
+            v.build()
+                .map_err(|e| Error::new("Building the HTTP client failed", e))
+        };
+        Ok(Self {
+            client: build(builder())?,
+            unredirected: build(builder().redirect(Policy::none()))?,
+        })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl HttpBackend for ReqwestBackend {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a Headers,
+    ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+        Box::pin(send(self.client.get(url), headers))
+    }
+
+    fn head<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a Headers,
+    ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+        Box::pin(send(self.client.head(url), headers))
+    }
+
+    fn head_unredirected<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a Headers,
+    ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+        Box::pin(send(self.unredirected.head(url), headers))
+    }
+}
+
+/// The default backend in browsers, requests go through `fetch`. It follows redirects without
+/// reporting them, so [`HttpBackend::head_unredirected`] gets the headers of the CDN.
+#[cfg(target_arch = "wasm32")]
+#[derive(Clone, Default)]
+pub struct FetchBackend {
+    client: Client,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl FetchBackend {
+    /// Builds the client from `builder`, for default headers sent with every request.
+    pub fn new(builder: ClientBuilder) -> Result<Self, Error> {
+        Ok(Self {
+            client: builder
+                .build()
+                .map_err(|e| Error::new("Building the HTTP client failed", e))?,
+        })
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl HttpBackend for FetchBackend {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a Headers,
+    ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+        Box::pin(send(self.client.get(url), headers))
+    }
+
+    fn head<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a Headers,
+    ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+        Box::pin(send(self.client.head(url), headers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Redirects `/from` to `/to` and records the user agent of every request.
+    async fn server() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let agents = Arc::new(Mutex::new(vec![]));
+        let seen = agents.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = vec![0; 4096];
+                let len = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..len]).to_lowercase();
+                if let Some(agent) = request.lines().find_map(|v| v.strip_prefix("user-agent: ")) {
+                    seen.lock().unwrap().push(agent.to_string());
+                }
+                let response = match request.starts_with("head /from ") {
+                    true => "HTTP/1.1 302 Found\r\nlocation: /to\r\ncontent-length: 0\r\n\r\n",
+                    false => "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n",
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, agents)
+    }
+
+    #[tokio::test]
+    async fn both_clients_use_the_builder() {
+        let (url, agents) = server().await;
+        let backend = ReqwestBackend::new(|| Client::builder().user_agent("configured")).unwrap();
+        let url = format!("{url}/from");
+
+        assert_eq!(backend.head(&url, &vec![]).await.unwrap().status, 200);
+        let res = backend.head_unredirected(&url, &vec![]).await.unwrap();
+        assert_eq!(res.status, 302);
+        assert_eq!(res.header("Location"), Some("/to"));
+        assert_eq!(*agents.lock().unwrap(), ["configured"; 3]);
+    }
+
+    #[test]
+    fn builder_errors_are_returned() {
+        let builder = || Client::builder().user_agent("invalid\nagent");
+        assert!(ReqwestBackend::new(builder).is_err());
+    }
+}
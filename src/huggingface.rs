@@ -1,215 +1,563 @@
 use std::collections::HashMap;
-use std::fs::remove_file;
-use std::io::SeekFrom;
-use std::path::Path;
-use std::sync::Arc;
 use rand::{Rng, thread_rng};
-use reqwest::header::{CONTENT_RANGE, HeaderMap, HeaderName, HeaderValue, RANGE};
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
-use tokio::sync::Semaphore;
-use tokio::time::sleep;
-
-const BASE_WAIT_TIME: usize = 300;
-const MAX_WAIT_TIME: usize = 10_000;
-
-fn download(
-    url: String,
-    filename: String,
-    max_files: usize,
-    chunk_size: usize,
-    parallel_failures: usize,
-    max_retries: usize,
-    headers: Option<HashMap<String, String>>,
-) -> Result<(), String> {
-    if parallel_failures > max_files {
-        return Err(
-            "Error parallel_failures cannot be > max_files".to_string(),
-        );
-    }
-    if (parallel_failures == 0) != (max_retries == 0) {
-        return Err("For retry mechanism you need to set both `parallel_failures` and `max_retries`"
-                .to_string(),
-        );
-    }
-    tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build().unwrap()
-        .block_on(async {
-            download_async(
-                url,
-                filename.clone(),
-                max_files,
-                chunk_size,
-                parallel_failures,
-                max_retries,
-                headers,
-            )
-                .await
-        })
-        .map_err(|err| {
-            let path = Path::new(&filename);
-            if path.exists() {
-                match remove_file(filename) {
-                    Ok(_) => err,
-                    Err(err) => {
-                        return format!(
-                            "Error while removing corrupted file: {err:?}"
-                        );
-                    }
-                }
-            } else {
-                err
+use reqwest::header::{AUTHORIZATION, HeaderMap, LINK};
+use reqwest::{RequestBuilder, Response, StatusCode, Url};
+use serde::Deserialize;
+
+use crate::error::Error;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::model_manager::RepoInfo;
+use crate::source::DownloadOptions;
+
+#[cfg(feature = "upload")]
+pub mod upload;
+#[cfg(feature = "xet")]
+pub(crate) mod xet;
+
+pub(crate) fn exponential_backoff(base_wait_time: usize, n: usize, max: usize) -> usize {
+    (base_wait_time + n.pow(2) + jitter()).min(max)
+}
+
+fn jitter() -> usize {
+    thread_rng().gen_range(0..=500)
+}
+
+#[derive(Deserialize)]
+struct RevisionInfo {
+    sha: String,
+}
+
+pub(crate) async fn resolve_revision(
+    endpoint: &str,
+    repo: &str,
+    revision: &str,
+    token: Option<&str>,
+) -> Result<String, Error> {
+    let url = format!(
+        "{}/api/models/{repo}/revision/{}",
+        endpoint.trim_end_matches('/'),
+        encode_segment(revision)
+    );
+    let info: RevisionInfo = get(&url, endpoint, repo, token)
+        .await?
+        .json()
+        .await
+        .map_err(Error::fetch)?;
+    Ok(info.sha)
+}
+
+/// Percent-encodes a single URL path segment, `/` included.
+pub(crate) fn encode_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
             }
+            _ => format!("%{b:02X}"),
         })
+        .collect()
 }
 
-async fn download_async(
-    url: String,
-    filename: String,
-    max_files: usize,
-    chunk_size: usize,
-    parallel_failures: usize,
-    max_retries: usize,
-    input_headers: Option<HashMap<String, String>>,
-) -> Result<(), String> {
-    let client = reqwest::Client::new();
-
-    let mut headers = HeaderMap::new();
-    if let Some(input_headers) = input_headers {
-        for (k, v) in input_headers {
-            let k: HeaderName = k
-                .try_into()
-                .map_err(|err| format!("Invalid header: {err:?}"))?;
-            let v: HeaderValue = v
-                .try_into()
-                .map_err(|err| format!("Invalid header value: {err:?}"))?;
-            headers.insert(k, v);
-        }
-    };
+/// Percent-encodes a file path inside a repo, keeping its `/` separators.
+pub(crate) fn encode_path(path: &str) -> String {
+    path.split('/').map(encode_segment).collect::<Vec<_>>().join("/")
+}
 
-    let response = client
-        .get(&url)
-        .headers(headers.clone())
-        .header(RANGE, "bytes=0-0")
+pub(crate) fn with_token(request: RequestBuilder, token: Option<&str>) -> RequestBuilder {
+    match token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+async fn get(url: &str, endpoint: &str, repo: &str, token: Option<&str>) -> Result<Response, Error> {
+    let res = with_token(reqwest::Client::new().get(url), token)
         .send()
         .await
-        .map_err(|err| format!("Error while downloading: {err:?}"))?;
-    let cl = response.content_length().ok_or("No content length")?;
+        .map_err(Error::fetch)?;
+    check_response(res, endpoint, repo)
+}
 
-    let content_range = response
+/// Maps Huggingface's answers for gated, private and missing repos to dedicated errors.
+pub(crate) fn check_response(res: Response, endpoint: &str, repo: &str) -> Result<Response, Error> {
+    let code = res
         .headers()
-        .get(CONTENT_RANGE)
-        .ok_or("No content length")?
-        .to_str()
-        .map_err(|err| format!("Error while downloading: {err:?}"))?;
-
-    let size: Vec<&str> = content_range.split('/').collect();
-    // Content-Range: bytes 0-0/702517648
-    // https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Content-Range
-    let length: usize = size
-        .last()
-        .ok_or("Error while downloading: No size was detected",
-        )?
-        .parse()
-        .map_err(|err| format!("Error while downloading: {err:?}"))?;
-
-    let mut handles = vec![];
-    let semaphore = Arc::new(Semaphore::new(max_files));
-    let parallel_failures_semaphore = Arc::new(Semaphore::new(parallel_failures));
-
-    let chunk_size = chunk_size;
-    for start in (0..length).step_by(chunk_size) {
-        let url = url.clone();
-        let filename = filename.clone();
-        let client = client.clone();
-        let headers = headers.clone();
-
-        let stop = std::cmp::min(start + chunk_size - 1, length);
-        let permit = semaphore
-            .clone()
-            .acquire_owned()
-            .await
-            .map_err(|err| format!("Error while downloading: {err:?}"))?;
-        let parallel_failures_semaphore = parallel_failures_semaphore.clone();
-        handles.push(tokio::spawn(async move {
-            let mut chunk = download_chunk(&client, &url, &filename, start, stop, headers.clone()).await;
-            let mut i = 0;
-            if parallel_failures > 0 {
-                while let Err(dlerr) = chunk {
-                    if i >= max_retries {
-                        return Err(format!(
-                            "Failed after too many retries ({max_retries:?}): {dlerr:?}"
-                        ));
-                    }
-                    let parallel_failure_permit = parallel_failures_semaphore.clone().try_acquire_owned().map_err(|err| {
-                        format!(
-                            "Failed too many failures in parallel ({parallel_failures:?}): {dlerr:?} ({err:?})"
-                        )
-                    })?;
-
-                    let wait_time = exponential_backoff(BASE_WAIT_TIME, i, MAX_WAIT_TIME);
-                    sleep(tokio::time::Duration::from_millis(wait_time as u64)).await;
-
-                    chunk = download_chunk(&client, &url, &filename, start, stop, headers.clone()).await;
-                    i += 1;
-                    drop(parallel_failure_permit);
-                }
-            }
-            drop(permit);
-            chunk
-        }));
+        .get("x-error-code")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    match hub_error(res.status().as_u16(), code, endpoint, repo) {
+        Some(err) => Err(err),
+        None => res.error_for_status().map_err(Error::fetch),
     }
+}
+
+/// The error for a refusal of the hub, `code` is its `x-error-code` header.
+pub(crate) fn hub_error(status: u16, code: &str, endpoint: &str, repo: &str) -> Option<Error> {
+    match (StatusCode::from_u16(status).ok()?, code) {
+        (_, "GatedRepo") | (StatusCode::FORBIDDEN, _) => Some(Error::GatedRepo {
+            repo: repo.to_string(),
+            url: format!("{}/{repo}", endpoint.trim_end_matches('/')),
+        }),
+        (_, "RepoNotFound") => Some(Error::RepoNotFound(repo.to_string())),
+        (StatusCode::UNAUTHORIZED, _) => Some(Error::Unauthorized(repo.to_string())),
+        (StatusCode::NOT_FOUND, "") => Some(Error::RepoNotFound(repo.to_string())),
+        _ => None,
+    }
+}
 
-    // Output the chained result
-    let results: Vec<Result<Result<(), String>, tokio::task::JoinError>> =
-        futures::future::join_all(handles).await;
-    let results: Result<(), String> = results.into_iter().flatten().collect();
-    results?;
-    Ok(())
+#[derive(Deserialize)]
+pub(crate) struct TreeEntry {
+    #[serde(rename = "type")]
+    kind: String,
+    pub(crate) path: String,
+    #[serde(default)]
+    pub(crate) size: Option<u64>,
+    /// Git blob id.
+    #[serde(default)]
+    pub(crate) oid: Option<String>,
+    #[serde(default)]
+    lfs: Option<TreeLfs>,
 }
 
-async fn download_chunk(
-    client: &reqwest::Client,
+#[derive(Deserialize)]
+struct TreeLfs {
+    oid: String,
+}
+
+impl TreeEntry {
+    /// Reported for LFS and Xet files only.
+    pub(crate) fn sha256(&self) -> Option<&str> {
+        self.lfs.as_ref().map(|v| v.oid.as_str())
+    }
+}
+
+/// All files of a repo at `revision`, following the pagination of the tree API.
+pub(crate) async fn list_repo_files(
+    endpoint: &str,
+    repo: &str,
+    revision: &str,
+    token: Option<&str>,
+) -> Result<Vec<TreeEntry>, Error> {
+    let mut url = Some(format!(
+        "{}/api/models/{repo}/tree/{}?recursive=true",
+        endpoint.trim_end_matches('/'),
+        encode_segment(revision)
+    ));
+    let mut files = vec![];
+    while let Some(current) = url {
+        let res = get(&current, endpoint, repo, token).await?;
+        url = next_link(res.headers());
+        let entries: Vec<TreeEntry> = res.json().await.map_err(Error::fetch)?;
+        files.extend(entries.into_iter().filter(|entry| entry.kind == "file"));
+    }
+    Ok(files)
+}
+
+fn next_link(headers: &HeaderMap) -> Option<String> {
+    // Link: <https://huggingface.co/api/...&cursor=...>; rel="next"
+    let link = headers.get(LINK)?.to_str().ok()?;
+    link.split(',')
+        .find(|v| v.contains("rel=\"next\""))?
+        .split(';')
+        .next()
+        .map(|v| v.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+}
+
+#[derive(Deserialize)]
+struct ShardIndex {
+    weight_map: HashMap<String, String>,
+}
+
+/// Shard files referenced by a `*.safetensors.index.json` weight map.
+pub(crate) async fn shard_files(
     url: &str,
-    filename: &str,
-    start: usize,
-    stop: usize,
-    headers: HeaderMap,
-) -> Result<(), String> {
-    // Process each socket concurrently.
-    let range = format!("bytes={start}-{stop}");
-    let mut file = tokio::fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(filename)
+    endpoint: &str,
+    repo: &str,
+    token: Option<&str>,
+) -> Result<Vec<String>, Error> {
+    let index: ShardIndex = get(url, endpoint, repo, token)
+        .await?
+        .json()
         .await
-        .map_err(|err| format!("Error while downloading: {err:?}"))?;
-    file.seek(SeekFrom::Start(start as u64))
+        .map_err(Error::fetch)?;
+    let mut shards = index.weight_map.into_values().collect::<Vec<_>>();
+    shards.sort();
+    shards.dedup();
+    Ok(shards)
+}
+
+/// All file names of the set `file` belongs to if it's named like `model-00001-of-00003.gguf`.
+pub(crate) fn gguf_split_set(file: &str) -> Option<Vec<String>> {
+    let stem = file.strip_suffix(".gguf")?;
+    let (rest, total) = stem.rsplit_once("-of-")?;
+    let (prefix, index) = rest.rsplit_once('-')?;
+    if index.len() != total.len() || !index.chars().chain(total.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let count: usize = total.parse().ok()?;
+    let width = total.len();
+    Some(
+        (1..=count)
+            .map(|i| format!("{prefix}-{i:0width$}-of-{total}.gguf"))
+            .collect(),
+    )
+}
+
+#[derive(Deserialize)]
+struct ApiModelInfo {
+    sha: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    pipeline_tag: Option<String>,
+    library_name: Option<String>,
+    #[serde(rename = "cardData")]
+    card_data: Option<CardData>,
+}
+
+#[derive(Deserialize)]
+struct CardData {
+    license: Option<String>,
+}
+
+/// Repo metadata and the README (model card) if the repo has one.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn fetch_repo_info(
+    endpoint: &str,
+    repo: &str,
+    token: Option<&str>,
+) -> Result<RepoInfo, Error> {
+    let endpoint = endpoint.trim_end_matches('/');
+    let info: ApiModelInfo = get(&format!("{endpoint}/api/models/{repo}"), endpoint, repo, token)
+        .await?
+        .json()
         .await
-        .map_err(|err|format!("Error while downloading: {err:?}"))?;
-    let response = client
-        .get(url)
-        .headers(headers)
-        .header(RANGE, range)
+        .map_err(Error::fetch)?;
+    let revision = info.sha.as_deref().unwrap_or("main");
+    let card = with_token(
+        reqwest::Client::new().get(format!("{endpoint}/{repo}/resolve/{revision}/README.md")),
+        token,
+    )
+    .send()
+    .await
+    .map_err(Error::fetch)?;
+    let card = match card.status().is_success() {
+        true => Some(card.text().await.map_err(Error::fetch)?),
+        false => None,
+    };
+    Ok(RepoInfo {
+        repo: repo.to_string(),
+        sha: info.sha,
+        tags: info.tags,
+        pipeline_tag: info.pipeline_tag,
+        library_name: info.library_name,
+        license: info.card_data.and_then(|v| v.license),
+        card,
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SearchSort {
+    Downloads,
+    Likes,
+    LastModified,
+    Trending,
+}
+
+impl SearchSort {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SearchSort::Downloads => "downloads",
+            SearchSort::Likes => "likes",
+            SearchSort::LastModified => "lastModified",
+            SearchSort::Trending => "trendingScore",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchFilters {
+    /// Pipeline tag like `text-generation`.
+    pub task: Option<String>,
+    /// Library like `gguf` or `transformers`.
+    pub library: Option<String>,
+    pub author: Option<String>,
+    /// Sorted descending.
+    pub sort: Option<SearchSort>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SearchResult {
+    #[serde(rename = "id")]
+    pub repo: String,
+    #[serde(default)]
+    pub downloads: u64,
+    #[serde(default)]
+    pub likes: u64,
+    pub pipeline_tag: Option<String>,
+    pub library_name: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Searches the Huggingface hub for model repos.
+pub async fn search(query: &str, filters: &SearchFilters) -> Result<Vec<SearchResult>, Error> {
+    search_with(query, filters, &DownloadOptions::default()).await
+}
+
+/// Like [`search`] with the endpoint and token of `options`.
+pub async fn search_with(
+    query: &str,
+    filters: &SearchFilters,
+    options: &DownloadOptions,
+) -> Result<Vec<SearchResult>, Error> {
+    let url = format!("{}/api/models", options.endpoint().trim_end_matches('/'));
+    let mut params = vec![("search", query.to_string())];
+    let optional = [
+        ("pipeline_tag", filters.task.clone()),
+        ("library", filters.library.clone()),
+        ("author", filters.author.clone()),
+        ("sort", filters.sort.map(|v| v.as_str().to_string())),
+        ("limit", filters.limit.map(|v| v.to_string())),
+    ];
+    params.extend(optional.into_iter().filter_map(|(k, v)| Some((k, v?))));
+    if filters.sort.is_some() {
+        params.push(("direction", "-1".to_string()));
+    }
+    with_token(reqwest::Client::new().get(url).query(&params), options.token.as_deref())
         .send()
         .await
-        .map_err(|err| format!("Error while downloading: {err:?}"))?
+        .map_err(Error::fetch)?
         .error_for_status()
-        .map_err(|err| format!("Error while downloading: {err:?}"))?;
-    let content = response
-        .bytes()
-        .await
-        .map_err(|err| format!("Error while downloading: {err:?}"))?;
-    file.write_all(&content)
+        .map_err(Error::fetch)?
+        .json()
         .await
-        .map_err(|err|format!("Error while downloading: {err:?}"))?;
-    Ok(())
+        .map_err(Error::fetch)
 }
 
-pub fn exponential_backoff(base_wait_time: usize, n: usize, max: usize) -> usize {
-    (base_wait_time + n.pow(2) + jitter()).min(max)
+/// What the resolve endpoint reports about a file, read without following the redirect to the
+/// CDN. Xet-backed files are served over plain HTTP through the bridge the redirect points at,
+/// with the `xet` feature they're reconstructed from their chunks instead.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FileMetadata {
+    pub(crate) size: Option<u64>,
+    /// Sha256 for LFS and Xet files, the git blob id otherwise.
+    pub(crate) etag: Option<String>,
+    #[cfg(feature = "xet")]
+    pub(crate) xet: Option<xet::XetFile>,
 }
 
-fn jitter() -> usize {
-    thread_rng().gen_range(0..=500)
+impl FileMetadata {
+    pub(crate) fn sha256(&self) -> Option<&str> {
+        self.etag
+            .as_deref()
+            .filter(|v| v.len() == 64 && v.bytes().all(|v| v.is_ascii_hexdigit()))
+    }
+}
+
+/// Sent through the HTTP backend of `options`, taking a connection of its host limit.
+pub(crate) async fn file_metadata(
+    url: &str,
+    endpoint: &str,
+    repo: &str,
+    token: Option<&str>,
+    options: &DownloadOptions,
+) -> Result<FileMetadata, Error> {
+    let headers = match token {
+        Some(token) => vec![(AUTHORIZATION.to_string(), format!("Bearer {token}"))],
+        None => vec![],
+    };
+    let mut url = Url::parse(url).map_err(|e| Error::new("Invalid url", e))?;
+    // renamed repos answer with relative redirects on the hub itself, follow only those
+    for _ in 0..5 {
+        #[cfg(not(target_arch = "wasm32"))]
+        let _connection = match &options.host_limit {
+            Some(limit) => limit.connection(&options.cancel).await?,
+            None => None,
+        };
+        let res = options.http.head_unredirected(url.as_str(), &headers).await?;
+        if !(300..400).contains(&res.status) {
+            let code = res.header("x-error-code").unwrap_or_default();
+            if let Some(err) = hub_error(res.status, code, endpoint, repo) {
+                return Err(err);
+            }
+            if res.status >= 400 {
+                return Err(Error::Http {
+                    status: res.status,
+                    url: url.to_string(),
+                });
+            }
+        }
+        let header = |name: &str| res.header(name).map(|v| v.to_string());
+        if let Some(location) = header("location") {
+            let next = url
+                .join(&location)
+                .map_err(|e| Error::new("Invalid redirect", e))?;
+            if next.host() == url.host() && header("x-linked-size").is_none() {
+                url = next;
+                continue;
+            }
+        }
+        return Ok(FileMetadata {
+            size: header("x-linked-size")
+                .or_else(|| header("content-length"))
+                .and_then(|v| v.parse().ok()),
+            etag: header("x-linked-etag")
+                .or_else(|| header("etag"))
+                .map(|v| v.trim_start_matches("W/").trim_matches('"').to_string()),
+            #[cfg(feature = "xet")]
+            xet: xet::XetFile::from_response(&url, &res),
+        });
+    }
+    Err(Error::fetch_custom("Too many redirects"))
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GitRef {
+    pub name: String,
+    #[serde(rename = "targetCommit")]
+    pub commit: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Commit {
+    #[serde(rename = "id")]
+    pub sha: String,
+    #[serde(default)]
+    pub title: String,
+    pub date: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Revisions {
+    pub branches: Vec<GitRef>,
+    pub tags: Vec<GitRef>,
+    /// History of `main`, newest first.
+    pub commits: Vec<Commit>,
+}
+
+#[derive(Deserialize)]
+struct ApiRefs {
+    #[serde(default)]
+    branches: Vec<GitRef>,
+    #[serde(default)]
+    tags: Vec<GitRef>,
+}
+
+/// Branches, tags and commits of a Huggingface repo, any of them can be pinned with
+/// [`crate::model_manager::HuggingfaceModel::revision`].
+pub async fn list_revisions(repo: &str) -> Result<Revisions, Error> {
+    list_revisions_with(repo, &DownloadOptions::default()).await
+}
+
+/// Like [`list_revisions`] with the endpoint and token of `options`.
+pub async fn list_revisions_with(repo: &str, options: &DownloadOptions) -> Result<Revisions, Error> {
+    let endpoint = options.endpoint();
+    let token = options.token.as_deref();
+    let base = format!("{}/api/models/{repo}", endpoint.trim_end_matches('/'));
+    let refs: ApiRefs = get(&format!("{base}/refs"), endpoint, repo, token)
+        .await?
+        .json()
+        .await
+        .map_err(Error::fetch)?;
+
+    let mut url = Some(format!("{base}/commits/main"));
+    let mut commits = vec![];
+    while let Some(current) = url {
+        let res = get(&current, endpoint, repo, token).await?;
+        url = next_link(res.headers());
+        let page: Vec<Commit> = res.json().await.map_err(Error::fetch)?;
+        commits.extend(page);
+    }
+    Ok(Revisions {
+        branches: refs.branches,
+        tags: refs.tags,
+        commits,
+    })
+}
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures::future::BoxFuture;
+    use futures::FutureExt;
+
+    use super::*;
+    use crate::http::{Headers, HttpBackend, HttpResponse};
+
+    /// Answers HEAD requests of the hub: a rename redirect, then the redirect to the CDN.
+    #[derive(Default)]
+    struct Hub {
+        requests: Mutex<Vec<String>>,
+    }
+
+    impl HttpBackend for Hub {
+        fn get<'a>(
+            &'a self,
+            _: &'a str,
+            _: &'a Headers,
+        ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+            unreachable!()
+        }
+
+        fn head<'a>(
+            &'a self,
+            url: &'a str,
+            headers: &'a Headers,
+        ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+            self.requests.lock().unwrap().push(url.to_string());
+            assert_eq!(
+                headers,
+                &vec![("authorization".to_string(), "Bearer token".to_string())]
+            );
+            let headers = match url.contains("/old/") {
+                true => vec![(
+                    "location".to_string(),
+                    "/new/repo/resolve/main/a.bin".to_string(),
+                )],
+                false => vec![
+                    (
+                        "location".to_string(),
+                        "https://cdn.example.com/a.bin".to_string(),
+                    ),
+                    ("x-linked-size".to_string(), "42".to_string()),
+                    ("x-linked-etag".to_string(), "\"abc\"".to_string()),
+                ],
+            };
+            async move {
+                Ok(HttpResponse {
+                    status: 302,
+                    headers,
+                    body: futures::StreamExt::boxed(futures::stream::empty()),
+                })
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn file_metadata_follows_renames_through_the_backend() {
+        let hub = Arc::new(Hub::default());
+        let options = DownloadOptions {
+            http: hub.clone(),
+            ..DownloadOptions::default()
+        };
+        let url = "https://hub.example.com/old/repo/resolve/main/a.bin";
+        let metadata = file_metadata(
+            url,
+            "https://hub.example.com",
+            "old/repo",
+            Some("token"),
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(metadata.size, Some(42));
+        assert_eq!(metadata.etag.as_deref(), Some("abc"));
+        assert_eq!(
+            *hub.requests.lock().unwrap(),
+            [url, "https://hub.example.com/new/repo/resolve/main/a.bin"]
+        );
+    }
 }
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use reqwest::header::ETAG;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::checksum::sha256_file;
+use crate::error::Error;
+use crate::huggingface::{check_response, encode_segment, with_token};
+use crate::model_manager::{list_files, HUGGINGFACE_ENDPOINT};
+
+#[derive(Clone, Debug)]
+pub struct UploadOptions {
+    pub token: String,
+    pub endpoint: Option<String>,
+    /// Branch to commit to, `main` by default.
+    pub revision: Option<String>,
+    /// Visibility of repos created by [`create_repo`].
+    pub private: bool,
+}
+
+impl UploadOptions {
+    pub fn new(token: impl ToString) -> Self {
+        Self {
+            token: token.to_string(),
+            endpoint: None,
+            revision: None,
+            private: false,
+        }
+    }
+
+    fn endpoint(&self) -> &str {
+        self.endpoint
+            .as_deref()
+            .unwrap_or(HUGGINGFACE_ENDPOINT)
+            .trim_end_matches('/')
+    }
+
+    fn revision(&self) -> String {
+        encode_segment(self.revision.as_deref().unwrap_or("main"))
+    }
+}
+
+/// Creates a model repo, an existing one is not an error.
+pub async fn create_repo(repo: &str, options: &UploadOptions) -> Result<(), Error> {
+    let (organization, name) = match repo.split_once('/') {
+        Some((organization, name)) => (Some(organization), name),
+        None => (None, repo),
+    };
+    let res = with_token(
+        Client::new().post(format!("{}/api/repos/create", options.endpoint())),
+        Some(&options.token),
+    )
+    .json(&json!({
+        "type": "model",
+        "name": name,
+        "organization": organization,
+        "private": options.private,
+    }))
+    .send()
+    .await
+    .map_err(Error::fetch)?;
+    if res.status() == StatusCode::CONFLICT {
+        return Ok(());
+    }
+    check_response(res, options.endpoint(), repo).map(|_| ())
+}
+
+/// Uploads every file below `folder`, keeping the directory structure.
+pub async fn upload_folder(
+    repo: &str,
+    folder: &Path,
+    message: &str,
+    options: &UploadOptions,
+) -> Result<String, Error> {
+    let mut files = vec![];
+    for file in list_files(folder) {
+        let path = file
+            .strip_prefix(folder)
+            .map_err(Error::pathbuf_custom)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        files.push((file, path));
+    }
+    upload_files(repo, &files, message, options).await
+}
+
+/// Commits `(local file, path in repo)` pairs in one commit and returns the commit SHA. Files the
+/// hub wants in LFS are uploaded to LFS storage first.
+pub async fn upload_files(
+    repo: &str,
+    files: &[(PathBuf, String)],
+    message: &str,
+    options: &UploadOptions,
+) -> Result<String, Error> {
+    let modes = preupload(repo, files, options).await?;
+    let mut lines = vec![json!({
+        "key": "header",
+        "value": { "summary": message, "description": "" },
+    })];
+    let mut lfs = vec![];
+    for (local, path) in files {
+        if modes.get(path).map(|v| v.as_str()) == Some("lfs") {
+            let object = LfsObject {
+                oid: sha256_file(local)?,
+                size: std::fs::metadata(local).map_err(Error::open_file)?.len(),
+            };
+            lines.push(json!({
+                "key": "lfsFile",
+                "value": { "path": path, "algo": "sha256", "oid": object.oid, "size": object.size },
+            }));
+            lfs.push((local, object));
+        } else {
+            let content = std::fs::read(local).map_err(Error::open_file)?;
+            lines.push(json!({
+                "key": "file",
+                "value": {
+                    "path": path,
+                    "encoding": "base64",
+                    "content": base64::engine::general_purpose::STANDARD.encode(content),
+                },
+            }));
+        }
+    }
+    upload_lfs(repo, &lfs, options).await?;
+
+    let body = lines
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let res = with_token(
+        Client::new().post(format!(
+            "{}/api/models/{repo}/commit/{}",
+            options.endpoint(),
+            options.revision()
+        )),
+        Some(&options.token),
+    )
+    .header("content-type", "application/x-ndjson")
+    .body(body)
+    .send()
+    .await
+    .map_err(Error::fetch)?;
+    let commit: CommitResponse = check_response(res, options.endpoint(), repo)?
+        .json()
+        .await
+        .map_err(Error::fetch)?;
+    Ok(commit.commit_oid)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CommitResponse {
+    commit_oid: String,
+}
+
+#[derive(Deserialize)]
+struct PreuploadResponse {
+    files: Vec<PreuploadFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PreuploadFile {
+    path: String,
+    upload_mode: String,
+}
+
+/// Asks the hub which files go to LFS, keyed by path in repo.
+async fn preupload(
+    repo: &str,
+    files: &[(PathBuf, String)],
+    options: &UploadOptions,
+) -> Result<HashMap<String, String>, Error> {
+    let mut entries = vec![];
+    for (local, path) in files {
+        let mut sample = vec![0; 512];
+        let mut file = std::fs::File::open(local).map_err(Error::open_file)?;
+        let read = file.read(&mut sample).map_err(Error::open_file)?;
+        sample.truncate(read);
+        entries.push(json!({
+            "path": path,
+            "size": std::fs::metadata(local).map_err(Error::open_file)?.len(),
+            "sample": base64::engine::general_purpose::STANDARD.encode(sample),
+        }));
+    }
+    let res = with_token(
+        Client::new().post(format!(
+            "{}/api/models/{repo}/preupload/{}",
+            options.endpoint(),
+            options.revision()
+        )),
+        Some(&options.token),
+    )
+    .json(&json!({ "files": entries }))
+    .send()
+    .await
+    .map_err(Error::fetch)?;
+    let res: PreuploadResponse = check_response(res, options.endpoint(), repo)?
+        .json()
+        .await
+        .map_err(Error::fetch)?;
+    Ok(res
+        .files
+        .into_iter()
+        .map(|v| (v.path, v.upload_mode))
+        .collect())
+}
+
+#[derive(Clone, Serialize)]
+struct LfsObject {
+    oid: String,
+    size: u64,
+}
+
+#[derive(Deserialize)]
+struct BatchResponse {
+    objects: Vec<BatchObject>,
+}
+
+#[derive(Deserialize)]
+struct BatchObject {
+    oid: String,
+    /// Missing if the hub already has the object.
+    actions: Option<BatchActions>,
+}
+
+#[derive(Deserialize)]
+struct BatchActions {
+    upload: Option<LfsAction>,
+    verify: Option<LfsAction>,
+}
+
+#[derive(Deserialize)]
+struct LfsAction {
+    href: String,
+    #[serde(default)]
+    header: HashMap<String, String>,
+}
+
+async fn upload_lfs(
+    repo: &str,
+    objects: &[(&PathBuf, LfsObject)],
+    options: &UploadOptions,
+) -> Result<(), Error> {
+    if objects.is_empty() {
+        return Ok(());
+    }
+    let res = with_token(
+        Client::new().post(format!(
+            "{}/{repo}.git/info/lfs/objects/batch",
+            options.endpoint()
+        )),
+        Some(&options.token),
+    )
+    .header("accept", "application/vnd.git-lfs+json")
+    .header("content-type", "application/vnd.git-lfs+json")
+    .json(&json!({
+        "operation": "upload",
+        "transfers": ["basic", "multipart"],
+        "hash_algo": "sha256",
+        "objects": objects.iter().map(|v| &v.1).collect::<Vec<_>>(),
+    }))
+    .send()
+    .await
+    .map_err(Error::fetch)?;
+    let batch: BatchResponse = check_response(res, options.endpoint(), repo)?
+        .json()
+        .await
+        .map_err(Error::fetch)?;
+
+    for object in batch.objects {
+        let Some(actions) = object.actions else {
+            continue;
+        };
+        let (local, lfs) = objects
+            .iter()
+            .find(|v| v.1.oid == object.oid)
+            .ok_or_else(|| Error::fetch_custom("LFS batch returned an unknown object"))?;
+        if let Some(upload) = actions.upload {
+            match upload.header.get("chunk_size") {
+                Some(chunk_size) => upload_multipart(local, lfs, &upload, chunk_size).await?,
+                None => {
+                    let file = tokio::fs::File::open(local)
+                        .await
+                        .map_err(Error::open_file)?;
+                    Client::new()
+                        .put(&upload.href)
+                        .body(file)
+                        .send()
+                        .await
+                        .map_err(Error::fetch)?
+                        .error_for_status()
+                        .map_err(Error::fetch)?;
+                }
+            }
+        }
+        if let Some(verify) = actions.verify {
+            let mut request = Client::new().post(&verify.href).json(lfs);
+            for (key, value) in &verify.header {
+                request = request.header(key, value);
+            }
+            request
+                .send()
+                .await
+                .map_err(Error::fetch)?
+                .error_for_status()
+                .map_err(Error::fetch)?;
+        }
+    }
+    Ok(())
+}
+
+/// Large objects are uploaded in `chunk_size` parts to the numbered URLs of the upload header,
+/// then completed with the ETags of all parts.
+async fn upload_multipart(
+    local: &Path,
+    lfs: &LfsObject,
+    upload: &LfsAction,
+    chunk_size: &str,
+) -> Result<(), Error> {
+    let chunk_size: usize = chunk_size
+        .parse()
+        .map_err(|_| Error::fetch_custom("Invalid LFS chunk size"))?;
+    let mut part_urls = upload
+        .header
+        .iter()
+        .filter_map(|(k, v)| Some((k.parse::<usize>().ok()?, v)))
+        .collect::<Vec<_>>();
+    part_urls.sort_by_key(|v| v.0);
+
+    let mut file = std::fs::File::open(local).map_err(Error::open_file)?;
+    let mut parts = vec![];
+    for (number, url) in part_urls {
+        let mut chunk = vec![0; chunk_size];
+        let mut read = 0;
+        while read < chunk_size {
+            match file.read(&mut chunk[read..]).map_err(Error::open_file)? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        chunk.truncate(read);
+        let res = Client::new()
+            .put(url)
+            .body(chunk)
+            .send()
+            .await
+            .map_err(Error::fetch)?
+            .error_for_status()
+            .map_err(Error::fetch)?;
+        let etag = res
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| Error::fetch_custom("LFS part upload returned no ETag"))?;
+        parts.push(json!({ "partNumber": number, "etag": etag }));
+    }
+    Client::new()
+        .post(&upload.href)
+        .json(&json!({ "oid": lfs.oid, "parts": parts }))
+        .send()
+        .await
+        .map_err(Error::fetch)?
+        .error_for_status()
+        .map_err(Error::fetch)?;
+    Ok(())
+}
@@ -0,0 +1,536 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
+use futures::{stream, StreamExt};
+use lz4_flex::frame::FrameDecoder;
+use reqwest::header::{AUTHORIZATION, RANGE};
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::http::{BoxFuture, Headers, HttpBackend, HttpResponse};
+
+/// A file stored in Xet, reported by the resolve endpoint along with its redirect.
+#[derive(Clone, Debug)]
+pub(crate) struct XetFile {
+    pub(crate) hash: String,
+    /// Hands out the URL of the content addressed storage (CAS) and a token for reading it.
+    pub(crate) refresh_route: String,
+}
+
+impl XetFile {
+    /// `None` for files outside of Xet. The route comes from the `xet-auth` link, older hubs
+    /// send it in a header of its own.
+    pub(crate) fn from_response(url: &Url, res: &HttpResponse) -> Option<Self> {
+        let hash = res.header("x-xet-hash")?.to_string();
+        let link = res
+            .headers
+            .iter()
+            .filter(|v| v.0.eq_ignore_ascii_case("link"))
+            .flat_map(|v| v.1.split(','))
+            .find(|v| v.contains("rel=\"xet-auth\""))
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().trim_start_matches('<').trim_end_matches('>'));
+        let route = link.or_else(|| res.header("x-xet-refresh-route"))?;
+        Some(Self {
+            hash,
+            refresh_route: url.join(route).ok()?.to_string(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Reconstruction {
+    offset_into_first_range: u64,
+    terms: Vec<Term>,
+    fetch_info: HashMap<String, Vec<FetchInfo>>,
+}
+
+/// The file continues with the chunks `range` of the xorb `hash`.
+#[derive(Deserialize)]
+struct Term {
+    hash: String,
+    unpacked_length: u64,
+    range: ChunkRange,
+}
+
+/// Chunk indexes of a xorb, `end` is exclusive.
+#[derive(Clone, Copy, Deserialize)]
+struct ChunkRange {
+    start: usize,
+    end: usize,
+}
+
+/// Where the chunks `range` of a xorb are fetched from, `url_range` is a byte range ending
+/// inclusively like the `Range` header.
+#[derive(Deserialize)]
+struct FetchInfo {
+    range: ChunkRange,
+    url: String,
+    url_range: ByteRange,
+}
+
+#[derive(Deserialize)]
+struct ByteRange {
+    start: u64,
+    end: u64,
+}
+
+/// Answers the GET requests of one Xet-backed file with its content, reconstructed from the
+/// chunks the CAS lists for it, and sends every other request to `inner`. The downloader writes
+/// and continues the `.part` file like for any other response.
+pub(crate) struct XetBackend {
+    inner: Arc<dyn HttpBackend>,
+    url: String,
+    file: XetFile,
+    size: Option<u64>,
+    /// Authorize the refresh route, the CAS gets its own token.
+    headers: Headers,
+}
+
+impl XetBackend {
+    pub(crate) fn new(
+        inner: Arc<dyn HttpBackend>,
+        url: &str,
+        file: XetFile,
+        size: Option<u64>,
+        headers: Headers,
+    ) -> Self {
+        Self {
+            inner,
+            url: url.to_string(),
+            file,
+            size,
+            headers,
+        }
+    }
+
+    /// The content from `start` on, a range needs the size of the file to be known.
+    async fn reconstruct(&self, start: u64) -> Result<HttpResponse, Error> {
+        let res = ok(
+            self.inner
+                .get(&self.file.refresh_route, &self.headers)
+                .await?,
+            &self.file.refresh_route,
+        )?;
+        let (Some(cas), Some(token)) = (
+            res.header("x-xet-cas-url"),
+            res.header("x-xet-access-token"),
+        ) else {
+            return Err(Error::fetch_custom(
+                "the Xet token response has no CAS URL or token",
+            ));
+        };
+        let url = format!(
+            "{}/v1/reconstructions/{}",
+            cas.trim_end_matches('/'),
+            self.file.hash
+        );
+        let mut headers = vec![(AUTHORIZATION.to_string(), format!("Bearer {token}"))];
+        let range = self.size.filter(|size| (1..*size).contains(&start));
+        if let Some(size) = range {
+            headers.push((RANGE.to_string(), format!("bytes={start}-{}", size - 1)));
+        }
+        let body = collect(ok(self.inner.get(&url, &headers).await?, &url)?).await?;
+        let reconstruction: Reconstruction = serde_json::from_slice(&body)
+            .map_err(|e| Error::new("Invalid Xet reconstruction", e))?;
+        let length = reconstruction
+            .terms
+            .iter()
+            .map(|v| v.unpacked_length)
+            .sum::<u64>()
+            .saturating_sub(reconstruction.offset_into_first_range);
+        let skip = reconstruction.offset_into_first_range as usize;
+        let fetch_info = Arc::new(reconstruction.fetch_info);
+        let inner = self.inner.clone();
+        let body =
+            stream::iter(reconstruction.terms.into_iter().enumerate()).then(move |(i, term)| {
+                let (inner, fetch_info) = (inner.clone(), fetch_info.clone());
+                async move {
+                    let bytes = term_bytes(inner.as_ref(), &term, &fetch_info).await?;
+                    Ok(match i {
+                        0 => bytes.slice(skip.min(bytes.len())..),
+                        _ => bytes,
+                    })
+                }
+            });
+        Ok(HttpResponse {
+            status: if range.is_some() { 206 } else { 200 },
+            headers: vec![("content-length".to_string(), length.to_string())],
+            body: Box::pin(body),
+        })
+    }
+}
+
+impl HttpBackend for XetBackend {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a Headers,
+    ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+        match url == self.url {
+            true => Box::pin(self.reconstruct(0)),
+            false => self.inner.get(url, headers),
+        }
+    }
+
+    fn get_range<'a>(
+        &'a self,
+        url: &'a str,
+        start: u64,
+        headers: &'a Headers,
+    ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+        match url == self.url {
+            true => Box::pin(self.reconstruct(start)),
+            false => self.inner.get_range(url, start, headers),
+        }
+    }
+
+    fn head<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a Headers,
+    ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+        self.inner.head(url, headers)
+    }
+
+    fn head_unredirected<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a Headers,
+    ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+        self.inner.head_unredirected(url, headers)
+    }
+}
+
+fn ok(res: HttpResponse, url: &str) -> Result<HttpResponse, Error> {
+    match res.status {
+        400.. => Err(Error::Http {
+            status: res.status,
+            url: url.to_string(),
+        }),
+        _ => Ok(res),
+    }
+}
+
+async fn collect(res: HttpResponse) -> Result<Bytes, Error> {
+    let mut bytes = BytesMut::new();
+    let mut body = res.body;
+    while let Some(chunk) = body.next().await {
+        bytes.extend_from_slice(&chunk?);
+    }
+    Ok(bytes.freeze())
+}
+
+fn invalid(message: impl ToString) -> Error {
+    Error::new("Invalid Xet data", message)
+}
+
+/// Fetches the part of the xorb holding the chunks of `term`, the presigned URL needs no token.
+async fn term_bytes(
+    http: &dyn HttpBackend,
+    term: &Term,
+    fetch_info: &HashMap<String, Vec<FetchInfo>>,
+) -> Result<Bytes, Error> {
+    let info = fetch_info
+        .get(&term.hash)
+        .and_then(|v| {
+            v.iter()
+                .find(|v| v.range.start <= term.range.start && term.range.end <= v.range.end)
+        })
+        .ok_or_else(|| {
+            invalid(format!(
+                "no fetch info for the chunks of xorb {}",
+                term.hash
+            ))
+        })?;
+    let headers = vec![(
+        RANGE.to_string(),
+        format!("bytes={}-{}", info.url_range.start, info.url_range.end),
+    )];
+    let xorb = collect(ok(http.get(&info.url, &headers).await?, &info.url)?).await?;
+    let skip = term.range.start - info.range.start;
+    let bytes = decode_chunks(&xorb, skip, term.range.end - term.range.start)?;
+    match bytes.len() as u64 == term.unpacked_length {
+        true => Ok(bytes.into()),
+        false => Err(invalid(format!(
+            "xorb {} has a chunk of another length",
+            term.hash
+        ))),
+    }
+}
+
+/// The content of `count` chunks after the first `skip`. Every chunk starts with an 8 byte
+/// header: version, compressed length (3 bytes), compression scheme and uncompressed length
+/// (3 bytes), lengths in little endian.
+fn decode_chunks(mut xorb: &[u8], skip: usize, count: usize) -> Result<Vec<u8>, Error> {
+    let length = |v: &[u8]| u32::from_le_bytes([v[0], v[1], v[2], 0]) as usize;
+    let mut content = vec![];
+    for i in 0..skip + count {
+        let header = xorb
+            .get(..8)
+            .ok_or_else(|| invalid("truncated chunk header"))?;
+        let (compressed, scheme, uncompressed) =
+            (length(&header[1..4]), header[4], length(&header[5..8]));
+        let data = xorb
+            .get(8..8 + compressed)
+            .ok_or_else(|| invalid("truncated chunk"))?;
+        xorb = &xorb[8 + compressed..];
+        if i < skip {
+            continue;
+        }
+        let chunk = match scheme {
+            0 => data.to_vec(),
+            1 => lz4(data)?,
+            2 => regroup(&lz4(data)?),
+            v => return Err(invalid(format!("unknown chunk compression {v}"))),
+        };
+        if chunk.len() != uncompressed {
+            return Err(invalid("chunk length differs from its header"));
+        }
+        content.extend_from_slice(&chunk);
+    }
+    Ok(content)
+}
+
+fn lz4(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut content = vec![];
+    FrameDecoder::new(data)
+        .read_to_end(&mut content)
+        .map_err(invalid)?;
+    Ok(content)
+}
+
+/// Undoes the byte grouping of chunks of 4 byte values, which stores byte `j` in group `j % 4`
+/// for LZ4 to find more repetitions. The first `len % 4` groups are a byte longer.
+fn regroup(grouped: &[u8]) -> Vec<u8> {
+    let len = grouped.len();
+    let mut starts = [0; 4];
+    for group in 1..4 {
+        starts[group] = starts[group - 1] + len / 4 + usize::from(group - 1 < len % 4);
+    }
+    (0..len).map(|j| grouped[starts[j % 4] + j / 4]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    use futures::FutureExt;
+    use lz4_flex::frame::FrameEncoder;
+
+    use super::*;
+
+    const CONTENT: &[u8] = b"hello xet world, grouped bytes";
+
+    fn chunk(scheme: u8, content: &[u8]) -> Vec<u8> {
+        let data = match scheme {
+            0 => content.to_vec(),
+            _ => {
+                let content = match scheme {
+                    2 => (0..4)
+                        .flat_map(|g| content.iter().skip(g).step_by(4).copied())
+                        .collect(),
+                    _ => content.to_vec(),
+                };
+                let mut encoder = FrameEncoder::new(vec![]);
+                encoder.write_all(&content).unwrap();
+                encoder.finish().unwrap()
+            }
+        };
+        let length = |v: usize| v.to_le_bytes()[..3].to_vec();
+        [
+            vec![0],
+            length(data.len()),
+            vec![scheme],
+            length(content.len()),
+            data,
+        ]
+        .concat()
+    }
+
+    /// A hub with `CONTENT` in a xorb of three chunks, one per compression scheme, and a
+    /// second of a single uncompressed chunk the file doesn't use.
+    struct Cas {
+        xorb: Vec<u8>,
+        requests: Mutex<Vec<(String, Headers)>>,
+    }
+
+    impl Cas {
+        fn new() -> Self {
+            let xorb = [
+                chunk(0, &CONTENT[..6]),
+                chunk(1, &CONTENT[6..16]),
+                chunk(2, &CONTENT[16..]),
+            ]
+            .concat();
+            Self {
+                xorb,
+                requests: Mutex::default(),
+            }
+        }
+
+        fn respond(&self, url: &str, headers: &Headers) -> Result<HttpResponse, Error> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((url.to_string(), headers.clone()));
+            let range = headers
+                .iter()
+                .find(|v| v.0 == "range")
+                .map(|v| v.1.as_str());
+            let (headers, body) = match url {
+                "https://hub.example.com/api/models/org/repo/xet-read-token/main" => (
+                    vec![
+                        (
+                            "x-xet-cas-url".to_string(),
+                            "https://cas.example.com".to_string(),
+                        ),
+                        ("x-xet-access-token".to_string(), "cas-token".to_string()),
+                    ],
+                    vec![],
+                ),
+                "https://cas.example.com/v1/reconstructions/filehash" => {
+                    let (offset, first) = match range {
+                        Some("bytes=8-29") => (2, 1),
+                        _ => (0, 0),
+                    };
+                    let terms = [(0, 1, 6), (1, 3, 24)][first..]
+                        .iter()
+                        .map(|(start, end, len)| {
+                            format!(
+                                r#"{{"hash": "xorb", "unpacked_length": {len}, "range": {{"start": {start}, "end": {end}}}}}"#
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    let end = self.xorb.len() - 1;
+                    let info = format!(
+                        r#"{{"offset_into_first_range": {offset}, "terms": [{terms}], "fetch_info": {{"xorb": [{{"range": {{"start": 0, "end": 3}}, "url": "https://xorbs.example.com/xorb", "url_range": {{"start": 0, "end": {end}}}}}]}}}}"#
+                    );
+                    (vec![], info.into_bytes())
+                }
+                "https://xorbs.example.com/xorb" => (vec![], self.xorb.clone()),
+                _ => {
+                    return Ok(HttpResponse {
+                        status: 404,
+                        headers: vec![],
+                        body: stream::empty().boxed(),
+                    })
+                }
+            };
+            Ok(HttpResponse {
+                status: 200,
+                headers,
+                body: stream::iter([Ok(Bytes::from(body))]).boxed(),
+            })
+        }
+    }
+
+    impl HttpBackend for Cas {
+        fn get<'a>(
+            &'a self,
+            url: &'a str,
+            headers: &'a Headers,
+        ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+            async move { self.respond(url, headers) }.boxed()
+        }
+
+        fn head<'a>(
+            &'a self,
+            _: &'a str,
+            _: &'a Headers,
+        ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+            unreachable!()
+        }
+    }
+
+    const URL: &str = "https://hub.example.com/org/repo/resolve/main/model.bin";
+
+    fn backend(cas: Arc<Cas>) -> XetBackend {
+        let file = XetFile {
+            hash: "filehash".to_string(),
+            refresh_route: "https://hub.example.com/api/models/org/repo/xet-read-token/main"
+                .to_string(),
+        };
+        let token = vec![(AUTHORIZATION.to_string(), "Bearer hf-token".to_string())];
+        XetBackend::new(cas, URL, file, Some(CONTENT.len() as u64), token)
+    }
+
+    #[tokio::test]
+    async fn files_are_reconstructed_from_chunks() {
+        let cas = Arc::new(Cas::new());
+        let res = backend(cas.clone()).get(URL, &vec![]).await.unwrap();
+        assert_eq!((res.status, res.content_length()), (200, Some(30)));
+        assert_eq!(collect(res).await.unwrap(), CONTENT);
+
+        let requests = cas.requests.lock().unwrap();
+        let authorization = |i: usize| {
+            let headers = &requests[i].1;
+            headers
+                .iter()
+                .find(|v| v.0 == "authorization")
+                .map(|v| v.1.clone())
+        };
+        // the hub token stays with the hub and the presigned xorb URLs get none
+        assert_eq!(authorization(0).as_deref(), Some("Bearer hf-token"));
+        assert_eq!(authorization(1).as_deref(), Some("Bearer cas-token"));
+        assert!(requests[2..]
+            .iter()
+            .all(|v| v.0.starts_with("https://xorbs.")));
+        assert_eq!(authorization(2), None);
+    }
+
+    #[tokio::test]
+    async fn reconstructions_continue_from_a_byte() {
+        let cas = Arc::new(Cas::new());
+        let res = backend(cas).get_range(URL, 8, &vec![]).await.unwrap();
+        assert_eq!((res.status, res.content_length()), (206, Some(22)));
+        assert_eq!(collect(res).await.unwrap(), CONTENT[8..]);
+    }
+
+    #[test]
+    fn regrouping_restores_the_byte_order() {
+        for len in 0..9 {
+            let content = (0..len).collect::<Vec<u8>>();
+            let grouped = (0..4)
+                .flat_map(|g| content.iter().skip(g).step_by(4).copied())
+                .collect::<Vec<_>>();
+            assert_eq!(regroup(&grouped), content);
+        }
+    }
+
+    #[test]
+    fn xet_files_are_read_from_the_resolve_response() {
+        let url = Url::parse(URL).unwrap();
+        let res = |headers: &[(&str, &str)]| HttpResponse {
+            status: 302,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: stream::empty().boxed(),
+        };
+        let link = "<https://cas.example.com/v1/reconstructions/h>; rel=\"xet-reconstruction-info\", <https://hub.example.com/api/models/org/repo/xet-read-token/abc>; rel=\"xet-auth\"";
+        let file =
+            XetFile::from_response(&url, &res(&[("X-Xet-Hash", "h"), ("Link", link)])).unwrap();
+        assert_eq!(file.hash, "h");
+        assert_eq!(
+            file.refresh_route,
+            "https://hub.example.com/api/models/org/repo/xet-read-token/abc"
+        );
+        let route = (
+            "X-Xet-Refresh-Route",
+            "/api/models/org/repo/xet-read-token/abc",
+        );
+        let file = XetFile::from_response(&url, &res(&[("X-Xet-Hash", "h"), route])).unwrap();
+        assert_eq!(
+            file.refresh_route,
+            "https://hub.example.com/api/models/org/repo/xet-read-token/abc"
+        );
+        assert!(XetFile::from_response(&url, &res(&[route])).is_none());
+    }
+}
@@ -0,0 +1,190 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Download,
+    Update,
+    Stage,
+    Apply,
+}
+
+/// An operation writing into a model directory. It is recorded before the first byte is written
+/// and removed once the directory is consistent again, so one left behind was interrupted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub operation: Operation,
+    pub ident: String,
+    pub version: String,
+    /// Directory the operation writes into.
+    pub target: PathBuf,
+    /// Unix timestamp.
+    pub started: i64,
+}
+
+impl JournalEntry {
+    pub(crate) fn new(operation: Operation, ident: &str, version: &str, target: &Path) -> Self {
+        Self {
+            operation,
+            ident: ident.to_string(),
+            version: version.to_string(),
+            target: target.to_path_buf(),
+            started: Utc::now().timestamp(),
+        }
+    }
+}
+
+pub(crate) fn read(path: &Path) -> Option<JournalEntry> {
+    toml::from_str(&std::fs::read_to_string(path).ok()?).ok()
+}
+
+pub(crate) fn begin(path: &Path, entry: &JournalEntry) -> Result<(), Error> {
+    write(path, entry)
+}
+
+fn write(path: &Path, entry: &impl Serialize) -> Result<(), Error> {
+    std::fs::create_dir_all(path.parent().unwrap_or(path)).map_err(Error::write_file)?;
+    let content = toml::to_string(entry).map_err(Error::config)?;
+    // a half-written entry would be as bad as none
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, content).map_err(Error::write_path(&tmp))?;
+    std::fs::rename(&tmp, path).map_err(Error::write_path(path))
+}
+
+pub(crate) fn finish(path: &Path) -> Result<(), Error> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::write_file(e)),
+        _ => Ok(()),
+    }
+}
+
+/// Renames done while cleaning the model directory, recorded before each of them so an
+/// interrupted cleanup can be undone.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct CleanJournal {
+    #[serde(default)]
+    pub moves: Vec<Move>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Move {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl CleanJournal {
+    pub(crate) fn read(path: &Path) -> Option<Self> {
+        toml::from_str(&std::fs::read_to_string(path).ok()?).ok()
+    }
+
+    /// Journals the rename before doing it.
+    pub(crate) fn rename(&mut self, path: &Path, from: &Path, to: &Path) -> Result<(), Error> {
+        self.moves.push(Move {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+        write(path, self)?;
+        std::fs::create_dir_all(to.parent().unwrap_or(to)).map_err(Error::write_file)?;
+        std::fs::rename(from, to).map_err(Error::write_path(to))
+    }
+
+    /// Moves everything back, newest rename first. Directories created in between are only
+    /// replaced while they are empty, the journal is kept if one isn't.
+    pub(crate) fn rollback(&self, path: &Path) -> Result<(), Error> {
+        for step in self.moves.iter().rev() {
+            if !step.to.exists() {
+                continue;
+            }
+            if step.from.exists() {
+                if !crate::model_manager::list_files(&step.from).is_empty() {
+                    return Err(Error::pathbuf_custom(format!(
+                        "can't move {} back, {} isn't empty",
+                        step.to.display(),
+                        step.from.display()
+                    )));
+                }
+                std::fs::remove_dir_all(&step.from).map_err(Error::write_path(&step.from))?;
+            }
+            std::fs::rename(&step.to, &step.from).map_err(Error::write_path(&step.from))?;
+        }
+        finish(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_removed_once_finished() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("journal");
+        let entry = JournalEntry::new(Operation::Update, "model", "2", dir.path());
+        begin(&path, &entry).unwrap();
+        let read = read(&path).unwrap();
+        assert_eq!(read.operation, Operation::Update);
+        assert_eq!(read.version, "2");
+        finish(&path).unwrap();
+        assert!(!path.exists());
+        // finishing twice is fine
+        finish(&path).unwrap();
+    }
+
+    #[test]
+    fn rollback_undoes_the_renames() {
+        let dir = tempfile::tempdir().unwrap();
+        let models = dir.path().join("models");
+        let backup = dir.path().join("backup");
+        let path = dir.path().join("clean");
+        std::fs::create_dir_all(models.join("kept")).unwrap();
+        std::fs::write(models.join("kept").join("weights"), "kept").unwrap();
+        std::fs::write(models.join("stray"), "stray").unwrap();
+
+        let mut journal = CleanJournal::default();
+        journal.rename(&path, &models, &backup).unwrap();
+        std::fs::create_dir_all(&models).unwrap();
+        journal
+            .rename(&path, &backup.join("kept"), &models.join("kept"))
+            .unwrap();
+        // an interrupted process only leaves the journal behind
+        let journal = CleanJournal::read(&path).unwrap();
+        assert_eq!(journal.moves.len(), 2);
+
+        journal.rollback(&path).unwrap();
+        assert!(!path.exists());
+        assert!(!backup.exists());
+        assert_eq!(
+            std::fs::read_to_string(models.join("kept").join("weights")).unwrap(),
+            "kept"
+        );
+        assert_eq!(
+            std::fs::read_to_string(models.join("stray")).unwrap(),
+            "stray"
+        );
+    }
+
+    #[test]
+    fn rollback_keeps_the_journal_if_a_directory_was_written_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let models = dir.path().join("models");
+        let backup = dir.path().join("backup");
+        let path = dir.path().join("clean");
+        std::fs::create_dir_all(&models).unwrap();
+        std::fs::write(models.join("stray"), "stray").unwrap();
+
+        let mut journal = CleanJournal::default();
+        journal.rename(&path, &models, &backup).unwrap();
+        std::fs::create_dir_all(&models).unwrap();
+        std::fs::write(models.join("new"), "new").unwrap();
+
+        assert!(journal.rollback(&path).is_err());
+        assert!(path.exists());
+        assert!(backup.join("stray").exists());
+        assert!(models.join("new").exists());
+    }
+}
@@ -1,4 +1,71 @@
+// wasm32 builds the download core only: sources, resolving them and streaming them through
+// `fetch` with `source::stream_source`. Everything working on the model directory is native,
+// the helpers it shares with the core go unused there.
+#![cfg_attr(target_arch = "wasm32", allow(dead_code))]
+#[cfg(all(feature = "blocking", not(target_arch = "wasm32")))]
+pub mod blocking;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod build;
+#[cfg(all(feature = "bundle", not(target_arch = "wasm32")))]
+pub mod bundle;
+pub mod cancel;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod candle;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod checksum;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod downloader;
 pub mod error;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod events;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod gguf;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod handle;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod health;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod history;
+pub mod hooks;
+pub mod http;
+pub mod huggingface;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod journal;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod limit;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod lockfile;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod logfile;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod manifest;
+pub mod messages;
+#[cfg(all(feature = "metrics", not(target_arch = "wasm32")))]
+pub mod metrics;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod model_manager;
-mod huggingface;
+#[cfg(all(feature = "json", not(target_arch = "wasm32")))]
+pub mod ndjson;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod notify;
+pub(crate) mod paths;
+#[cfg(all(feature = "mdns", not(target_arch = "wasm32")))]
+pub mod peers;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod plan;
+pub mod progress;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod queue;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod registry;
+pub mod report;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod snapshot;
+pub mod source;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod state;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod storage;
+pub(crate) mod sync;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod variants;
@@ -0,0 +1,186 @@
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fs4::TryLockError;
+
+use crate::cancel::CancelToken;
+use crate::error::Error;
+
+const BUCKET: &str = "bandwidth";
+const POLL: Duration = Duration::from_millis(100);
+
+/// Bandwidth and connection budget shared by every process on the machine that uses the same
+/// state directory. Connections are lock files the OS releases if a process dies, the bandwidth
+/// is a token bucket kept in a file.
+#[derive(Clone, Debug)]
+pub struct HostLimit {
+    pub bytes_per_sec: Option<u64>,
+    pub connections: Option<usize>,
+    pub state_dir: PathBuf,
+}
+
+impl Default for HostLimit {
+    fn default() -> Self {
+        Self {
+            bytes_per_sec: None,
+            connections: None,
+            state_dir: std::env::temp_dir().join("model-manager-limits"),
+        }
+    }
+}
+
+impl HostLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bytes_per_sec(mut self, bytes_per_sec: u64) -> Self {
+        self.bytes_per_sec = Some(bytes_per_sec);
+        self
+    }
+
+    /// Transfers open at the same time, across all processes.
+    pub fn connections(mut self, connections: usize) -> Self {
+        self.connections = Some(connections);
+        self
+    }
+
+    pub fn state_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.state_dir = dir.into();
+        self
+    }
+
+    /// Waits for a free connection slot, held until the returned guard is dropped.
+    pub(crate) async fn connection(&self, cancel: &CancelToken) -> Result<Option<File>, Error> {
+        let Some(connections) = self.connections else {
+            return Ok(None);
+        };
+        let mut slots = (0..connections.max(1))
+            .map(|i| open(&self.state_dir.join(format!("connection-{i}.lock"))))
+            .collect::<Result<Vec<_>, _>>()?;
+        loop {
+            for i in 0..slots.len() {
+                match fs4::FileExt::try_lock(&slots[i]) {
+                    Ok(()) => return Ok(Some(slots.swap_remove(i))),
+                    Err(TryLockError::WouldBlock) => {}
+                    Err(TryLockError::Error(e)) => return Err(Error::open_file(e)),
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(POLL) => {}
+                _ = cancel.cancelled() => return Err(Error::Cancelled),
+            }
+        }
+    }
+
+    pub(crate) fn bandwidth(&self) -> Bandwidth<'_> {
+        Bandwidth {
+            limit: self,
+            reserved: 0,
+        }
+    }
+}
+
+/// Bytes a single transfer took out of the shared bucket, taken in slices so the bucket file
+/// isn't locked for every chunk.
+pub(crate) struct Bandwidth<'a> {
+    limit: &'a HostLimit,
+    reserved: u64,
+}
+
+impl Bandwidth<'_> {
+    pub(crate) async fn consume(&mut self, bytes: u64, cancel: &CancelToken) -> Result<(), Error> {
+        let Some(rate) = self.limit.bytes_per_sec.filter(|v| *v > 0) else {
+            return Ok(());
+        };
+        while self.reserved < bytes {
+            let slice = (rate / 10).max(1024);
+            let wait = reserve(&self.limit.state_dir, rate, slice)?;
+            self.reserved += slice;
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = cancel.cancelled() => return Err(Error::Cancelled),
+            }
+        }
+        self.reserved -= bytes;
+        Ok(())
+    }
+}
+
+/// Takes `bytes` out of the bucket and returns how long to wait until they are covered. The
+/// bucket holds up to a second worth of bytes and goes into debt for slices it can't cover.
+fn reserve(dir: &Path, rate: u64, bytes: u64) -> Result<Duration, Error> {
+    let lock = open(&dir.join(format!("{BUCKET}.lock")))?;
+    fs4::FileExt::lock(&lock).map_err(Error::open_path(dir))?;
+    let path = dir.join(BUCKET);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    let rate = rate as f64;
+    // a missing or garbled state starts with a full bucket
+    let (tokens, updated) = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|v| {
+            let (tokens, updated) = v.trim().split_once(' ')?;
+            Some((tokens.parse::<f64>().ok()?, updated.parse::<f64>().ok()?))
+        })
+        .unwrap_or((rate, now));
+    let tokens = (tokens + (now - updated).max(0.0) * rate).min(rate) - bytes as f64;
+    std::fs::write(&path, format!("{tokens} {now}")).map_err(Error::write_path(&path))?;
+    Ok(match tokens < 0.0 {
+        true => Duration::from_secs_f64(-tokens / rate),
+        false => Duration::ZERO,
+    })
+}
+
+fn open(path: &Path) -> Result<File, Error> {
+    std::fs::create_dir_all(path.parent().unwrap_or(path)).map_err(Error::write_file)?;
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(Error::open_path(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserve_waits_once_the_bucket_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(reserve(dir.path(), 1000, 1000).unwrap(), Duration::ZERO);
+        let wait = reserve(dir.path(), 1000, 500).unwrap();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+        // the debt carries over to the next slice
+        let wait = reserve(dir.path(), 1000, 500).unwrap();
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn garbled_state_starts_with_a_full_bucket() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(BUCKET), "garbled").unwrap();
+        assert_eq!(reserve(dir.path(), 1000, 1000).unwrap(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn connections_wait_for_a_free_slot() {
+        let dir = tempfile::tempdir().unwrap();
+        let limit = HostLimit::new().connections(1).state_dir(dir.path());
+        let cancel = CancelToken::default();
+        let slot = limit.connection(&cancel).await.unwrap();
+        assert!(slot.is_some());
+        let waiting = CancelToken::default();
+        waiting.cancel();
+        assert!(matches!(
+            limit.connection(&waiting).await,
+            Err(Error::Cancelled)
+        ));
+        drop(slot);
+        assert!(limit.connection(&cancel).await.unwrap().is_some());
+    }
+}
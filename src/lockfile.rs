@@ -0,0 +1,180 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::checksum::sha256_bytes;
+use crate::error::Error;
+use crate::model_manager::Model;
+
+const FORMAT: u32 = 1;
+
+/// The exact revision and files every model of a manifest resolved to, like `Cargo.lock`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub format: u32,
+    #[serde(default)]
+    pub models: BTreeMap<String, LockedModel>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LockedModel {
+    pub version: String,
+    /// Hash of the model's definition in the manifest, a different one means it drifted.
+    pub definition: String,
+    /// Commit of a Huggingface repo or ETag of an archive, `None` if the server has neither.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    #[serde(default)]
+    pub files: BTreeMap<String, LockedFile>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LockedFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// How a manifest differs from its lockfile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LockDrift {
+    /// In the manifest but not locked.
+    Added(String),
+    /// Locked but gone from the manifest.
+    Removed(String),
+    /// The definition changed since it was locked.
+    Changed(String),
+}
+
+impl Default for Lockfile {
+    fn default() -> Self {
+        Self {
+            format: FORMAT,
+            models: BTreeMap::new(),
+        }
+    }
+}
+
+impl Lockfile {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Lockfile, Error> {
+        let content = std::fs::read_to_string(path).map_err(Error::open_file)?;
+        let lockfile: Lockfile = toml::from_str(&content).map_err(Error::config)?;
+        if lockfile.format != FORMAT {
+            return Err(Error::config(format!(
+                "Unsupported lockfile format {}",
+                lockfile.format
+            )));
+        }
+        Ok(lockfile)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let content = toml::to_string(self).map_err(Error::config)?;
+        std::fs::write(path, content).map_err(Error::write_file)
+    }
+
+    /// Differences between `models` and what's locked, sorted by ident. Empty if the lockfile is
+    /// up to date.
+    pub fn drift(&self, models: &HashMap<String, Model>) -> Result<Vec<LockDrift>, Error> {
+        let mut drift = vec![];
+        for (ident, model) in models {
+            match self.models.get(ident) {
+                None => drift.push(LockDrift::Added(ident.to_string())),
+                Some(locked) if locked.definition != definition_hash(model)? => {
+                    drift.push(LockDrift::Changed(ident.to_string()))
+                }
+                Some(_) => {}
+            }
+        }
+        drift.extend(
+            self.models
+                .keys()
+                .filter(|v| !models.contains_key(*v))
+                .map(|v| LockDrift::Removed(v.to_string())),
+        );
+        drift.sort_by(|a, b| drift_ident(a).cmp(drift_ident(b)));
+        Ok(drift)
+    }
+}
+
+fn drift_ident(drift: &LockDrift) -> &str {
+    match drift {
+        LockDrift::Added(v) | LockDrift::Removed(v) | LockDrift::Changed(v) => v,
+    }
+}
+
+/// Stable across runs, maps are serialized in key order.
+pub(crate) fn definition_hash(model: &Model) -> Result<String, Error> {
+    let value = toml::Value::try_from(model).map_err(Error::config)?;
+    let content = toml::to_string(&value).map_err(Error::config)?;
+    Ok(sha256_bytes(content.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_manager::ModelSource;
+
+    fn model(version: &str) -> Model {
+        Model::new(
+            "model",
+            version,
+            ModelSource::Zip("https://example.com/model.zip".into()),
+        )
+    }
+
+    fn locked(model: &Model) -> LockedModel {
+        LockedModel {
+            version: model.version.clone(),
+            definition: definition_hash(model).unwrap(),
+            revision: None,
+            files: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn drift_is_sorted_by_ident() {
+        let mut lockfile = Lockfile::default();
+        lockfile.models.insert("a".into(), locked(&model("1")));
+        lockfile.models.insert("c".into(), locked(&model("1")));
+        lockfile.models.insert("d".into(), locked(&model("1")));
+        let models = HashMap::from([
+            ("a".to_string(), model("1")),
+            ("b".to_string(), model("1")),
+            ("d".to_string(), model("2")),
+        ]);
+        assert_eq!(
+            lockfile.drift(&models).unwrap(),
+            vec![
+                LockDrift::Added("b".into()),
+                LockDrift::Removed("c".into()),
+                LockDrift::Changed("d".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip_has_no_drift() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("models.lock");
+        let mut lockfile = Lockfile::default();
+        lockfile.models.insert("a".into(), locked(&model("1")));
+        lockfile.write(&path).unwrap();
+        let models = HashMap::from([("a".to_string(), model("1"))]);
+        assert!(Lockfile::from_file(&path)
+            .unwrap()
+            .drift(&models)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn unknown_formats_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("models.lock");
+        std::fs::write(&path, "format = 2\n").unwrap();
+        assert!(Lockfile::from_file(&path).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{SecondsFormat, Utc};
+
+use crate::error::Error;
+use crate::paths;
+use crate::progress::ProgressObserver;
+use crate::sync;
+
+/// A log of every download step, rotated to `<path>.1`, `<path>.2` and so on once it's larger
+/// than `max_size`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFile {
+    pub path: PathBuf,
+    pub max_size: u64,
+    /// Rotated files kept next to the current one.
+    pub keep: usize,
+}
+
+impl LogFile {
+    /// Rotates at 10 MiB and keeps 3 old files.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_size: 10 * 1024 * 1024,
+            keep: 3,
+        }
+    }
+
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    pub fn keep(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        paths::with_suffix(&self.path, &format!(".{n}"))
+    }
+}
+
+/// The open log shared by every observer of a manager and its clones.
+#[derive(Debug)]
+pub(crate) struct LogSink {
+    config: LogFile,
+    file: Mutex<Option<File>>,
+}
+
+impl LogSink {
+    pub(crate) fn new(config: LogFile) -> Self {
+        Self {
+            config,
+            file: Mutex::default(),
+        }
+    }
+
+    /// Logging is best effort, a full disk must not fail the download.
+    fn write(&self, message: &str) {
+        let line = format!(
+            "{} {message}\n",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+        );
+        let mut file = sync::lock(&self.file);
+        let _ = self.write_line(&mut file, &line);
+    }
+
+    fn write_line(&self, file: &mut Option<File>, line: &str) -> Result<(), Error> {
+        let path = &self.config.path;
+        let size = std::fs::metadata(path).map(|v| v.len()).unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > self.config.max_size {
+            *file = None;
+            self.rotate()?;
+        }
+        if file.is_none() {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(Error::write_path(dir))?;
+            }
+            let opened = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(Error::open_path(path))?;
+            *file = Some(opened);
+        }
+        if let Some(file) = file {
+            file.write_all(line.as_bytes())
+                .map_err(Error::write_path(path))?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&self) -> Result<(), Error> {
+        let path = &self.config.path;
+        if self.config.keep == 0 {
+            return remove(path);
+        }
+        remove(&self.config.rotated(self.config.keep))?;
+        for n in (1..self.config.keep).rev() {
+            rename(&self.config.rotated(n), &self.config.rotated(n + 1))?;
+        }
+        rename(path, &self.config.rotated(1))
+    }
+}
+
+fn remove(path: &Path) -> Result<(), Error> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::write_path(path)(e)),
+        _ => Ok(()),
+    }
+}
+
+fn rename(from: &Path, to: &Path) -> Result<(), Error> {
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::write_path(to)(e)),
+        _ => Ok(()),
+    }
+}
+
+/// Writes the lifecycle of downloads to the log, then forwards to `inner`.
+pub(crate) struct LogObserver {
+    pub(crate) inner: Arc<dyn ProgressObserver>,
+    pub(crate) sink: Arc<LogSink>,
+}
+
+impl ProgressObserver for LogObserver {
+    fn on_batch(&self, models: usize, total: Option<u64>) {
+        self.inner.on_batch(models, total);
+        match total {
+            Some(total) => self
+                .sink
+                .write(&format!("batch of {models} models, {total} bytes")),
+            None => self.sink.write(&format!("batch of {models} models")),
+        }
+    }
+
+    fn on_files(&self, model: &str, files: usize, total: Option<u64>) {
+        self.inner.on_files(model, files, total);
+        self.sink.write(&format!("{model}: {files} files"));
+    }
+
+    fn on_queued(&self, model: &str) {
+        self.inner.on_queued(model);
+        self.sink.write(&format!("{model}: queued"));
+    }
+
+    fn on_start(&self, model: &str, file: &str, total: Option<u64>) {
+        self.inner.on_start(model, file, total);
+        match total {
+            Some(total) => self
+                .sink
+                .write(&format!("{model}: started {file}, {total} bytes")),
+            None => self.sink.write(&format!("{model}: started {file}")),
+        }
+    }
+
+    fn on_bytes(&self, model: &str, file: &str, downloaded: u64) {
+        self.inner.on_bytes(model, file, downloaded);
+    }
+
+    fn on_file_done(&self, model: &str, file: &str) {
+        self.inner.on_file_done(model, file);
+        self.sink.write(&format!("{model}: finished {file}"));
+    }
+
+    fn on_retry(&self, model: &str, file: &str, attempt: usize) {
+        self.inner.on_retry(model, file, attempt);
+        self.sink
+            .write(&format!("{model}: retrying {file}, attempt {attempt}"));
+    }
+
+    fn on_unpack(&self, model: &str) {
+        self.inner.on_unpack(model);
+        self.sink.write(&format!("{model}: unpacking"));
+    }
+
+    fn on_model_done(&self, model: &str) {
+        self.inner.on_model_done(model);
+        self.sink.write(&format!("{model}: done"));
+    }
+
+    fn on_error(&self, model: &str, error: &Error) {
+        self.inner.on_error(model, error);
+        self.sink
+            .write(&format!("{model}: failed: {}", error.report()));
+    }
+}
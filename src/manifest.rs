@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::paths;
+
+pub(crate) const MANIFEST: &str = "manifest.json";
+
+/// Every file of an installed model version, written once it's complete. A file that went
+/// missing or has another size makes the model download again, even if its version matches.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: String,
+    /// Commit a Huggingface repo was resolved to.
+    pub revision: Option<String>,
+    /// Repo or archive the files came from, `None` in manifests written before it was recorded.
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Unix timestamp.
+    #[serde(default)]
+    pub downloaded: Option<i64>,
+    /// Tags of the model definition when it was downloaded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Sorted by name.
+    pub files: Vec<ManifestFile>,
+}
+
+/// What's known about the installed version of a model, read from its manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelMetadata {
+    pub version: String,
+    pub revision: Option<String>,
+    pub source: Option<String>,
+    pub downloaded: Option<DateTime<Utc>>,
+    /// Sum of the file sizes, without the manager's own metadata files.
+    pub size: u64,
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Relative to the model directory, with `/` separators.
+    pub name: String,
+    pub size: u64,
+    /// From the checksums of the model definition, or the one the hub reported for LFS files.
+    pub sha256: Option<String>,
+    /// Where the file was downloaded from, the archive for files unpacked from one.
+    pub url: Option<String>,
+}
+
+impl Manifest {
+    pub(crate) fn read(dir: &Path) -> Option<Self> {
+        let content = std::fs::read(dir.join(MANIFEST)).ok()?;
+        serde_json::from_slice(&content).ok()
+    }
+
+    pub(crate) fn write(&self, dir: &Path) -> Result<(), Error> {
+        let path = dir.join(MANIFEST);
+        let content = serde_json::to_vec_pretty(self).map_err(Error::config)?;
+        std::fs::write(&path, content).map_err(Error::write_path(&path))
+    }
+
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|v| v.size).sum()
+    }
+
+    pub fn metadata(&self) -> ModelMetadata {
+        ModelMetadata {
+            version: self.version.clone(),
+            revision: self.revision.clone(),
+            source: self.source.clone(),
+            downloaded: self
+                .downloaded
+                .and_then(|v| Utc.timestamp_opt(v, 0).single()),
+            size: self.size(),
+            tags: self.tags.clone(),
+        }
+    }
+
+    /// Files of the manifest that are missing in `dir` or have another size.
+    pub fn damaged(&self, dir: &Path) -> Vec<&ManifestFile> {
+        self.files
+            .iter()
+            .filter(|file| {
+                let size = paths::relative(&file.name)
+                    .ok()
+                    .and_then(|v| std::fs::metadata(dir.join(v)).ok());
+                size.map(|v| v.len()) != Some(file.size)
+            })
+            .collect()
+    }
+}
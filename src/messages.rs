@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::progress;
+use crate::report::DownloadReport;
+
+/// The text of the terminal output, set with
+/// [`ModelManagerBuilder::messages`](crate::model_manager::ModelManagerBuilder::messages) to
+/// localize or rewrite it. Every method defaults to the English message, emoji and step labels
+/// like `[1/3]` are added around the batch steps.
+pub trait Messages: Send + Sync {
+    /// Label of the bar of a model with a single file.
+    fn downloading(&self, model: &str) -> String {
+        format!("Downloading {model}")
+    }
+
+    fn unpacking(&self, model: &str) -> String {
+        format!("Unpacking {model}")
+    }
+
+    /// Label of the bar of a model downloading several files, once their number is known.
+    fn files_finished(&self, model: &str, finished: usize, files: usize) -> String {
+        format!("{finished}/{files} files of {model}")
+    }
+
+    /// Label of the bar of a model downloading several files before their number is known.
+    fn files_started(&self, model: &str, started: usize) -> String {
+        format!("{started} files of {model}")
+    }
+
+    /// Label of the bar of a batch download.
+    fn models_finished(&self, finished: usize, models: usize) -> String {
+        format!("{finished}/{models} models")
+    }
+
+    /// First step of a batch download.
+    fn resolving(&self, models: usize) -> String {
+        format!("Resolving {models} models...")
+    }
+
+    /// Second step of a batch download, `models` still need downloading.
+    fn processing(&self, models: usize) -> String {
+        format!("Processing {models} models...")
+    }
+
+    /// Last step of a batch download.
+    fn downloading_models(&self) -> String {
+        "Downloading models...".to_string()
+    }
+
+    fn done(&self, report: &DownloadReport) -> String {
+        format!("Done in {}: {report}", progress::elapsed(report.elapsed))
+    }
+
+    /// A model of the resumed queue failed and is tried again after `retry`.
+    fn retrying(&self, model: &str, error: &Error, retry: Duration) -> String {
+        format!("{model}: {}, retrying in {retry:?}", error.report())
+    }
+
+    fn update_check_failed(&self, error: &Error) -> String {
+        format!("Checking for updates failed: {}", error.report())
+    }
+}
+
+/// The English messages.
+pub struct DefaultMessages;
+
+impl Messages for DefaultMessages {}
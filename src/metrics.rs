@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::{Error, ErrorKind};
+use crate::progress::ProgressObserver;
+use crate::sync;
+
+/// Upper bounds of the download duration buckets, in seconds.
+const DURATION_BUCKETS: [f64; 9] = [1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 1800.0, 3600.0];
+
+/// Counters of a manager and its clones, rendered in the Prometheus text format by
+/// [`Metrics::render`].
+#[derive(Debug, Default)]
+pub struct Metrics {
+    bytes: AtomicU64,
+    retries: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    failures: Mutex<BTreeMap<&'static str, u64>>,
+    durations: Mutex<Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Count per bucket of [`DURATION_BUCKETS`], not cumulative.
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Metrics {
+    pub fn bytes_downloaded(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// Calls of `get_model` whose model was already on disk.
+    pub fn cache_hits(&self) -> u64 {
+        self.cache_hits.load(Ordering::Relaxed)
+    }
+
+    pub fn cache_misses(&self) -> u64 {
+        self.cache_misses.load(Ordering::Relaxed)
+    }
+
+    pub fn failures(&self, kind: ErrorKind) -> u64 {
+        let failures = sync::lock(&self.failures);
+        failures.get(kind.as_str()).copied().unwrap_or_default()
+    }
+
+    pub(crate) fn record_cache(&self, hit: bool) {
+        match hit {
+            true => self.cache_hits.fetch_add(1, Ordering::Relaxed),
+            false => self.cache_misses.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// A model download finished after `duration`, failed ones are counted by kind too.
+    pub(crate) fn record_download(&self, duration: Duration, result: &Result<(), Error>) {
+        let seconds = duration.as_secs_f64();
+        {
+            let mut durations = sync::lock(&self.durations);
+            if let Some(i) = DURATION_BUCKETS.iter().position(|v| seconds <= *v) {
+                durations.buckets[i] += 1;
+            }
+            durations.count += 1;
+            durations.sum += seconds;
+        }
+        if let Err(err) = result {
+            self.record_failure(err);
+        }
+    }
+
+    pub(crate) fn record_failure(&self, err: &Error) {
+        *sync::lock(&self.failures)
+            .entry(err.kind().as_str())
+            .or_default() += 1;
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, value: u64| {
+            let _ = writeln!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}"
+            );
+        };
+        counter(
+            "model_manager_downloaded_bytes_total",
+            "Bytes received by downloads.",
+            self.bytes_downloaded(),
+        );
+        counter(
+            "model_manager_download_retries_total",
+            "Retried file downloads.",
+            self.retries(),
+        );
+        counter(
+            "model_manager_cache_hits_total",
+            "get_model calls served from disk.",
+            self.cache_hits(),
+        );
+        counter(
+            "model_manager_cache_misses_total",
+            "get_model calls that needed a download.",
+            self.cache_misses(),
+        );
+
+        let name = "model_manager_download_failures_total";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Failed model downloads by error kind.\n# TYPE {name} counter"
+        );
+        for (kind, count) in sync::lock(&self.failures).iter() {
+            let _ = writeln!(out, "{name}{{kind=\"{kind}\"}} {count}");
+        }
+
+        let name = "model_manager_download_duration_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Time to download a model.\n# TYPE {name} histogram"
+        );
+        let durations = sync::lock(&self.durations);
+        let mut cumulative = 0;
+        for (le, count) in DURATION_BUCKETS.iter().zip(durations.buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", durations.count);
+        let _ = writeln!(out, "{name}_sum {}", durations.sum);
+        let _ = writeln!(out, "{name}_count {}", durations.count);
+        out
+    }
+}
+
+/// Counts received bytes and retries, then forwards to `inner`.
+pub(crate) struct MetricsObserver {
+    pub(crate) inner: Arc<dyn ProgressObserver>,
+    pub(crate) metrics: Arc<Metrics>,
+    /// Bytes of each running file counted so far.
+    pub(crate) files: Mutex<HashMap<(String, String), u64>>,
+}
+
+impl MetricsObserver {
+    fn forget(&self, model: &str) {
+        sync::lock(&self.files).retain(|(m, _), _| m != model);
+    }
+}
+
+impl ProgressObserver for MetricsObserver {
+    fn on_batch(&self, models: usize, total: Option<u64>) {
+        self.inner.on_batch(models, total);
+    }
+
+    fn on_files(&self, model: &str, files: usize, total: Option<u64>) {
+        self.inner.on_files(model, files, total);
+    }
+
+    fn on_queued(&self, model: &str) {
+        self.inner.on_queued(model);
+    }
+
+    fn on_start(&self, model: &str, file: &str, total: Option<u64>) {
+        self.inner.on_start(model, file, total);
+    }
+
+    fn on_bytes(&self, model: &str, file: &str, downloaded: u64) {
+        self.inner.on_bytes(model, file, downloaded);
+        let mut files = sync::lock(&self.files);
+        // the first report is what a resumed file already had on disk
+        let key = (model.to_string(), file.to_string());
+        if let Some(counted) = files.insert(key, downloaded) {
+            let received = downloaded.saturating_sub(counted);
+            self.metrics.bytes.fetch_add(received, Ordering::Relaxed);
+        }
+    }
+
+    fn on_file_done(&self, model: &str, file: &str) {
+        self.inner.on_file_done(model, file);
+        sync::lock(&self.files).remove(&(model.to_string(), file.to_string()));
+    }
+
+    fn on_retry(&self, model: &str, file: &str, attempt: usize) {
+        self.inner.on_retry(model, file, attempt);
+        self.metrics.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_unpack(&self, model: &str) {
+        self.inner.on_unpack(model);
+    }
+
+    fn on_model_done(&self, model: &str) {
+        self.inner.on_model_done(model);
+        self.forget(model);
+    }
+
+    fn on_error(&self, model: &str, error: &Error) {
+        self.inner.on_error(model, error);
+        self.forget(model);
+    }
+}
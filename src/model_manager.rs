@@ -1,216 +1,4758 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+#[cfg(feature = "bundle")]
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::Instant;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use chrono::Utc;
-use console::{style, Emoji};
-use fs_extra::dir::CopyOptions;
+use bytes::Bytes;
+use chrono::{DateTime, TimeZone, Utc};
+use fs4::TryLockError;
 use futures::{stream, StreamExt};
-use indicatif::{HumanDuration, MultiProgress};
+#[cfg(feature = "progress")]
+use indicatif::MultiProgress;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use tokio::task::JoinHandle;
 
-use crate::downloader::download_file;
+#[cfg(feature = "bundle")]
+use crate::bundle;
+use crate::cancel::{within_deadline, CancelToken};
+use crate::candle::ModelRepo;
+use crate::checksum::sha256_file;
+use crate::downloader::{
+    download_bytes, download_changed_files, download_file, plan_files, probe_source,
+    read_checksums, DownloadOptions, RetryPolicy, CHECKSUMS,
+};
 use crate::error::Error;
+use crate::events::{DownloadEvent, EventObserver, EVENT_CAPACITY};
+use crate::gguf::find_gguf;
+use crate::handle::{DownloadHandle, UpdateChecker};
+use crate::health::{check_disk, HealthReport, SourceHealth, SourceStatus};
+use crate::history::{self, DownloadRecord, HistoryStats, Tally};
+use crate::hooks::{HookContext, HookPoint, Hooks};
+use crate::http::HttpBackend;
+use crate::huggingface::fetch_repo_info;
+use crate::journal::{self, CleanJournal, JournalEntry, Operation};
+use crate::limit::HostLimit;
+use crate::lockfile::{definition_hash, LockDrift, LockedFile, LockedModel, Lockfile};
+use crate::logfile::{LogFile, LogObserver, LogSink};
+use crate::manifest::{Manifest, ManifestFile, ModelMetadata, MANIFEST};
+use crate::messages::{DefaultMessages, Messages};
+#[cfg(feature = "metrics")]
+use crate::metrics::{Metrics, MetricsObserver};
+use crate::notify::{DownloadNotification, Notifier};
+use crate::paths;
+#[cfg(feature = "mdns")]
+use crate::peers::{self, Peer};
+use crate::plan::{DownloadPlan, PlannedModel};
+use crate::progress::{self, ProgressObserver, SilentObserver, Verbosity, LOOKING_GLASS, SPARKLE};
+#[cfg(feature = "progress")]
+use crate::progress::{IndicatifObserver, ProgressTheme};
+use crate::queue::{self, QueuedModel};
+use crate::registry::{Registry, RemoteRegistryOptions};
+use crate::report::DownloadReport;
+use crate::source::latest_revision;
+pub use crate::source::{HuggingfaceModel, ModelSource, HUGGINGFACE_ENDPOINT};
+use crate::state::{FileRecord, ModelRecord, StateStore, TomlStateStore};
+use crate::storage::{self, Permissions, Placement, StorageLayout};
+use crate::sync;
+use crate::variants::{list_variants, Variant};
 
-static LOOKING_GLASS: Emoji<'_, '_> = Emoji("🔍  ", "");
-static SPARKLE: Emoji<'_, '_> = Emoji("✨ ", ":-)");
+/// Per model directory, shared by clones so calls for the same model wait for each other.
+type InFlight = Arc<Mutex<HashMap<PathBuf, Arc<AsyncMutex<()>>>>>;
+type Prefetch = JoinHandle<Result<PathBuf, Error>>;
 
 #[derive(Clone)]
 pub struct ModelManager {
     model_path: PathBuf,
+    /// Further directories models are stored in, after `model_path`.
+    roots: Vec<PathBuf>,
+    placement: Placement,
+    /// Storage root chosen for a model directory that isn't on disk yet.
+    placements: Arc<Mutex<HashMap<PathBuf, PathBuf>>>,
     models: HashMap<String, Model>,
+    /// Models with channels as they were registered, `models` has them on `channel`.
+    definitions: HashMap<String, Model>,
+    channel: Option<String>,
+    aliases: HashMap<String, String>,
+    size_cache: Option<Arc<Mutex<HashMap<PathBuf, u64>>>>,
+    in_flight: InFlight,
+    prefetching: Arc<Mutex<HashMap<String, Prefetch>>>,
+    /// Models of batch downloads that failed with a transient error.
+    failed: Arc<Mutex<Vec<String>>>,
+    max_size: Option<u64>,
+    pinned: HashSet<String>,
+    layout: StorageLayout,
+    concurrency: usize,
+    offline: bool,
+    read_only: bool,
+    /// Where downloads are written before they're moved into the model directories.
+    temp_dir: Option<PathBuf>,
+    permissions: Permissions,
+    check_disk_space: bool,
+    continue_on_error: bool,
+    verbosity: Verbosity,
+    emoji: bool,
+    messages: Arc<dyn Messages>,
+    #[cfg(feature = "progress")]
+    multi_progress: Option<MultiProgress>,
+    #[cfg(feature = "progress")]
+    progress_theme: ProgressTheme,
+    observer: Option<Arc<dyn ProgressObserver>>,
+    notifiers: Vec<Notifier>,
+    hooks: Hooks,
+    log: Option<Arc<LogSink>>,
+    state: Option<Arc<dyn StateStore>>,
+    /// `state` is the [`TomlStateStore`] in the model path.
+    state_file: bool,
+    events: Sender<DownloadEvent>,
+    download_options: DownloadOptions,
+    handle_ctrl_c: bool,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<Metrics>,
 }
 
 impl ModelManager {
     pub fn new() -> Result<ModelManager, Error> {
-        let models = HashMap::new();
-        std::fs::create_dir_all("models").map_err(Error::write_file)?;
+        Self::builder().build()
+    }
 
-        Ok(Self {
-            model_path: PathBuf::from_str("models").map_err(Error::pathbuf_open)?,
-            models,
-        })
+    pub fn new_for_app(app_name: &str) -> Result<ModelManager, Error> {
+        Self::builder().app_name(app_name).build()
     }
 
     pub fn new_custom(path: PathBuf) -> ModelManager {
         Self {
-            model_path: path,
+            model_path: paths::long(path),
+            roots: vec![],
+            placement: Placement::default(),
+            placements: Arc::default(),
             models: HashMap::new(),
+            definitions: HashMap::new(),
+            channel: None,
+            aliases: HashMap::new(),
+            size_cache: None,
+            in_flight: Arc::default(),
+            prefetching: Arc::default(),
+            failed: Arc::default(),
+            max_size: None,
+            pinned: HashSet::new(),
+            layout: StorageLayout::default(),
+            concurrency: DEFAULT_CONCURRENCY,
+            offline: false,
+            read_only: false,
+            temp_dir: None,
+            permissions: Permissions::default(),
+            check_disk_space: true,
+            continue_on_error: false,
+            verbosity: Verbosity::default(),
+            emoji: true,
+            messages: Arc::new(DefaultMessages),
+            #[cfg(feature = "progress")]
+            multi_progress: None,
+            #[cfg(feature = "progress")]
+            progress_theme: ProgressTheme::default(),
+            observer: None,
+            notifiers: vec![],
+            hooks: Hooks::default(),
+            log: None,
+            state: None,
+            state_file: false,
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            download_options: DownloadOptions::default(),
+            handle_ctrl_c: false,
+            #[cfg(feature = "metrics")]
+            metrics: Arc::default(),
         }
     }
 
-    pub fn register_models(&mut self, map: HashMap<String, Model>) {
-        self.models.extend(map)
+    pub fn from_config(path: impl AsRef<Path>) -> Result<ModelManager, Error> {
+        Self::from_registry(Registry::from_file(path)?)
     }
 
-    pub fn get_model(&self, ident: &str) -> Result<(&PathBuf, &Model), Error> {
-        async_std::task::block_on(self.get_model_async(ident))
+    pub fn from_remote_registry(
+        url: &str,
+        options: &RemoteRegistryOptions,
+    ) -> Result<ModelManager, Error> {
+        sync::block_on(Self::from_remote_registry_async(url, options))?
     }
 
-    pub async fn get_model_async(&self, ident: &str) -> Result<(&PathBuf, &Model), Error> {
-        let model = self.models.get(ident).ok_or(Error::ModelNotFound)?;
-        let download_needed = self.check_download_needed(
-            self.model_path.join(&model.directory),
-            model.version.to_string(),
-        );
-        if download_needed {
-            let v = MultiProgress::new();
-            self.create_paths(&vec![(&ident.to_string(), model)])?;
-            download_file(
-                &model.source,
-                ident.to_string(),
-                model.version.to_string(),
-                self.model_path.join(&model.directory),
-                &v,
-            )
-            .await?;
+    pub async fn from_remote_registry_async(
+        url: &str,
+        options: &RemoteRegistryOptions,
+    ) -> Result<ModelManager, Error> {
+        Self::from_registry(Registry::fetch(url, options).await?)
+    }
+
+    pub fn from_registry(registry: Registry) -> Result<ModelManager, Error> {
+        let mut builder = Self::builder();
+        if let Some(path) = &registry.path {
+            builder = builder.model_path(path);
         }
-        Ok((&self.model_path, model))
+        if let Some(layout) = &registry.layout {
+            builder = builder.storage_layout(layout.clone());
+        }
+        if let Some(channel) = &registry.channel {
+            builder = builder.channel(channel);
+        }
+        let mut manager = builder.build()?;
+        manager.register_registry(registry)?;
+        Ok(manager)
     }
 
-    pub fn clean_directory(&self) -> Result<(), Error> {
-        use fs_extra::dir::move_dir;
-        let timestamp = Utc::now().timestamp();
+    /// Opens a directory written by [`ModelManager::write_offline_bundle`], never touching the
+    /// network. Fails if its registry and lockfile don't match.
+    pub fn from_offline_bundle(path: impl AsRef<Path>) -> Result<ModelManager, Error> {
+        let path = path.as_ref();
+        let registry = Registry::from_file(path.join(OFFLINE_REGISTRY))?;
+        let lockfile = Lockfile::from_file(path.join(OFFLINE_LOCKFILE))?;
+        let mut manager = Self::builder()
+            .model_path(path.join(OFFLINE_MODELS))
+            .offline(true)
+            .build()?;
+        manager.register_registry(registry)?;
+        if !manager.lock_drift(&lockfile)?.is_empty() {
+            return Err(Error::config(
+                "The registry of the offline bundle doesn't match its lockfile",
+            ));
+        }
+        Ok(manager)
+    }
 
-        let mut options = CopyOptions::new(); //Initialize default values for CopyOptions
-        options.content_only = true;
-        let mut to = self
-            .model_path
-            .iter()
-            .map(|v| v.to_str())
-            .collect::<Option<Vec<&str>>>()
-            .ok_or_else(|| Error::pathbuf_custom("Path has empty element"))?
+    pub fn register_config(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        self.register_registry(Registry::from_file(path)?)
+    }
+
+    pub fn register_registry(&mut self, registry: Registry) -> Result<(), Error> {
+        self.register_models(registry.models);
+        for (alias, ident) in registry.aliases {
+            self.register_alias(&alias, &ident)?;
+        }
+        Ok(())
+    }
+
+    pub fn builder() -> ModelManagerBuilder {
+        ModelManagerBuilder::default()
+    }
+
+    pub fn model_path(&self) -> &Path {
+        &self.model_path
+    }
+
+    /// Adds a directory models spill over to once the earlier ones are full. Models are found
+    /// in whichever root they're stored in, the manager's own state stays in the model path.
+    pub fn add_storage_root(&mut self, path: impl Into<PathBuf>) {
+        self.roots.push(paths::long(path.into()));
+    }
+
+    pub fn set_placement(&mut self, placement: Placement) {
+        self.placement = placement;
+    }
+
+    /// A manager for the same models whose directories, size limit and eviction are separate
+    /// from this one's and every other namespace's, for downloading on behalf of tenants. It
+    /// stores into `<root>/.namespaces/<name>` of every storage root and shares the
+    /// configuration, observers and a custom state store with this manager. Models with an
+    /// absolute directory are shared by all namespaces.
+    pub fn namespace(&self, name: &str) -> Result<ModelManager, Error> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(Error::config(format!("Invalid namespace: {name}")));
+        }
+        let mut manager = self.clone();
+        manager.model_path = self.model_path.join(NAMESPACES).join(name);
+        manager.roots = self
+            .roots
             .iter()
-            .map(|v| v.to_string())
-            .collect::<Vec<_>>();
-        match to.last_mut() {
-            None => return Err(Error::pathbuf_custom("path is empty")),
-            Some(v) => {
-                v.push('-');
-                v.push_str(timestamp.to_string().as_ref())
-            }
+            .map(|v| v.join(NAMESPACES).join(name))
+            .collect();
+        manager.placements = Arc::default();
+        manager.prefetching = Arc::default();
+        manager.failed = Arc::default();
+        if self.state_file {
+            manager.state = Some(Arc::new(TomlStateStore::new(
+                manager.model_path.join(STATE),
+            )));
+        }
+        if !self.read_only {
+            std::fs::create_dir_all(&manager.model_path)
+                .map_err(Error::write_path(&manager.model_path))?;
+        }
+        Ok(manager)
+    }
+
+    /// Names of the namespaces with a directory in the model path, sorted.
+    pub fn namespaces(&self) -> Vec<String> {
+        let mut names = std::fs::read_dir(self.model_path.join(NAMESPACES))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| entry.path().is_dir())
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    /// The model path followed by the storage roots.
+    pub fn storage_roots(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.model_path.as_path()).chain(self.roots.iter().map(PathBuf::as_path))
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    pub fn cache_sizes(&mut self, enabled: bool) {
+        self.size_cache = enabled.then(Default::default);
+    }
+
+    pub fn set_storage_layout(&mut self, layout: StorageLayout) {
+        self.layout = layout;
+    }
+
+    /// Replaces the terminal progress bars with a custom observer.
+    pub fn set_progress_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.observer = Some(observer);
+    }
+
+    fn observer(&self) -> Arc<dyn ProgressObserver> {
+        let inner = self
+            .observer
+            .clone()
+            .unwrap_or_else(|| self.default_observer());
+        let mut observer: Arc<dyn ProgressObserver> = Arc::new(EventObserver {
+            inner,
+            sender: self.events.clone(),
+        });
+        if let Some(sink) = &self.log {
+            observer = Arc::new(LogObserver {
+                inner: observer,
+                sink: sink.clone(),
+            });
         }
-        let to = PathBuf::from(&to.join("/"));
-        std::fs::create_dir_all(&to).map_err(Error::write_file)?;
-        move_dir(&self.model_path, &to, &options).map_err(Error::write_file_extra)?;
+        #[cfg(feature = "metrics")]
+        let observer = Arc::new(MetricsObserver {
+            inner: observer,
+            metrics: self.metrics.clone(),
+            files: Mutex::default(),
+        });
+        observer
+    }
+
+    /// Writes every download step to `log`, next to whatever the terminal shows.
+    pub fn set_log_file(&mut self, log: LogFile) {
+        self.log = Some(Arc::new(LogSink::new(log)));
+    }
+
+    /// Notifies `notifier` whenever a model download finished or failed.
+    pub fn add_notifier(&mut self, notifier: Notifier) {
+        self.notifiers.push(notifier);
+    }
+
+    /// Counters of this manager and its clones, [`Metrics::render`] gives the Prometheus text
+    /// format.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
 
-        for model in &self.models {
-            let from = &to.join(&model.1.directory);
-            let to = &self.model_path.join(&model.1.directory);
-            std::fs::create_dir_all(to).map_err(Error::write_file)?;
-            move_dir(from, to, &options).map_err(Error::write_file_extra)?;
+    #[cfg(feature = "progress")]
+    fn default_observer(&self) -> Arc<dyn ProgressObserver> {
+        match self.verbosity < Verbosity::Verbose {
+            true => Arc::new(SilentObserver),
+            false => Arc::new(
+                IndicatifObserver::with_theme(
+                    self.multi_progress.clone().unwrap_or_default(),
+                    self.progress_theme.clone(),
+                )
+                .messages(self.messages.clone()),
+            ),
         }
-        std::fs::remove_dir_all(to).map_err(Error::write_file)?;
+    }
+
+    #[cfg(not(feature = "progress"))]
+    fn default_observer(&self) -> Arc<dyn ProgressObserver> {
+        Arc::new(SilentObserver)
+    }
+
+    /// Live events of all downloads started by this manager or its clones.
+    pub fn subscribe(&self) -> Receiver<DownloadEvent> {
+        self.events.subscribe()
+    }
+
+    /// Stops the downloads running on this manager and its clones, they return
+    /// [`Error::Cancelled`] and keep their partial files to resume from.
+    pub fn cancel_downloads(&self) {
+        self.download_options.cancel.cancel();
+    }
+
+    /// Suppresses all terminal output, custom progress observers still get notified.
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.verbosity = match quiet {
+            true => Verbosity::Silent,
+            false => Verbosity::Verbose,
+        };
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.verbosity = verbosity;
+    }
+
+    /// Leaves the emoji out of the status lines, for minimal terminals and log scrapers.
+    pub fn set_emoji(&mut self, enabled: bool) {
+        self.emoji = enabled;
+    }
+
+    /// Replaces the English text of the terminal output, to localize or rewrite it.
+    pub fn set_messages(&mut self, messages: Arc<dyn Messages>) {
+        self.messages = messages;
+    }
+
+    /// Draws download bars into the application's own `MultiProgress`.
+    #[cfg(feature = "progress")]
+    pub fn set_multi_progress(&mut self, multi: MultiProgress) {
+        self.multi_progress = Some(multi);
+    }
+
+    /// Styles the download bars, fails if the theme is invalid.
+    #[cfg(feature = "progress")]
+    pub fn set_progress_theme(&mut self, theme: ProgressTheme) -> Result<(), Error> {
+        theme.validate()?;
+        self.progress_theme = theme;
         Ok(())
     }
 
-    fn check_download_needed(&self, path: PathBuf, version: String) -> bool {
-        let ver = std::fs::read_to_string(path.join("version"));
-        if let Ok(v) = ver {
-            return v != version;
+    pub fn set_continue_on_error(&mut self, enabled: bool) {
+        self.continue_on_error = enabled;
+    }
+
+    pub fn set_endpoint(&mut self, endpoint: Option<String>) {
+        self.download_options.endpoint = endpoint;
+    }
+
+    pub fn set_peers(&mut self, peers: Vec<String>) {
+        self.download_options.peers = peers;
+    }
+
+    /// Looks for mirrors on the LAN for `timeout` and prefers the ones found for downloads,
+    /// replacing the peers set before.
+    #[cfg(feature = "mdns")]
+    pub fn discover_peers(&mut self, timeout: Duration) -> Result<Vec<Peer>, Error> {
+        let peers = sync::block_on(peers::discover(timeout))??;
+        self.set_peers(peers.iter().map(|v| v.url.clone()).collect());
+        Ok(peers)
+    }
+
+    pub fn set_http_backend(&mut self, backend: Arc<dyn HttpBackend>) {
+        self.download_options.http = backend;
+    }
+
+    pub fn set_max_size(&mut self, max_size: Option<u64>) {
+        self.max_size = max_size;
+    }
+
+    /// Never writes to or deletes from the model directory: models are served from disk or fail
+    /// with [`Error::ReadOnly`], as do downloads, updates and cleanups. For model volumes an
+    /// init job populated and that are mounted read-only.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Downloads and unpacks into `dir` and moves the result into the model directory once
+    /// it's complete, e.g. for a scratch disk. On the same filesystem as the model path the
+    /// move is a rename, otherwise files are copied. Partial downloads in it are resumed.
+    pub fn set_temp_dir(&mut self, dir: Option<PathBuf>) {
+        self.temp_dir = dir;
+    }
+
+    /// Mode bits and owner installed models get, e.g. [`Permissions::shared`] for an init
+    /// container running as root and an inference server that doesn't.
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = permissions;
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn writable(&self, ident: &str) -> Result<(), Error> {
+        match self.read_only {
+            true => Err(Error::ReadOnly(ident.to_string())),
+            false => Ok(()),
         }
-        true
     }
 
-    fn create_paths(&self, down: &Vec<(&String, &Model)>) -> Result<(), Error> {
-        for model in down {
-            let path = self.model_path.join(&model.1.directory);
-            let _ = std::fs::remove_dir_all(&path).map_err(Error::write_file);
-            std::fs::create_dir_all(path).map_err(Error::write_file)?;
+    pub fn pin(&mut self, ident: &str) -> Result<(), Error> {
+        let ident = self.resolve(ident).to_string();
+        if !self.models.contains_key(&ident) {
+            return Err(Error::ModelNotFound);
         }
+        self.pinned.insert(ident);
         Ok(())
     }
 
-    pub async fn download_all(&self, processes: usize) -> Result<(), Error> {
-        let started = Instant::now();
-        println!(
-            "{} {}Resolving {} models...",
-            style("[1/3]").bold().dim(),
-            LOOKING_GLASS,
-            self.models.len()
-        );
-        let download = self
+    pub fn unpin(&mut self, ident: &str) {
+        let ident = self.resolve(ident).to_string();
+        self.pinned.remove(&ident);
+    }
+
+    pub fn gc(&self) -> Result<Vec<String>, Error> {
+        self.writable(&self.model_path.display().to_string())?;
+        let evicted = self.gc_plan()?;
+        for ident in &evicted {
+            let model = &self.models[ident];
+            let (root, dir) = self.location(model);
+            remove_model_dir(&root, &dir)?;
+            self.invalidate_size(&self.model_root(model));
+            self.forget_state(ident);
+        }
+        Ok(evicted)
+    }
+
+    /// The models [`ModelManager::gc`] would evict, in eviction order: expired ones first, then
+    /// the least recently used until the directory fits `max_size`.
+    pub fn gc_plan(&self) -> Result<Vec<String>, Error> {
+        let mut evicted = vec![];
+        let mut total = self.total_size_on_disk()?;
+        for (ident, model) in &self.models {
+            let path = self.model_root(model);
+            let installed = self.installed_dir(model);
+            if !self.pinned.contains(ident) && path.exists() && is_expired(&installed, model.ttl) {
+                total = total.saturating_sub(self.cached_size(&path));
+                evicted.push(ident.to_string());
+            }
+        }
+        let max_size = match self.max_size {
+            None => return Ok(evicted),
+            Some(v) => v,
+        };
+        let mut candidates = self
             .models
             .iter()
-            .filter(|m| {
-                self.check_download_needed(
-                    self.model_path.join(&m.1.directory),
-                    m.1.version.to_string(),
-                )
+            .filter(|(ident, _)| !self.pinned.contains(*ident) && !evicted.contains(ident))
+            .map(|(ident, model)| (ident, self.model_root(model)))
+            .filter(|(_, path)| path.exists())
+            .collect::<Vec<_>>();
+        let state = match &self.state {
+            Some(state) => state.all()?,
+            None => vec![],
+        };
+        let last_used = |ident: &str, path: &Path| match state
+            .iter()
+            .find(|v| v.ident == ident)
+            .and_then(|v| v.last_used)
+        {
+            Some(v) => Utc.timestamp_opt(v, 0).single(),
+            None => read_last_used(path),
+        };
+        candidates.sort_by_key(|(ident, path)| last_used(ident, path));
+
+        for (ident, path) in candidates {
+            if total <= max_size {
+                break;
+            }
+            total = total.saturating_sub(self.cached_size(&path));
+            evicted.push(ident.to_string());
+        }
+        Ok(evicted)
+    }
+
+    pub fn size_on_disk(&self, ident: &str) -> Result<u64, Error> {
+        let ident = self.resolve(ident);
+        let model = self.models.get(ident).ok_or(Error::ModelNotFound)?;
+        Ok(self.cached_size(&self.model_root(model)))
+    }
+
+    pub fn total_size_on_disk(&self) -> Result<u64, Error> {
+        let mut total = 0;
+        for root in self.storage_roots().filter(|v| v.exists()) {
+            for entry in std::fs::read_dir(root).map_err(Error::open_file)? {
+                let entry = entry.map_err(Error::open_file)?;
+                // namespaces have their own size limits
+                if entry.file_name() == NAMESPACES {
+                    continue;
+                }
+                let file_type = entry.file_type().map_err(Error::open_file)?;
+                if file_type.is_dir() {
+                    total += self.cached_size(&entry.path());
+                } else if file_type.is_file() {
+                    total += entry.metadata().map_err(Error::open_file)?.len();
+                }
+            }
+        }
+        for model in self.models.values() {
+            if self.layout.model_dir(model).is_absolute() {
+                total += self.cached_size(&self.model_root(model));
+            }
+        }
+        Ok(total)
+    }
+
+    /// Registered and unregistered models by size on disk, largest first, with the size of each
+    /// file. Blob store links count towards a file but only once towards the model.
+    pub fn disk_usage(&self) -> Result<Vec<DiskUsage>, Error> {
+        let mut usage = self
+            .models
+            .iter()
+            .map(|(ident, model)| (ident.to_string(), true, self.model_root(model)))
+            .chain(
+                self.unregistered_directories()?
+                    .into_iter()
+                    .map(|(name, path)| (name, false, path)),
+            )
+            .filter(|v| v.2.exists())
+            .map(|(ident, registered, root)| {
+                // blobs are reached through the snapshots linking them
+                let all = list_files_following(&root)
+                    .into_iter()
+                    .filter(|file| !file.starts_with(root.join("blobs")))
+                    .filter_map(|file| Some((std::fs::metadata(&file).ok()?.len(), file)))
+                    .collect::<Vec<_>>();
+                let mut files = all
+                    .iter()
+                    .filter(|(_, file)| !METADATA_FILES.iter().any(|v| file.ends_with(v)))
+                    .filter_map(|(size, file)| {
+                        Some(FileUsage {
+                            path: file.strip_prefix(&root).ok()?.to_path_buf(),
+                            size: *size,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                files.sort_by(|a, b| b.size.cmp(&a.size).then(a.path.cmp(&b.path)));
+                DiskUsage {
+                    ident,
+                    registered,
+                    size: self.cached_size(&root),
+                    apparent_size: all.iter().map(|v| v.0).sum(),
+                    files,
+                }
             })
             .collect::<Vec<_>>();
-        self.create_paths(&download)?;
-        println!(
-            "{} {}Processing {} models...",
-            style("[2/3]").bold().dim(),
-            LOOKING_GLASS,
-            download.len()
-        );
+        usage.sort_by(|a, b| b.size.cmp(&a.size).then(a.ident.cmp(&b.ident)));
+        Ok(usage)
+    }
 
-        println!(
-            "{} {}Downloading models...",
-            style("[3/3]").bold().dim(),
-            LOOKING_GLASS
-        );
+    fn cached_size(&self, path: &Path) -> u64 {
+        let cache = match &self.size_cache {
+            None => return dir_size(path),
+            Some(v) => v,
+        };
+        if let Some(size) = cache.lock().ok().and_then(|c| c.get(path).copied()) {
+            return size;
+        }
+        let size = dir_size(path);
+        if let Ok(mut cache) = cache.lock() {
+            cache.insert(path.to_path_buf(), size);
+        }
+        size
+    }
 
-        let m = MultiProgress::new();
-        let handles = stream::iter(download)
-            .map(|v| async {
-                download_file(
-                    &v.1.source,
-                    v.0.to_string(),
-                    v.1.version.to_string(),
-                    self.model_path.join(&v.1.directory),
-                    &m,
-                )
-                .await
+    fn invalidate_size(&self, path: &Path) {
+        if let Some(Ok(mut cache)) = self.size_cache.as_ref().map(|c| c.lock()) {
+            cache.retain(|k, _| !(path.starts_with(k) || k.starts_with(path)));
+        }
+    }
+
+    pub fn register_models(&mut self, map: HashMap<String, Model>) {
+        for (ident, model) in map {
+            self.definitions.remove(&ident);
+            if !model.channels.is_empty() {
+                self.definitions.insert(ident.clone(), model.clone());
+            }
+            let model = on_channel(model, self.channel.as_deref());
+            self.models.insert(ident, model);
+        }
+    }
+
+    /// The channel the models follow, `None` for their registered versions.
+    pub fn channel(&self) -> Option<&str> {
+        self.channel.as_deref()
+    }
+
+    /// Follows `channel`, `None` for the registered versions: models defining it are downloaded
+    /// in the version it points at from now on, the others in their registered version. Nothing
+    /// is downloaded or activated, see [`ModelManager::switch_channel`] for that.
+    pub fn set_channel(&mut self, channel: Option<&str>) {
+        self.channel = channel.map(str::to_string);
+        for (ident, model) in &self.definitions {
+            let model = on_channel(model.clone(), channel);
+            self.models.insert(ident.clone(), model);
+        }
+    }
+
+    pub fn switch_channel(&mut self, channel: Option<&str>) -> Result<Vec<String>, Error> {
+        sync::block_on(self.switch_channel_async(channel))?
+    }
+
+    /// Follows `channel` and moves the downloaded models whose version changed with it onto
+    /// their new version, activating it if it's still installed, which needs a versioned layout,
+    /// and downloading it otherwise. Models that aren't downloaded are left for
+    /// [`ModelManager::get_model`]. Returns the models switched, sorted.
+    pub async fn switch_channel_async(
+        &mut self,
+        channel: Option<&str>,
+    ) -> Result<Vec<String>, Error> {
+        let active = self
+            .definitions
+            .keys()
+            .filter_map(|ident| {
+                let version = read_version(&self.active_dir(&self.models[ident]))?;
+                Some((ident.clone(), version))
             })
-            .buffer_unordered(processes);
-        let v = handles.collect::<Vec<Result<(), Error>>>().await;
-        v.into_iter().collect::<Result<Vec<_>, Error>>()?;
-        m.clear().map_err(Error::console_clear)?;
+            .collect::<Vec<_>>();
+        self.set_channel(channel);
+        let mut switched = vec![];
+        for (ident, version) in active {
+            let model = &self.models[&ident];
+            if model.version == version {
+                continue;
+            }
+            self.writable(&ident)?;
+            if self.check_download_needed(model) {
+                self.get_model_async(&ident).await?;
+            } else {
+                let _guard = self.lock_model(model).await?;
+                self.activate(model, &self.model_root(model))?;
+                self.record_state(&ident, model);
+            }
+            switched.push(ident);
+        }
+        switched.sort();
+        Ok(switched)
+    }
 
-        println!("{} Done in {}", SPARKLE, HumanDuration(started.elapsed()));
+    pub fn register_alias(&mut self, alias: &str, ident: &str) -> Result<(), Error> {
+        let ident = self.resolve(ident).to_string();
+        if !self.models.contains_key(&ident) {
+            return Err(Error::ModelNotFound);
+        }
+        if self.models.contains_key(alias) {
+            return Err(Error::AliasConflict(alias.to_string()));
+        }
+        self.aliases.insert(alias.to_string(), ident);
+        Ok(())
+    }
 
+    /// Runs `hook` on the directory of every version of a model that's downloaded from now on,
+    /// like converting its weights or checking that it loads before it's activated. Hooks of
+    /// the same point run in the order they were added, the first failing one fails the
+    /// download with [`Error::Hook`].
+    pub fn add_hook<F>(
+        &mut self,
+        ident: &str,
+        point: HookPoint,
+        hook: impl Fn(HookContext) -> F + Send + Sync + 'static,
+    ) -> Result<(), Error>
+    where
+        F: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let ident = self.resolve(ident).to_string();
+        if !self.models.contains_key(&ident) {
+            return Err(Error::ModelNotFound);
+        }
+        self.hooks.add(&ident, point, hook);
         Ok(())
     }
-}
 
-#[derive(Clone)]
-pub struct Model {
-    pub directory: PathBuf,
-    pub version: String,
-    pub source: ModelSource,
-}
+    async fn run_hooks(
+        &self,
+        point: HookPoint,
+        ident: &str,
+        version: &str,
+        dir: &Path,
+    ) -> Result<(), Error> {
+        let context = HookContext {
+            ident: ident.to_string(),
+            version: version.to_string(),
+            dir: dir.to_path_buf(),
+        };
+        self.hooks.run(point, context).await
+    }
 
-#[derive(Clone)]
-pub enum ModelSource {
-    Huggingface(HuggingfaceModel),
-    Zip(String),
-}
+    pub fn dependencies(&self, ident: &str) -> Result<Vec<(&String, &Model)>, Error> {
+        let mut order = self.with_dependencies(ident)?;
+        order.pop();
+        Ok(order)
+    }
 
-#[derive(Clone)]
-pub struct HuggingfaceModel {
-    pub repo: String,
-    pub files: Vec<String>,
-    pub commit: Option<String>,
-}
+    fn with_dependencies(&self, ident: &str) -> Result<Vec<(&String, &Model)>, Error> {
+        let mut order = vec![];
+        self.collect_dependencies(self.resolve(ident), &mut vec![], &mut order)?;
+        Ok(order)
+    }
 
-impl HuggingfaceModel {
-    pub fn url(&self) -> Vec<(String, String)> {
-        self.files
-            .iter()
-            .map(|file| {
-                (
-                    file.to_string(),
-                    format!(
-                        "https://huggingface.co/{}/resolve/{}/{}",
-                        self.repo,
-                        self.commit.as_ref().unwrap_or(&"main".to_string()),
-                        file
-                    ),
-                )
-            })
-            .collect()
+    fn collect_dependencies<'a>(
+        &'a self,
+        ident: &str,
+        visiting: &mut Vec<String>,
+        order: &mut Vec<(&'a String, &'a Model)>,
+    ) -> Result<(), Error> {
+        let (ident, model) = self
+            .models
+            .get_key_value(self.resolve(ident))
+            .ok_or(Error::ModelNotFound)?;
+        if order.iter().any(|v| v.0 == ident) {
+            return Ok(());
+        }
+        if visiting.contains(ident) {
+            return Err(Error::DependencyCycle(ident.to_string()));
+        }
+        visiting.push(ident.to_string());
+        for dep in &model.dependencies {
+            self.collect_dependencies(dep, visiting, order)?;
+        }
+        visiting.pop();
+        order.push((ident, model));
+        Ok(())
+    }
+
+    fn resolve<'a>(&'a self, ident: &'a str) -> &'a str {
+        match self.models.contains_key(ident) {
+            true => ident,
+            false => self.aliases.get(ident).map_or(ident, |v| v.as_str()),
+        }
+    }
+
+    /// The registered model for `ident` or an alias of it, without downloading anything.
+    pub fn model(&self, ident: &str) -> Option<&Model> {
+        self.models.get(self.resolve(ident))
+    }
+
+    pub fn get_model(&self, ident: &str) -> Result<(PathBuf, &Model), Error> {
+        sync::block_on(self.get_model_async(ident))?
+    }
+
+    pub async fn get_model_async(&self, ident: &str) -> Result<(PathBuf, &Model), Error> {
+        let _running = self.download_options.cancel.enter(self.handle_ctrl_c);
+        let ident = self.resolve(ident);
+        let model = self.models.get(ident).ok_or(Error::ModelNotFound)?;
+        // dependencies first, the requested model is always last
+        let required = self.with_dependencies(ident)?;
+        #[cfg(feature = "metrics")]
+        self.metrics
+            .record_cache(!self.check_download_needed(model));
+        if self.read_only {
+            if let Some((ident, _)) = required.iter().find(|v| self.check_download_needed(v.1)) {
+                return Err(Error::ReadOnly(ident.to_string()));
+            }
+        }
+        let space = self.ensure_disk_space(required.clone()).await;
+        // planning the download failed, it never started
+        #[cfg(feature = "metrics")]
+        if let Err(err) = &space {
+            self.metrics.record_failure(err);
+        }
+        space?;
+        let observer = self.observer();
+        for (ident, model) in required {
+            if self.check_download_needed(model) {
+                observer.on_queued(ident);
+            }
+            self.download_within_deadline(ident, model, observer.as_ref())
+                .await?;
+        }
+        self.touch_last_used(&self.model_root(model))?;
+        Ok((self.active_dir(model), model))
+    }
+
+    pub fn get_model_with_metadata(
+        &self,
+        ident: &str,
+    ) -> Result<(PathBuf, Option<ModelMetadata>), Error> {
+        sync::block_on(self.get_model_with_metadata_async(ident))?
+    }
+
+    /// [`ModelManager::get_model_async`] with the metadata of the version it returned.
+    pub async fn get_model_with_metadata_async(
+        &self,
+        ident: &str,
+    ) -> Result<(PathBuf, Option<ModelMetadata>), Error> {
+        let (path, _) = self.get_model_async(ident).await?;
+        Ok((path, self.metadata(ident)?))
+    }
+
+    pub fn get_model_with_tokenizer(
+        &self,
+        ident: &str,
+    ) -> Result<(PathBuf, TokenizerFiles), Error> {
+        sync::block_on(self.get_model_with_tokenizer_async(ident))?
+    }
+
+    /// [`ModelManager::get_model_async`] with the tokenizer of the model. Its files are taken
+    /// from the model if it has them, otherwise they're downloaded from the tokenizer repo linked
+    /// with [`HuggingfaceModel::tokenizer`] or, if there is none, the model's own repo at the
+    /// same commit.
+    pub async fn get_model_with_tokenizer_async(
+        &self,
+        ident: &str,
+    ) -> Result<(PathBuf, TokenizerFiles), Error> {
+        let (path, model) = self.get_model_async(ident).await?;
+        let missing = || Error::new_option(format!("No tokenizer found for {ident}"));
+        let ModelSource::Huggingface(source) = &model.source else {
+            return Ok((
+                path.clone(),
+                TokenizerFiles::find(&path).ok_or_else(missing)?,
+            ));
+        };
+        if source.tokenizer.is_none() {
+            if let Some(files) = TokenizerFiles::find(&path) {
+                return Ok((path, files));
+            }
+        }
+        let tokenizer = tokenizer_model(source, read_revision(&path));
+        let ident = format!("{}/tokenizer", self.resolve(ident));
+        if self.check_download_needed(&tokenizer) {
+            self.writable(&ident)?;
+            let observer = self.observer();
+            observer.on_queued(&ident);
+            self.download_within_deadline(&ident, &tokenizer, observer.as_ref())
+                .await?;
+        }
+        let files = TokenizerFiles::find(&self.active_dir(&tokenizer)).ok_or_else(missing)?;
+        Ok((path, files))
+    }
+
+    /// Downloads the model like [`ModelManager::get_model`] and returns the GGUF file to load
+    /// with llama.cpp, see [`find_gguf`]. [`check_mmap`](crate::gguf::check_mmap) checks that
+    /// it's ready to be memory-mapped.
+    pub fn get_gguf(&self, ident: &str) -> Result<PathBuf, Error> {
+        sync::block_on(self.get_gguf_async(ident))?
+    }
+
+    pub async fn get_gguf_async(&self, ident: &str) -> Result<PathBuf, Error> {
+        find_gguf(&self.get_model_async(ident).await?.0)
+    }
+
+    /// Downloads the model like [`ModelManager::get_model`] and returns its files the way
+    /// candle loads them, in place of hf-hub's `Api::model`.
+    pub fn repo(&self, ident: &str) -> Result<ModelRepo, Error> {
+        sync::block_on(self.repo_async(ident))?
+    }
+
+    pub async fn repo_async(&self, ident: &str) -> Result<ModelRepo, Error> {
+        Ok(ModelRepo::new(self.get_model_async(ident).await?.0))
+    }
+
+    /// Opens a file of the model, downloading the model first if needed. `file` is relative to
+    /// the model directory with `/` separators, like `onnx/model.onnx`.
+    pub fn open(&self, ident: &str, file: &str) -> Result<File, Error> {
+        let path = sync::block_on(self.repo_async(ident))??.get(file)?;
+        File::open(&path).map_err(Error::open_path(&path))
+    }
+
+    /// [`ModelManager::open`] as a [`tokio::io::AsyncRead`], to stream weights.
+    pub async fn open_async(&self, ident: &str, file: &str) -> Result<tokio::fs::File, Error> {
+        let path = self.repo_async(ident).await?.get(file)?;
+        tokio::fs::File::open(&path)
+            .await
+            .map_err(Error::open_path(&path))
+    }
+
+    /// Memory-maps a file of the model like [`ModelManager::open`] opens it. The manager
+    /// replaces installed files by renaming over them and never writes into them, so updates
+    /// leave the map intact, something else writing to the file doesn't.
+    #[cfg(feature = "mmap")]
+    pub fn mmap(&self, ident: &str, file: &str) -> Result<memmap2::Mmap, Error> {
+        let file = self.open(ident, file)?;
+        // safe as long as nothing outside the manager modifies installed files
+        unsafe { memmap2::Mmap::map(&file) }.map_err(Error::open_file)
+    }
+
+    #[cfg(feature = "mmap")]
+    pub async fn mmap_async(&self, ident: &str, file: &str) -> Result<memmap2::Mmap, Error> {
+        let file = self.open_async(ident, file).await?.into_std().await;
+        unsafe { memmap2::Mmap::map(&file) }.map_err(Error::open_file)
+    }
+
+    /// Downloads a small file like a config, `tokenizer.json` or a label map into memory,
+    /// without writing to the model directory or recording a version. The source is a single
+    /// file of a repo like `hf://org/repo:config.json`, or the URL of the file, which isn't
+    /// unpacked even if it's an archive.
+    pub fn download_to_bytes(&self, source: &ModelSource) -> Result<Bytes, Error> {
+        sync::block_on(self.download_to_bytes_async(source))?
+    }
+
+    pub async fn download_to_bytes_async(&self, source: &ModelSource) -> Result<Bytes, Error> {
+        if self.offline {
+            return Err(Error::Offline(match source {
+                ModelSource::Huggingface(links) => links.repo.to_string(),
+                ModelSource::Zip(url) => url.to_string(),
+            }));
+        }
+        download_bytes(source, &self.download_options).await
+    }
+
+    /// [`ModelManager::get_model_async`] giving up after `deadline` with
+    /// [`Error::DeadlineExceeded`], partial files are kept for the next attempt. Other downloads
+    /// of this manager aren't affected.
+    pub async fn get_model_async_with_deadline(
+        &self,
+        ident: &str,
+        deadline: Duration,
+    ) -> Result<(PathBuf, &Model), Error> {
+        let ident = self.resolve(ident);
+        let model = self.models.get(ident).ok_or(Error::ModelNotFound)?;
+        let _running = self.download_options.cancel.enter(false);
+        let manager = self.controlled();
+        let path = within_deadline(
+            async { Ok(manager.get_model_async(ident).await?.0) },
+            &manager.download_options.cancel,
+            &self.download_options.cancel,
+            deadline,
+            ident,
+        )
+        .await?;
+        Ok((path, model))
+    }
+
+    pub fn model_files(&self, ident: &str) -> Result<Vec<PathBuf>, Error> {
+        let ident = self.resolve(ident);
+        let model = self.models.get(ident).ok_or(Error::ModelNotFound)?;
+        let path = self.active_dir(model);
+        Ok(match &model.source {
+            ModelSource::Huggingface(v) if !v.files.is_empty() => v
+                .files
+                .iter()
+                .map(|file| Ok(path.join(paths::relative(file)?)))
+                .collect::<Result<_, Error>>()?,
+            _ => {
+                let mut files = list_files(&path);
+                files.retain(|file| {
+                    file.parent() != Some(&path)
+                        || !METADATA_FILES.iter().any(|v| file.ends_with(v))
+                });
+                files.sort();
+                files
+            }
+        })
+    }
+
+    /// Downloads a model again even if its version is installed. With `only_changed` the files
+    /// of a Huggingface repo that still have the sha256 or git blob id the hub reports are kept,
+    /// archives are always downloaded completely.
+    pub fn update(&self, ident: &str, only_changed: bool) -> Result<(PathBuf, &Model), Error> {
+        sync::block_on(self.update_async(ident, only_changed))?
+    }
+
+    pub async fn update_async(
+        &self,
+        ident: &str,
+        only_changed: bool,
+    ) -> Result<(PathBuf, &Model), Error> {
+        let _running = self.download_options.cancel.enter(self.handle_ctrl_c);
+        let ident = self.resolve(ident);
+        let model = self.models.get(ident).ok_or(Error::ModelNotFound)?;
+        if self.offline {
+            return Err(Error::Offline(ident.to_string()));
+        }
+        let path = self.model_root(model);
+        let observer = self.observer();
+        let _guard = self.lock_model(model).await?;
+        if only_changed && matches!(model.source, ModelSource::Huggingface(_)) {
+            let installed = self.install_dir(model);
+            std::fs::create_dir_all(&installed).map_err(Error::write_path(&installed))?;
+            self.begin(Operation::Update, ident, &model.version, &installed)?;
+            self.run_hooks(HookPoint::BeforeDownload, ident, &model.version, &installed)
+                .await?;
+            let result = download_changed_files(
+                &model.source,
+                ident.to_string(),
+                model.version.to_string(),
+                installed,
+                observer.as_ref(),
+                &self.download_options,
+            )
+            .await;
+            self.invalidate_size(&path);
+            result?;
+            let installed = self.install_dir(model);
+            verify_checksums(model, &installed)?;
+            self.run_hooks(HookPoint::AfterDownload, ident, &model.version, &installed)
+                .await?;
+            self.write_manifest(model, &installed)?;
+            self.activate(model, &path)?;
+            self.finish(&installed)?;
+        } else {
+            self.download_model(ident, model, observer.as_ref()).await?;
+        }
+        self.touch_last_used(&path)?;
+        Ok((self.active_dir(model), model))
+    }
+
+    pub fn get_model_version(
+        &self,
+        ident: &str,
+        version: &str,
+    ) -> Result<(PathBuf, &Model), Error> {
+        sync::block_on(self.get_model_version_async(ident, version))?
+    }
+
+    pub async fn get_model_version_async(
+        &self,
+        ident: &str,
+        version: &str,
+    ) -> Result<(PathBuf, &Model), Error> {
+        let ident = self.resolve(ident);
+        let model = self.models.get(ident).ok_or(Error::ModelNotFound)?;
+        if version == model.version {
+            return self.get_model_async(ident).await;
+        }
+        if !self.layout.is_versioned() {
+            return Err(Error::new_option(
+                "Multiple versions require a versioned storage layout",
+            ));
+        }
+        let source = model.versions.get(version).ok_or(Error::VersionNotFound)?;
+        let _running = self.download_options.cancel.enter(self.handle_ctrl_c);
+        let root = self.model_root(model);
+        let target = self.layout.install_dir(&root, version);
+        let installed = self.layout.installed_dir(&root, version);
+        let needed = || {
+            read_version(&installed).as_deref() != Some(version)
+                || self.interrupted(&target).is_some()
+        };
+        // served from disk without the lock, so it works read-only
+        let lock = match needed() {
+            true => Some(self.lock_model(model).await?),
+            false => None,
+        };
+        // another process may have finished it while waiting for the lock
+        if lock.is_some() && needed() {
+            self.begin(Operation::Download, ident, version, &target)?;
+            self.discard(&target);
+            self.download_into(
+                ident,
+                source,
+                version,
+                target.clone(),
+                &root,
+                self.observer().as_ref(),
+            )
+            .await?;
+            self.run_hooks(HookPoint::AfterDownload, ident, version, &target)
+                .await?;
+            if matches!(self.layout, StorageLayout::Snapshots { .. }) {
+                storage::store_snapshot(&root, version)?;
+            }
+            self.apply_permissions(model, &root)?;
+            self.finish(&target)?;
+        }
+        self.touch_last_used(&root)?;
+        Ok((installed, model))
+    }
+
+    /// Downloads the highest version of a model matching a semver requirement like `^2.1` or
+    /// `>=1.0, <2`, preferring versions that are already installed. The candidates are the
+    /// model's version and, with a versioned layout, its alternate versions, see
+    /// [`ModelManager::add_remote_versions`] for the ones of a remote registry. Versions like
+    /// `2` or `v2.1` count as `2.0.0` and `2.1.0`, others that aren't semver are skipped.
+    /// Returns the directory and the version chosen.
+    pub fn get_model_matching(
+        &self,
+        ident: &str,
+        requirement: &str,
+    ) -> Result<(PathBuf, String), Error> {
+        sync::block_on(self.get_model_matching_async(ident, requirement))?
+    }
+
+    pub async fn get_model_matching_async(
+        &self,
+        ident: &str,
+        requirement: &str,
+    ) -> Result<(PathBuf, String), Error> {
+        let requirement = VersionReq::parse(requirement).map_err(Error::config)?;
+        let version = self.matching_version(ident, &requirement)?;
+        let (path, _) = self.get_model_version_async(ident, &version).await?;
+        Ok((path, version))
+    }
+
+    fn matching_version(&self, ident: &str, requirement: &VersionReq) -> Result<String, Error> {
+        let model = self
+            .models
+            .get(self.resolve(ident))
+            .ok_or(Error::ModelNotFound)?;
+        let root = self.model_root(model);
+        let mut matching = std::iter::once(&model.version)
+            .chain(model.versions.keys())
+            .filter(|v| self.layout.is_versioned() || **v == model.version)
+            .filter_map(|v| Some((parse_version(v)?, v)))
+            .filter(|(semver, _)| requirement.matches(semver))
+            .map(|(semver, version)| {
+                let installed = read_version(&self.layout.installed_dir(&root, version));
+                (
+                    installed.as_ref() == Some(version),
+                    semver,
+                    version.to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        matching.sort();
+        matching.pop().map(|v| v.2).ok_or(Error::VersionNotFound)
+    }
+
+    /// Adds the versions a remote registry lists for the models registered here as their
+    /// alternate versions, for [`ModelManager::get_model_matching`]. Versions known already
+    /// keep their source. Returns how many were added.
+    pub fn add_remote_versions(
+        &mut self,
+        url: &str,
+        options: &RemoteRegistryOptions,
+    ) -> Result<usize, Error> {
+        let registry = sync::block_on(Registry::fetch(url, options))??;
+        Ok(self.add_versions(registry))
+    }
+
+    pub async fn add_remote_versions_async(
+        &mut self,
+        url: &str,
+        options: &RemoteRegistryOptions,
+    ) -> Result<usize, Error> {
+        let registry = Registry::fetch(url, options).await?;
+        Ok(self.add_versions(registry))
+    }
+
+    fn add_versions(&mut self, registry: Registry) -> usize {
+        let mut added = 0;
+        for (ident, remote) in registry.models {
+            let Some(model) = self.models.get_mut(&ident) else {
+                continue;
+            };
+            let versions = std::iter::once((remote.version, remote.source)).chain(remote.versions);
+            for (version, source) in versions {
+                if version != model.version && !model.versions.contains_key(&version) {
+                    if let Some(definition) = self.definitions.get_mut(&ident) {
+                        definition.versions.insert(version.clone(), source.clone());
+                    }
+                    model.versions.insert(version, source);
+                    added += 1;
+                }
+            }
+        }
+        added
+    }
+
+    pub fn get_model_if_cached(&self, ident: &str) -> Option<(PathBuf, &Model)> {
+        let ident = self.resolve(ident);
+        let model = self.models.get(ident)?;
+        if self.check_download_needed(model) {
+            return None;
+        }
+        let _ = self.touch_last_used(&self.model_root(model));
+        Some((self.active_dir(model), model))
+    }
+
+    pub fn is_downloaded(&self, ident: &str) -> bool {
+        self.models
+            .get(self.resolve(ident))
+            .is_some_and(|model| !self.check_download_needed(model))
+    }
+
+    /// Re-hashes the installed files of a downloaded model against the checksums of its
+    /// definition, the ones its manifest recorded (the sha256 the hub reported for LFS files)
+    /// and, for the `HuggingfaceHub` and `Snapshots` layouts, against the names of its blobs.
+    /// Unlike a mismatch while downloading this leaves the model in place. Returns the files
+    /// checked, `0` if there was nothing to check them against.
+    pub fn verify(&self, ident: &str) -> Result<usize, Error> {
+        self.verify_checked(ident, None)
+    }
+
+    /// Like [`ModelManager::verify`], also checking the files of a Huggingface model against the
+    /// checksums `lockfile` locked.
+    pub fn verify_locked(&self, ident: &str, lockfile: &Lockfile) -> Result<usize, Error> {
+        self.verify_checked(ident, Some(lockfile))
+    }
+
+    fn verify_checked(&self, ident: &str, lockfile: Option<&Lockfile>) -> Result<usize, Error> {
+        let ident = self.resolve(ident);
+        let model = self.models.get(ident).ok_or(Error::ModelNotFound)?;
+        if self.check_download_needed(model) {
+            return Err(Error::VersionNotFound);
+        }
+        let mut expected = model
+            .checksums
+            .iter()
+            .map(|(file, sha256)| (file.clone(), sha256.to_ascii_lowercase()))
+            .collect::<BTreeSet<_>>();
+        let manifest = Manifest::read(&self.installed_dir(model));
+        for file in manifest.into_iter().flat_map(|v| v.files) {
+            if let Some(sha256) = file.sha256 {
+                expected.insert((file.name, sha256.to_ascii_lowercase()));
+            }
+        }
+        // archives are locked as a whole, their files can't be checked against it
+        let locked = lockfile
+            .and_then(|v| v.models.get(ident))
+            .filter(|_| matches!(model.source, ModelSource::Huggingface(_)));
+        for (file, locked) in locked.into_iter().flat_map(|v| &v.files) {
+            if let Some(sha256) = &locked.sha256 {
+                expected.insert((file.clone(), sha256.to_ascii_lowercase()));
+            }
+        }
+        let dir = self.active_dir(model);
+        let mut hashed = HashMap::new();
+        for (file, expected) in expected {
+            let actual = match hashed.get(&file) {
+                Some(v) => v,
+                None => {
+                    let actual = sha256_file(&dir.join(paths::relative(&file)?))?;
+                    hashed.entry(file.clone()).or_insert(actual)
+                }
+            };
+            if *actual != expected {
+                return Err(Error::ChecksumMismatch {
+                    file,
+                    expected,
+                    actual: actual.clone(),
+                });
+            }
+        }
+        let mut checked = hashed.len();
+        if matches!(
+            self.layout,
+            StorageLayout::HuggingfaceHub | StorageLayout::Snapshots { .. }
+        ) {
+            let blobs = self.model_root(model).join("blobs");
+            for blob in list_files(&blobs) {
+                let name = blob.file_name().unwrap_or_default().to_string_lossy();
+                if name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+                    check_checksum(&blobs, &name, &name)?;
+                    checked += 1;
+                }
+            }
+        }
+        Ok(checked)
+    }
+
+    /// Links (or copies) registered Huggingface models out of the local `huggingface_hub` cache
+    /// instead of downloading them again. Returns the imported models.
+    pub fn import_from_hf_cache(&self) -> Result<Vec<String>, Error> {
+        match hf_cache_dir() {
+            Some(cache) => self.import_from_hf_cache_at(&cache),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub fn import_from_hf_cache_at(&self, cache: &Path) -> Result<Vec<String>, Error> {
+        let mut imported = vec![];
+        for (ident, model) in &self.models {
+            let ModelSource::Huggingface(source) = &model.source else {
+                continue;
+            };
+            if !self.check_download_needed(model) {
+                continue;
+            }
+            let Some((sha, files)) = cached_snapshot(cache, source)? else {
+                continue;
+            };
+            self.writable(ident)?;
+            let root = self.model_root(model);
+            let target = self.install_dir(model);
+            let _ = std::fs::remove_dir_all(&target);
+            for (from, file) in files {
+                let to = target.join(paths::relative(&file)?);
+                std::fs::create_dir_all(to.parent().unwrap_or(&target))
+                    .map_err(Error::write_file)?;
+                if std::fs::hard_link(&from, &to).is_err() {
+                    std::fs::copy(&from, &to).map_err(Error::write_file)?;
+                }
+            }
+            std::fs::write(target.join("revision"), &sha).map_err(Error::write_file)?;
+            if source.commit.is_none() {
+                std::fs::write(target.join("reference"), source.reference())
+                    .map_err(Error::write_file)?;
+            }
+            std::fs::write(target.join("version"), &model.version).map_err(Error::write_file)?;
+            self.invalidate_size(&root);
+            verify_checksums(model, &target)?;
+            self.activate(model, &root)?;
+            imported.push(ident.to_string());
+        }
+        Ok(imported)
+    }
+
+    /// Packs the installed files of a downloaded model together with its definition and
+    /// checksums into a `.tar.zst` bundle for [`ModelManager::import_bundle`].
+    #[cfg(feature = "bundle")]
+    pub fn export_bundle(&self, ident: &str, out: impl AsRef<Path>) -> Result<(), Error> {
+        let out = out.as_ref();
+        let file = File::create(out).map_err(Error::write_path(out))?;
+        self.export(ident, BufWriter::new(file))
+    }
+
+    /// Streams the bundle [`ModelManager::export_bundle`] writes to `writer`, e.g. a socket to
+    /// another host. The same installed files always give the same bytes.
+    #[cfg(feature = "bundle")]
+    pub fn export(&self, ident: &str, writer: impl Write) -> Result<(), Error> {
+        let ident = self.resolve(ident);
+        let model = self.models.get(ident).ok_or(Error::ModelNotFound)?;
+        if self.check_download_needed(model) {
+            return Err(Error::VersionNotFound);
+        }
+        bundle::write_bundle(writer, ident, model, &self.active_dir(model))
+    }
+
+    /// Installs a bundle written by [`ModelManager::export_bundle`] without network access,
+    /// registering its model if the ident is unknown. Returns the ident.
+    #[cfg(feature = "bundle")]
+    pub fn import_bundle(&mut self, bundle: impl AsRef<Path>) -> Result<String, Error> {
+        let bundle = bundle.as_ref();
+        let file = File::open(bundle).map_err(Error::open_path(bundle))?;
+        self.import(BufReader::new(file))
+    }
+
+    /// [`ModelManager::import_bundle`] reading the bundle from `reader`, e.g. stdin or a
+    /// socket. Files are unpacked next to the installed version and checked against the hashes
+    /// of the bundle's manifest and the model's checksums before they replace it, as if the model
+    /// had been downloaded.
+    #[cfg(feature = "bundle")]
+    pub fn import(&mut self, reader: impl Read) -> Result<String, Error> {
+        let mut installed = None;
+        let result = bundle::unpack_bundle(reader, |manifest| {
+            let ident = manifest.ident.to_string();
+            let model = match self.models.get(self.resolve(&ident)) {
+                Some(model) if model.version != manifest.model.version => {
+                    return Err(Error::config(format!(
+                        "Bundle has version {} of {ident}, {} is registered",
+                        manifest.model.version, model.version
+                    )))
+                }
+                Some(model) => model.clone(),
+                None => {
+                    // the bundle defines the model, it mustn't place it outside the model path
+                    let dir = self.layout.model_dir(&manifest.model);
+                    if !paths::is_contained(&dir) {
+                        return Err(Error::config(format!(
+                            "Invalid directory in bundle: {}",
+                            dir.display()
+                        )));
+                    }
+                    manifest.model.clone()
+                }
+            };
+            self.writable(&ident)?;
+            let staging = self.import_dir(&model);
+            let _ = std::fs::remove_dir_all(&staging);
+            std::fs::create_dir_all(&staging).map_err(Error::write_path(&staging))?;
+            installed = Some((ident, model, staging.clone()));
+            Ok(staging)
+        });
+        let Some((ident, model, staging)) = installed else {
+            return Err(result.err().unwrap_or(Error::VersionNotFound));
+        };
+        let files = staging.join(bundle::FILES);
+        if let Err(err) = result.and_then(|_| verify_checksums(&model, &files)) {
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(err);
+        }
+        let root = self.model_root(&model);
+        let install = self.install_dir(&model);
+        if install.exists() {
+            std::fs::remove_dir_all(&install).map_err(Error::write_path(&install))?;
+        }
+        std::fs::create_dir_all(install.parent().unwrap_or(&root)).map_err(Error::write_file)?;
+        let result = std::fs::rename(&files, &install).map_err(Error::write_path(&install));
+        let _ = std::fs::remove_dir_all(&staging);
+        self.invalidate_size(&root);
+        result?;
+        self.activate(&model, &root)?;
+        if !self.models.contains_key(self.resolve(&ident)) {
+            self.models.insert(ident.to_string(), model);
+        }
+        Ok(ident)
+    }
+
+    pub fn repo_info(&self, ident: &str, refresh: bool) -> Result<RepoInfo, Error> {
+        sync::block_on(self.repo_info_async(ident, refresh))?
+    }
+
+    /// Model card and Huggingface metadata, cached next to the weights. `refresh` fetches it
+    /// again even if it is cached.
+    pub async fn repo_info_async(&self, ident: &str, refresh: bool) -> Result<RepoInfo, Error> {
+        let ident = self.resolve(ident);
+        let model = self.models.get(ident).ok_or(Error::ModelNotFound)?;
+        let ModelSource::Huggingface(source) = &model.source else {
+            return Err(Error::new_option("Only Huggingface models have repo info"));
+        };
+        let cached = self.model_root(model).join(REPO_INFO);
+        if !refresh || self.offline {
+            if let Some(info) = std::fs::read_to_string(&cached)
+                .ok()
+                .and_then(|v| toml::from_str(&v).ok())
+            {
+                return Ok(info);
+            }
+        }
+        if self.offline {
+            return Err(Error::Offline(ident.to_string()));
+        }
+        let options = self.download_options.for_model(source);
+        let info =
+            fetch_repo_info(options.endpoint(), &source.repo, options.token.as_deref()).await?;
+        if self.read_only {
+            return Ok(info);
+        }
+        std::fs::create_dir_all(self.model_root(model)).map_err(Error::write_file)?;
+        std::fs::write(&cached, toml::to_string(&info).map_err(Error::config)?)
+            .map_err(Error::write_file)?;
+        Ok(info)
+    }
+
+    pub fn lock(&self, previous: Option<&Lockfile>) -> Result<Lockfile, Error> {
+        sync::block_on(self.lock_async(previous))?
+    }
+
+    /// Resolves every registered model to a revision and its files. Models whose definition is
+    /// unchanged since `previous` keep their locked entry, pass `None` to resolve all of them.
+    pub async fn lock_async(&self, previous: Option<&Lockfile>) -> Result<Lockfile, Error> {
+        let mut lockfile = Lockfile::default();
+        for (ident, model) in &self.models {
+            let definition = definition_hash(model)?;
+            let locked = previous
+                .and_then(|v| v.models.get(ident))
+                .filter(|v| v.definition == definition);
+            if let Some(locked) = locked {
+                lockfile.models.insert(ident.to_string(), locked.clone());
+                continue;
+            }
+            if self.offline {
+                return Err(Error::Offline(ident.to_string()));
+            }
+            let revision = match &model.source {
+                ModelSource::Huggingface(v) if v.commit.is_some() => v.commit.clone(),
+                source => latest_revision(source, &self.download_options).await?,
+            };
+            // list the files of the resolved commit, not of whatever the branch points at later
+            let source = match (&model.source, &revision) {
+                (ModelSource::Huggingface(v), Some(commit)) => {
+                    ModelSource::Huggingface(v.clone().commit(commit))
+                }
+                (source, _) => source.clone(),
+            };
+            let files = plan_files(&source, &self.download_options)
+                .await?
+                .into_iter()
+                .map(|file| {
+                    let sha256 = model.checksums.get(&file.name).cloned().or(file.sha256);
+                    let locked = LockedFile {
+                        size: file.size,
+                        sha256,
+                    };
+                    (file.name, locked)
+                })
+                .collect();
+            lockfile.models.insert(
+                ident.to_string(),
+                LockedModel {
+                    version: model.version.to_string(),
+                    definition,
+                    revision,
+                    files,
+                },
+            );
+        }
+        Ok(lockfile)
+    }
+
+    /// How the registered models differ from `lockfile`, empty if it is up to date.
+    pub fn lock_drift(&self, lockfile: &Lockfile) -> Result<Vec<LockDrift>, Error> {
+        lockfile.drift(&self.models)
+    }
+
+    pub fn write_offline_bundle(&self, out: impl AsRef<Path>) -> Result<Lockfile, Error> {
+        sync::block_on(self.write_offline_bundle_async(out))?
+    }
+
+    /// Downloads every registered model into `out` together with the registry and a lockfile of
+    /// what was downloaded, for [`ModelManager::from_offline_bundle`] on air-gapped machines.
+    pub async fn write_offline_bundle_async(
+        &self,
+        out: impl AsRef<Path>,
+    ) -> Result<Lockfile, Error> {
+        let out = out.as_ref();
+        let mut bundle = self.clone();
+        bundle.model_path = out.join(OFFLINE_MODELS);
+        // the reading side doesn't know the layout this manager was configured with
+        bundle.layout = StorageLayout::Flat;
+        bundle.continue_on_error = false;
+        std::fs::create_dir_all(&bundle.model_path)
+            .map_err(Error::write_path(&bundle.model_path))?;
+        bundle.download_all(bundle.concurrency).await?;
+
+        let registry = Registry {
+            path: None,
+            layout: None,
+            // the models are written on the channel, following it again keeps them there
+            channel: self.channel.clone(),
+            models: bundle.models.clone(),
+            aliases: bundle.aliases.clone(),
+        };
+        let content = toml::to_string(&registry).map_err(Error::config)?;
+        std::fs::write(out.join(OFFLINE_REGISTRY), content).map_err(Error::write_file)?;
+        let lockfile = bundle.lock_installed()?;
+        lockfile.write(out.join(OFFLINE_LOCKFILE))?;
+        Ok(lockfile)
+    }
+
+    pub fn vendor(&self, out: impl AsRef<Path>, link: bool) -> Result<Lockfile, Error> {
+        sync::block_on(self.vendor_async(out, link))?
+    }
+
+    /// Places every registered model into `out` in the layout of
+    /// [`ModelManager::write_offline_bundle`], taking them from this manager's directory and
+    /// downloading only the missing ones, so a project can check in the lockfile and stay
+    /// self-contained. With `link` files are hard-linked where possible instead of copied,
+    /// they then must not be modified in place.
+    pub async fn vendor_async(&self, out: impl AsRef<Path>, link: bool) -> Result<Lockfile, Error> {
+        let out = out.as_ref();
+        let mut strict = self.clone();
+        strict.continue_on_error = false;
+        strict.download_all(self.concurrency).await?;
+
+        let mut vendored = ModelManager::new_custom(out.join(OFFLINE_MODELS));
+        vendored.aliases = self.aliases.clone();
+        for (ident, model) in &self.models {
+            let mut model = model.clone();
+            // the project needn't have the mount a model lives on
+            if model.directory.is_absolute() {
+                model.directory = model
+                    .directory
+                    .file_name()
+                    .map(PathBuf::from)
+                    .unwrap_or_default();
+            }
+            let from = self.active_dir(&self.models[ident]);
+            let to = vendored.model_root(&model);
+            let _ = std::fs::remove_dir_all(&to);
+            for file in list_files_following(&from) {
+                let relative = file.strip_prefix(&from).map_err(Error::pathbuf_custom)?;
+                if relative == Path::new("last_used") {
+                    continue;
+                }
+                let target = to.join(relative);
+                std::fs::create_dir_all(target.parent().unwrap_or(&to))
+                    .map_err(Error::write_path(&to))?;
+                // links of a blob store are resolved to the blob
+                let source = std::fs::canonicalize(&file).map_err(Error::open_path(&file))?;
+                if !link || std::fs::hard_link(&source, &target).is_err() {
+                    std::fs::copy(&source, &target).map_err(Error::write_path(&target))?;
+                }
+            }
+            vendored.models.insert(ident.to_string(), model);
+        }
+
+        let registry = Registry {
+            path: None,
+            layout: None,
+            channel: self.channel.clone(),
+            models: vendored.models.clone(),
+            aliases: vendored.aliases.clone(),
+        };
+        let content = toml::to_string(&registry).map_err(Error::config)?;
+        std::fs::write(out.join(OFFLINE_REGISTRY), content).map_err(Error::write_file)?;
+        let lockfile = vendored.lock_installed()?;
+        lockfile.write(out.join(OFFLINE_LOCKFILE))?;
+        Ok(lockfile)
+    }
+
+    /// Locks what's on disk, with the checksum of every file.
+    fn lock_installed(&self) -> Result<Lockfile, Error> {
+        let mut lockfile = Lockfile::default();
+        for (ident, model) in &self.models {
+            let installed = self.installed_dir(model);
+            let mut files = BTreeMap::new();
+            for file in list_files(&installed) {
+                let name = file
+                    .strip_prefix(&installed)
+                    .map_err(Error::pathbuf_custom)
+                    .map(paths::slash)?;
+                if METADATA_FILES.contains(&name.as_str()) {
+                    continue;
+                }
+                let locked = LockedFile {
+                    size: Some(std::fs::metadata(&file).map_err(Error::open_file)?.len()),
+                    sha256: Some(sha256_file(&file)?),
+                };
+                files.insert(name, locked);
+            }
+            lockfile.models.insert(
+                ident.to_string(),
+                LockedModel {
+                    version: model.version.to_string(),
+                    definition: definition_hash(model)?,
+                    revision: recorded_revision(model, &installed),
+                    files,
+                },
+            );
+        }
+        Ok(lockfile)
+    }
+
+    pub fn variants(&self, links: &HuggingfaceModel) -> Result<Vec<Variant>, Error> {
+        sync::block_on(self.variants_async(links))?
+    }
+
+    /// Quantizations or precisions a repo offers, to pick the files of a model from.
+    pub async fn variants_async(&self, links: &HuggingfaceModel) -> Result<Vec<Variant>, Error> {
+        if self.offline {
+            return Err(Error::Offline(links.repo.to_string()));
+        }
+        list_variants(links, &self.download_options).await
+    }
+
+    pub fn check_updates(&self) -> Result<Vec<AvailableUpdate>, Error> {
+        sync::block_on(self.check_updates_async())?
+    }
+
+    /// Installed models whose upstream moved on since they were downloaded: unpinned Huggingface
+    /// repos with a new commit and archives with a new ETag. Nothing is downloaded.
+    pub async fn check_updates_async(&self) -> Result<Vec<AvailableUpdate>, Error> {
+        if self.offline {
+            return Err(Error::Offline("check_updates".to_string()));
+        }
+        let mut updates = vec![];
+        for (ident, model) in &self.models {
+            let installed = self.installed_dir(model);
+            if read_version(&installed).is_none() {
+                continue;
+            }
+            let recorded = recorded_revision(model, &installed);
+            let latest = latest_revision(&model.source, &self.download_options).await?;
+            if let Some(latest) = latest.filter(|v| Some(v) != recorded.as_ref()) {
+                updates.push(AvailableUpdate {
+                    ident: ident.to_string(),
+                    installed: recorded,
+                    latest,
+                });
+            }
+        }
+        updates.sort_by(|a, b| a.ident.cmp(&b.ident));
+        Ok(updates)
+    }
+
+    pub fn updates_available(&self) -> Result<Vec<ModelUpdate>, Error> {
+        sync::block_on(self.updates_available_async())?
+    }
+
+    /// Installed models with an update, sorted: a registered version that isn't the installed
+    /// one, or an upstream that moved on since the installed version was downloaded like
+    /// [`ModelManager::check_updates`] finds, which is skipped offline. Nothing is downloaded.
+    pub async fn updates_available_async(&self) -> Result<Vec<ModelUpdate>, Error> {
+        let mut updates = self
+            .models
+            .iter()
+            .filter_map(|(ident, model)| {
+                let installed = read_version(&self.active_dir(model))?;
+                (installed != model.version).then(|| ModelUpdate {
+                    ident: ident.to_string(),
+                    kind: UpdateKind::Version,
+                    current: Some(installed),
+                    latest: model.version.to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+        if !self.offline {
+            for update in self.check_updates_async().await? {
+                updates.push(ModelUpdate {
+                    ident: update.ident,
+                    kind: UpdateKind::Revision,
+                    current: update.installed,
+                    latest: update.latest,
+                });
+            }
+        }
+        updates.sort_by(|a, b| a.ident.cmp(&b.ident));
+        Ok(updates)
+    }
+
+    /// Looks for [`ModelManager::updates_available`] on a background task right away and every
+    /// `interval` after, calling `callback` with the updates of every check, so an application
+    /// can show which models have one without waiting for the network at startup. Failed checks
+    /// are retried on the next tick. Fails outside of a Tokio runtime.
+    pub fn watch_updates(
+        &self,
+        interval: Duration,
+        callback: impl Fn(Vec<ModelUpdate>) + Send + 'static,
+    ) -> Result<UpdateChecker, Error> {
+        let manager = self.clone();
+        let task = sync::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                match manager.updates_available_async().await {
+                    Ok(updates) => callback(updates),
+                    Err(err) => manager.status(
+                        Verbosity::Summary,
+                        manager.messages.update_check_failed(&err),
+                    ),
+                }
+            }
+        })?;
+        Ok(UpdateChecker::new(task))
+    }
+
+    /// Downloads every available update into a staging directory next to the installed version,
+    /// which keeps being served until [`ModelManager::apply_update`] swaps the staged one in.
+    /// Publishes [`DownloadEvent::UpdateReady`] for every newly staged update.
+    pub async fn stage_updates_async(&self) -> Result<Vec<AvailableUpdate>, Error> {
+        let _running = self.download_options.cancel.enter(self.handle_ctrl_c);
+        let mut staged = vec![];
+        let observer = self.observer();
+        for update in self.check_updates_async().await? {
+            let model = &self.models[&update.ident];
+            let staging = self.staging_dir(model);
+            if recorded_revision(model, &staging).as_ref() == Some(&update.latest) {
+                continue;
+            }
+            let root = self.model_root(model);
+            let guard = self.lock_model(model).await?;
+            self.begin(Operation::Stage, &update.ident, &model.version, &staging)?;
+            self.discard(&staging);
+            let result = self
+                .download_into(
+                    &update.ident,
+                    &model.source,
+                    &model.version,
+                    staging.clone(),
+                    &root,
+                    observer.as_ref(),
+                )
+                .await
+                .and_then(|_| verify_checksums(model, &staging));
+            let result = match result {
+                Ok(()) => {
+                    self.run_hooks(
+                        HookPoint::AfterDownload,
+                        &update.ident,
+                        &model.version,
+                        &staging,
+                    )
+                    .await
+                }
+                Err(err) => Err(err),
+            };
+            if result.is_err() {
+                let _ = std::fs::remove_dir_all(&staging);
+            }
+            self.finish(&staging)?;
+            // subscribers may apply the update right away
+            drop(guard);
+            if let Err(err) = result {
+                match self.continue_on_error {
+                    true => continue,
+                    false => return Err(err),
+                }
+            }
+            let _ = self.events.send(DownloadEvent::UpdateReady {
+                model: update.ident.to_string(),
+                revision: update.latest.to_string(),
+            });
+            staged.push(update);
+        }
+        Ok(staged)
+    }
+
+    /// Commit or ETag of the update staged for a model, if any.
+    pub fn staged_update(&self, ident: &str) -> Option<String> {
+        let model = self.models.get(self.resolve(ident))?;
+        let staging = self.staging_dir(model);
+        read_version(&staging)?;
+        if self.interrupted(&staging).is_some() {
+            return None;
+        }
+        recorded_revision(model, &staging)
+    }
+
+    pub fn apply_update(&self, ident: &str) -> Result<PathBuf, Error> {
+        sync::block_on(self.apply_update_async(ident))?
+    }
+
+    /// Replaces the installed files of a model with its staged update.
+    pub async fn apply_update_async(&self, ident: &str) -> Result<PathBuf, Error> {
+        let ident = self.resolve(ident);
+        let model = self.models.get(ident).ok_or(Error::ModelNotFound)?;
+        let staging = self.staging_dir(model);
+        if read_version(&staging).is_none() {
+            return Err(Error::VersionNotFound);
+        }
+        let root = self.model_root(model);
+        let _guard = self.lock_model(model).await?;
+        if self.interrupted(&staging).is_some() {
+            return Err(Error::VersionNotFound);
+        }
+        let install = self.install_dir(model);
+        self.begin(Operation::Apply, ident, &model.version, &install)?;
+        if install.exists() {
+            std::fs::remove_dir_all(&install).map_err(Error::write_file)?;
+        }
+        std::fs::create_dir_all(install.parent().unwrap_or(&root)).map_err(Error::write_file)?;
+        std::fs::rename(&staging, &install).map_err(Error::write_path(&install))?;
+        self.invalidate_size(&root);
+        self.activate(model, &root)?;
+        self.finish(&install)?;
+        self.touch_last_used(&root)?;
+        Ok(self.active_dir(model))
+    }
+
+    pub fn recover(&self) -> Result<Vec<JournalEntry>, Error> {
+        sync::block_on(self.recover_async())?
+    }
+
+    /// Finishes operations a crashed process left behind: interrupted cleanups are undone,
+    /// interrupted downloads and updates are resumed, staged updates discarded. Models that are
+    /// no longer registered or can't be downloaded while offline are only marked as not
+    /// downloaded. Until then the journal keeps them from being reported as downloaded.
+    pub async fn recover_async(&self) -> Result<Vec<JournalEntry>, Error> {
+        self.rollback_cleanups()?;
+        let Ok(entries) = std::fs::read_dir(self.model_path.join(JOURNAL)) else {
+            return Ok(vec![]);
+        };
+        let mut recovered = vec![];
+        for path in entries.flatten().map(|v| v.path()) {
+            let Some(entry) = journal::read(&path) else {
+                continue;
+            };
+            let model = self.models.get(&entry.ident);
+            if let Some(model) = model {
+                let _guard = self.lock_model(model).await?;
+                // it may have been running in another process that finished meanwhile
+                if self.interrupted(&entry.target).is_none() {
+                    continue;
+                }
+            }
+            match (entry.operation, model) {
+                (Operation::Stage, _) => {
+                    let _ = std::fs::remove_dir_all(&entry.target);
+                    journal::finish(&path)?;
+                }
+                (_, Some(model)) if !self.offline && entry.target == self.install_dir(model) => {
+                    let observer = self.observer();
+                    self.download_if_needed(&entry.ident, model, observer.as_ref())
+                        .await?;
+                }
+                _ => {
+                    let _ = std::fs::remove_file(entry.target.join("version"));
+                    // the expected version stays journaled until it can be downloaded again
+                    if model.is_none_or(|v| entry.target != self.install_dir(v)) {
+                        journal::finish(&path)?;
+                    }
+                }
+            }
+            recovered.push(entry);
+        }
+        Ok(recovered)
+    }
+
+    /// Stages updates every `interval` until the future is dropped. Failed checks are retried on
+    /// the next tick, subscribe to the events to learn about staged updates.
+    pub async fn auto_update(&self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = self.stage_updates_async().await {
+                self.status(Verbosity::Summary, self.messages.update_check_failed(&err));
+            }
+        }
+    }
+
+    pub fn rollback(&self, ident: &str) -> Result<String, Error> {
+        let ident = self.resolve(ident);
+        let model = self.models.get(ident).ok_or(Error::ModelNotFound)?;
+        self.writable(ident)?;
+        self.layout.rollback(&self.model_root(model))
+    }
+
+    pub fn list(&self) -> Result<Vec<ModelStatus>, Error> {
+        let mut list = self
+            .models
+            .iter()
+            .map(|(ident, model)| {
+                let path = self.model_root(model);
+                let installed_version = read_version(&self.active_dir(model));
+                let record = self
+                    .record(ident)
+                    .filter(|v| Some(&v.version) == installed_version.as_ref());
+                ModelStatus {
+                    ident: ident.to_string(),
+                    registered: true,
+                    downloaded: installed_version.as_ref() == Some(&model.version)
+                        && self.interrupted(&self.install_dir(model)).is_none(),
+                    expected_version: Some(model.version.to_string()),
+                    installed_version,
+                    revision: read_revision(&self.active_dir(model)),
+                    size: match &record {
+                        Some(record) => record.size(),
+                        None => self.cached_size(&path),
+                    },
+                    last_used: match record.and_then(|v| v.last_used) {
+                        Some(v) => Utc.timestamp_opt(v, 0).single(),
+                        None => read_last_used(&path),
+                    },
+                    metadata: Manifest::read(&self.installed_dir(model)).map(|v| v.metadata()),
+                    capabilities: model.capabilities.clone(),
+                }
+            })
+            .collect::<Vec<_>>();
+        list.extend(
+            self.unregistered_directories()?
+                .into_iter()
+                .map(|(name, path)| ModelStatus {
+                    ident: name,
+                    registered: false,
+                    downloaded: false,
+                    expected_version: None,
+                    installed_version: read_version(&path),
+                    revision: read_revision(&path),
+                    size: self.cached_size(&path),
+                    last_used: read_last_used(&path),
+                    metadata: Manifest::read(&path).map(|v| v.metadata()),
+                    capabilities: None,
+                }),
+        );
+        list.sort_by(|a, b| a.ident.cmp(&b.ident));
+        Ok(list)
+    }
+
+    /// The downloaded model for `task` with the most parameters, then the longest context, that
+    /// fits into `vram` bytes of GPU memory. Models that don't declare what they need count as
+    /// fitting, as does every model without a `vram` limit.
+    pub fn best_model(&self, task: &str, vram: Option<u64>) -> Result<Option<ModelStatus>, Error> {
+        let fits = |v: &Capabilities| match (v.vram, vram) {
+            (Some(needed), Some(available)) => needed <= available,
+            _ => true,
+        };
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|v| v.downloaded)
+            .filter(|v| {
+                v.capabilities
+                    .as_ref()
+                    .is_some_and(|v| v.task.as_deref() == Some(task) && fits(v))
+            })
+            .max_by_key(|v| {
+                let capabilities = v.capabilities.as_ref();
+                (
+                    capabilities.and_then(|v| v.parameters),
+                    capabilities.and_then(|v| v.context_length),
+                )
+            }))
+    }
+
+    pub fn remove(&mut self, ident: &str) -> Result<Model, Error> {
+        let ident = self.resolve(ident).to_string();
+        let model = self.models.get(&ident).ok_or(Error::ModelNotFound)?;
+        self.writable(&ident)?;
+        let (root, dir) = self.location(model);
+        remove_model_dir(&root, &dir)?;
+        self.invalidate_size(&self.model_root(model));
+        self.forget_state(&ident);
+        self.pinned.remove(&ident);
+        self.aliases.retain(|_, v| *v != ident);
+        self.definitions.remove(&ident);
+        self.hooks.remove(&ident);
+        self.models.remove(&ident).ok_or(Error::ModelNotFound)
+    }
+
+    pub fn remove_all_unregistered(&self) -> Result<Vec<String>, Error> {
+        self.writable(&self.model_path.display().to_string())?;
+        let dirs = self.unregistered_directories()?;
+        for (name, _) in &dirs {
+            remove_model_dir(&self.model_path, Path::new(name))?;
+            self.invalidate_size(&self.model_path.join(name));
+        }
+        Ok(dirs.into_iter().map(|v| v.0).collect())
+    }
+
+    fn unregistered_directories(&self) -> Result<Vec<(String, PathBuf)>, Error> {
+        if !self.model_path.exists() {
+            return Ok(vec![]);
+        }
+        let mut dirs = vec![];
+        for entry in std::fs::read_dir(&self.model_path).map_err(Error::open_file)? {
+            let entry = entry.map_err(Error::open_file)?;
+            let name = entry.file_name();
+            let used = self
+                .models
+                .values()
+                .any(|m| self.layout.model_dir(m).iter().next() == Some(name.as_os_str()));
+            // the manager's own state lives in hidden directories
+            let hidden = name.to_string_lossy().starts_with('.');
+            if !used && !hidden && entry.path().is_dir() {
+                dirs.push((name.to_string_lossy().to_string(), entry.path()));
+            }
+        }
+        Ok(dirs)
+    }
+
+    fn touch_last_used(&self, path: &Path) -> Result<(), Error> {
+        if self.read_only {
+            return Ok(());
+        }
+        let now = Utc::now().timestamp();
+        if let Some(state) = &self.state {
+            for (ident, model) in &self.models {
+                if self.model_root(model) == path {
+                    state.touch(ident, now)?;
+                }
+            }
+        }
+        std::fs::write(path.join("last_used"), now.to_string()).map_err(Error::write_file)
+    }
+
+    /// Keeps a [`ModelRecord`] of every installed model in `state`, which status queries and
+    /// eviction read instead of walking the model directories.
+    pub fn set_state_store(&mut self, state: Arc<dyn StateStore>) {
+        self.state = Some(state);
+        self.state_file = false;
+    }
+
+    pub fn state_store(&self) -> Option<&dyn StateStore> {
+        self.state.as_deref()
+    }
+
+    fn record(&self, ident: &str) -> Option<ModelRecord> {
+        self.state.as_ref()?.get(ident).ok().flatten()
+    }
+
+    /// The state is best effort like the history, the model directory stays the truth.
+    fn record_state(&self, ident: &str, model: &Model) {
+        let Some(state) = &self.state else {
+            return;
+        };
+        let dir = self.active_dir(model);
+        let mut files = list_files_following(&dir)
+            .into_iter()
+            .filter(|file| !METADATA_FILES.iter().any(|v| file.ends_with(v)))
+            .filter_map(|file| {
+                let name = paths::slash(file.strip_prefix(&dir).ok()?);
+                Some(FileRecord {
+                    size: std::fs::metadata(&file).ok()?.len(),
+                    sha256: model.checksums.get(&name).cloned(),
+                    name,
+                })
+            })
+            .collect::<Vec<_>>();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        let last_used = self.record(ident).and_then(|v| v.last_used);
+        let _ = state.put(ModelRecord {
+            ident: ident.to_string(),
+            version: model.version.clone(),
+            revision: read_revision(&dir),
+            files,
+            downloaded: Utc::now().timestamp(),
+            last_used,
+        });
+    }
+
+    /// Files of the state store that are missing or have another size on disk, as
+    /// `(ident, file)`. Only sizes are compared, [`ModelManager::verify`] re-hashes.
+    pub fn verify_state(&self) -> Result<Vec<(String, String)>, Error> {
+        let Some(state) = &self.state else {
+            return Ok(vec![]);
+        };
+        let mut mismatched = vec![];
+        for record in state.all()? {
+            let Some(model) = self.models.get(&record.ident) else {
+                continue;
+            };
+            let dir = self.active_dir(model);
+            for file in &record.files {
+                let size = paths::relative(&file.name)
+                    .and_then(|v| std::fs::metadata(dir.join(v)).map_err(Error::open_file))
+                    .map(|v| v.len());
+                if size.ok() != Some(file.size) {
+                    mismatched.push((record.ident.clone(), file.name.clone()));
+                }
+            }
+        }
+        Ok(mismatched)
+    }
+
+    fn forget_state(&self, ident: &str) {
+        if let Some(state) = &self.state {
+            let _ = state.remove(ident);
+        }
+    }
+
+    /// Removes everything but the registered models from the model directory, without keeping a
+    /// backup. See [`ModelManager::clean_directory_with`].
+    pub fn clean_directory(&self) -> Result<(), Error> {
+        self.clean_directory_with(&CleanOptions {
+            keep_backups: 0,
+            confirm_delete: true,
+        })
+        .map(|_| ())
+    }
+
+    /// Moves the model directory into a timestamped backup next to it and moves the registered
+    /// models and the manager's own state back, so the backup holds everything else. Backups
+    /// beyond [`CleanOptions::keep_backups`] are only deleted with
+    /// [`CleanOptions::confirm_delete`]. Every move is journaled, if one fails the model
+    /// directory is restored as it was.
+    pub fn clean_directory_with(&self, clean: &CleanOptions) -> Result<CleanReport, Error> {
+        self.writable(&self.model_path.display().to_string())?;
+        self.rollback_cleanups()?;
+        let mut timestamp = Utc::now().timestamp();
+        while self.backup_path(timestamp)?.exists() {
+            timestamp += 1;
+        }
+        let backup = self.backup_path(timestamp)?;
+        let journal_path = self.backup_path_with(timestamp, CLEAN_JOURNAL)?;
+        let mut journal = CleanJournal::default();
+        if let Err(err) = self.move_unregistered(&mut journal, &journal_path, &backup) {
+            // restoring may fail the same way, the journal is kept then
+            let _ = journal.rollback(&journal_path);
+            return Err(err);
+        }
+        journal::finish(&journal_path)?;
+        self.invalidate_size(&self.model_path);
+
+        let mut removed = list_files(&backup)
+            .into_iter()
+            .filter_map(|v| v.strip_prefix(&backup).ok().map(Path::to_path_buf))
+            .collect::<Vec<_>>();
+        removed.sort();
+        let mut report = CleanReport {
+            backup: Some(backup.clone()),
+            removed,
+            ..CleanReport::default()
+        };
+        if report.removed.is_empty() {
+            std::fs::remove_dir_all(&backup).map_err(Error::write_file)?;
+            report.backup = None;
+        }
+        for old in self.backups()?.into_iter().skip(clean.keep_backups) {
+            if !clean.confirm_delete {
+                report.unconfirmed.push(old);
+                continue;
+            }
+            std::fs::remove_dir_all(&old).map_err(Error::write_file)?;
+            if report.backup.as_ref() == Some(&old) {
+                report.backup = None;
+            }
+            report.deleted.push(old);
+        }
+        Ok(report)
+    }
+
+    fn move_unregistered(
+        &self,
+        journal: &mut CleanJournal,
+        journal_path: &Path,
+        backup: &Path,
+    ) -> Result<(), Error> {
+        journal.rename(journal_path, &self.model_path, backup)?;
+        std::fs::create_dir_all(&self.model_path).map_err(Error::write_path(&self.model_path))?;
+        for model in self.models.values() {
+            let dir = self.layout.model_dir(model);
+            let from = backup.join(&dir);
+            if from.exists() && !dir.is_absolute() {
+                journal.rename(journal_path, &from, &self.model_root(model))?;
+            }
+        }
+        // journal, locks, queue and the like
+        for entry in std::fs::read_dir(backup)
+            .map_err(Error::open_file)?
+            .flatten()
+        {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                let to = self.model_path.join(entry.file_name());
+                journal.rename(journal_path, &entry.path(), &to)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Undoes cleanups a crashed process left halfway, restoring the model directory as it was
+    /// before.
+    fn rollback_cleanups(&self) -> Result<(), Error> {
+        let prefix = self.backup_prefix()?;
+        let suffix = format!(".{CLEAN_JOURNAL}");
+        let parent = match self.model_path.parent() {
+            Some(v) if !v.as_os_str().is_empty() => v,
+            _ => Path::new("."),
+        };
+        let Ok(entries) = std::fs::read_dir(parent) else {
+            return Ok(());
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            if !paths::strip_prefix(&name, &prefix).is_some_and(|v| v.ends_with(&suffix)) {
+                continue;
+            }
+            let path = self.model_path.with_file_name(&name);
+            match CleanJournal::read(&path) {
+                Some(journal) => journal.rollback(&path)?,
+                None => journal::finish(&path)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Backups of earlier cleanups, newest first.
+    pub fn backups(&self) -> Result<Vec<PathBuf>, Error> {
+        let prefix = self.backup_prefix()?;
+        let parent = match self.model_path.parent() {
+            Some(v) if !v.as_os_str().is_empty() => v,
+            _ => Path::new("."),
+        };
+        let mut backups = std::fs::read_dir(parent)
+            .map_err(Error::open_file)?
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let timestamp = paths::strip_prefix(&name, &prefix)?.parse::<i64>().ok()?;
+                // same form as the model path, without a leading `./`
+                let path = self.model_path.with_file_name(&name);
+                entry.path().is_dir().then_some((timestamp, path))
+            })
+            .collect::<Vec<_>>();
+        backups.sort_by_key(|v| std::cmp::Reverse(v.0));
+        Ok(backups.into_iter().map(|v| v.1).collect())
+    }
+
+    /// `<name>-` of the model directory, backups add their timestamp.
+    fn backup_prefix(&self) -> Result<OsString, Error> {
+        self.model_path
+            .file_name()
+            .map(|v| paths::concat(v, "-"))
+            .ok_or_else(|| Error::pathbuf_custom("path is empty"))
+    }
+
+    fn backup_path(&self, timestamp: i64) -> Result<PathBuf, Error> {
+        let name = paths::concat(&self.backup_prefix()?, &timestamp.to_string());
+        Ok(self.model_path.with_file_name(name))
+    }
+
+    fn backup_path_with(&self, timestamp: i64, extension: &str) -> Result<PathBuf, Error> {
+        let name = paths::concat(&self.backup_prefix()?, &format!("{timestamp}.{extension}"));
+        Ok(self.model_path.with_file_name(name))
+    }
+
+    fn model_root(&self, model: &Model) -> PathBuf {
+        let (root, dir) = self.location(model);
+        root.join(dir)
+    }
+
+    /// The directory a model is stored in and its path relative to that. Models with an
+    /// absolute directory live in its parent, outside the storage roots.
+    fn location(&self, model: &Model) -> (PathBuf, PathBuf) {
+        let dir = self.layout.model_dir(model);
+        if dir.is_absolute() {
+            let root = dir.parent().map_or_else(|| dir.clone(), Path::to_path_buf);
+            let name = dir.file_name().map(PathBuf::from).unwrap_or_default();
+            return (root, name);
+        }
+        (self.storage_root(&dir), dir)
+    }
+
+    /// The root a model directory is stored in, or the one it was placed in before its
+    /// download, the model path otherwise.
+    fn storage_root(&self, dir: &Path) -> PathBuf {
+        if self.roots.is_empty() {
+            return self.model_path.clone();
+        }
+        if let Some(root) = self.storage_roots().find(|v| v.join(dir).exists()) {
+            return root.to_path_buf();
+        }
+        sync::lock(&self.placements)
+            .get(dir)
+            .cloned()
+            .unwrap_or_else(|| self.model_path.clone())
+    }
+
+    /// Chooses a storage root for every planned model that isn't on disk yet, fails if one
+    /// doesn't fit any of them.
+    fn place(&self, plan: &DownloadPlan) -> Result<(), Error> {
+        let roots = self
+            .storage_roots()
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        let mut available = vec![];
+        for root in &roots {
+            std::fs::create_dir_all(root).map_err(Error::write_path(root))?;
+            available.push(fs4::available_space(root).map_err(Error::open_file)?);
+        }
+        for planned in &plan.models {
+            let model = &self.models[&planned.ident];
+            let needed = planned.total_bytes();
+            let dir = self.layout.model_dir(model);
+            if dir.is_absolute() {
+                continue;
+            }
+            let index = match roots.iter().position(|v| v.join(&dir).exists()) {
+                Some(index) if available[index] >= needed => index,
+                Some(index) => {
+                    return Err(Error::InsufficientDiskSpace {
+                        needed,
+                        available: available[index],
+                    })
+                }
+                None => self.placement.choose(needed, &available).ok_or_else(|| {
+                    Error::InsufficientDiskSpace {
+                        needed,
+                        available: available.iter().copied().max().unwrap_or_default(),
+                    }
+                })?,
+            };
+            available[index] -= needed;
+            sync::lock(&self.placements).insert(dir, roots[index].clone());
+        }
+        Ok(())
+    }
+
+    /// Where updates are downloaded to while the installed version stays in use, in the
+    /// model's storage root so applying them is a rename.
+    fn staging_dir(&self, model: &Model) -> PathBuf {
+        let (root, dir) = self.location(model);
+        root.join(STAGING).join(dir)
+    }
+
+    /// Where a bundle is unpacked to before it replaces the installed version.
+    #[cfg(feature = "bundle")]
+    fn import_dir(&self, model: &Model) -> PathBuf {
+        paths::with_suffix(&self.staging_dir(model), ".import")
+    }
+
+    /// Where the expected version is downloaded to.
+    fn install_dir(&self, model: &Model) -> PathBuf {
+        self.layout
+            .install_dir(&self.model_root(model), &model.version)
+    }
+
+    /// Where the expected version lives once it's installed.
+    fn installed_dir(&self, model: &Model) -> PathBuf {
+        self.layout
+            .installed_dir(&self.model_root(model), &model.version)
+    }
+
+    fn active_dir(&self, model: &Model) -> PathBuf {
+        self.layout
+            .active_dir(&self.model_root(model), &model.version)
+    }
+
+    fn check_download_needed(&self, model: &Model) -> bool {
+        if self.interrupted(&self.install_dir(model)).is_some() {
+            return true;
+        }
+        let path = self.installed_dir(model);
+        let ver = std::fs::read_to_string(path.join("version"));
+        if let Ok(v) = ver {
+            if v != model.version || is_expired(&path, model.ttl) {
+                return true;
+            }
+            // versions installed before manifests were written only have the version file
+            return Manifest::read(&path).is_some_and(|v| !v.damaged(&path).is_empty());
+        }
+        true
+    }
+
+    /// The manifest of the installed version of a model, if it was downloaded with one.
+    pub fn manifest(&self, ident: &str) -> Result<Option<Manifest>, Error> {
+        let model = self
+            .models
+            .get(self.resolve(ident))
+            .ok_or(Error::ModelNotFound)?;
+        Ok(Manifest::read(&self.installed_dir(model)))
+    }
+
+    /// Metadata of the installed version of a model, `None` if it isn't installed or was
+    /// downloaded before manifests were written.
+    pub fn metadata(&self, ident: &str) -> Result<Option<ModelMetadata>, Error> {
+        Ok(self.manifest(ident)?.map(|v| v.metadata()))
+    }
+
+    fn write_manifest(&self, model: &Model, dir: &Path) -> Result<(), Error> {
+        let mut files = list_files_following(dir)
+            .into_iter()
+            .filter(|file| !METADATA_FILES.iter().any(|v| file.ends_with(v)))
+            .filter_map(|file| {
+                let name = paths::slash(file.strip_prefix(dir).ok()?);
+                Some((name, std::fs::metadata(&file).ok()?.len()))
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        let revision = read_revision(dir);
+        let recorded = read_checksums(dir);
+        let urls = match &model.source {
+            ModelSource::Huggingface(v) => HuggingfaceModel {
+                files: files.iter().map(|v| v.0.clone()).collect(),
+                commit: revision.clone().or(v.commit.clone()),
+                ..v.clone()
+            }
+            .url_at(self.download_options.for_model(v).endpoint())
+            .into_iter()
+            .collect(),
+            ModelSource::Zip(url) => files
+                .iter()
+                .map(|v| (v.0.clone(), url.to_string()))
+                .collect::<HashMap<_, _>>(),
+        };
+        let source = match &model.source {
+            ModelSource::Huggingface(v) => format!(
+                "{}/{}",
+                self.endpoint(&model.source).trim_end_matches('/'),
+                v.repo
+            ),
+            ModelSource::Zip(url) => url.to_string(),
+        };
+        Manifest {
+            version: model.version.clone(),
+            revision,
+            source: Some(source),
+            downloaded: Some(Utc::now().timestamp()),
+            tags: model.tags.clone(),
+            files: files
+                .into_iter()
+                .map(|(name, size)| ManifestFile {
+                    sha256: model
+                        .checksums
+                        .get(&name)
+                        .or_else(|| recorded.get(&name))
+                        .cloned(),
+                    url: urls.get(&name).cloned(),
+                    name,
+                    size,
+                })
+                .collect(),
+        }
+        .write(dir)
+    }
+
+    /// [`ModelManager::download_if_needed`] bounded by the deadline of the model, if it has one.
+    async fn download_within_deadline(
+        &self,
+        ident: &str,
+        model: &Model,
+        observer: &dyn ProgressObserver,
+    ) -> Result<(), Error> {
+        let Some(deadline) = model.deadline else {
+            return self.download_if_needed(ident, model, observer).await;
+        };
+        // a token of its own, so only this model is cancelled when its time is up
+        let manager = self.controlled();
+        within_deadline(
+            manager.download_if_needed(ident, model, observer),
+            &manager.download_options.cancel,
+            &self.download_options.cancel,
+            deadline,
+            ident,
+        )
+        .await
+    }
+
+    /// Concurrent callers for the same model, in this or another process, wait for one shared
+    /// download instead of writing into the same directory.
+    async fn download_if_needed(
+        &self,
+        ident: &str,
+        model: &Model,
+        observer: &dyn ProgressObserver,
+    ) -> Result<(), Error> {
+        if !self.check_download_needed(model) {
+            return Ok(());
+        }
+        let guard = self.lock_model(model).await?;
+        if !self.check_download_needed(model) {
+            return Ok(());
+        }
+        let started = Instant::now();
+        let tally = Tally::new(observer);
+        let result = self.download_model(ident, model, &tally).await;
+        #[cfg(feature = "metrics")]
+        self.metrics.record_download(started.elapsed(), &result);
+        if result.is_ok() {
+            self.record_history(ident, model, &tally, started.elapsed());
+            self.record_state(ident, model);
+        }
+        // other callers don't wait for the notifications
+        drop(guard);
+        let notification =
+            DownloadNotification::new(ident, &model.version, started.elapsed(), &result);
+        for notifier in &self.notifiers {
+            notifier.send(&notification).await;
+        }
+        result
+    }
+
+    /// Endpoint of a Huggingface repo or URL of an archive.
+    fn endpoint(&self, source: &ModelSource) -> String {
+        match source {
+            ModelSource::Huggingface(v) => {
+                self.download_options.for_model(v).endpoint().to_string()
+            }
+            ModelSource::Zip(url) => url.to_string(),
+        }
+    }
+
+    /// History is best effort, failing to write it doesn't fail the download.
+    fn record_history(&self, ident: &str, model: &Model, tally: &Tally, duration: Duration) {
+        let endpoint = self.endpoint(&model.source);
+        let host = reqwest::Url::parse(&endpoint)
+            .ok()
+            .and_then(|v| v.host_str().map(|v| v.to_string()))
+            .unwrap_or_else(|| endpoint.clone());
+        let record = DownloadRecord {
+            ident: ident.to_string(),
+            version: model.version.clone(),
+            endpoint,
+            host,
+            bytes: tally.bytes.load(Ordering::Relaxed),
+            duration_ms: duration.as_millis() as u64,
+            retries: tally.retries.load(Ordering::Relaxed),
+            finished: Utc::now().timestamp(),
+        };
+        let _ = history::append(&self.model_path.join(HISTORY), record);
+    }
+
+    /// Every download that finished, oldest first.
+    pub fn history(&self) -> Result<Vec<DownloadRecord>, Error> {
+        history::read(&self.model_path.join(HISTORY))
+    }
+
+    /// [`ModelManager::history`] summed up per host and per model.
+    pub fn history_stats(&self) -> Result<HistoryStats, Error> {
+        Ok(HistoryStats::new(&self.history()?))
+    }
+
+    fn flight_lock(&self, model: &Model) -> Arc<AsyncMutex<()>> {
+        let mut in_flight = sync::lock(&self.in_flight);
+        in_flight.entry(self.model_root(model)).or_default().clone()
+    }
+
+    /// Models a download, update or removal is working on in this process or one of its clones,
+    /// sorted. Calls for them wait for it and reuse what it downloaded, calls for other models
+    /// run alongside.
+    pub fn in_flight(&self) -> Vec<String> {
+        let in_flight = sync::lock(&self.in_flight);
+        let mut idents = self
+            .models
+            .iter()
+            .filter(|(_, model)| {
+                in_flight
+                    .get(&self.model_root(model))
+                    .is_some_and(|v| v.try_lock().is_err())
+            })
+            .map(|(ident, _)| ident.clone())
+            .collect::<Vec<_>>();
+        idents.sort();
+        idents
+    }
+
+    /// Exclusive access to a model directory across processes sharing the model path. The lock
+    /// file lives outside the model directory since downloads replace that.
+    async fn lock_model(&self, model: &Model) -> Result<ModelLock, Error> {
+        self.writable(&self.layout.model_dir(model).display().to_string())?;
+        let flight = self.flight_lock(model).lock_owned().await;
+        let name = paths::flat(&self.layout.model_dir(model));
+        let path = self.model_path.join(LOCKS).join(format!("{name}.lock"));
+        std::fs::create_dir_all(path.parent().unwrap_or(&self.model_path))
+            .map_err(Error::write_file)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(Error::open_file)?;
+        loop {
+            match fs4::FileExt::try_lock(&file) {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) => tokio::time::sleep(LOCK_POLL).await,
+                Err(TryLockError::Error(e)) => return Err(Error::open_file(e)),
+            }
+        }
+        Ok(ModelLock {
+            _flight: flight,
+            _file: file,
+            in_flight: self.in_flight.clone(),
+            root: self.model_root(model),
+        })
+    }
+
+    /// Spans of models and their files follow the model and repo through `tracing`, a
+    /// `tracing-opentelemetry` layer exports them to distributed traces.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                model = ident,
+                version = %model.version,
+                repo = tracing::field::Empty,
+                revision = tracing::field::Empty,
+                mirror = %self.endpoint(&model.source),
+                bytes = tracing::field::Empty,
+                retries = tracing::field::Empty,
+            )
+        )
+    )]
+    async fn download_model(
+        &self,
+        ident: &str,
+        model: &Model,
+        observer: &dyn ProgressObserver,
+    ) -> Result<(), Error> {
+        #[cfg(feature = "tracing")]
+        {
+            let span = tracing::Span::current();
+            if let ModelSource::Huggingface(v) = &model.source {
+                span.record("repo", &v.repo);
+                span.record("revision", v.reference());
+            }
+            let tally = Tally::new(observer);
+            let result = self.install_model(ident, model, &tally).await;
+            span.record("bytes", tally.bytes.load(Ordering::Relaxed));
+            span.record("retries", tally.retries.load(Ordering::Relaxed));
+            result
+        }
+        #[cfg(not(feature = "tracing"))]
+        self.install_model(ident, model, observer).await
+    }
+
+    async fn install_model(
+        &self,
+        ident: &str,
+        model: &Model,
+        observer: &dyn ProgressObserver,
+    ) -> Result<(), Error> {
+        let path = self.model_root(model);
+        let install = self.install_dir(model);
+        // files an interrupted download of this version completed are kept
+        let resume = self
+            .interrupted(&install)
+            .is_some_and(|v| v.version == model.version && v.operation != Operation::Apply);
+        self.begin(Operation::Download, ident, &model.version, &install)?;
+        if resume && !self.offline && matches!(model.source, ModelSource::Huggingface(_)) {
+            self.run_hooks(HookPoint::BeforeDownload, ident, &model.version, &install)
+                .await?;
+            let result = download_changed_files(
+                &model.source,
+                ident.to_string(),
+                model.version.to_string(),
+                install.clone(),
+                observer,
+                &self.download_options,
+            )
+            .await;
+            self.invalidate_size(&path);
+            result?;
+        } else {
+            // a resumed download continues the `.part` files left in the target
+            if !resume {
+                self.discard(&install);
+            }
+            self.download_into(
+                ident,
+                &model.source,
+                &model.version,
+                install.clone(),
+                &path,
+                observer,
+            )
+            .await?;
+        }
+        verify_checksums(model, &install)?;
+        self.run_hooks(HookPoint::AfterDownload, ident, &model.version, &install)
+            .await?;
+        self.write_manifest(model, &install)?;
+        self.activate(model, &path)?;
+        self.finish(&install)
+    }
+
+    /// Makes the installed version of `model` in `root` the active one and applies the
+    /// configured permissions.
+    fn activate(&self, model: &Model, root: &Path) -> Result<(), Error> {
+        self.layout
+            .activate(root, &model.version, model.versions.keys())?;
+        self.apply_permissions(model, root)
+    }
+
+    /// Applies the configured permissions to everything in `root` and to the directories
+    /// leading to it from its storage root.
+    fn apply_permissions(&self, model: &Model, root: &Path) -> Result<(), Error> {
+        let (base, _) = self.location(model);
+        for dir in root
+            .ancestors()
+            .skip(1)
+            .take_while(|v| v.starts_with(&base))
+        {
+            self.permissions.apply_dir(dir)?;
+        }
+        self.permissions.apply(root)
+    }
+
+    fn journal_path(&self, target: &Path) -> PathBuf {
+        let relative = self
+            .storage_roots()
+            .find_map(|v| target.strip_prefix(v).ok())
+            .unwrap_or(target);
+        let name = paths::flat(relative);
+        self.model_path.join(JOURNAL).join(format!("{name}.toml"))
+    }
+
+    /// Records an operation on `target`, callers hold the lock of the model.
+    fn begin(
+        &self,
+        operation: Operation,
+        ident: &str,
+        version: &str,
+        target: &Path,
+    ) -> Result<(), Error> {
+        let entry = JournalEntry::new(operation, ident, version, target);
+        journal::begin(&self.journal_path(target), &entry)
+    }
+
+    fn finish(&self, target: &Path) -> Result<(), Error> {
+        journal::finish(&self.journal_path(target))
+    }
+
+    /// The operation that was writing into `target` without finishing, or is still running in
+    /// another process.
+    fn interrupted(&self, target: &Path) -> Option<JournalEntry> {
+        journal::read(&self.journal_path(target))
+    }
+
+    async fn download_into(
+        &self,
+        ident: &str,
+        source: &ModelSource,
+        version: &str,
+        target: PathBuf,
+        root: &Path,
+        observer: &dyn ProgressObserver,
+    ) -> Result<(), Error> {
+        if self.offline {
+            return Err(Error::Offline(ident.to_string()));
+        }
+        self.run_hooks(HookPoint::BeforeDownload, ident, version, &target)
+            .await?;
+        let scratch = self.scratch_dir(&target).unwrap_or_else(|| target.clone());
+        std::fs::create_dir_all(&scratch).map_err(Error::write_path(&scratch))?;
+        let mut result = download_file(
+            source,
+            ident.to_string(),
+            version.to_string(),
+            scratch.clone(),
+            observer,
+            &self.download_options,
+        )
+        .await;
+        if result.is_ok() && scratch != target {
+            result = storage::move_files(&scratch, &target);
+        }
+        self.invalidate_size(root);
+        result?;
+        if matches!(source, ModelSource::Zip(_)) {
+            self.run_hooks(HookPoint::AfterExtract, ident, version, &target)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Where a download into `target` is written first, if there's a temp dir. Named after the
+    /// whole path so managers sharing the temp dir don't collide.
+    fn scratch_dir(&self, target: &Path) -> Option<PathBuf> {
+        Some(self.temp_dir.as_ref()?.join(paths::flat(target)))
+    }
+
+    /// Removes `target` and what an earlier download left of it in the temp dir.
+    fn discard(&self, target: &Path) {
+        let _ = std::fs::remove_dir_all(target);
+        if let Some(scratch) = self.scratch_dir(target) {
+            let _ = std::fs::remove_dir_all(scratch);
+        }
+    }
+
+    pub fn plan(&self) -> Result<DownloadPlan, Error> {
+        sync::block_on(self.plan_async())?
+    }
+
+    pub async fn plan_async(&self) -> Result<DownloadPlan, Error> {
+        self.plan_for(self.models.iter().collect()).await
+    }
+
+    pub fn plan_model(&self, ident: &str) -> Result<PlannedModel, Error> {
+        sync::block_on(self.plan_model_async(ident))?
+    }
+
+    /// Files and sizes of a model's download, whether or not it's already installed.
+    pub async fn plan_model_async(&self, ident: &str) -> Result<PlannedModel, Error> {
+        let ident = self.resolve(ident);
+        let model = self.models.get(ident).ok_or(Error::ModelNotFound)?;
+        Ok(PlannedModel {
+            ident: ident.to_string(),
+            version: model.version.to_string(),
+            files: plan_files(&model.source, &self.download_options).await?,
+        })
+    }
+
+    async fn plan_for(&self, models: Vec<(&String, &Model)>) -> Result<DownloadPlan, Error> {
+        let mut plan = DownloadPlan::default();
+        for (ident, model) in models {
+            if !self.check_download_needed(model) {
+                continue;
+            }
+            plan.models.push(PlannedModel {
+                ident: ident.to_string(),
+                version: model.version.to_string(),
+                files: plan_files(&model.source, &self.download_options).await?,
+            });
+        }
+        plan.models.sort_by(|a, b| a.ident.cmp(&b.ident));
+        Ok(plan)
+    }
+
+    /// Returns the size of the downloads if every file size is known.
+    async fn ensure_disk_space(
+        &self,
+        models: Vec<(&String, &Model)>,
+    ) -> Result<Option<u64>, Error> {
+        if !self.check_disk_space || self.offline {
+            return Ok(None);
+        }
+        let plan = self.plan_for(models).await?;
+        let needed = plan.total_bytes();
+        let exact = plan.is_exact().then_some(needed);
+        if needed == 0 {
+            return Ok(exact);
+        }
+        if !self.roots.is_empty() {
+            self.place(&plan)?;
+            return Ok(exact);
+        }
+        std::fs::create_dir_all(&self.model_path).map_err(Error::write_path(&self.model_path))?;
+        let available = fs4::available_space(&self.model_path).map_err(Error::open_file)?;
+        if needed > available {
+            return Err(Error::InsufficientDiskSpace { needed, available });
+        }
+        Ok(exact)
+    }
+
+    /// Like [`ModelManager::get_model_async`] on a spawned task that can be paused, resumed and
+    /// cancelled through the returned handle. Fails outside of a Tokio runtime.
+    pub fn start_download(&self, ident: &str) -> Result<DownloadHandle<PathBuf>, Error> {
+        let manager = self.controlled();
+        let control = manager.download_options.cancel.clone();
+        let ident = ident.to_string();
+        Ok(DownloadHandle::new(
+            control,
+            sync::spawn(async move { Ok(manager.get_model_async(&ident).await?.0) })?,
+        ))
+    }
+
+    /// [`ModelManager::download_all`] as a [`DownloadHandle`].
+    pub fn start_download_all(
+        &self,
+        processes: usize,
+    ) -> Result<DownloadHandle<DownloadReport>, Error> {
+        let manager = self.controlled();
+        let control = manager.download_options.cancel.clone();
+        Ok(DownloadHandle::new(
+            control,
+            sync::spawn(async move { manager.download_all(processes).await })?,
+        ))
+    }
+
+    pub fn health_check(&self) -> Result<HealthReport, Error> {
+        sync::block_on(self.health_check_async())
+    }
+
+    /// Probes whether the model directory is writable and every registered source reachable with
+    /// the configured token, for readiness checks. Nothing is downloaded.
+    pub async fn health_check_async(&self) -> HealthReport {
+        let mut models = self.models.iter().collect::<Vec<_>>();
+        models.sort_by_key(|v| v.0);
+        let sources = models.into_iter().map(|(ident, model)| async move {
+            let endpoint = self.endpoint(&model.source);
+            let (status, error) = match self.offline {
+                true => (SourceStatus::Offline, None),
+                false => probe_source(&model.source, &self.download_options).await,
+            };
+            SourceHealth {
+                ident: ident.to_string(),
+                endpoint,
+                status,
+                error,
+            }
+        });
+        HealthReport {
+            disk: check_disk(&self.model_path),
+            sources: futures::future::join_all(sources).await,
+        }
+    }
+
+    /// Starts downloading the models on background tasks and returns right away, join them with
+    /// [`ModelManager::await_ready`]. Fails outside of a Tokio runtime.
+    pub fn prefetch(&self, idents: &[impl AsRef<str>]) -> Result<(), Error> {
+        let idents = idents
+            .iter()
+            .map(|v| match self.model(v.as_ref()) {
+                Some(_) => Ok(self.resolve(v.as_ref()).to_string()),
+                None => Err(Error::ModelNotFound),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut prefetching = sync::lock(&self.prefetching);
+        for ident in idents {
+            if prefetching.contains_key(&ident) {
+                continue;
+            }
+            let manager = self.clone();
+            let task = {
+                let ident = ident.to_string();
+                sync::spawn(async move { Ok(manager.get_model_async(&ident).await?.0) })?
+            };
+            prefetching.insert(ident, task);
+        }
+        Ok(())
+    }
+
+    /// Waits for the prefetch of a model and returns its path or the error it failed with. Models
+    /// that weren't prefetched, or were awaited before, are downloaded like
+    /// [`ModelManager::get_model_async`] does.
+    pub async fn await_ready(&self, ident: &str) -> Result<PathBuf, Error> {
+        let ident = self.resolve(ident);
+        let task = sync::lock(&self.prefetching).remove(ident);
+        match task {
+            Some(task) => task.await.map_err(Error::async_thread_join)?,
+            None => Ok(self.get_model_async(ident).await?.0),
+        }
+    }
+
+    /// Records a model and its dependencies in the download queue of the model directory, which
+    /// outlives the process. See [`ModelManager::process_queue`].
+    pub fn enqueue(&self, ident: &str) -> Result<(), Error> {
+        self.writable(ident)?;
+        let required = self.with_dependencies(self.resolve(ident))?;
+        queue::update(&self.queue_path(), |queued| {
+            for (ident, model) in required {
+                if !queued.iter().any(|v| &v.ident == ident) {
+                    queued.push(QueuedModel::new(ident, model));
+                }
+            }
+        })
+    }
+
+    /// Returns whether the model was queued.
+    pub fn dequeue(&self, ident: &str) -> Result<bool, Error> {
+        let ident = self.resolve(ident);
+        self.writable(ident)?;
+        queue::update(&self.queue_path(), |queued| {
+            let len = queued.len();
+            queued.retain(|v| v.ident != ident);
+            queued.len() != len
+        })
+    }
+
+    pub fn queue(&self) -> Result<Vec<QueuedModel>, Error> {
+        queue::read(&self.queue_path())
+    }
+
+    pub fn process_queue(&self) -> Result<DownloadReport, Error> {
+        sync::block_on(self.process_queue_async())?
+    }
+
+    /// Downloads the queued models in the order they were enqueued, removing each once it's
+    /// downloaded. Failed ones stay queued for the next run. Models that aren't registered are
+    /// downloaded with the definition they were enqueued with.
+    pub async fn process_queue_async(&self) -> Result<DownloadReport, Error> {
+        let queued = self.queue()?;
+        let mut manager = self.clone();
+        manager.register_models(
+            queued
+                .iter()
+                .filter(|v| self.model(&v.ident).is_none())
+                .map(|v| (v.ident.to_string(), v.model.clone()))
+                .collect(),
+        );
+        let mut report = DownloadReport::default();
+        for entry in queued {
+            match manager.get_model_async(&entry.ident).await {
+                Ok(_) => {
+                    self.dequeue(&entry.ident)?;
+                    report.succeeded.push(entry.ident);
+                }
+                Err(Error::Cancelled) => return Err(Error::Cancelled),
+                Err(err) => report.failed.push((entry.ident, err)),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Processes the queue on a spawned task until it's empty, trying again every `retry` while
+    /// downloads fail. Fails outside of a Tokio runtime.
+    pub fn start_queue(&self, retry: Duration) -> Result<DownloadHandle<DownloadReport>, Error> {
+        let manager = self.controlled();
+        let control = manager.download_options.cancel.clone();
+        Ok(DownloadHandle::new(
+            control.clone(),
+            sync::spawn(async move {
+                loop {
+                    let report = manager.process_queue_async().await?;
+                    // models enqueued meanwhile are picked up by the next pass
+                    if manager.queue()?.is_empty() {
+                        return Ok(report);
+                    }
+                    for (ident, err) in &report.failed {
+                        manager.status(
+                            Verbosity::Summary,
+                            manager.messages.retrying(ident, err, retry),
+                        );
+                    }
+                    tokio::select! {
+                        _ = tokio::time::sleep(retry) => {}
+                        _ = control.cancelled() => return Err(Error::Cancelled),
+                    }
+                }
+            })?,
+        ))
+    }
+
+    fn queue_path(&self) -> PathBuf {
+        self.model_path.join(QUEUE)
+    }
+
+    /// A clone whose downloads are paused and cancelled independently of this manager's, and
+    /// which are paused along with them.
+    fn controlled(&self) -> ModelManager {
+        let mut manager = self.clone();
+        manager.download_options.cancel = self.download_options.cancel.child();
+        manager
+    }
+
+    pub async fn download_all(&self, processes: usize) -> Result<DownloadReport, Error> {
+        self.download_models(self.models.iter().collect(), processes)
+            .await
+    }
+
+    /// Models of earlier batch downloads that failed with a transient error and haven't been
+    /// downloaded since.
+    pub fn failed_models(&self) -> Vec<String> {
+        sync::lock(&self.failed).clone()
+    }
+
+    /// Downloads the models of [`ModelManager::failed_models`] again, waiting between rounds by
+    /// the retry policy until they succeed, fail for good or the retries run out. The report has
+    /// every model that was attempted.
+    pub async fn retry_failed(&self) -> Result<DownloadReport, Error> {
+        let mut manager = self.clone();
+        manager.continue_on_error = true;
+        let mut report = DownloadReport::default();
+        let mut attempt = 0;
+        loop {
+            let mut models = vec![];
+            for ident in self.failed_models() {
+                if self.models.contains_key(&ident) {
+                    self.collect_dependencies(&ident, &mut vec![], &mut models)?;
+                }
+            }
+            if models.is_empty() {
+                return Ok(report);
+            }
+            let round = manager.download_models(models, self.concurrency).await?;
+            report.succeeded.extend(round.succeeded);
+            report.bytes += round.bytes;
+            report.retries += round.retries;
+            report.elapsed += round.elapsed;
+            let (transient, failed): (Vec<_>, Vec<_>) =
+                round.failed.into_iter().partition(|v| v.1.is_retryable());
+            report.failed.extend(failed);
+            if transient.is_empty() || attempt >= self.download_options.retry.max_retries {
+                report.failed.extend(transient);
+                return Ok(report);
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(self.download_options.retry.wait(attempt)) => {}
+                _ = self.download_options.cancel.cancelled() => return Err(Error::Cancelled),
+            }
+            attempt += 1;
+        }
+    }
+
+    pub async fn download_group(
+        &self,
+        group: &str,
+        processes: usize,
+    ) -> Result<DownloadReport, Error> {
+        let mut models = vec![];
+        for (ident, model) in &self.models {
+            if model.groups.iter().any(|g| g == group) {
+                self.collect_dependencies(ident, &mut vec![], &mut models)?;
+            }
+        }
+        self.download_models(models, processes).await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(models = models.len(), processes))
+    )]
+    async fn download_models(
+        &self,
+        models: Vec<(&String, &Model)>,
+        processes: usize,
+    ) -> Result<DownloadReport, Error> {
+        let _running = self.download_options.cancel.enter(self.handle_ctrl_c);
+        let started = Instant::now();
+        self.status(
+            Verbosity::Verbose,
+            format!(
+                "{} {}{}",
+                progress::step("[1/3]"),
+                self.emoji(&LOOKING_GLASS),
+                self.messages.resolving(models.len())
+            ),
+        );
+        let mut report = DownloadReport::default();
+        let (mut download, skipped): (Vec<_>, Vec<_>) = models
+            .into_iter()
+            .partition(|m| self.check_download_needed(m.1));
+        download.sort_by_key(|m| std::cmp::Reverse(m.1.priority));
+        report.skipped = skipped.into_iter().map(|m| m.0.to_string()).collect();
+        sync::lock(&self.failed).retain(|v| !report.skipped.contains(v));
+        let total = self.ensure_disk_space(download.clone()).await?;
+        self.status(
+            Verbosity::Verbose,
+            format!(
+                "{} {}{}",
+                progress::step("[2/3]"),
+                self.emoji(&LOOKING_GLASS),
+                self.messages.processing(download.len())
+            ),
+        );
+
+        self.status(
+            Verbosity::Verbose,
+            format!(
+                "{} {}{}",
+                progress::step("[3/3]"),
+                self.emoji(&LOOKING_GLASS),
+                self.messages.downloading_models()
+            ),
+        );
+
+        let observer = self.observer();
+        let tally = Tally::new(observer.as_ref());
+        let observer = &tally;
+        observer.on_batch(download.len(), total);
+        for (ident, _) in &download {
+            observer.on_queued(ident);
+        }
+        // futures created upfront, a closure in the stream keeps it from being spawned
+        let downloads = download
+            .into_iter()
+            .map(|v| async move { (v.0, self.download_within_deadline(v.0, v.1, observer).await) })
+            .collect::<Vec<_>>();
+        let mut handles = stream::iter(downloads).buffer_unordered(processes.max(1));
+        while let Some((ident, result)) = handles.next().await {
+            {
+                let mut failed = sync::lock(&self.failed);
+                failed.retain(|v| v != ident);
+                if result.as_ref().is_err_and(|err| err.is_retryable()) {
+                    failed.push(ident.to_string());
+                }
+            }
+            match result {
+                Ok(()) => report.succeeded.push(ident.to_string()),
+                Err(Error::Cancelled) => return Err(Error::Cancelled),
+                Err(err) if self.continue_on_error => report.failed.push((ident.to_string(), err)),
+                Err(err) => return Err(err),
+            }
+        }
+        report.bytes = tally.bytes.load(Ordering::Relaxed);
+        report.retries = tally.retries.load(Ordering::Relaxed);
+        report.elapsed = started.elapsed();
+        self.status(
+            Verbosity::Summary,
+            format!("{}{}", self.emoji(&SPARKLE), self.messages.done(&report)),
+        );
+
+        Ok(report)
+    }
+
+    fn emoji(&self, emoji: &dyn std::fmt::Display) -> String {
+        match self.emoji {
+            true => emoji.to_string(),
+            false => String::new(),
+        }
+    }
+
+    /// Summaries are logged at info, the steps of batch downloads at debug.
+    #[cfg(feature = "tracing")]
+    fn status(&self, level: Verbosity, message: String) {
+        if self.verbosity < level {
+            return;
+        }
+        let message = progress::plain(&message);
+        match level {
+            Verbosity::Silent => {}
+            Verbosity::Summary => tracing::info!("{}", message.trim()),
+            Verbosity::Verbose => tracing::debug!("{}", message.trim()),
+        }
+    }
+
+    #[cfg(all(feature = "progress", not(feature = "tracing")))]
+    fn status(&self, level: Verbosity, message: String) {
+        if self.verbosity < level {
+            return;
+        }
+        match &self.multi_progress {
+            Some(multi) => {
+                let _ = multi.println(message);
+            }
+            None => println!("{}", message),
+        }
+    }
+
+    /// Without the `progress` feature the library never writes to the terminal.
+    #[cfg(not(any(feature = "progress", feature = "tracing")))]
+    fn status(&self, _level: Verbosity, _message: String) {}
+}
+
+const DEFAULT_CONCURRENCY: usize = 4;
+const STAGING: &str = ".staging";
+const LOCKS: &str = ".locks";
+const JOURNAL: &str = ".journal";
+/// Extension of the journal next to the backup of a running cleanup.
+const CLEAN_JOURNAL: &str = "clean.toml";
+const QUEUE: &str = ".queue.toml";
+const HISTORY: &str = ".history.toml";
+const STATE: &str = ".state.toml";
+const NAMESPACES: &str = ".namespaces";
+/// Tokenizers downloaded for models whose own files don't include one.
+const TOKENIZERS: &str = ".tokenizers";
+const TOKENIZER_FILES: [&str; 4] = [
+    "tokenizer.json",
+    "tokenizer.model",
+    "tokenizer_config.json",
+    "special_tokens_map.json",
+];
+/// Time between attempts of a queue resumed by [`ModelManagerBuilder::resume_queue`].
+const QUEUE_RETRY: Duration = Duration::from_secs(60);
+const LOCK_POLL: Duration = Duration::from_millis(100);
+
+/// Released on drop, closing the file releases the advisory lock.
+struct ModelLock {
+    _flight: OwnedMutexGuard<()>,
+    _file: File,
+    in_flight: InFlight,
+    root: PathBuf,
+}
+
+impl Drop for ModelLock {
+    fn drop(&mut self) {
+        // the map and this guard hold the mutex, anything more is a waiting call
+        let mut in_flight = sync::lock(&self.in_flight);
+        if in_flight
+            .get(&self.root)
+            .is_some_and(|v| Arc::strong_count(v) <= 2)
+        {
+            in_flight.remove(&self.root);
+        }
+    }
+}
+const OFFLINE_REGISTRY: &str = "models.toml";
+const OFFLINE_LOCKFILE: &str = "models.lock";
+const OFFLINE_MODELS: &str = "models";
+
+#[derive(Default)]
+pub struct ModelManagerBuilder {
+    model_path: Option<PathBuf>,
+    app_name: Option<String>,
+    concurrency: Option<usize>,
+    parallel_files: Option<usize>,
+    retry: RetryPolicy,
+    token: Option<String>,
+    endpoint: Option<String>,
+    peers: Vec<String>,
+    host_limit: Option<HostLimit>,
+    http: Option<Arc<dyn HttpBackend>>,
+    offline: Option<bool>,
+    channel: Option<String>,
+    read_only: Option<bool>,
+    temp_dir: Option<PathBuf>,
+    permissions: Permissions,
+    layout: StorageLayout,
+    roots: Vec<PathBuf>,
+    placement: Placement,
+    max_size: Option<u64>,
+    cache_sizes: bool,
+    skip_disk_space_check: bool,
+    continue_on_error: bool,
+    verbosity: Verbosity,
+    no_emoji: bool,
+    messages: Option<Arc<dyn Messages>>,
+    handle_ctrl_c: bool,
+    resume_queue: bool,
+    #[cfg(feature = "progress")]
+    multi_progress: Option<MultiProgress>,
+    #[cfg(feature = "progress")]
+    progress_theme: Option<ProgressTheme>,
+    observer: Option<Arc<dyn ProgressObserver>>,
+    notifiers: Vec<Notifier>,
+    log: Option<LogFile>,
+    state: Option<Arc<dyn StateStore>>,
+    state_file: bool,
+}
+
+impl ModelManagerBuilder {
+    pub fn model_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.model_path = Some(path.into());
+        self
+    }
+
+    /// Store models in the per-user data directory of `app_name`
+    /// (e.g. `~/.local/share/<app_name>/models`) unless a model path is set.
+    pub fn app_name(mut self, app_name: impl ToString) -> Self {
+        self.app_name = Some(app_name.to_string());
+        self
+    }
+
+    /// Models downloaded at the same time, [`ModelManagerBuilder::build`] fails for 0.
+    pub fn concurrency(mut self, processes: usize) -> Self {
+        self.concurrency = Some(processes);
+        self
+    }
+
+    /// How many files of a single model are downloaded concurrently.
+    pub fn parallel_files(mut self, parallel_files: usize) -> Self {
+        self.parallel_files = Some(parallel_files);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn token(mut self, token: impl ToString) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Base URL replacing `https://huggingface.co`, e.g. a mirror or caching proxy.
+    pub fn endpoint(mut self, endpoint: impl ToString) -> Self {
+        self.endpoint = Some(endpoint.to_string());
+        self
+    }
+
+    /// Mirror started with `model-manager mirror` on another machine, preferred over the
+    /// endpoint for the files it has with the same sha256.
+    pub fn peer(mut self, url: impl ToString) -> Self {
+        self.peers.push(url.to_string());
+        self
+    }
+
+    /// Bandwidth and connections shared with other processes on the machine, see [`HostLimit`].
+    pub fn host_limit(mut self, limit: HostLimit) -> Self {
+        self.host_limit = Some(limit);
+        self
+    }
+
+    /// Sends file downloads through `backend` instead of reqwest.
+    pub fn http_backend(mut self, backend: Arc<dyn HttpBackend>) -> Self {
+        self.http = Some(backend);
+        self
+    }
+
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = Some(offline);
+        self
+    }
+
+    /// See [`ModelManager::set_channel`].
+    pub fn channel(mut self, channel: impl ToString) -> Self {
+        self.channel = Some(channel.to_string());
+        self
+    }
+
+    /// See [`ModelManager::set_permissions`].
+    pub fn permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// See [`ModelManager::set_temp_dir`].
+    pub fn temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = Some(dir.into());
+        self
+    }
+
+    /// See [`ModelManager::set_read_only`], defaults to the `MODEL_MANAGER_READ_ONLY` variable.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = Some(read_only);
+        self
+    }
+
+    /// See [`ModelManager::add_storage_root`], roots are filled in the order they're added.
+    pub fn storage_root(mut self, path: impl Into<PathBuf>) -> Self {
+        self.roots.push(path.into());
+        self
+    }
+
+    pub fn placement(mut self, placement: Placement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    pub fn storage_layout(mut self, layout: StorageLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    pub fn cache_sizes(mut self, enabled: bool) -> Self {
+        self.cache_sizes = enabled;
+        self
+    }
+
+    pub fn check_disk_space(mut self, enabled: bool) -> Self {
+        self.skip_disk_space_check = !enabled;
+        self
+    }
+
+    /// Keep downloading the remaining models of a batch when one fails and report the
+    /// failures instead of returning the first error.
+    pub fn continue_on_error(mut self, enabled: bool) -> Self {
+        self.continue_on_error = enabled;
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.verbosity = match quiet {
+            true => Verbosity::Silent,
+            false => Verbosity::Verbose,
+        };
+        self
+    }
+
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// See [`ModelManager::set_emoji`].
+    pub fn emoji(mut self, enabled: bool) -> Self {
+        self.no_emoji = !enabled;
+        self
+    }
+
+    /// See [`ModelManager::set_messages`].
+    pub fn messages(mut self, messages: Arc<dyn Messages>) -> Self {
+        self.messages = Some(messages);
+        self
+    }
+
+    /// While downloading, Ctrl-C cancels the downloads like [`ModelManager::cancel_downloads`]
+    /// instead of ending the process. Needs a tokio runtime.
+    pub fn handle_ctrl_c(mut self, enabled: bool) -> Self {
+        self.handle_ctrl_c = enabled;
+        self
+    }
+
+    /// Starts processing the download queue left by an earlier run in the background once
+    /// built, like [`ModelManager::start_queue`]. Building fails outside of a Tokio runtime then.
+    pub fn resume_queue(mut self, enabled: bool) -> Self {
+        self.resume_queue = enabled;
+        self
+    }
+
+    #[cfg(feature = "progress")]
+    pub fn multi_progress(mut self, multi: MultiProgress) -> Self {
+        self.multi_progress = Some(multi);
+        self
+    }
+
+    /// See [`ModelManager::set_progress_theme`].
+    #[cfg(feature = "progress")]
+    pub fn progress_theme(mut self, theme: ProgressTheme) -> Self {
+        self.progress_theme = Some(theme);
+        self
+    }
+
+    pub fn progress_observer(mut self, observer: Arc<dyn ProgressObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// See [`ModelManager::set_state_store`].
+    pub fn state_store(mut self, state: Arc<dyn StateStore>) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Keeps the state in a [`TomlStateStore`] at `.state.toml` in the model directory.
+    pub fn state_file(mut self, enabled: bool) -> Self {
+        self.state_file = enabled;
+        self
+    }
+
+    /// See [`ModelManager::set_log_file`].
+    pub fn log_file(mut self, log: LogFile) -> Self {
+        self.log = Some(log);
+        self
+    }
+
+    /// Async callback for every finished or failed model download, see
+    /// [`ModelManager::add_notifier`].
+    pub fn notify<F>(
+        mut self,
+        hook: impl Fn(DownloadNotification) -> F + Send + Sync + 'static,
+    ) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.notifiers.push(Notifier::hook(hook));
+        self
+    }
+
+    /// POSTs a JSON [`DownloadNotification`] to `url` for every finished or failed model
+    /// download.
+    pub fn webhook(mut self, url: impl ToString) -> Self {
+        self.notifiers.push(Notifier::webhook(url));
+        self
+    }
+
+    /// Unset options fall back to `MODEL_MANAGER_HOME`, `MODEL_MANAGER_OFFLINE`,
+    /// `MODEL_MANAGER_MAX_CONCURRENCY`, `HF_TOKEN` and `HF_ENDPOINT`.
+    pub fn build(self) -> Result<ModelManager, Error> {
+        let model_path = match (
+            self.model_path
+                .or_else(|| env("MODEL_MANAGER_HOME").map(PathBuf::from)),
+            self.app_name,
+        ) {
+            (Some(v), _) => v,
+            (None, Some(app)) => dirs::data_local_dir()
+                .ok_or_else(|| Error::pathbuf_custom("No data directory for this platform"))?
+                .join(app)
+                .join("models"),
+            (None, None) => PathBuf::from_str("models").map_err(Error::pathbuf_open)?,
+        };
+        let read_only = self
+            .read_only
+            .unwrap_or_else(|| env("MODEL_MANAGER_READ_ONLY").is_some_and(|v| is_true(&v)));
+        if !read_only {
+            std::fs::create_dir_all(&model_path).map_err(Error::write_path(&model_path))?;
+        }
+        let concurrency = match (self.concurrency, env("MODEL_MANAGER_MAX_CONCURRENCY")) {
+            (Some(v), _) => v,
+            (None, Some(v)) => v.parse().map_err(|_| {
+                Error::config(format!("Invalid MODEL_MANAGER_MAX_CONCURRENCY: {v}"))
+            })?,
+            (None, None) => DEFAULT_CONCURRENCY,
+        };
+        if concurrency == 0 {
+            return Err(Error::config("Concurrency must be at least 1"));
+        }
+        let offline = self
+            .offline
+            .unwrap_or_else(|| env("MODEL_MANAGER_OFFLINE").is_some_and(|v| is_true(&v)));
+
+        let mut manager = ModelManager::new_custom(model_path);
+        manager.concurrency = concurrency;
+        manager.offline = offline;
+        manager.channel = self.channel;
+        manager.read_only = read_only;
+        manager.temp_dir = self.temp_dir.map(paths::long);
+        manager.permissions = self.permissions;
+        manager.layout = self.layout;
+        for root in self.roots {
+            manager.add_storage_root(root);
+        }
+        manager.placement = self.placement;
+        manager.max_size = self.max_size;
+        manager.cache_sizes(self.cache_sizes);
+        manager.check_disk_space = !self.skip_disk_space_check;
+        manager.continue_on_error = self.continue_on_error;
+        manager.verbosity = self.verbosity;
+        manager.emoji = !self.no_emoji;
+        if let Some(messages) = self.messages {
+            manager.messages = messages;
+        }
+        manager.handle_ctrl_c = self.handle_ctrl_c;
+        #[cfg(feature = "progress")]
+        {
+            manager.multi_progress = self.multi_progress;
+            if let Some(theme) = self.progress_theme {
+                manager.set_progress_theme(theme)?;
+            }
+        }
+        manager.observer = self.observer;
+        manager.notifiers = self.notifiers;
+        if let Some(log) = self.log {
+            manager.set_log_file(log);
+        }
+        manager.state = match (self.state, self.state_file) {
+            (Some(state), _) => Some(state),
+            (None, true) => Some(Arc::new(TomlStateStore::new(
+                manager.model_path.join(STATE),
+            ))),
+            (None, false) => None,
+        };
+        manager.state_file = manager.state.is_some() && self.state_file;
+        manager.download_options = DownloadOptions {
+            retry: self.retry,
+            token: self.token.or_else(|| env("HF_TOKEN")),
+            endpoint: self.endpoint.or_else(|| env("HF_ENDPOINT")),
+            parallel_files: self
+                .parallel_files
+                .unwrap_or(DownloadOptions::default().parallel_files),
+            cancel: CancelToken::default(),
+            host_limit: self.host_limit,
+            http: self.http.unwrap_or_else(|| DownloadOptions::default().http),
+            peers: self.peers,
+        };
+        if self.resume_queue && !read_only && !manager.queue()?.is_empty() {
+            manager.start_queue(QUEUE_RETRY)?;
+        }
+        Ok(manager)
+    }
+}
+
+/// Semver, or a version like `2` or `v2.1` padded to `2.0.0` and `2.1.0`.
+fn parse_version(version: &str) -> Option<Version> {
+    let version = version.strip_prefix('v').unwrap_or(version);
+    if let Ok(version) = Version::parse(version) {
+        return Some(version);
+    }
+    let parts = version
+        .split('.')
+        .map(|v| v.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match parts[..] {
+        [major] => Some(Version::new(major, 0, 0)),
+        [major, minor] => Some(Version::new(major, minor, 0)),
+        _ => None,
+    }
+}
+
+fn env(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|v| !v.is_empty())
+}
+
+fn is_true(value: &str) -> bool {
+    matches!(value.to_lowercase().as_str(), "1" | "true" | "yes")
+}
+
+fn hf_cache_dir() -> Option<PathBuf> {
+    env("HF_HUB_CACHE")
+        .map(PathBuf::from)
+        .or_else(|| env("HF_HOME").map(|v| PathBuf::from(v).join("hub")))
+        .or_else(|| dirs::home_dir().map(|v| v.join(".cache").join("huggingface").join("hub")))
+}
+
+/// `(path in cache, file name)`
+type CachedFile = (PathBuf, String);
+
+/// The snapshot commit and every file of `source`, if the cache has all of them.
+fn cached_snapshot(
+    cache: &Path,
+    source: &HuggingfaceModel,
+) -> Result<Option<(String, Vec<CachedFile>)>, Error> {
+    let repo = cache.join(format!("models--{}", source.repo.replace('/', "--")));
+    let sha = match &source.commit {
+        Some(v) => v.to_string(),
+        None => match std::fs::read_to_string(repo.join("refs").join(source.reference())) {
+            Ok(v) => v.trim().to_string(),
+            Err(_) => return Ok(None),
+        },
+    };
+    let snapshot = repo.join("snapshots").join(&sha);
+    if !snapshot.is_dir() {
+        return Ok(None);
+    }
+    let names = match source.files.is_empty() {
+        false => source.files.clone(),
+        true => {
+            let mut names = vec![];
+            for file in list_files(&snapshot) {
+                let name = file
+                    .strip_prefix(&snapshot)
+                    .map_err(Error::pathbuf_custom)
+                    .map(paths::slash)?;
+                if source.matches_patterns(&name)? {
+                    names.push(name);
+                }
+            }
+            names
+        }
+    };
+    let mut files = vec![];
+    for name in names {
+        // snapshot entries are symlinks into `blobs/`
+        match std::fs::canonicalize(snapshot.join(paths::relative(&name)?)) {
+            Ok(path) if path.is_file() => files.push((path, name)),
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some((sha, files)))
+}
+
+#[derive(Clone, Debug)]
+pub struct ModelStatus {
+    pub ident: String,
+    pub registered: bool,
+    pub downloaded: bool,
+    pub expected_version: Option<String>,
+    pub installed_version: Option<String>,
+    pub revision: Option<String>,
+    pub size: u64,
+    pub last_used: Option<DateTime<Utc>>,
+    pub metadata: Option<ModelMetadata>,
+    /// From the definition, `None` for unregistered models.
+    pub capabilities: Option<Capabilities>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CleanOptions {
+    /// Backups left next to the model directory, this run's included.
+    pub keep_backups: usize,
+    /// Deleting backups needs this, otherwise they are listed in [`CleanReport::unconfirmed`].
+    pub confirm_delete: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CleanReport {
+    /// Backup of this run, unless nothing was removed or it was deleted right away.
+    pub backup: Option<PathBuf>,
+    /// Files moved out of the model directory, relative to it.
+    pub removed: Vec<PathBuf>,
+    pub deleted: Vec<PathBuf>,
+    /// Backups beyond the ones to keep, left in place without confirmation.
+    pub unconfirmed: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiskUsage {
+    pub ident: String,
+    pub registered: bool,
+    /// Bytes actually used.
+    pub size: u64,
+    /// Bytes of all files as seen through blob store links.
+    pub apparent_size: u64,
+    /// Largest first, relative to the model directory.
+    pub files: Vec<FileUsage>,
+}
+
+impl DiskUsage {
+    /// Bytes the blob store saves by storing identical files once.
+    pub fn dedup_savings(&self) -> u64 {
+        self.apparent_size.saturating_sub(self.size)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FileUsage {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct AvailableUpdate {
+    pub ident: String,
+    /// Commit or ETag on disk, `None` if it wasn't recorded.
+    pub installed: Option<String>,
+    pub latest: String,
+}
+
+/// See [`ModelManager::updates_available`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelUpdate {
+    pub ident: String,
+    pub kind: UpdateKind,
+    /// Installed version, or commit or ETag on disk, `None` if it wasn't recorded.
+    pub current: Option<String>,
+    pub latest: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateKind {
+    /// `latest` is the registered version.
+    Version,
+    /// `latest` is the upstream commit or ETag of the installed version.
+    Revision,
+}
+
+/// Tokenizer files of a model, at least one of `tokenizer` and `sentencepiece` is present.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TokenizerFiles {
+    pub dir: PathBuf,
+    /// `tokenizer.json` of the tokenizers library.
+    pub tokenizer: Option<PathBuf>,
+    /// `tokenizer.model` of sentencepiece.
+    pub sentencepiece: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub special_tokens_map: Option<PathBuf>,
+}
+
+impl TokenizerFiles {
+    fn find(dir: &Path) -> Option<Self> {
+        let [tokenizer, sentencepiece, config, special_tokens_map] =
+            TOKENIZER_FILES.map(|v| Some(dir.join(v)).filter(|v| v.is_file()));
+        if tokenizer.is_none() && sentencepiece.is_none() {
+            return None;
+        }
+        Some(Self {
+            dir: dir.to_path_buf(),
+            tokenizer,
+            sentencepiece,
+            config,
+            special_tokens_map,
+        })
+    }
+}
+
+/// The tokenizer files of the repo linked by `source`, or of its own repo at the commit the
+/// model was installed from, kept apart from the model with the commit or branch as the version.
+fn tokenizer_model(source: &HuggingfaceModel, installed: Option<String>) -> Model {
+    let mut links = match &source.tokenizer {
+        Some(repo) => HuggingfaceModel::new(repo),
+        None => HuggingfaceModel {
+            commit: installed.or(source.commit.clone()),
+            revision: source.revision.clone(),
+            ..HuggingfaceModel::new(&source.repo)
+        },
+    };
+    links.endpoint = source.endpoint.clone();
+    links.allow_patterns = TOKENIZER_FILES.map(String::from).to_vec();
+    let directory = Path::new(TOKENIZERS).join(paths::flat(Path::new(&links.repo)));
+    Model::new(directory, links.reference().to_string(), links.into())
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RepoInfo {
+    pub repo: String,
+    pub sha: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub pipeline_tag: Option<String>,
+    pub library_name: Option<String>,
+    pub license: Option<String>,
+    /// The README of the repo.
+    pub card: Option<String>,
+}
+
+fn read_version(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path.join("version")).ok()
+}
+
+fn verify_checksums(model: &Model, path: &Path) -> Result<(), Error> {
+    for (file, expected) in &model.checksums {
+        if let Err(err) = check_checksum(path, file, expected) {
+            // drop the version marker so the model is fetched again next time
+            let _ = std::fs::remove_file(path.join("version"));
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+fn check_checksum(path: &Path, file: &str, expected: &str) -> Result<(), Error> {
+    let actual = sha256_file(&path.join(paths::relative(file)?))?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(Error::ChecksumMismatch {
+            file: file.to_string(),
+            expected: expected.to_string(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+fn read_revision(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path.join("revision")).ok()
+}
+
+/// Commit of a Huggingface download or ETag of an archive.
+fn recorded_revision(model: &Model, path: &Path) -> Option<String> {
+    match model.source {
+        ModelSource::Huggingface(_) => read_revision(path),
+        ModelSource::Zip(_) => std::fs::read_to_string(path.join("etag")).ok(),
+    }
+}
+
+fn is_expired(path: &Path, ttl: Option<Duration>) -> bool {
+    let ttl = match ttl {
+        None => return false,
+        Some(v) => v,
+    };
+    std::fs::metadata(path.join("version"))
+        .and_then(|meta| meta.modified())
+        .map(|modified| modified.elapsed().is_ok_and(|age| age > ttl))
+        .unwrap_or(true)
+}
+
+fn read_last_used(path: &Path) -> Option<DateTime<Utc>> {
+    let timestamp = std::fs::read_to_string(path.join("last_used")).ok()?;
+    Utc.timestamp_opt(timestamp.trim().parse().ok()?, 0)
+        .single()
+}
+
+fn remove_model_dir(root: &Path, directory: &Path) -> Result<(), Error> {
+    use std::path::Component;
+    if directory
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+        || directory.as_os_str().is_empty()
+    {
+        return Err(Error::pathbuf_custom(format!(
+            "Refusing to remove {:?}, it is not a directory inside the model path",
+            directory
+        )));
+    }
+    let path = root.join(directory);
+    if path.exists() {
+        std::fs::remove_dir_all(path).map_err(Error::write_file)?;
+    }
+    Ok(())
+}
+
+pub(crate) const METADATA_FILES: [&str; 8] = [
+    "version",
+    MANIFEST,
+    CHECKSUMS,
+    "last_used",
+    "revision",
+    "reference",
+    "etag",
+    REPO_INFO,
+];
+const REPO_INFO: &str = "repo_info.toml";
+
+pub(crate) fn list_files(path: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .flat_map(|entry| match entry.file_type() {
+                    Ok(t) if t.is_dir() => list_files(&entry.path()),
+                    Ok(t) if t.is_file() => vec![entry.path()],
+                    _ => vec![],
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Like `list_files` but including links to files, like the blobs of a hub snapshot. Linked
+/// directories such as `latest` are skipped so nothing is listed twice.
+pub(crate) fn list_files_following(path: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .flat_map(|entry| match entry.file_type() {
+                    Ok(t) if t.is_dir() => list_files_following(&entry.path()),
+                    Ok(t) if t.is_file() => vec![entry.path()],
+                    Ok(t) if t.is_symlink() && entry.path().is_file() => vec![entry.path()],
+                    _ => vec![],
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Files hard-linked between versions are counted once.
+fn dir_size(path: &Path) -> u64 {
+    dir_size_with(path, &mut HashSet::new())
+}
+
+fn dir_size_with(path: &Path, linked: &mut HashSet<(u64, u64)>) -> u64 {
+    std::fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| match entry.file_type() {
+                    Ok(t) if t.is_dir() => dir_size_with(&entry.path(), linked),
+                    Ok(t) if t.is_file() => match entry.metadata() {
+                        Ok(m) if first_link(&m, linked) => m.len(),
+                        _ => 0,
+                    },
+                    _ => 0,
+                })
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(unix)]
+fn first_link(metadata: &std::fs::Metadata, linked: &mut HashSet<(u64, u64)>) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink() < 2 || linked.insert((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn first_link(_: &std::fs::Metadata, _: &mut HashSet<(u64, u64)>) -> bool {
+    true
+}
+
+/// Not `Hash`, its maps aren't.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Model {
+    /// Relative to the model path, or absolute for a model that must live on a specific mount.
+    #[serde(default)]
+    pub directory: PathBuf,
+    pub version: String,
+    pub source: ModelSource,
+    #[serde(default, with = "seconds", skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<Duration>,
+    /// Time budget for downloading the model, after which the download is cancelled with
+    /// [`Error::DeadlineExceeded`] and its partial files kept.
+    #[serde(default, with = "seconds", skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<Duration>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub versions: HashMap<String, ModelSource>,
+    /// Channels like `stable` or `beta` and the version they point at, the model's version or
+    /// one of its alternate versions. See [`ModelManager::set_channel`].
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub channels: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// Free-form labels, recorded with the installed version.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub checksums: HashMap<String, String>,
+    /// Higher priorities are downloaded first by batch downloads.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Capabilities>,
+}
+
+/// What a model does and needs, declared in its definition so applications can pick the best
+/// model that's downloaded and fits the hardware, see [`ModelManager::best_model`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Like `text-generation` or `automatic-speech-recognition`, as the hub's pipeline tags.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task: Option<String>,
+    /// In tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<u64>,
+    /// Like `bf16`, `fp16` or `q4_k_m`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precision: Option<String>,
+    /// GPU memory needed to run the model, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vram: Option<u64>,
+}
+
+impl Capabilities {
+    pub fn task(mut self, task: impl ToString) -> Self {
+        self.task = Some(task.to_string());
+        self
+    }
+
+    pub fn context_length(mut self, tokens: u64) -> Self {
+        self.context_length = Some(tokens);
+        self
+    }
+
+    pub fn parameters(mut self, parameters: u64) -> Self {
+        self.parameters = Some(parameters);
+        self
+    }
+
+    pub fn precision(mut self, precision: impl ToString) -> Self {
+        self.precision = Some(precision.to_string());
+        self
+    }
+
+    pub fn vram(mut self, bytes: u64) -> Self {
+        self.vram = Some(bytes);
+        self
+    }
+}
+
+/// `model` with the version `channel` points at as its version and the registered one as an
+/// alternate version. Unchanged if it doesn't define the channel.
+fn on_channel(mut model: Model, channel: Option<&str>) -> Model {
+    let Some(version) = channel.and_then(|v| model.channels.get(v)).cloned() else {
+        return model;
+    };
+    if let Some(source) = model.versions.remove(&version) {
+        let registered = std::mem::replace(&mut model.version, version);
+        let registered_source = std::mem::replace(&mut model.source, source);
+        model.versions.insert(registered, registered_source);
+    }
+    model
+}
+
+fn is_zero(v: &i32) -> bool {
+    *v == 0
+}
+
+mod seconds {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(ttl: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        match ttl {
+            Some(v) => s.serialize_some(&v.as_secs()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(d)?.map(Duration::from_secs))
+    }
+}
+
+impl Model {
+    pub fn new(directory: impl Into<PathBuf>, version: impl ToString, source: ModelSource) -> Self {
+        Self {
+            directory: directory.into(),
+            version: version.to_string(),
+            source,
+            ttl: None,
+            deadline: None,
+            versions: HashMap::new(),
+            channels: HashMap::new(),
+            groups: vec![],
+            tags: vec![],
+            dependencies: vec![],
+            checksums: HashMap::new(),
+            priority: 0,
+            capabilities: None,
+        }
+    }
+
+    pub fn huggingface(repo: impl ToString) -> Self {
+        let source = HuggingfaceModel::new(repo);
+        Self::new(source.repo.clone(), "1", source.into())
+    }
+
+    pub fn zip(directory: impl Into<PathBuf>, url: impl ToString) -> Self {
+        Self::new(directory, "1", ModelSource::Zip(url.to_string()))
+    }
+
+    /// Only applies to Huggingface sources.
+    pub fn files(mut self, files: impl IntoIterator<Item = impl ToString>) -> Self {
+        if let ModelSource::Huggingface(v) = &mut self.source {
+            v.files = files.into_iter().map(|f| f.to_string()).collect();
+        }
+        self
+    }
+
+    /// Only applies to Huggingface sources.
+    pub fn commit(mut self, commit: impl ToString) -> Self {
+        if let ModelSource::Huggingface(v) = &mut self.source {
+            v.commit = Some(commit.to_string());
+        }
+        self
+    }
+
+    /// Only applies to Huggingface sources.
+    pub fn revision(mut self, revision: impl ToString) -> Self {
+        if let ModelSource::Huggingface(v) = &mut self.source {
+            v.revision = Some(revision.to_string());
+        }
+        self
+    }
+
+    /// Only applies to Huggingface sources without an explicit file list.
+    pub fn allow_pattern(mut self, pattern: impl ToString) -> Self {
+        if let ModelSource::Huggingface(v) = &mut self.source {
+            v.allow_patterns.push(pattern.to_string());
+        }
+        self
+    }
+
+    /// Only applies to Huggingface sources without an explicit file list.
+    pub fn ignore_pattern(mut self, pattern: impl ToString) -> Self {
+        if let ModelSource::Huggingface(v) = &mut self.source {
+            v.ignore_patterns.push(pattern.to_string());
+        }
+        self
+    }
+
+    pub fn directory(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.directory = directory.into();
+        self
+    }
+
+    pub fn version(mut self, version: impl ToString) -> Self {
+        self.version = version.to_string();
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn alternate_version(mut self, version: impl ToString, source: ModelSource) -> Self {
+        self.versions.insert(version.to_string(), source);
+        self
+    }
+
+    pub fn channel(mut self, channel: impl ToString, version: impl ToString) -> Self {
+        self.channels
+            .insert(channel.to_string(), version.to_string());
+        self
+    }
+
+    pub fn group(mut self, group: impl ToString) -> Self {
+        self.groups.push(group.to_string());
+        self
+    }
+
+    pub fn depends_on(mut self, ident: impl ToString) -> Self {
+        self.dependencies.push(ident.to_string());
+        self
+    }
+
+    pub fn checksum(mut self, file: impl ToString, sha256: impl ToString) -> Self {
+        self.checksums.insert(file.to_string(), sha256.to_string());
+        self
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn tag(mut self, tag: impl ToString) -> Self {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zip_model(directory: &str) -> Model {
+        Model::new(
+            directory,
+            "1",
+            ModelSource::Zip("https://example.com/model.zip".into()),
+        )
+    }
+
+    /// A model directory with `size` bytes of weights, last used at `last_used`.
+    fn install(path: &Path, directory: &str, size: usize, last_used: i64) {
+        let dir = path.join(directory);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("weights"), vec![0; size]).unwrap();
+        std::fs::write(dir.join("version"), "1").unwrap();
+        std::fs::write(dir.join("last_used"), last_used.to_string()).unwrap();
+    }
+
+    #[test]
+    fn parse_zip_sources() {
+        assert_eq!(
+            "zip://example.com/model.zip"
+                .parse::<ModelSource>()
+                .unwrap(),
+            ModelSource::Zip("https://example.com/model.zip".to_string())
+        );
+        assert_eq!(
+            "http://example.com/model.zip"
+                .parse::<ModelSource>()
+                .unwrap(),
+            ModelSource::Zip("http://example.com/model.zip".to_string())
+        );
+        // registries accept the compact form in place of the tagged one
+        let model: Model = toml::from_str(
+            "directory = \"m\"\nversion = \"1\"\nsource = \"zip://example.com/m.zip\"",
+        )
+        .unwrap();
+        assert_eq!(
+            model.source,
+            ModelSource::Zip("https://example.com/m.zip".to_string())
+        );
+    }
+
+    #[test]
+    fn dependencies_come_before_their_dependents() {
+        let mut manager = ModelManager::new_custom(PathBuf::from("models"));
+        manager.register_models(HashMap::from([
+            (
+                "app".to_string(),
+                zip_model("app").depends_on("a").depends_on("b"),
+            ),
+            ("a".to_string(), zip_model("a").depends_on("base")),
+            ("b".to_string(), zip_model("b").depends_on("base")),
+            ("base".to_string(), zip_model("base")),
+        ]));
+        let order = manager.dependencies("app").unwrap();
+        let order = order.iter().map(|v| v.0.as_str()).collect::<Vec<_>>();
+        assert_eq!(order, ["base", "a", "b"]);
+        assert!(manager.dependencies("base").unwrap().is_empty());
+    }
+
+    #[test]
+    fn dependency_cycles_are_reported() {
+        let mut manager = ModelManager::new_custom(PathBuf::from("models"));
+        manager.register_models(HashMap::from([
+            ("a".to_string(), zip_model("a").depends_on("b")),
+            ("b".to_string(), zip_model("b").depends_on("c")),
+            ("c".to_string(), zip_model("c").depends_on("a")),
+            (
+                "missing".to_string(),
+                zip_model("missing").depends_on("unknown"),
+            ),
+        ]));
+        assert!(matches!(
+            manager.dependencies("a"),
+            Err(Error::DependencyCycle(v)) if v == "a"
+        ));
+        assert!(matches!(
+            manager.dependencies("missing"),
+            Err(Error::ModelNotFound)
+        ));
+    }
+
+    #[test]
+    fn clean_moves_unregistered_files_into_a_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let models = dir.path().join("models");
+        let mut manager = ModelManager::new_custom(models.clone());
+        manager.register_models(HashMap::from([("kept".to_string(), zip_model("kept"))]));
+        install(&models, "kept", 10, 100);
+        install(&models, "stray", 10, 100);
+
+        let report = manager
+            .clean_directory_with(&CleanOptions::default())
+            .unwrap();
+        let backup = report.backup.unwrap();
+        assert!(models.join("kept").join("weights").exists());
+        assert!(!models.join("stray").exists());
+        assert!(backup.join("stray").join("weights").exists());
+        assert!(!backup.with_extension(CLEAN_JOURNAL).exists());
+    }
+
+    #[test]
+    fn interrupted_cleanups_are_rolled_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let models = dir.path().join("models");
+        let mut manager = ModelManager::new_custom(models.clone());
+        manager.register_models(HashMap::from([("kept".to_string(), zip_model("kept"))]));
+        install(&models, "kept", 10, 100);
+        install(&models, "stray", 10, 100);
+
+        // a process that died right after moving the directory away
+        let journal_path = manager.backup_path_with(1, CLEAN_JOURNAL).unwrap();
+        let mut journal = CleanJournal::default();
+        journal
+            .rename(&journal_path, &models, &manager.backup_path(1).unwrap())
+            .unwrap();
+        assert!(!models.exists());
+
+        manager.rollback_cleanups().unwrap();
+        assert!(!journal_path.exists());
+        assert!(!manager.backup_path(1).unwrap().exists());
+        assert!(models.join("kept").join("weights").exists());
+        assert!(models.join("stray").join("weights").exists());
+    }
+
+    #[test]
+    fn parse_loose_versions() {
+        assert_eq!(parse_version("1.2.3"), Some(Version::new(1, 2, 3)));
+        assert_eq!(parse_version("v1.2.3"), Some(Version::new(1, 2, 3)));
+        assert_eq!(parse_version("2"), Some(Version::new(2, 0, 0)));
+        assert_eq!(parse_version("v2.1"), Some(Version::new(2, 1, 0)));
+        assert_eq!(
+            parse_version("1.0.0-beta.1"),
+            Version::parse("1.0.0-beta.1").ok()
+        );
+        for invalid in ["", "latest", "1.2.3.4", "1.x", "2024-01-01"] {
+            assert_eq!(parse_version(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn matching_version_prefers_installed_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = ModelManager::new_custom(dir.path().to_path_buf());
+        manager.set_storage_layout(StorageLayout::Versioned { keep: 1 });
+        let mut model = zip_model("model");
+        for version in ["1.1", "v1.2.0", "2.0.0", "nightly"] {
+            model.versions.insert(
+                version.to_string(),
+                ModelSource::Zip(format!("https://example.com/{version}.zip")),
+            );
+        }
+        manager.register_models(HashMap::from([("model".to_string(), model)]));
+        let matching = |v: &str| manager.matching_version("model", &VersionReq::parse(v).unwrap());
+
+        assert_eq!(matching("*").unwrap(), "2.0.0");
+        assert_eq!(matching("^1").unwrap(), "v1.2.0");
+        assert_eq!(matching("~1.1").unwrap(), "1.1");
+        assert!(matches!(matching(">=3"), Err(Error::VersionNotFound)));
+
+        install(&dir.path().join("model"), "1.1", 10, 100);
+        std::fs::write(dir.path().join("model").join("1.1").join("version"), "1.1").unwrap();
+        assert_eq!(matching("^1").unwrap(), "1.1");
+        assert_eq!(matching("*").unwrap(), "1.1");
+    }
+
+    #[test]
+    fn matching_version_ignores_alternates_without_a_versioned_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = ModelManager::new_custom(dir.path().to_path_buf());
+        let mut model = zip_model("model");
+        model.versions.insert(
+            "2".to_string(),
+            ModelSource::Zip("https://example.com/2.zip".to_string()),
+        );
+        manager.register_models(HashMap::from([("model".to_string(), model)]));
+        let matching = |v: &str| manager.matching_version("model", &VersionReq::parse(v).unwrap());
+
+        assert_eq!(matching("*").unwrap(), "1");
+        assert!(matches!(matching("^2"), Err(Error::VersionNotFound)));
+    }
+
+    /// Sends SIGINT to the test process, only while a download handling it runs.
+    #[cfg(unix)]
+    #[test]
+    fn ctrl_c_cancels_consecutive_blocking_downloads() {
+        // answers with the headers of a large archive and never sends its body
+        let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/model.zip", server.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let mut connections = vec![];
+            for mut stream in server.incoming().flatten() {
+                let _ = std::io::Read::read(&mut stream, &mut [0; 4096]);
+                let _ = std::io::Write::write_all(
+                    &mut stream,
+                    b"HTTP/1.1 200 OK\r\ncontent-length: 1000000\r\n\r\n",
+                );
+                connections.push(stream);
+            }
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = ModelManager::new_custom(dir.path().to_path_buf());
+        manager.handle_ctrl_c = true;
+        manager.set_quiet(true);
+        let model = Model::new("model", "1", ModelSource::Zip(url));
+        manager.register_models(HashMap::from([("model".to_string(), model)]));
+        let manager = Arc::new(manager);
+
+        for _ in 0..2 {
+            let (done, finished) = std::sync::mpsc::channel();
+            std::thread::spawn({
+                let manager = manager.clone();
+                move || {
+                    let _ = done.send(manager.get_model("model").map(|_| ()));
+                }
+            });
+            std::thread::sleep(Duration::from_millis(500));
+            std::process::Command::new("kill")
+                .args(["-INT", &std::process::id().to_string()])
+                .status()
+                .unwrap();
+            let result = finished.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert!(matches!(result, Err(Error::Cancelled)), "{result:?}");
+        }
+    }
+
+    #[test]
+    fn read_only_serves_installed_alternate_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = ModelManager::new_custom(dir.path().to_path_buf());
+        manager.set_storage_layout(StorageLayout::Versioned { keep: 1 });
+        let mut model = zip_model("model");
+        for version in ["2.0.0", "3.0.0"] {
+            model.versions.insert(
+                version.to_string(),
+                ModelSource::Zip(format!("https://example.com/{version}.zip")),
+            );
+        }
+        manager.register_models(HashMap::from([("model".to_string(), model)]));
+        install(&dir.path().join("model"), "2.0.0", 10, 100);
+        std::fs::write(
+            dir.path().join("model").join("2.0.0").join("version"),
+            "2.0.0",
+        )
+        .unwrap();
+        manager.set_read_only(true);
+
+        let (path, _) = manager.get_model_version("model", "2.0.0").unwrap();
+        assert_eq!(path, dir.path().join("model").join("2.0.0"));
+        assert_eq!(
+            manager.get_model_matching("model", "^2").unwrap(),
+            (path, "2.0.0".to_string())
+        );
+        assert!(matches!(
+            manager.get_model_version("model", "3.0.0"),
+            Err(Error::ReadOnly(_))
+        ));
+    }
+
+    #[test]
+    fn verify_checks_recorded_and_locked_checksums() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = ModelManager::new_custom(dir.path().to_path_buf());
+        let model = Model::new(
+            "hf",
+            "1",
+            ModelSource::Huggingface(HuggingfaceModel::new("org/repo")),
+        );
+        manager.register_models(HashMap::from([
+            ("hf".to_string(), model.clone()),
+            ("zip".to_string(), zip_model("zip")),
+        ]));
+        install(dir.path(), "hf", 10, 100);
+        install(dir.path(), "zip", 10, 100);
+        let installed = dir.path().join("hf");
+        let sha256 = sha256_file(&installed.join("weights")).unwrap();
+        std::fs::write(
+            installed.join(CHECKSUMS),
+            format!("weights = \"{sha256}\"\n"),
+        )
+        .unwrap();
+        manager.write_manifest(&model, &installed).unwrap();
+
+        assert_eq!(manager.verify("hf").unwrap(), 1);
+        // nothing recorded to check against
+        assert_eq!(manager.verify("zip").unwrap(), 0);
+
+        let mut lockfile = Lockfile::default();
+        lockfile.models.insert(
+            "hf".to_string(),
+            LockedModel {
+                version: "1".to_string(),
+                definition: definition_hash(&model).unwrap(),
+                revision: None,
+                files: BTreeMap::from([(
+                    "weights".to_string(),
+                    LockedFile {
+                        size: Some(10),
+                        sha256: Some("0".repeat(64)),
+                    },
+                )]),
+            },
+        );
+        assert!(matches!(
+            manager.verify_locked("hf", &lockfile),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+
+        std::fs::write(installed.join("weights"), vec![1; 10]).unwrap();
+        assert!(matches!(
+            manager.verify("hf"),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn gc_evicts_least_recently_used_until_under_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = ModelManager::new_custom(dir.path().to_path_buf());
+        manager.register_models(HashMap::from([
+            ("old".to_string(), zip_model("old")),
+            ("new".to_string(), zip_model("new")),
+            ("pinned".to_string(), zip_model("pinned")),
+        ]));
+        install(dir.path(), "old", 1000, 100);
+        install(dir.path(), "new", 1000, 200);
+        install(dir.path(), "pinned", 1000, 50);
+        manager.pin("pinned").unwrap();
+
+        assert!(manager.gc_plan().unwrap().is_empty());
+        manager.set_max_size(Some(2500));
+        assert_eq!(manager.gc_plan().unwrap(), ["old"]);
+        manager.set_max_size(Some(1500));
+        assert_eq!(manager.gc_plan().unwrap(), ["old", "new"]);
+        manager.set_max_size(Some(0));
+        assert_eq!(manager.gc().unwrap(), ["old", "new"]);
+        assert!(!dir.path().join("old").exists());
+        assert!(!dir.path().join("new").exists());
+        assert!(dir.path().join("pinned").exists());
+    }
+
+    #[test]
+    fn zero_concurrency_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let builder = || ModelManager::builder().model_path(dir.path());
+        assert!(matches!(
+            builder().concurrency(0).build(),
+            Err(Error::ConfigError(_))
+        ));
+        assert_eq!(builder().concurrency(2).build().unwrap().concurrency(), 2);
+    }
+
+    #[cfg(feature = "bundle")]
+    fn bundle_of(model: &Model, files: &[(&str, &str)]) -> Vec<u8> {
+        let source = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            std::fs::write(source.path().join(name), content).unwrap();
+        }
+        let mut out = vec![];
+        bundle::write_bundle(&mut out, "model", model, source.path()).unwrap();
+        out
+    }
+
+    #[cfg(feature = "bundle")]
+    #[test]
+    fn import_rejects_directories_outside_the_model_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let victim = dir.path().join("victim");
+        std::fs::create_dir_all(&victim).unwrap();
+        std::fs::write(victim.join("keep"), "keep").unwrap();
+        let mut manager = ModelManager::new_custom(dir.path().join("models"));
+        for directory in [victim.clone(), PathBuf::from("../victim")] {
+            let model = Model::new(directory, "1", ModelSource::Zip("zip".into()));
+            let bundle = bundle_of(&model, &[("version", "1")]);
+            assert!(manager.import(bundle.as_slice()).is_err());
+            assert!(victim.join("keep").exists());
+        }
+        assert!(manager.model("model").is_none());
+    }
+
+    #[cfg(feature = "bundle")]
+    #[test]
+    fn failed_import_keeps_the_installed_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = ModelManager::new_custom(dir.path().to_path_buf());
+        let model = Model::new("model", "1", ModelSource::Zip("zip".into()));
+        let bundle = bundle_of(&model, &[("version", "1"), ("weights", "old")]);
+        assert_eq!(manager.import(bundle.as_slice()).unwrap(), "model");
+
+        let bundle = bundle_of(&model, &[("version", "1"), ("weights", "new")]);
+        assert!(manager.import(&bundle[..bundle.len() / 2]).is_err());
+        let weights = dir.path().join("model").join("weights");
+        assert_eq!(std::fs::read_to_string(&weights).unwrap(), "old");
+
+        assert!(manager.import(bundle.as_slice()).is_ok());
+        assert_eq!(std::fs::read_to_string(&weights).unwrap(), "new");
     }
 }
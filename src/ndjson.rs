@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::progress::ProgressObserver;
+use crate::sync;
+
+/// Writes one JSON object per line for every step of a download, for CI logs and log
+/// aggregators that can't use redrawn progress bars. Byte counts of a file are written at most
+/// once per interval, plus once it's done.
+pub struct NdjsonObserver {
+    writer: Mutex<Box<dyn Write + Send>>,
+    interval: Duration,
+    files: Mutex<HashMap<(String, String), FileProgress>>,
+}
+
+struct FileProgress {
+    total: Option<u64>,
+    /// Bytes the transfer started from, a resumed file already had them.
+    initial: Option<u64>,
+    done: u64,
+    started: Instant,
+    written: Option<Instant>,
+}
+
+impl FileProgress {
+    /// Average bytes per second since the transfer started.
+    fn speed(&self) -> Option<u64> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let received = self.done.saturating_sub(self.initial?);
+        (elapsed > 0.0).then(|| (received as f64 / elapsed) as u64)
+    }
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ProgressState {
+    Queued,
+    Started,
+    Downloading,
+    Retrying,
+    FileDone,
+    Unpacking,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    model: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<&'a str>,
+    state: ProgressState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes_done: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<u64>,
+    /// Bytes per second.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl<'a> Line<'a> {
+    fn new(model: &'a str, file: Option<&'a str>, state: ProgressState) -> Self {
+        Self {
+            model,
+            file,
+            state,
+            bytes_done: None,
+            total: None,
+            speed: None,
+            error: None,
+        }
+    }
+
+    fn progress(mut self, progress: &FileProgress) -> Self {
+        self.bytes_done = Some(progress.done);
+        self.total = progress.total;
+        self.speed = progress.speed();
+        self
+    }
+}
+
+impl NdjsonObserver {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+            interval: Duration::from_secs(1),
+            files: Mutex::default(),
+        }
+    }
+
+    /// Time between two lines with the bytes of the same file, one second by default.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    fn write(&self, line: Line<'_>) {
+        let Ok(mut json) = serde_json::to_vec(&line) else {
+            return;
+        };
+        json.push(b'\n');
+        let mut writer = sync::lock(&self.writer);
+        // progress is best effort, a closed pipe must not fail the download
+        let _ = writer.write_all(&json).and_then(|_| writer.flush());
+    }
+}
+
+impl ProgressObserver for NdjsonObserver {
+    fn on_queued(&self, model: &str) {
+        self.write(Line::new(model, None, ProgressState::Queued));
+    }
+
+    fn on_start(&self, model: &str, file: &str, total: Option<u64>) {
+        let progress = FileProgress {
+            total,
+            initial: None,
+            done: 0,
+            started: Instant::now(),
+            written: None,
+        };
+        let line = Line::new(model, Some(file), ProgressState::Started).progress(&progress);
+        self.write(line);
+        let key = (model.to_string(), file.to_string());
+        sync::lock(&self.files).insert(key, progress);
+    }
+
+    fn on_bytes(&self, model: &str, file: &str, downloaded: u64) {
+        let mut files = sync::lock(&self.files);
+        let Some(progress) = files.get_mut(&(model.to_string(), file.to_string())) else {
+            return;
+        };
+        progress.initial.get_or_insert(downloaded);
+        progress.done = downloaded;
+        if progress
+            .written
+            .is_some_and(|v| v.elapsed() < self.interval)
+        {
+            return;
+        }
+        progress.written = Some(Instant::now());
+        let line = Line::new(model, Some(file), ProgressState::Downloading).progress(progress);
+        self.write(line);
+    }
+
+    fn on_file_done(&self, model: &str, file: &str) {
+        let progress = sync::lock(&self.files).remove(&(model.to_string(), file.to_string()));
+        let line = Line::new(model, Some(file), ProgressState::FileDone);
+        self.write(match &progress {
+            Some(progress) => line.progress(progress),
+            None => line,
+        });
+    }
+
+    fn on_retry(&self, model: &str, file: &str, _attempt: usize) {
+        self.write(Line::new(model, Some(file), ProgressState::Retrying));
+    }
+
+    fn on_unpack(&self, model: &str) {
+        self.write(Line::new(model, None, ProgressState::Unpacking));
+    }
+
+    fn on_model_done(&self, model: &str) {
+        sync::lock(&self.files).retain(|(m, _), _| m != model);
+        self.write(Line::new(model, None, ProgressState::Done));
+    }
+
+    fn on_error(&self, model: &str, error: &Error) {
+        sync::lock(&self.files).retain(|(m, _), _| m != model);
+        let mut line = Line::new(model, None, ProgressState::Failed);
+        line.error = Some(error.report());
+        self.write(line);
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Time a webhook gets to answer before it's given up on.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sent when a model download finished or failed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadNotification {
+    pub ident: String,
+    pub version: String,
+    pub duration_ms: u64,
+    /// `None` if the download succeeded.
+    pub error: Option<String>,
+}
+
+impl DownloadNotification {
+    pub(crate) fn new(
+        ident: &str,
+        version: &str,
+        duration: Duration,
+        result: &Result<(), Error>,
+    ) -> Self {
+        Self {
+            ident: ident.to_string(),
+            version: version.to_string(),
+            duration_ms: duration.as_millis() as u64,
+            error: result.as_ref().err().map(|v| v.report()),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+type Hook = Arc<dyn Fn(DownloadNotification) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Where [`DownloadNotification`]s go.
+#[derive(Clone)]
+pub enum Notifier {
+    Hook(Hook),
+    /// The notification is POSTed as JSON.
+    Webhook(String),
+}
+
+impl Notifier {
+    pub fn hook<F>(hook: impl Fn(DownloadNotification) -> F + Send + Sync + 'static) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        Self::Hook(Arc::new(move |v| hook(v).boxed()))
+    }
+
+    pub fn webhook(url: impl ToString) -> Self {
+        Self::Webhook(url.to_string())
+    }
+
+    /// Notifications are best effort, a failing webhook doesn't fail the download.
+    pub(crate) async fn send(&self, notification: &DownloadNotification) {
+        match self {
+            Notifier::Hook(hook) => hook(notification.clone()).await,
+            Notifier::Webhook(url) => {
+                let request = reqwest::Client::new()
+                    .post(url)
+                    .timeout(WEBHOOK_TIMEOUT)
+                    .json(notification);
+                let _ = request.send().await;
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Notifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Notifier::Hook(_) => f.write_str("Hook"),
+            Notifier::Webhook(url) => f.debug_tuple("Webhook").field(url).finish(),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::Error;
+
+/// Local path of a file name from a repo or archive, which always separates directories with
+/// `/`. File lists come from servers, names leaving the model directory are refused.
+pub(crate) fn relative(name: &str) -> Result<PathBuf, Error> {
+    let path = name
+        .split('/')
+        .filter(|v| !v.is_empty() && *v != ".")
+        .collect::<PathBuf>();
+    match is_contained(&path) {
+        true => Ok(path),
+        false => Err(Error::pathbuf_custom(format!(
+            "{name} leaves the model directory"
+        ))),
+    }
+}
+
+/// Name of a local path relative to a model directory in the `/` form repos use.
+pub(crate) fn slash(path: &Path) -> String {
+    join(path, "/")
+}
+
+/// A relative path as a single file name, like `org--model` for `org/model`.
+pub(crate) fn flat(path: &Path) -> String {
+    join(path, "--")
+}
+
+fn join(path: &Path, separator: &str) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(v) => Some(v.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Whether `path` only names directories below the one it's joined to, without a root, `.` or
+/// `..`.
+pub(crate) fn is_contained(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Rebuilds a relative path from its components, so `/` in a registry becomes the native
+/// separator.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+/// `path` with `suffix` appended to its file name, without going through a string.
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// `name` with `prefix` removed, compared byte by byte so non UTF-8 names work.
+pub(crate) fn strip_prefix<'a>(name: &'a OsStr, prefix: &OsStr) -> Option<&'a str> {
+    let rest = name
+        .as_encoded_bytes()
+        .strip_prefix(prefix.as_encoded_bytes())?;
+    std::str::from_utf8(rest).ok()
+}
+
+/// `name` followed by `suffix`.
+pub(crate) fn concat(name: &OsStr, suffix: &str) -> OsString {
+    let mut name = name.to_os_string();
+    name.push(suffix);
+    name
+}
+
+/// On Windows an absolute `\\?\` path, which isn't limited to 260 characters. Elsewhere the path
+/// is returned as is.
+#[cfg(windows)]
+pub(crate) fn long(path: PathBuf) -> PathBuf {
+    use std::path::Prefix;
+
+    let Ok(path) = std::path::absolute(&path) else {
+        return path;
+    };
+    let mut components = path.components();
+    let Some(Component::Prefix(prefix)) = components.next() else {
+        return path;
+    };
+    let mut long = match prefix.kind() {
+        Prefix::Disk(_) => {
+            let mut long = OsString::from(r"\\?\");
+            long.push(prefix.as_os_str());
+            long
+        }
+        Prefix::UNC(server, share) => {
+            let mut long = OsString::from(r"\\?\UNC\");
+            long.push(server);
+            long.push(r"\");
+            long.push(share);
+            long
+        }
+        // verbatim and device paths are left alone
+        _ => return path,
+    };
+    long.push(r"\");
+    let mut long = PathBuf::from(long);
+    // verbatim paths aren't normalized by Windows, `absolute` already resolved `..`
+    for component in components {
+        if let Component::Normal(v) = component {
+            long.push(v);
+        }
+    }
+    long
+}
+
+#[cfg(not(windows))]
+pub(crate) fn long(path: PathBuf) -> PathBuf {
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_names() {
+        assert_eq!(
+            relative("onnx//./model.onnx").unwrap(),
+            Path::new("onnx").join("model.onnx")
+        );
+        // the leading `/` of an absolute name is dropped like empty segments
+        assert_eq!(
+            relative("/etc/passwd").unwrap(),
+            Path::new("etc").join("passwd")
+        );
+        for name in ["", ".", "../model.onnx", "onnx/../../model.onnx", "onnx/.."] {
+            assert!(relative(name).is_err(), "{name}");
+        }
+    }
+}
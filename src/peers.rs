@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use crate::error::Error;
+
+/// Service type mirrors started with `model-manager mirror` announce themselves as.
+pub const SERVICE_TYPE: &str = "_model-manager._tcp.local.";
+
+/// A mirror found on the LAN.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Peer {
+    pub name: String,
+    /// Base URL, it answers like a Huggingface endpoint.
+    pub url: String,
+}
+
+/// Announces a mirror listening on `port` on every interface until it's dropped.
+pub struct Announcement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Announcement {
+    pub fn new(name: &str, port: u16) -> Result<Self, Error> {
+        let daemon = ServiceDaemon::new().map_err(|e| Error::new("mDNS failed", e))?;
+        let info = ServiceInfo::new(
+            SERVICE_TYPE,
+            name,
+            &format!("{name}.local."),
+            "",
+            port,
+            None::<HashMap<String, String>>,
+        )
+        .map_err(|e| Error::new("mDNS failed", e))?
+        .enable_addr_auto();
+        let fullname = info.get_fullname().to_string();
+        daemon
+            .register(info)
+            .map_err(|e| Error::new("mDNS failed", e))?;
+        Ok(Self { daemon, fullname })
+    }
+}
+
+impl Drop for Announcement {
+    fn drop(&mut self) {
+        // lets peers forget the mirror right away instead of once its record expires
+        if let Ok(receiver) = self.daemon.unregister(&self.fullname) {
+            let _ = receiver.recv_timeout(Duration::from_secs(1));
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Mirrors answering within `timeout`, sorted by name. IPv4 addresses are preferred.
+pub async fn discover(timeout: Duration) -> Result<Vec<Peer>, Error> {
+    let daemon = ServiceDaemon::new().map_err(|e| Error::new("mDNS failed", e))?;
+    let receiver = daemon
+        .browse(SERVICE_TYPE)
+        .map_err(|e| Error::new("mDNS failed", e))?;
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+    let mut peers = vec![];
+    loop {
+        let event = tokio::select! {
+            event = receiver.recv_async() => event,
+            _ = &mut deadline => break,
+        };
+        let service = match event {
+            Ok(ServiceEvent::ServiceResolved(service)) => service,
+            Ok(_) => continue,
+            Err(_) => break,
+        };
+        let mut addresses = service
+            .get_addresses()
+            .iter()
+            .map(|v| v.to_ip_addr())
+            .collect::<Vec<_>>();
+        addresses.sort_by_key(|v| (v.is_loopback(), !v.is_ipv4()));
+        if let Some(ip) = addresses.first() {
+            let name = service.get_fullname();
+            peers.push(Peer {
+                name: name
+                    .strip_suffix(SERVICE_TYPE)
+                    .unwrap_or(name)
+                    .trim_end_matches('.')
+                    .to_string(),
+                url: format!("http://{}", SocketAddr::new(*ip, service.get_port())),
+            });
+        }
+    }
+    let _ = daemon.shutdown();
+    peers.sort();
+    peers.dedup_by(|a, b| a.url == b.url);
+    Ok(peers)
+}
@@ -0,0 +1,39 @@
+#[derive(Clone, Debug, Default)]
+pub struct DownloadPlan {
+    pub models: Vec<PlannedModel>,
+}
+
+impl DownloadPlan {
+    pub fn total_bytes(&self) -> u64 {
+        self.models.iter().map(|m| m.total_bytes()).sum()
+    }
+
+    /// Whether every file size could be determined upfront.
+    pub fn is_exact(&self) -> bool {
+        self.models
+            .iter()
+            .all(|m| m.files.iter().all(|f| f.size.is_some()))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlannedModel {
+    pub ident: String,
+    pub version: String,
+    pub files: Vec<PlannedFile>,
+}
+
+impl PlannedModel {
+    pub fn total_bytes(&self) -> u64 {
+        self.files.iter().filter_map(|f| f.size).sum()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlannedFile {
+    pub name: String,
+    pub url: String,
+    pub size: Option<u64>,
+    /// Reported by the hub for LFS files.
+    pub sha256: Option<String>,
+}
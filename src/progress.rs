@@ -0,0 +1,531 @@
+#[cfg(feature = "progress")]
+use std::collections::HashMap;
+use std::str::FromStr;
+#[cfg(feature = "progress")]
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(feature = "progress")]
+use console::{style, Emoji};
+#[cfg(feature = "progress")]
+use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+
+use crate::error::Error;
+#[cfg(feature = "progress")]
+use crate::messages::{DefaultMessages, Messages};
+#[cfg(feature = "progress")]
+use crate::sync;
+
+#[cfg(feature = "progress")]
+const RELOAD_SPEED: u64 = 40;
+
+#[cfg(feature = "progress")]
+pub(crate) static LOOKING_GLASS: Emoji<'_, '_> = Emoji("🔍  ", "");
+#[cfg(feature = "progress")]
+pub(crate) static SPARKLE: Emoji<'_, '_> = Emoji("✨  ", ":-) ");
+#[cfg(not(feature = "progress"))]
+pub(crate) static LOOKING_GLASS: &str = "";
+#[cfg(not(feature = "progress"))]
+pub(crate) static SPARKLE: &str = ":-) ";
+
+/// How much the manager writes to the terminal, custom progress observers are notified either
+/// way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Verbosity {
+    Silent,
+    /// Only the line batch downloads finish with and warnings.
+    Summary,
+    /// Progress bars and the steps of batch downloads too.
+    #[default]
+    Verbose,
+}
+
+impl FromStr for Verbosity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "silent" | "quiet" => Ok(Verbosity::Silent),
+            "summary" => Ok(Verbosity::Summary),
+            "verbose" => Ok(Verbosity::Verbose),
+            _ => Err(Error::config(format!("Unknown verbosity: {s}"))),
+        }
+    }
+}
+
+/// Receives progress of model downloads. All methods default to doing nothing.
+pub trait ProgressObserver: Send + Sync {
+    /// A batch download of `models` models started, `total` is their size if every file size
+    /// was known upfront.
+    fn on_batch(&self, _models: usize, _total: Option<u64>) {}
+    /// The files of a model are resolved, `total` is their size if the repo listed all of them.
+    fn on_files(&self, _model: &str, _files: usize, _total: Option<u64>) {}
+    /// A model is waiting for a download slot.
+    fn on_queued(&self, _model: &str) {}
+    /// A file started downloading, `total` is its size if the server reported one.
+    fn on_start(&self, _model: &str, _file: &str, _total: Option<u64>) {}
+    /// `downloaded` is the number of bytes of `file` received so far.
+    fn on_bytes(&self, _model: &str, _file: &str, _downloaded: u64) {}
+    fn on_file_done(&self, _model: &str, _file: &str) {}
+    /// Downloading `file` failed and is retried, `attempt` starts at 1.
+    fn on_retry(&self, _model: &str, _file: &str, _attempt: usize) {}
+    /// A downloaded archive is being extracted.
+    fn on_unpack(&self, _model: &str) {}
+    fn on_model_done(&self, _model: &str) {}
+    fn on_error(&self, _model: &str, _error: &Error) {}
+}
+
+/// Ignores all progress.
+pub struct SilentObserver;
+
+impl ProgressObserver for SilentObserver {}
+
+/// `[1/3]` style step of a status line, dimmed on terminals.
+#[cfg(feature = "progress")]
+pub(crate) fn step(label: &str) -> String {
+    style(label).bold().dim().to_string()
+}
+
+#[cfg(not(feature = "progress"))]
+pub(crate) fn step(label: &str) -> String {
+    label.to_string()
+}
+
+#[cfg(feature = "progress")]
+pub(crate) fn elapsed(duration: Duration) -> String {
+    HumanDuration(duration).to_string()
+}
+
+#[cfg(not(feature = "progress"))]
+pub(crate) fn elapsed(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
+
+/// Sizes the way indicatif's `HumanBytes` prints them, which isn't available without the
+/// `progress` feature.
+pub(crate) fn bytes(bytes: u64) -> String {
+    const UNITS: [&str; 8] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB", "ZiB", "YiB"];
+    let mut size = bytes as f64;
+    if size < 1024.0 {
+        return format!("{bytes} B");
+    }
+    let mut unit = 0;
+    size /= 1024.0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.2} {}", UNITS[unit])
+}
+
+/// A status line without terminal colors, for logs.
+#[cfg(all(feature = "tracing", feature = "progress"))]
+pub(crate) fn plain(message: &str) -> String {
+    console::strip_ansi_codes(message).to_string()
+}
+
+#[cfg(all(feature = "tracing", not(feature = "progress")))]
+pub(crate) fn plain(message: &str) -> String {
+    message.to_string()
+}
+
+#[cfg(feature = "progress")]
+/// Renders a bar for the whole model once it downloads more than one file, with a child bar per
+/// running file below it.
+pub struct IndicatifObserver {
+    multi: MultiProgress,
+    theme: ProgressTheme,
+    messages: Arc<dyn Messages>,
+    bars: Mutex<HashMap<(String, String), ProgressBar>>,
+    models: Mutex<HashMap<String, ModelProgress>>,
+    batch: Mutex<Option<BatchProgress>>,
+}
+
+/// The top bar of a batch download, with the bytes of all its files.
+#[cfg(feature = "progress")]
+struct BatchProgress {
+    bar: ProgressBar,
+    models: usize,
+    finished: usize,
+    /// The length is the planned size, otherwise it grows as files start.
+    planned: bool,
+}
+
+#[cfg(feature = "progress")]
+impl BatchProgress {
+    fn set_message(&self, messages: &dyn Messages) {
+        self.bar
+            .set_message(messages.models_finished(self.finished, self.models));
+    }
+}
+
+#[cfg(feature = "progress")]
+#[derive(Default)]
+struct ModelProgress {
+    /// `(total, downloaded)` of every started file.
+    files: HashMap<String, (u64, u64)>,
+    /// Number and size of all files, once they're resolved.
+    expected: Option<(usize, Option<u64>)>,
+    finished: usize,
+    bar: Option<ProgressBar>,
+}
+
+#[cfg(feature = "progress")]
+impl ModelProgress {
+    fn update(&self, model: &str, messages: &dyn Messages) {
+        let Some(bar) = &self.bar else {
+            return;
+        };
+        let started = self.files.values().map(|v| v.0).sum();
+        match self.expected {
+            Some((files, total)) => {
+                bar.set_length(total.unwrap_or(started));
+                bar.set_message(messages.files_finished(model, self.finished, files));
+            }
+            None => {
+                bar.set_length(started);
+                bar.set_message(messages.files_started(model, self.files.len()));
+            }
+        }
+        bar.set_position(self.files.values().map(|v| v.1).sum());
+    }
+}
+
+#[cfg(feature = "progress")]
+impl Default for IndicatifObserver {
+    fn default() -> Self {
+        Self::new(MultiProgress::default())
+    }
+}
+
+#[cfg(feature = "progress")]
+impl IndicatifObserver {
+    pub fn new(multi: MultiProgress) -> Self {
+        Self::with_theme(multi, ProgressTheme::default())
+    }
+
+    pub fn with_theme(multi: MultiProgress, theme: ProgressTheme) -> Self {
+        Self {
+            multi,
+            theme,
+            messages: Arc::new(DefaultMessages),
+            bars: Mutex::default(),
+            models: Mutex::default(),
+            batch: Mutex::default(),
+        }
+    }
+
+    /// Labels the bars with `messages` instead of the English ones.
+    pub fn messages(mut self, messages: Arc<dyn Messages>) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    fn insert(&self, model: &str, file: &str, pb: ProgressBar) {
+        let old = sync::lock(&self.bars).insert((model.to_string(), file.to_string()), pb);
+        if let Some(old) = old {
+            self.multi.remove(&old);
+        }
+    }
+
+    fn remove_model(&self, model: &str) {
+        let mut bars = sync::lock(&self.bars);
+        bars.retain(|(m, _), pb| {
+            if m == model {
+                pb.finish_and_clear();
+                self.multi.remove(pb);
+            }
+            m != model
+        });
+        if let Some(bar) = sync::lock(&self.models).remove(model).and_then(|v| v.bar) {
+            bar.finish_and_clear();
+            self.multi.remove(&bar);
+        }
+    }
+
+    fn update_model(&self, model: &str, file: &str, update: impl FnOnce(&mut (u64, u64))) {
+        let mut models = sync::lock(&self.models);
+        let progress = models.entry(model.to_string()).or_default();
+        let entry = progress.files.entry(file.to_string()).or_default();
+        let before = *entry;
+        update(entry);
+        let after = *entry;
+        if let Some(batch) = sync::lock(&self.batch).as_ref() {
+            if !batch.planned {
+                batch.bar.inc_length(after.0.saturating_sub(before.0));
+            }
+            batch.bar.inc(after.1.saturating_sub(before.1));
+        }
+        if progress.bar.is_none() && progress.files.len() > 1 {
+            progress.bar = Some(self.model_bar());
+        }
+        progress.update(model, self.messages.as_ref());
+    }
+
+    fn model_bar(&self) -> ProgressBar {
+        // below the batch bar
+        let index = usize::from(sync::lock(&self.batch).is_some());
+        let bar = self.multi.insert(index, ProgressBar::new(0));
+        if let Ok(style) = self.theme.bar_style() {
+            bar.set_style(style);
+        }
+        bar
+    }
+
+    fn finish_model(&self) {
+        let mut batch = sync::lock(&self.batch);
+        let Some(progress) = batch.as_mut() else {
+            return;
+        };
+        progress.finished += 1;
+        progress.set_message(self.messages.as_ref());
+        if progress.finished >= progress.models {
+            progress.bar.finish_and_clear();
+            self.multi.remove(&progress.bar);
+            *batch = None;
+        }
+    }
+}
+
+#[cfg(feature = "progress")]
+impl ProgressObserver for IndicatifObserver {
+    /// A single model already has its own bars.
+    fn on_batch(&self, models: usize, total: Option<u64>) {
+        if models < 2 {
+            return;
+        }
+        let bar = self
+            .multi
+            .insert(0, ProgressBar::new(total.unwrap_or_default()));
+        if let Ok(style) = self.theme.bar_style() {
+            bar.set_style(style);
+        }
+        let batch = BatchProgress {
+            bar,
+            models,
+            finished: 0,
+            planned: total.is_some(),
+        };
+        batch.set_message(self.messages.as_ref());
+        if let Some(old) = sync::lock(&self.batch).replace(batch) {
+            old.bar.finish_and_clear();
+            self.multi.remove(&old.bar);
+        }
+    }
+
+    fn on_files(&self, model: &str, files: usize, total: Option<u64>) {
+        let mut models = sync::lock(&self.models);
+        let progress = models.entry(model.to_string()).or_default();
+        progress.expected = Some((files, total));
+        if progress.bar.is_none() && files > 1 {
+            progress.bar = Some(self.model_bar());
+        }
+        progress.update(model, self.messages.as_ref());
+    }
+
+    fn on_start(&self, model: &str, file: &str, total: Option<u64>) {
+        self.update_model(model, file, |v| *v = (total.unwrap_or_default(), 0));
+        let parent = sync::lock(&self.models)
+            .get(model)
+            .and_then(|v| v.bar.clone());
+        let pb = ProgressBar::new(total.unwrap_or_default());
+        let pb = match &parent {
+            Some(parent) => self.multi.insert_after(parent, pb),
+            None => self.multi.add(pb),
+        };
+        if let Ok(style) = self.theme.bar_style() {
+            pb.set_style(style);
+        }
+        pb.set_message(match parent {
+            Some(_) => format!("  {}", file),
+            None => self.messages.downloading(model),
+        });
+        self.insert(model, file, pb);
+    }
+
+    fn on_bytes(&self, model: &str, file: &str, downloaded: u64) {
+        if let Some(pb) = sync::lock(&self.bars).get(&(model.to_string(), file.to_string())) {
+            pb.set_position(downloaded);
+        }
+        self.update_model(model, file, |v| v.1 = downloaded);
+    }
+
+    fn on_file_done(&self, model: &str, file: &str) {
+        let pb = sync::lock(&self.bars).remove(&(model.to_string(), file.to_string()));
+        if let Some(pb) = pb {
+            self.multi.remove(&pb);
+        }
+        self.update_model(model, file, |v| v.1 = v.0.max(v.1));
+        if let Some(progress) = sync::lock(&self.models).get_mut(model) {
+            progress.finished += 1;
+            progress.update(model, self.messages.as_ref());
+        }
+    }
+
+    fn on_unpack(&self, model: &str) {
+        let pb = self.multi.add(ProgressBar::new_spinner());
+        if let Ok(style) = self.theme.spinner_style() {
+            pb.set_style(style);
+        }
+        pb.set_message(self.messages.unpacking(model));
+        pb.enable_steady_tick(Duration::from_millis(RELOAD_SPEED));
+        self.insert(model, "", pb);
+    }
+
+    fn on_model_done(&self, model: &str) {
+        self.remove_model(model);
+        self.finish_model();
+    }
+
+    fn on_error(&self, model: &str, _: &Error) {
+        self.remove_model(model);
+        self.finish_model();
+    }
+}
+
+/// Looks of the bars [`IndicatifObserver`] draws, to match the styling of the application
+/// embedding the manager. `$spinner`, `$filled`, `$empty`, `$bytes`, `$speed` and `$eta` in the
+/// indicatif templates are replaced by the colors.
+#[cfg(feature = "progress")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgressTheme {
+    /// Of the bars of files, models and batches.
+    pub template: String,
+    /// Of the spinner shown while an archive is unpacked.
+    pub spinner_template: String,
+    /// Filled, current and empty part of the bar.
+    pub progress_chars: String,
+    /// Frames of the spinner, the last one is shown once it's done. `None` is indicatif's
+    /// default.
+    pub tick_chars: Option<String>,
+    pub colors: ProgressColors,
+}
+
+/// Console styles like `cyan`, `bold.red` or a 256 color number like `33`.
+#[cfg(feature = "progress")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgressColors {
+    pub spinner: String,
+    pub filled: String,
+    pub empty: String,
+    pub bytes: String,
+    pub speed: String,
+    pub eta: String,
+}
+
+#[cfg(feature = "progress")]
+impl Default for ProgressTheme {
+    fn default() -> Self {
+        Self {
+            template: " {spinner:.$spinner} {msg} {wide_bar:.$filled/$empty} {bytes:.$bytes}/{total_bytes:.$bytes} {bytes_per_sec:.$speed} eta {eta:.$eta}".to_string(),
+            spinner_template: " {spinner:.$spinner} {msg}".to_string(),
+            progress_chars: "━╸━".to_string(),
+            tick_chars: None,
+            colors: ProgressColors::default(),
+        }
+    }
+}
+
+#[cfg(feature = "progress")]
+impl Default for ProgressColors {
+    fn default() -> Self {
+        Self {
+            spinner: "33".to_string(),
+            filled: "magenta".to_string(),
+            empty: "white".to_string(),
+            bytes: "green".to_string(),
+            speed: "red".to_string(),
+            eta: "cyan".to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "progress")]
+impl ProgressTheme {
+    pub fn template(mut self, template: impl ToString) -> Self {
+        self.template = template.to_string();
+        self
+    }
+
+    pub fn spinner_template(mut self, template: impl ToString) -> Self {
+        self.spinner_template = template.to_string();
+        self
+    }
+
+    /// Needs at least two characters of the same width.
+    pub fn progress_chars(mut self, chars: impl ToString) -> Self {
+        self.progress_chars = chars.to_string();
+        self
+    }
+
+    pub fn tick_chars(mut self, chars: impl ToString) -> Self {
+        self.tick_chars = Some(chars.to_string());
+        self
+    }
+
+    pub fn colors(mut self, colors: ProgressColors) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    /// Fails if a template doesn't parse or there are too few characters.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.bar_style()?;
+        self.spinner_style()?;
+        Ok(())
+    }
+
+    /// indicatif panics otherwise.
+    fn check_chars(&self) -> Result<(), Error> {
+        let mut widths = self
+            .progress_chars
+            .chars()
+            .map(|v| console::measure_text_width(&v.to_string()));
+        let first = widths.next();
+        if self.progress_chars.chars().count() < 2 || !widths.all(|v| Some(v) == first) {
+            return Err(Error::config(
+                "A progress bar needs at least two progress chars of the same width",
+            ));
+        }
+        if self
+            .tick_chars
+            .as_ref()
+            .is_some_and(|v| v.chars().count() < 2)
+        {
+            return Err(Error::config("A spinner needs at least two tick chars"));
+        }
+        Ok(())
+    }
+
+    fn bar_style(&self) -> Result<ProgressStyle, Error> {
+        self.check_chars()?;
+        let style = ProgressStyle::with_template(&self.colored(&self.template))
+            .map_err(Error::console_template)?;
+        Ok(self.ticks(style).progress_chars(&self.progress_chars))
+    }
+
+    fn spinner_style(&self) -> Result<ProgressStyle, Error> {
+        self.check_chars()?;
+        let style = ProgressStyle::with_template(&self.colored(&self.spinner_template))
+            .map_err(Error::console_template)?;
+        Ok(self.ticks(style))
+    }
+
+    fn ticks(&self, style: ProgressStyle) -> ProgressStyle {
+        match &self.tick_chars {
+            Some(chars) => style.tick_chars(chars),
+            None => style,
+        }
+    }
+
+    fn colored(&self, template: &str) -> String {
+        let colors = &self.colors;
+        template
+            .replace("$spinner", &colors.spinner)
+            .replace("$filled", &colors.filled)
+            .replace("$empty", &colors.empty)
+            .replace("$bytes", &colors.bytes)
+            .replace("$speed", &colors.speed)
+            .replace("$eta", &colors.eta)
+    }
+}
@@ -0,0 +1,73 @@
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::model_manager::Model;
+
+/// A model waiting to be downloaded. Its definition is kept so it can be downloaded before the
+/// application registered it again after a restart.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedModel {
+    pub ident: String,
+    pub model: Model,
+    /// Unix timestamp.
+    pub enqueued: i64,
+}
+
+impl QueuedModel {
+    pub(crate) fn new(ident: &str, model: &Model) -> Self {
+        Self {
+            ident: ident.to_string(),
+            model: model.clone(),
+            enqueued: Utc::now().timestamp(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct QueueFile {
+    #[serde(default)]
+    models: Vec<QueuedModel>,
+}
+
+pub(crate) fn read(path: &Path) -> Result<Vec<QueuedModel>, Error> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(toml::from_str::<QueueFile>(&content)
+            .map_err(Error::config)?
+            .models),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(Error::open_path(path)(e)),
+    }
+}
+
+/// Changes the queue at `path` while holding a lock next to it, so processes sharing the model
+/// directory don't lose each other's changes.
+pub(crate) fn update<T>(
+    path: &Path,
+    f: impl FnOnce(&mut Vec<QueuedModel>) -> T,
+) -> Result<T, Error> {
+    std::fs::create_dir_all(path.parent().unwrap_or(path)).map_err(Error::write_file)?;
+    let lock = lock(&path.with_extension("lock"))?;
+    let mut models = read(path)?;
+    let result = f(&mut models);
+    let content = toml::to_string(&QueueFile { models }).map_err(Error::config)?;
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, content).map_err(Error::write_path(&tmp))?;
+    std::fs::rename(&tmp, path).map_err(Error::write_path(path))?;
+    drop(lock);
+    Ok(result)
+}
+
+pub(crate) fn lock(path: &Path) -> Result<File, Error> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(Error::open_path(path))?;
+    fs4::FileExt::lock(&file).map_err(Error::open_path(path))?;
+    Ok(file)
+}
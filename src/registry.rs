@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::model_manager::Model;
+use crate::storage::StorageLayout;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<StorageLayout>,
+    /// Channel the models follow instead of their registered versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub models: HashMap<String, Model>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub aliases: HashMap<String, String>,
+}
+
+impl Registry {
+    pub fn from_toml(content: &str) -> Result<Registry, Error> {
+        let registry: Registry = toml::from_str(content).map_err(Error::config)?;
+        Ok(registry.with_default_directories())
+    }
+
+    #[cfg(feature = "json")]
+    pub fn from_json(content: &str) -> Result<Registry, Error> {
+        let registry: Registry = serde_json::from_str(content).map_err(Error::config)?;
+        Ok(registry.with_default_directories())
+    }
+
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(content: &str) -> Result<Registry, Error> {
+        let registry: Registry = serde_yaml::from_str(content).map_err(Error::config)?;
+        Ok(registry.with_default_directories())
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Registry, Error> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(Error::open_file)?;
+        Self::parse(&content, path.extension().and_then(|v| v.to_str()))
+    }
+
+    pub async fn fetch(url: &str, options: &RemoteRegistryOptions) -> Result<Registry, Error> {
+        let content = fetch_text(url, options.token.as_deref()).await?;
+        #[cfg(feature = "signature")]
+        if let Some(key) = &options.public_key {
+            let signature = fetch_text(&format!("{url}.sig"), options.token.as_deref()).await?;
+            verify_signature(key, content.as_bytes(), signature.trim())?;
+        }
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        Self::parse(
+            &content,
+            Path::new(path).extension().and_then(|v| v.to_str()),
+        )
+    }
+
+    fn parse(content: &str, extension: Option<&str>) -> Result<Registry, Error> {
+        match extension {
+            #[cfg(feature = "json")]
+            Some("json") => Self::from_json(content),
+            #[cfg(not(feature = "json"))]
+            Some("json") => Err(Error::config("JSON registries require the `json` feature")),
+            #[cfg(feature = "yaml")]
+            Some("yaml" | "yml") => Self::from_yaml(content),
+            #[cfg(not(feature = "yaml"))]
+            Some("yaml" | "yml") => {
+                Err(Error::config("YAML registries require the `yaml` feature"))
+            }
+            _ => Self::from_toml(content),
+        }
+    }
+
+    fn with_default_directories(mut self) -> Self {
+        for (ident, model) in self.models.iter_mut() {
+            if model.directory.as_os_str().is_empty() {
+                model.directory = PathBuf::from(ident);
+            }
+        }
+        self
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct RemoteRegistryOptions {
+    pub token: Option<String>,
+    /// Ed25519 key the registry is signed with, the hex signature is expected at `<url>.sig`.
+    #[cfg(feature = "signature")]
+    pub public_key: Option<[u8; 32]>,
+}
+
+async fn fetch_text(url: &str, token: Option<&str>) -> Result<String, Error> {
+    let request = reqwest::Client::new().get(url);
+    let request = match token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    };
+    request
+        .send()
+        .await
+        .map_err(Error::fetch)?
+        .error_for_status()
+        .map_err(Error::fetch)?
+        .text()
+        .await
+        .map_err(Error::fetch)
+}
+
+#[cfg(feature = "signature")]
+fn verify_signature(key: &[u8; 32], content: &[u8], signature: &str) -> Result<(), Error> {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    let key = VerifyingKey::from_bytes(key).map_err(|_| Error::SignatureInvalid)?;
+    let bytes = (0..signature.len())
+        .step_by(2)
+        .map(|i| {
+            signature
+                .get(i..i + 2)
+                .and_then(|v| u8::from_str_radix(v, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .ok_or(Error::SignatureInvalid)?;
+    let signature = Signature::from_slice(&bytes).map_err(|_| Error::SignatureInvalid)?;
+    key.verify(content, &signature)
+        .map_err(|_| Error::SignatureInvalid)
+}
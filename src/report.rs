@@ -0,0 +1,43 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::progress;
+
+#[derive(Debug, Default)]
+pub struct DownloadReport {
+    pub succeeded: Vec<String>,
+    pub failed: Vec<(String, Error)>,
+    pub skipped: Vec<String>,
+    /// Bytes received, without those resumed files already had.
+    pub bytes: u64,
+    pub retries: usize,
+    pub elapsed: Duration,
+}
+
+impl DownloadReport {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Average bytes per second over the whole batch.
+    pub fn throughput(&self) -> u64 {
+        (self.bytes as f64 / self.elapsed.as_secs_f64().max(0.001)) as u64
+    }
+}
+
+/// One line like `2 downloaded, 1 skipped, 0 failed, 1.20 GiB at 40.00 MiB/s, 3 retries`.
+impl fmt::Display for DownloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} downloaded, {} skipped, {} failed, {} at {}/s, {} retries",
+            self.succeeded.len(),
+            self.skipped.len(),
+            self.failed.len(),
+            progress::bytes(self.bytes),
+            progress::bytes(self.throughput()),
+            self.retries
+        )
+    }
+}
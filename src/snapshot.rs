@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::error::Error;
+use crate::progress::ProgressObserver;
+use crate::sync;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotState {
+    Queued,
+    Downloading,
+    Retrying,
+    Unpacking,
+    Done,
+    Failed,
+}
+
+/// Progress of one model, ready to bind to a UI or send to a webview.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProgressSnapshot {
+    pub model: String,
+    pub state: SnapshotState,
+    /// Bytes of all started files received so far.
+    pub downloaded: u64,
+    /// Size of all files if the repo listed them, otherwise of the started ones.
+    pub total: Option<u64>,
+    pub files_done: usize,
+    /// Number of files once they're resolved.
+    pub files: Option<usize>,
+    /// Bytes per second since the download started.
+    pub speed: u64,
+    /// Share of `total` in `0.0..=1.0`.
+    pub fraction: Option<f64>,
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct ModelState {
+    /// `(total, downloaded)` of every started file, `None` until its first report.
+    files: HashMap<String, (Option<u64>, Option<u64>)>,
+    expected: Option<(usize, Option<u64>)>,
+    files_done: usize,
+    /// Bytes resumed files already had, they don't count for the speed.
+    resumed: u64,
+    started: Option<Instant>,
+    sent: Option<Instant>,
+}
+
+/// Forwards progress to a channel as [`ProgressSnapshot`]s, for desktop apps that render
+/// downloads in their UI. Byte updates of a model are sent at most once per interval, state
+/// changes always.
+pub struct ChannelObserver {
+    sender: UnboundedSender<ProgressSnapshot>,
+    interval: Duration,
+    models: Mutex<HashMap<String, ModelState>>,
+}
+
+impl ChannelObserver {
+    /// The observer and the receiving end of its snapshots, sent every 100ms at most.
+    pub fn new() -> (Self, UnboundedReceiver<ProgressSnapshot>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let observer = Self {
+            sender,
+            interval: Duration::from_millis(100),
+            models: Mutex::default(),
+        };
+        (observer, receiver)
+    }
+
+    /// Time between two byte updates of the same model.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    fn send(&self, model: &str, state: SnapshotState, error: Option<String>) {
+        let mut models = sync::lock(&self.models);
+        let progress = models.entry(model.to_string()).or_default();
+        progress.sent = Some(Instant::now());
+        let snapshot = snapshot(model, progress, state, error);
+        // a closed window is not an error of the download
+        let _ = self.sender.send(snapshot);
+    }
+
+    fn update(&self, model: &str, update: impl FnOnce(&mut ModelState)) {
+        let snapshot = {
+            let mut models = sync::lock(&self.models);
+            let progress = models.entry(model.to_string()).or_default();
+            update(progress);
+            if progress.sent.is_some_and(|v| v.elapsed() < self.interval) {
+                return;
+            }
+            progress.sent = Some(Instant::now());
+            snapshot(model, progress, SnapshotState::Downloading, None)
+        };
+        let _ = self.sender.send(snapshot);
+    }
+}
+
+fn snapshot(
+    model: &str,
+    progress: &ModelState,
+    state: SnapshotState,
+    error: Option<String>,
+) -> ProgressSnapshot {
+    let downloaded = progress.files.values().filter_map(|v| v.1).sum::<u64>();
+    let started = match progress.files.is_empty() {
+        true => None,
+        false => progress.files.values().map(|v| v.0).sum::<Option<u64>>(),
+    };
+    let total = progress.expected.and_then(|v| v.1).or(started);
+    let elapsed = progress
+        .started
+        .map(|v| v.elapsed().as_secs_f64())
+        .unwrap_or_default();
+    let received = downloaded.saturating_sub(progress.resumed);
+    ProgressSnapshot {
+        model: model.to_string(),
+        state,
+        downloaded,
+        total,
+        files_done: progress.files_done,
+        files: progress.expected.map(|v| v.0),
+        speed: match elapsed > 0.0 {
+            true => (received as f64 / elapsed) as u64,
+            false => 0,
+        },
+        fraction: total
+            .filter(|v| *v > 0)
+            .map(|v| (downloaded as f64 / v as f64).min(1.0)),
+        error,
+    }
+}
+
+impl ProgressObserver for ChannelObserver {
+    fn on_files(&self, model: &str, files: usize, total: Option<u64>) {
+        self.update(model, |v| v.expected = Some((files, total)));
+    }
+
+    fn on_queued(&self, model: &str) {
+        sync::lock(&self.models).insert(model.to_string(), ModelState::default());
+        self.send(model, SnapshotState::Queued, None);
+    }
+
+    fn on_start(&self, model: &str, file: &str, total: Option<u64>) {
+        self.update(model, |v| {
+            v.started.get_or_insert_with(Instant::now);
+            v.files.insert(file.to_string(), (total, None));
+        });
+    }
+
+    fn on_bytes(&self, model: &str, file: &str, downloaded: u64) {
+        self.update(model, |v| {
+            if let Some(entry) = v.files.get_mut(file) {
+                // the first report is what a resumed file already had on disk
+                if entry.1.replace(downloaded).is_none() {
+                    v.resumed += downloaded;
+                }
+            }
+        });
+    }
+
+    fn on_file_done(&self, model: &str, file: &str) {
+        self.update(model, |v| {
+            v.files_done += 1;
+            if let Some(entry) = v.files.get_mut(file) {
+                let downloaded = entry.1.unwrap_or_default();
+                entry.1 = Some(entry.0.unwrap_or(downloaded).max(downloaded));
+            }
+        });
+    }
+
+    fn on_retry(&self, model: &str, _file: &str, _attempt: usize) {
+        self.send(model, SnapshotState::Retrying, None);
+    }
+
+    fn on_unpack(&self, model: &str) {
+        self.send(model, SnapshotState::Unpacking, None);
+    }
+
+    fn on_model_done(&self, model: &str) {
+        self.send(model, SnapshotState::Done, None);
+        sync::lock(&self.models).remove(model);
+    }
+
+    fn on_error(&self, model: &str, error: &Error) {
+        self.send(model, SnapshotState::Failed, Some(error.report()));
+        sync::lock(&self.models).remove(model);
+    }
+}
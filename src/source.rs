@@ -0,0 +1,659 @@
+use std::future::Future;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use reqwest::header::{AUTHORIZATION, ETAG};
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::error::Error;
+#[cfg(target_arch = "wasm32")]
+use crate::http::FetchBackend;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::http::ReqwestBackend;
+use crate::http::{Headers, HttpBackend, HttpResponse};
+use crate::huggingface::{
+    encode_path, encode_segment, exponential_backoff, gguf_split_set, hub_error, list_repo_files,
+    resolve_revision, shard_files,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::limit::HostLimit;
+use crate::paths;
+
+pub const HUGGINGFACE_ENDPOINT: &str = "https://huggingface.co";
+
+/// Deserializes from the tagged form or from a URI accepted by [`ModelSource::parse`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", try_from = "SourceRepr")]
+pub enum ModelSource {
+    Huggingface(HuggingfaceModel),
+    Zip(String),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SourceRepr {
+    Uri(String),
+    Tagged(TaggedSource),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TaggedSource {
+    Huggingface(HuggingfaceModel),
+    Zip(String),
+}
+
+impl TryFrom<SourceRepr> for ModelSource {
+    type Error = String;
+
+    fn try_from(value: SourceRepr) -> Result<Self, Self::Error> {
+        match value {
+            SourceRepr::Uri(uri) => ModelSource::parse(&uri).map_err(|e| e.report()),
+            SourceRepr::Tagged(TaggedSource::Huggingface(v)) => Ok(ModelSource::Huggingface(v)),
+            SourceRepr::Tagged(TaggedSource::Zip(v)) => Ok(ModelSource::Zip(v)),
+        }
+    }
+}
+
+impl ModelSource {
+    /// Parses a compact source string:
+    /// - `hf://org/repo[@revision][:path]`, a path with `*`, `?` or `[` is an allow pattern,
+    ///   otherwise a single file. A 40 character hex revision pins a commit.
+    /// - `zip://host/path.zip` or an `http(s)://` URL for a zip archive.
+    pub fn parse(uri: &str) -> Result<Self, Error> {
+        let (scheme, rest) = uri
+            .split_once("://")
+            .ok_or_else(|| Error::config(format!("Missing scheme in model source {uri}")))?;
+        match scheme {
+            "hf" | "huggingface" => {
+                let (repo, path) = match rest.split_once(':') {
+                    Some((repo, path)) => (repo, Some(path)),
+                    None => (rest, None),
+                };
+                let (repo, revision) = match repo.split_once('@') {
+                    Some((repo, revision)) => (repo, Some(revision)),
+                    None => (repo, None),
+                };
+                if repo.split('/').count() != 2 || repo.split('/').any(str::is_empty) {
+                    return Err(Error::config(format!("Invalid Huggingface repo in {uri}")));
+                }
+                let mut model = HuggingfaceModel::new(repo);
+                match revision {
+                    Some(v) if v.len() == 40 && v.chars().all(|c| c.is_ascii_hexdigit()) => {
+                        model = model.commit(v)
+                    }
+                    Some(v) if !v.is_empty() => model = model.revision(v),
+                    _ => {}
+                }
+                match path {
+                    Some(v) if v.contains(['*', '?', '[']) => model = model.allow_pattern(v),
+                    Some(v) if !v.is_empty() => model = model.files([v]),
+                    _ => {}
+                }
+                Ok(ModelSource::Huggingface(model))
+            }
+            "zip" => Ok(ModelSource::Zip(format!("https://{rest}"))),
+            "http" | "https" => Ok(ModelSource::Zip(uri.to_string())),
+            _ => Err(Error::config(format!(
+                "Unknown model source scheme {scheme}"
+            ))),
+        }
+    }
+}
+
+impl FromStr for ModelSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ModelSource::parse(s)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct HuggingfaceModel {
+    pub repo: String,
+    /// Leave empty to download every file of the repo.
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Branch or tag, resolved to a commit SHA at download time. Ignored if `commit` is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    /// Glob patterns an auto-discovered file must match one of, e.g. `*.safetensors`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow_patterns: Vec<String>,
+    /// Glob patterns excluding auto-discovered files, e.g. `*.msgpack`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore_patterns: Vec<String>,
+    /// Only auto-discover files below this folder of the repo, e.g. `onnx`. Files keep their full
+    /// repo path on disk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subfolder: Option<String>,
+    /// Overrides the manager's Huggingface endpoint for this repo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    /// Repo the tokenizer comes from if this one has none, like the original model of a GGUF
+    /// conversion. See
+    /// [`ModelManager::get_model_with_tokenizer`](crate::model_manager::ModelManager::get_model_with_tokenizer).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokenizer: Option<String>,
+}
+
+impl From<HuggingfaceModel> for ModelSource {
+    fn from(value: HuggingfaceModel) -> Self {
+        ModelSource::Huggingface(value)
+    }
+}
+
+impl HuggingfaceModel {
+    pub fn new(repo: impl ToString) -> Self {
+        Self {
+            repo: repo.to_string(),
+            files: vec![],
+            commit: None,
+            revision: None,
+            allow_patterns: vec![],
+            ignore_patterns: vec![],
+            subfolder: None,
+            endpoint: None,
+            tokenizer: None,
+        }
+    }
+
+    pub fn files(mut self, files: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.files = files.into_iter().map(|f| f.to_string()).collect();
+        self
+    }
+
+    pub fn commit(mut self, commit: impl ToString) -> Self {
+        self.commit = Some(commit.to_string());
+        self
+    }
+
+    pub fn revision(mut self, revision: impl ToString) -> Self {
+        self.revision = Some(revision.to_string());
+        self
+    }
+
+    pub(crate) fn reference(&self) -> &str {
+        self.commit
+            .as_deref()
+            .or(self.revision.as_deref())
+            .unwrap_or("main")
+    }
+
+    pub fn allow_pattern(mut self, pattern: impl ToString) -> Self {
+        self.allow_patterns.push(pattern.to_string());
+        self
+    }
+
+    pub fn ignore_pattern(mut self, pattern: impl ToString) -> Self {
+        self.ignore_patterns.push(pattern.to_string());
+        self
+    }
+
+    pub fn endpoint(mut self, endpoint: impl ToString) -> Self {
+        self.endpoint = Some(endpoint.to_string());
+        self
+    }
+
+    pub fn tokenizer(mut self, repo: impl ToString) -> Self {
+        self.tokenizer = Some(repo.to_string());
+        self
+    }
+
+    pub fn subfolder(mut self, subfolder: impl ToString) -> Self {
+        self.subfolder = Some(subfolder.to_string().trim_matches('/').to_string());
+        self
+    }
+
+    pub(crate) fn matches_patterns(&self, file: &str) -> Result<bool, Error> {
+        if let Some(subfolder) = &self.subfolder {
+            if !file.starts_with(&format!("{subfolder}/")) {
+                return Ok(false);
+            }
+        }
+        let any = |patterns: &[String]| -> Result<bool, Error> {
+            for pattern in patterns {
+                if glob::Pattern::new(pattern)
+                    .map_err(Error::config)?
+                    .matches(file)
+                {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        };
+        Ok(
+            (self.allow_patterns.is_empty() || any(&self.allow_patterns)?)
+                && !any(&self.ignore_patterns)?,
+        )
+    }
+
+    pub fn url(&self) -> Vec<(String, String)> {
+        self.url_at(HUGGINGFACE_ENDPOINT)
+    }
+
+    pub fn url_at(&self, endpoint: &str) -> Vec<(String, String)> {
+        self.files
+            .iter()
+            .map(|file| {
+                (
+                    file.to_string(),
+                    format!(
+                        "{}/{}/resolve/{}/{}",
+                        endpoint.trim_end_matches('/'),
+                        self.repo,
+                        encode_segment(self.reference()),
+                        encode_path(file)
+                    ),
+                )
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: usize,
+    pub base_wait: Duration,
+    pub max_wait: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_wait: Duration::from_millis(300),
+            max_wait: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn wait(&self, attempt: usize) -> Duration {
+        Duration::from_millis(exponential_backoff(
+            self.base_wait.as_millis() as usize,
+            attempt,
+            self.max_wait.as_millis() as usize,
+        ) as u64)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DownloadOptions {
+    pub retry: RetryPolicy,
+    pub token: Option<String>,
+    pub endpoint: Option<String>,
+    /// Files of one model downloaded at the same time.
+    pub parallel_files: usize,
+    pub cancel: CancelToken,
+    /// Budget shared with other processes on the machine.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub host_limit: Option<HostLimit>,
+    pub http: Arc<dyn HttpBackend>,
+    /// Mirrors on the LAN tried before the endpoint, for files they report the sha256 of.
+    pub peers: Vec<String>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            retry: RetryPolicy::default(),
+            token: None,
+            endpoint: None,
+            parallel_files: 4,
+            cancel: CancelToken::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            host_limit: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            http: Arc::new(ReqwestBackend::default()),
+            #[cfg(target_arch = "wasm32")]
+            http: Arc::new(FetchBackend::default()),
+            peers: vec![],
+        }
+    }
+}
+
+impl DownloadOptions {
+    pub(crate) fn endpoint(&self) -> &str {
+        self.endpoint.as_deref().unwrap_or(HUGGINGFACE_ENDPOINT)
+    }
+
+    /// These options with the endpoint of `links` if it sets one.
+    pub(crate) fn for_model(&self, links: &HuggingfaceModel) -> DownloadOptions {
+        DownloadOptions {
+            endpoint: links.endpoint.clone().or_else(|| self.endpoint.clone()),
+            ..self.clone()
+        }
+    }
+}
+
+/// Pins unpinned repos to the commit their branch or tag (`main` by default) currently points
+/// at, so every file comes from the same revision even if the branch moves mid-download.
+pub(crate) async fn lock_revision(
+    links: &HuggingfaceModel,
+    options: &DownloadOptions,
+) -> Result<HuggingfaceModel, Error> {
+    if links.commit.is_some() {
+        return Ok(links.clone());
+    }
+    let sha = resolve_revision(
+        options.endpoint(),
+        &links.repo,
+        links.reference(),
+        options.token.as_deref(),
+    )
+    .await?;
+    Ok(HuggingfaceModel {
+        commit: Some(sha),
+        ..links.clone()
+    })
+}
+
+/// Repos without an explicit file list are downloaded completely, sharded checkpoint indexes
+/// pull in every shard they reference.
+pub(crate) async fn resolve_files(
+    mut links: HuggingfaceModel,
+    options: &DownloadOptions,
+) -> Result<HuggingfaceModel, Error> {
+    if links.files.is_empty() {
+        let files = list_repo_files(
+            options.endpoint(),
+            &links.repo,
+            links.reference(),
+            options.token.as_deref(),
+        )
+        .await?;
+        for file in files {
+            if links.matches_patterns(&file.path)? {
+                links.files.push(file.path);
+            }
+        }
+    }
+    for file in links.files.clone() {
+        for split in gguf_split_set(&file).unwrap_or_default() {
+            if !links.files.contains(&split) {
+                links.files.push(split);
+            }
+        }
+    }
+    for (filename, url) in links.url_at(options.endpoint()) {
+        if !filename.ends_with(".index.json") {
+            continue;
+        }
+        let file = paths::relative(&filename)?;
+        let dir = file.parent().unwrap_or(Path::new(""));
+        for shard in shard_files(
+            &url,
+            options.endpoint(),
+            &links.repo,
+            options.token.as_deref(),
+        )
+        .await?
+        {
+            let shard = paths::slash(&dir.join(paths::relative(&shard)?));
+            if !links.files.contains(&shard) {
+                links.files.push(shard);
+            }
+        }
+    }
+    Ok(links)
+}
+
+/// Streams every file of `source` to `on_chunk`, one after another, an error of `on_chunk`
+/// stops the transfer. Nothing is written to disk, for callers keeping the files in memory or
+/// passing them on, like a browser handing them to IndexedDB. Returns the number of bytes
+/// received.
+pub async fn stream_source(
+    source: &ModelSource,
+    options: &DownloadOptions,
+    mut on_chunk: impl FnMut(&str, &[u8]) -> Result<(), Error>,
+) -> Result<u64, Error> {
+    let (options, links) = match source {
+        ModelSource::Huggingface(v) => {
+            let options = options.for_model(v);
+            let links = resolve_files(v.clone(), &options).await?;
+            (options, Some(links))
+        }
+        ModelSource::Zip(_) => (options.clone(), None),
+    };
+    let files = match (source, &links) {
+        (ModelSource::Zip(url), _) => vec![("archive".to_string(), url.to_string())],
+        (_, links) => links
+            .iter()
+            .flat_map(|v| v.url_at(options.endpoint()))
+            .collect(),
+    };
+    let hf = links.as_ref().map(|v| HfContext::new(v, &options));
+    let mut received = 0;
+    for (name, url) in files {
+        let headers = headers(hf);
+        let res = send(options.http.get(&url, &headers), &url, hf).await?;
+        let mut body = res.body;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            on_chunk(&name, &chunk)?;
+            received += chunk.len() as u64;
+        }
+    }
+    Ok(received)
+}
+
+/// The Huggingface repo a file belongs to, requests for it are authorized with the token and
+/// get refusals mapped to dedicated errors. Other sources never see the token.
+#[derive(Clone, Copy)]
+pub(crate) struct HfContext<'a> {
+    pub(crate) repo: &'a str,
+    pub(crate) endpoint: &'a str,
+    pub(crate) token: Option<&'a str>,
+}
+
+impl<'a> HfContext<'a> {
+    pub(crate) fn new(links: &'a HuggingfaceModel, options: &'a DownloadOptions) -> Self {
+        Self {
+            repo: &links.repo,
+            endpoint: options.endpoint(),
+            token: options.token.as_deref(),
+        }
+    }
+}
+
+/// Only Huggingface requests carry the token.
+pub(crate) fn headers(hf: Option<HfContext<'_>>) -> Headers {
+    match hf.and_then(|v| v.token) {
+        Some(token) => vec![(AUTHORIZATION.to_string(), format!("Bearer {token}"))],
+        None => vec![],
+    }
+}
+
+pub(crate) async fn send(
+    response: impl Future<Output = Result<HttpResponse, Error>>,
+    url: &str,
+    hf: Option<HfContext<'_>>,
+) -> Result<HttpResponse, Error> {
+    let res = response.await?;
+    let code = res.header("x-error-code").unwrap_or_default();
+    if let Some(err) = hf.and_then(|hf| hub_error(res.status, code, hf.endpoint, hf.repo)) {
+        return Err(err);
+    }
+    match res.status {
+        400.. => Err(Error::Http {
+            status: res.status,
+            url: url.to_string(),
+        }),
+        _ => Ok(res),
+    }
+}
+
+pub(crate) async fn remote_etag(
+    url: &str,
+    options: &DownloadOptions,
+) -> Result<Option<String>, Error> {
+    let res = send(options.http.head(url, &vec![]), url, None).await?;
+    Ok(res.header(ETAG.as_str()).map(|v| v.to_string()))
+}
+
+/// What the source currently points at upstream: the commit of an unpinned Huggingface repo or
+/// the ETag of an archive. `None` for pinned commits, which never change.
+pub(crate) async fn latest_revision(
+    source: &ModelSource,
+    options: &DownloadOptions,
+) -> Result<Option<String>, Error> {
+    match source {
+        ModelSource::Huggingface(v) if v.commit.is_some() => Ok(None),
+        ModelSource::Huggingface(v) => resolve_revision(
+            options.for_model(v).endpoint(),
+            &v.repo,
+            v.reference(),
+            options.token.as_deref(),
+        )
+        .await
+        .map(Some),
+        ModelSource::Zip(url) => remote_etag(url, options).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use bytes::Bytes;
+    use futures::future::BoxFuture;
+    use futures::FutureExt;
+
+    use super::*;
+
+    /// Answers every GET with `abc` in two chunks and records the URL and whether it was
+    /// authorized.
+    #[derive(Default)]
+    struct Files {
+        requests: Mutex<Vec<(String, bool)>>,
+    }
+
+    impl HttpBackend for Files {
+        fn get<'a>(
+            &'a self,
+            url: &'a str,
+            headers: &'a Headers,
+        ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+            let authorized = headers.iter().any(|v| v.0 == AUTHORIZATION.as_str());
+            self.requests
+                .lock()
+                .unwrap()
+                .push((url.to_string(), authorized));
+            let chunks = [Bytes::from("ab"), Bytes::from("c")].map(Ok);
+            async move {
+                Ok(HttpResponse {
+                    status: 200,
+                    headers: vec![],
+                    body: futures::stream::iter(chunks).boxed(),
+                })
+            }
+            .boxed()
+        }
+
+        fn head<'a>(
+            &'a self,
+            _: &'a str,
+            _: &'a Headers,
+        ) -> BoxFuture<'a, Result<HttpResponse, Error>> {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn parse_huggingface_sources() {
+        let hf = |v: HuggingfaceModel| Ok(ModelSource::Huggingface(v));
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let parse = |v: &str| ModelSource::parse(v).map_err(|e| e.report());
+        assert_eq!(
+            parse("hf://org/repo"),
+            hf(HuggingfaceModel::new("org/repo"))
+        );
+        assert_eq!(
+            parse("huggingface://org/repo@v1.0"),
+            hf(HuggingfaceModel::new("org/repo").revision("v1.0"))
+        );
+        assert_eq!(
+            parse(&format!("hf://org/repo@{commit}:model.onnx")),
+            hf(HuggingfaceModel::new("org/repo")
+                .commit(commit)
+                .files(["model.onnx"]))
+        );
+        assert_eq!(
+            parse("hf://org/repo:onnx/*.onnx"),
+            hf(HuggingfaceModel::new("org/repo").allow_pattern("onnx/*.onnx"))
+        );
+        for invalid in [
+            "org/repo",
+            "hf://repo",
+            "hf://org/repo/extra",
+            "hf://org/",
+            "ftp://x",
+        ] {
+            assert!(ModelSource::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn sources_stream_to_the_callback() {
+        let files = Arc::new(Files::default());
+        let options = DownloadOptions {
+            http: files.clone(),
+            token: Some("token".to_string()),
+            ..DownloadOptions::default()
+        };
+        let hf = HuggingfaceModel::new("org/repo")
+            .endpoint("https://hub.example.com")
+            .files(["a.bin", "b.bin"]);
+        let mut received = String::new();
+        let mut on_chunk = |file: &str, chunk: &[u8]| {
+            received += &format!("{file}:{} ", String::from_utf8_lossy(chunk));
+            Ok(())
+        };
+        let zip = ModelSource::Zip("https://example.com/m.zip".to_string());
+
+        assert_eq!(
+            stream_source(&hf.into(), &options, &mut on_chunk)
+                .await
+                .unwrap(),
+            6
+        );
+        assert_eq!(
+            stream_source(&zip, &options, &mut on_chunk).await.unwrap(),
+            3
+        );
+        assert_eq!(
+            received,
+            "a.bin:ab a.bin:c b.bin:ab b.bin:c archive:ab archive:c "
+        );
+        // the token stays with the hub
+        assert_eq!(
+            *files.requests.lock().unwrap(),
+            [
+                (
+                    "https://hub.example.com/org/repo/resolve/main/a.bin".to_string(),
+                    true
+                ),
+                (
+                    "https://hub.example.com/org/repo/resolve/main/b.bin".to_string(),
+                    true
+                ),
+                ("https://example.com/m.zip".to_string(), false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "sqlite")]
+use std::sync::Mutex;
+#[cfg(feature = "sqlite")]
+use std::time::Duration;
+
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::queue;
+#[cfg(feature = "sqlite")]
+use crate::sync;
+
+/// What's known about an installed model, so status queries and eviction don't walk its
+/// directory.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelRecord {
+    pub ident: String,
+    pub version: String,
+    pub revision: Option<String>,
+    /// Sorted by name.
+    pub files: Vec<FileRecord>,
+    /// Unix timestamp.
+    pub downloaded: i64,
+    /// Unix timestamp, `None` until the model was first returned by `get_model`.
+    pub last_used: Option<i64>,
+}
+
+impl ModelRecord {
+    /// Sum of the file sizes, without the manager's own metadata files.
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|v| v.size).sum()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileRecord {
+    /// Relative to the model directory, with `/` separators.
+    pub name: String,
+    pub size: u64,
+    /// From the checksums of the model definition, files are not hashed to record them.
+    pub sha256: Option<String>,
+}
+
+/// Where [`ModelRecord`]s are kept, keyed by ident. [`TomlStateStore`] keeps them in one file
+/// of the model directory, `SqliteStateStore` of the `sqlite` feature in a database for large
+/// directories.
+pub trait StateStore: Send + Sync {
+    fn get(&self, ident: &str) -> Result<Option<ModelRecord>, Error>;
+    fn put(&self, record: ModelRecord) -> Result<(), Error>;
+    fn remove(&self, ident: &str) -> Result<(), Error>;
+    /// Sorted by ident.
+    fn all(&self) -> Result<Vec<ModelRecord>, Error>;
+
+    /// Records that `ident` was used at `timestamp`, if it has a record. Stores shared between
+    /// processes override it to change the record in one step, so a concurrent `put` isn't
+    /// overwritten with the record read here.
+    fn touch(&self, ident: &str, timestamp: i64) -> Result<(), Error> {
+        match self.get(ident)? {
+            Some(record) => self.put(ModelRecord {
+                last_used: Some(timestamp),
+                ..record
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Debug for dyn StateStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StateStore")
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StateFile {
+    #[serde(default)]
+    models: BTreeMap<String, ModelRecord>,
+}
+
+/// Keeps every record in one TOML file, changed under a lock so processes sharing the model
+/// directory don't lose each other's changes.
+#[derive(Clone, Debug)]
+pub struct TomlStateStore {
+    path: PathBuf,
+}
+
+impl TomlStateStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn read(&self) -> Result<StateFile, Error> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(content) => toml::from_str(&content).map_err(Error::config),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(StateFile::default()),
+            Err(e) => Err(Error::open_path(&self.path)(e)),
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut BTreeMap<String, ModelRecord>)) -> Result<(), Error> {
+        let dir = self.path.parent().unwrap_or(&self.path);
+        std::fs::create_dir_all(dir).map_err(Error::write_path(dir))?;
+        let lock = queue::lock(&self.path.with_extension("lock"))?;
+        let mut state = self.read()?;
+        f(&mut state.models);
+        let content = toml::to_string(&state).map_err(Error::config)?;
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, content).map_err(Error::write_path(&tmp))?;
+        std::fs::rename(&tmp, &self.path).map_err(Error::write_path(&self.path))?;
+        drop(lock);
+        Ok(())
+    }
+}
+
+impl StateStore for TomlStateStore {
+    fn get(&self, ident: &str) -> Result<Option<ModelRecord>, Error> {
+        Ok(self.read()?.models.remove(ident))
+    }
+
+    fn put(&self, record: ModelRecord) -> Result<(), Error> {
+        self.update(|models| {
+            models.insert(record.ident.clone(), record);
+        })
+    }
+
+    fn remove(&self, ident: &str) -> Result<(), Error> {
+        self.update(|models| {
+            models.remove(ident);
+        })
+    }
+
+    fn all(&self) -> Result<Vec<ModelRecord>, Error> {
+        Ok(self.read()?.models.into_values().collect())
+    }
+
+    fn touch(&self, ident: &str, timestamp: i64) -> Result<(), Error> {
+        self.update(|models| {
+            if let Some(record) = models.get_mut(ident) {
+                record.last_used = Some(timestamp);
+            }
+        })
+    }
+}
+
+/// Keeps the records in an SQLite database, which changes single records in place instead of
+/// rewriting every one of them. Processes sharing it wait for each other's writes.
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct SqliteStateStore {
+    connection: Mutex<Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+CREATE TABLE IF NOT EXISTS models (
+    ident TEXT PRIMARY KEY,
+    version TEXT NOT NULL,
+    revision TEXT,
+    downloaded INTEGER NOT NULL,
+    last_used INTEGER
+);
+CREATE TABLE IF NOT EXISTS files (
+    ident TEXT NOT NULL,
+    name TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    sha256 TEXT,
+    PRIMARY KEY (ident, name)
+);
+";
+
+#[cfg(feature = "sqlite")]
+impl SqliteStateStore {
+    /// Opens the database at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(Error::write_path(dir))?;
+        }
+        let connection = Connection::open(path).map_err(database)?;
+        connection
+            .busy_timeout(Duration::from_secs(30))
+            .map_err(database)?;
+        connection.execute_batch(SCHEMA).map_err(database)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl StateStore for SqliteStateStore {
+    fn get(&self, ident: &str) -> Result<Option<ModelRecord>, Error> {
+        let connection = sync::lock(&self.connection);
+        let record = connection
+            .query_row(
+                "SELECT ident, version, revision, downloaded, last_used FROM models
+                 WHERE ident = ?1",
+                [ident],
+                model_row,
+            )
+            .optional()
+            .map_err(database)?;
+        match record {
+            Some(record) => Ok(Some(with_files(&connection, record)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, record: ModelRecord) -> Result<(), Error> {
+        let mut connection = sync::lock(&self.connection);
+        let transaction = connection
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(database)?;
+        transaction
+            .execute(
+                "INSERT INTO models (ident, version, revision, downloaded, last_used)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (ident) DO UPDATE SET version = ?2, revision = ?3,
+                 downloaded = ?4, last_used = ?5",
+                params![
+                    record.ident,
+                    record.version,
+                    record.revision,
+                    record.downloaded,
+                    record.last_used
+                ],
+            )
+            .map_err(database)?;
+        transaction
+            .execute("DELETE FROM files WHERE ident = ?1", [&record.ident])
+            .map_err(database)?;
+        for file in &record.files {
+            transaction
+                .execute(
+                    "INSERT INTO files (ident, name, size, sha256) VALUES (?1, ?2, ?3, ?4)",
+                    params![record.ident, file.name, file.size as i64, file.sha256],
+                )
+                .map_err(database)?;
+        }
+        transaction.commit().map_err(database)
+    }
+
+    fn remove(&self, ident: &str) -> Result<(), Error> {
+        let mut connection = sync::lock(&self.connection);
+        let transaction = connection
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(database)?;
+        for table in ["models", "files"] {
+            transaction
+                .execute(&format!("DELETE FROM {table} WHERE ident = ?1"), [ident])
+                .map_err(database)?;
+        }
+        transaction.commit().map_err(database)
+    }
+
+    fn all(&self) -> Result<Vec<ModelRecord>, Error> {
+        let connection = sync::lock(&self.connection);
+        let records = connection
+            .prepare(
+                "SELECT ident, version, revision, downloaded, last_used FROM models ORDER BY ident",
+            )
+            .and_then(|mut v| v.query_map([], model_row)?.collect::<Result<Vec<_>, _>>())
+            .map_err(database)?;
+        records
+            .into_iter()
+            .map(|v| with_files(&connection, v))
+            .collect()
+    }
+
+    fn touch(&self, ident: &str, timestamp: i64) -> Result<(), Error> {
+        sync::lock(&self.connection)
+            .execute(
+                "UPDATE models SET last_used = ?2 WHERE ident = ?1",
+                params![ident, timestamp],
+            )
+            .map(|_| ())
+            .map_err(database)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn model_row(row: &rusqlite::Row) -> rusqlite::Result<ModelRecord> {
+    Ok(ModelRecord {
+        ident: row.get(0)?,
+        version: row.get(1)?,
+        revision: row.get(2)?,
+        files: vec![],
+        downloaded: row.get(3)?,
+        last_used: row.get(4)?,
+    })
+}
+
+#[cfg(feature = "sqlite")]
+fn with_files(connection: &Connection, record: ModelRecord) -> Result<ModelRecord, Error> {
+    let files = connection
+        .prepare("SELECT name, size, sha256 FROM files WHERE ident = ?1 ORDER BY name")
+        .and_then(|mut v| {
+            v.query_map([&record.ident], |row| {
+                Ok(FileRecord {
+                    name: row.get(0)?,
+                    size: row.get::<_, i64>(1)? as u64,
+                    sha256: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(database)?;
+    Ok(ModelRecord { files, ..record })
+}
+
+#[cfg(feature = "sqlite")]
+fn database(error: rusqlite::Error) -> Error {
+    Error::new("State database failed", error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(ident: &str, version: &str) -> ModelRecord {
+        ModelRecord {
+            ident: ident.to_string(),
+            version: version.to_string(),
+            revision: Some("abc".to_string()),
+            files: vec![
+                FileRecord {
+                    name: "a.bin".to_string(),
+                    size: 1,
+                    sha256: None,
+                },
+                FileRecord {
+                    name: "sub/b.bin".to_string(),
+                    size: 2,
+                    sha256: Some("00".to_string()),
+                },
+            ],
+            downloaded: 10,
+            last_used: None,
+        }
+    }
+
+    fn check(store: &dyn StateStore) {
+        assert_eq!(store.get("a").unwrap(), None);
+        store.put(record("b", "1")).unwrap();
+        store.put(record("a", "1")).unwrap();
+        assert_eq!(store.get("a").unwrap(), Some(record("a", "1")));
+        assert_eq!(store.get("a").unwrap().unwrap().size(), 3);
+
+        let mut replaced = record("a", "2");
+        replaced.files.pop();
+        store.put(replaced.clone()).unwrap();
+        store.touch("a", 20).unwrap();
+        store.touch("missing", 20).unwrap();
+        replaced.last_used = Some(20);
+        assert_eq!(store.get("a").unwrap(), Some(replaced.clone()));
+        assert_eq!(store.all().unwrap(), [replaced, record("b", "1")]);
+
+        store.remove("b").unwrap();
+        assert_eq!(store.all().unwrap().len(), 1);
+        assert_eq!(store.get("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn toml_store() {
+        let dir = tempfile::tempdir().unwrap();
+        check(&TomlStateStore::new(dir.path().join("state.toml")));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        check(&SqliteStateStore::open(&path).unwrap());
+        // the records outlive the connection
+        let store = SqliteStateStore::open(&path).unwrap();
+        assert_eq!(store.get("a").unwrap().unwrap().version, "2");
+    }
+}
@@ -0,0 +1,532 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::checksum::sha256_file;
+use crate::error::Error;
+use crate::model_manager::{list_files, list_files_following, Model, ModelSource, METADATA_FILES};
+use crate::paths;
+
+/// How models are laid out on disk, chosen to match the convention of an existing deployment.
+/// Written as `flat`, `versioned:<keep>`, `huggingface-hub` or `snapshots:<keep>` in registries
+/// and on the command line, `keep` defaults to 1.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum StorageLayout {
+    /// Files live directly in the model directory and are replaced on version change.
+    #[default]
+    Flat,
+    /// Every version gets its own `<directory>/<version>` subdirectory, the `keep` most recent
+    /// inactive versions are retained for rollback. `<directory>/latest` links to the active one.
+    Versioned { keep: usize },
+    /// The `huggingface_hub` cache structure (`models--org--repo/{blobs,snapshots,refs}`), so
+    /// transformers, candle and this crate find each other's downloads. Point the model path at
+    /// the hub cache (usually `~/.cache/huggingface/hub`) to share it.
+    HuggingfaceHub,
+    /// Content addressed files in `<directory>/blobs` with a directory of links per version in
+    /// `<directory>/snapshots/<version>`, so versions share identical files, switching is
+    /// instant and old versions are cheap to keep. `keep` and `latest` work like `Versioned`.
+    Snapshots { keep: usize },
+}
+
+/// Which storage root a model that isn't on disk yet is downloaded to, see
+/// [`crate::model_manager::ModelManagerBuilder::storage_root`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Placement {
+    /// The first root in order with enough free space, so later roots only take what no longer
+    /// fits the faster ones.
+    #[default]
+    Spillover,
+    /// The root with the most free space.
+    MostFree,
+}
+
+impl Placement {
+    /// Index of the root for a download of `needed` bytes, given the free space of every root.
+    pub(crate) fn choose(&self, needed: u64, available: &[u64]) -> Option<usize> {
+        let fitting = available
+            .iter()
+            .enumerate()
+            .filter(|(_, available)| **available >= needed);
+        match self {
+            Placement::Spillover => fitting.map(|v| v.0).next(),
+            Placement::MostFree => fitting
+                .max_by_key(|v| (*v.1, std::cmp::Reverse(v.0)))
+                .map(|v| v.0),
+        }
+    }
+}
+
+/// Mode bits and owner given to every file and directory of a model once it's installed,
+/// `None` leaves what the umask and the downloading user gave them. Only applied on Unix.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Permissions {
+    pub file_mode: Option<u32>,
+    pub dir_mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl Permissions {
+    /// `0644` for files and `0755` for directories, readable by every user.
+    pub fn shared() -> Self {
+        Self::default().file_mode(0o644).dir_mode(0o755)
+    }
+
+    pub fn file_mode(mut self, mode: u32) -> Self {
+        self.file_mode = Some(mode);
+        self
+    }
+
+    pub fn dir_mode(mut self, mode: u32) -> Self {
+        self.dir_mode = Some(mode);
+        self
+    }
+
+    pub fn owner(mut self, uid: Option<u32>, gid: Option<u32>) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// Applies to `path` and everything below it. Links keep their mode, they're only
+    /// given the owner.
+    pub(crate) fn apply(&self, path: &Path) -> Result<(), Error> {
+        self.apply_to(path, true)
+    }
+
+    /// Applies to the directory `path` only, so its models can be reached.
+    pub(crate) fn apply_dir(&self, path: &Path) -> Result<(), Error> {
+        self.apply_to(path, false)
+    }
+
+    #[cfg(unix)]
+    fn apply_to(&self, path: &Path, recursive: bool) -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+        if *self == Self::default() {
+            return Ok(());
+        }
+        let Ok(metadata) = std::fs::symlink_metadata(path) else {
+            return Ok(());
+        };
+        if self.uid.is_some() || self.gid.is_some() {
+            std::os::unix::fs::lchown(path, self.uid, self.gid).map_err(Error::write_path(path))?;
+        }
+        let mode = match metadata.is_dir() {
+            true => self.dir_mode,
+            false if metadata.is_file() => self.file_mode,
+            false => None,
+        };
+        if let Some(mode) = mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+                .map_err(Error::write_path(path))?;
+        }
+        if recursive && metadata.is_dir() {
+            for entry in std::fs::read_dir(path).map_err(Error::open_path(path))? {
+                let entry = entry.map_err(Error::open_path(path))?;
+                self.apply_to(&entry.path(), true)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply_to(&self, _: &Path, _: bool) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl FromStr for StorageLayout {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, keep) = match s.split_once(':') {
+            Some((name, keep)) => (name, Some(keep)),
+            None => (s, None),
+        };
+        let keep = match keep {
+            Some(keep) => keep
+                .parse()
+                .map_err(|_| Error::config(format!("Invalid number of kept versions: {keep}")))?,
+            None => 1,
+        };
+        match name.to_ascii_lowercase().replace('_', "-").as_str() {
+            "flat" => Ok(StorageLayout::Flat),
+            "versioned" => Ok(StorageLayout::Versioned { keep }),
+            "huggingface-hub" | "hf-hub" | "hf-hub-compatible" => Ok(StorageLayout::HuggingfaceHub),
+            "snapshots" | "content-addressed" => Ok(StorageLayout::Snapshots { keep }),
+            _ => Err(Error::config(format!("Unknown storage layout: {s}"))),
+        }
+    }
+}
+
+impl TryFrom<String> for StorageLayout {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<StorageLayout> for String {
+    fn from(value: StorageLayout) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for StorageLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageLayout::Flat => f.write_str("flat"),
+            StorageLayout::Versioned { keep } => write!(f, "versioned:{keep}"),
+            StorageLayout::HuggingfaceHub => f.write_str("huggingface-hub"),
+            StorageLayout::Snapshots { keep } => write!(f, "snapshots:{keep}"),
+        }
+    }
+}
+
+impl StorageLayout {
+    /// Directory of a model relative to the model path.
+    pub(crate) fn model_dir(&self, model: &Model) -> PathBuf {
+        match (self, &model.source) {
+            (StorageLayout::HuggingfaceHub, ModelSource::Huggingface(v)) => {
+                PathBuf::from(format!("models--{}", v.repo.replace('/', "--")))
+            }
+            _ => paths::normalize(&model.directory),
+        }
+    }
+
+    pub(crate) fn install_dir(&self, root: &Path, version: &str) -> PathBuf {
+        match self {
+            StorageLayout::Flat => root.to_path_buf(),
+            StorageLayout::Versioned { .. } => root.join(version_dir_name(version)),
+            StorageLayout::HuggingfaceHub | StorageLayout::Snapshots { .. } => {
+                root.join(INCOMPLETE).join(version_dir_name(version))
+            }
+        }
+    }
+
+    pub(crate) fn installed_dir(&self, root: &Path, version: &str) -> PathBuf {
+        match self {
+            StorageLayout::HuggingfaceHub => self.active_dir(root, version),
+            StorageLayout::Snapshots { .. } => root.join(SNAPSHOTS).join(version_dir_name(version)),
+            _ => self.install_dir(root, version),
+        }
+    }
+
+    /// Makes the version installed before the active one active again, returns its directory
+    /// name.
+    pub(crate) fn rollback(&self, root: &Path) -> Result<String, Error> {
+        match self {
+            StorageLayout::Versioned { .. } => {
+                let previous = previous_version(root, root).ok_or(Error::NoPreviousVersion)?;
+                set_active(root, &previous)?;
+                Ok(previous)
+            }
+            StorageLayout::Snapshots { .. } => {
+                let previous = previous_version(root, &root.join(SNAPSHOTS))
+                    .ok_or(Error::NoPreviousVersion)?;
+                set_active_snapshot(root, &previous)?;
+                Ok(previous)
+            }
+            _ => Err(Error::new_option(
+                "Rollback requires a versioned storage layout",
+            )),
+        }
+    }
+
+    /// Layouts keeping more than one version of a model.
+    pub(crate) fn is_versioned(&self) -> bool {
+        matches!(
+            self,
+            StorageLayout::Versioned { .. } | StorageLayout::Snapshots { .. }
+        )
+    }
+
+    pub(crate) fn active_dir(&self, root: &Path, version: &str) -> PathBuf {
+        match self {
+            StorageLayout::Flat => root.to_path_buf(),
+            StorageLayout::Versioned { .. } => {
+                root.join(read_active(root).unwrap_or_else(|| version_dir_name(version)))
+            }
+            StorageLayout::HuggingfaceHub | StorageLayout::Snapshots { .. } => root
+                .join(SNAPSHOTS)
+                .join(read_active(root).unwrap_or_else(|| version_dir_name(version))),
+        }
+    }
+
+    pub(crate) fn activate<'a>(
+        &self,
+        root: &Path,
+        version: &str,
+        retain: impl IntoIterator<Item = &'a String>,
+    ) -> Result<(), Error> {
+        match self {
+            StorageLayout::Flat => Ok(()),
+            StorageLayout::Versioned { keep } => {
+                let name = version_dir_name(version);
+                set_active(root, &name)?;
+                let retain = retain
+                    .into_iter()
+                    .map(|v| version_dir_name(v))
+                    .collect::<Vec<_>>();
+                prune(root, *keep, &retain, read_active(root))?;
+                link_unchanged(root, &name)
+            }
+            StorageLayout::HuggingfaceHub => activate_snapshot(root, version),
+            StorageLayout::Snapshots { keep } => {
+                let name = store_snapshot(root, version)?;
+                set_active_snapshot(root, &name)?;
+                let retain = retain
+                    .into_iter()
+                    .map(|v| version_dir_name(v))
+                    .collect::<Vec<_>>();
+                prune_snapshots(root, *keep, &retain)
+            }
+        }
+    }
+}
+
+/// Moves a finished download of `version` into `blobs/` and links it from
+/// `snapshots/<version>/`, without making it the active version. Returns the snapshot name.
+pub(crate) fn store_snapshot(root: &Path, version: &str) -> Result<String, Error> {
+    let name = version_dir_name(version);
+    let staging = root.join(INCOMPLETE).join(&name);
+    let snapshot = root.join(SNAPSHOTS).join(&name);
+    move_to_snapshot(root, &staging, &snapshot)?;
+    std::fs::remove_dir_all(root.join(INCOMPLETE)).map_err(Error::write_file)?;
+    Ok(name)
+}
+
+/// Points `active` and `latest` at `snapshots/<name>`.
+fn set_active_snapshot(root: &Path, name: &str) -> Result<(), Error> {
+    std::fs::write(root.join("active"), name).map_err(Error::write_file)?;
+    link_latest(root, &format!("{SNAPSHOTS}/{name}"))
+}
+
+/// Keeps the `keep` most recent inactive snapshots, then deletes the blobs none of the
+/// remaining ones link to.
+fn prune_snapshots(root: &Path, keep: usize, retain: &[String]) -> Result<(), Error> {
+    let snapshots = root.join(SNAPSHOTS);
+    prune(&snapshots, keep, retain, read_active(root))?;
+    let linked = list_files_following(&snapshots)
+        .into_iter()
+        .filter_map(|v| std::fs::canonicalize(v).ok())
+        .collect::<HashSet<_>>();
+    for blob in list_files(&root.join(BLOBS)) {
+        let linked = std::fs::canonicalize(&blob).is_ok_and(|v| linked.contains(&v));
+        if !linked {
+            std::fs::remove_file(&blob).map_err(Error::write_path(&blob))?;
+        }
+    }
+    Ok(())
+}
+
+const INCOMPLETE: &str = ".incomplete";
+const SNAPSHOTS: &str = "snapshots";
+const BLOBS: &str = "blobs";
+
+/// Moves a finished download into `blobs/`, links it from `snapshots/<sha>/` and points the
+/// branch or tag it was resolved from at the snapshot in `refs/`.
+/// Replaces the files of version `name` that are identical to the same file of another kept
+/// version with a hard link to it, so old versions only cost the bytes that changed.
+#[cfg(unix)]
+fn link_unchanged(root: &Path, name: &str) -> Result<(), Error> {
+    use std::os::unix::fs::MetadataExt;
+    let dir = root.join(name);
+    let others = installed_versions(root)
+        .into_iter()
+        .filter(|v| v.0 != name)
+        .map(|v| root.join(v.0))
+        .collect::<Vec<_>>();
+    for file in list_files(&dir) {
+        let relative = file.strip_prefix(&dir).map_err(Error::pathbuf_custom)?;
+        if METADATA_FILES.iter().any(|v| relative == Path::new(v)) {
+            continue;
+        }
+        let mut hash = None;
+        for other in others.iter().map(|v| v.join(relative)) {
+            let (Ok(new), Ok(old)) = (std::fs::metadata(&file), std::fs::metadata(&other)) else {
+                continue;
+            };
+            if new.len() != old.len() || (new.dev(), new.ino()) == (old.dev(), old.ino()) {
+                continue;
+            }
+            let hash = match &hash {
+                Some(hash) => hash,
+                None => hash.insert(sha256_file(&file)?),
+            };
+            if *hash != sha256_file(&other)? {
+                continue;
+            }
+            // swapped in with a rename, the file never goes missing
+            let tmp = paths::with_suffix(&file, ".link");
+            let _ = std::fs::remove_file(&tmp);
+            if std::fs::hard_link(&other, &tmp).is_ok() {
+                std::fs::rename(&tmp, &file).map_err(Error::write_path(&file))?;
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn link_unchanged(_: &Path, _: &str) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Moves every file of `from` into `to`, replacing files already there, then removes `from`.
+/// Files are copied if `to` is on another filesystem.
+pub(crate) fn move_files(from: &Path, to: &Path) -> Result<(), Error> {
+    for file in list_files(from) {
+        let relative = file.strip_prefix(from).map_err(Error::pathbuf_custom)?;
+        let target = to.join(relative);
+        std::fs::create_dir_all(target.parent().unwrap_or(to)).map_err(Error::write_file)?;
+        if std::fs::rename(&file, &target).is_err() {
+            std::fs::copy(&file, &target).map_err(Error::write_path(&target))?;
+            std::fs::remove_file(&file).map_err(Error::write_path(&file))?;
+        }
+    }
+    std::fs::remove_dir_all(from).map_err(Error::write_path(from))
+}
+
+/// Moves the files of `staging` into `blobs/` and links them from `snapshot`, metadata files
+/// are moved as they are.
+fn move_to_snapshot(root: &Path, staging: &Path, snapshot: &Path) -> Result<(), Error> {
+    let _ = std::fs::remove_dir_all(snapshot);
+    for file in list_files(staging) {
+        let relative = file.strip_prefix(staging).map_err(Error::pathbuf_custom)?;
+        let target = snapshot.join(relative);
+        std::fs::create_dir_all(target.parent().unwrap_or(snapshot)).map_err(Error::write_file)?;
+        if METADATA_FILES.iter().any(|v| relative == Path::new(v)) {
+            std::fs::rename(&file, &target).map_err(Error::write_path(&target))?;
+        } else {
+            store_blob(root, &file, &target, relative)?;
+        }
+    }
+    Ok(())
+}
+
+fn activate_snapshot(root: &Path, version: &str) -> Result<(), Error> {
+    let staging = root.join(INCOMPLETE).join(version_dir_name(version));
+    let sha = std::fs::read_to_string(staging.join("revision"))
+        .unwrap_or_else(|_| version_dir_name(version));
+    let snapshot = root.join(SNAPSHOTS).join(&sha);
+    move_to_snapshot(root, &staging, &snapshot)?;
+    if let Ok(reference) = std::fs::read_to_string(snapshot.join("reference")) {
+        let refs = root.join("refs").join(&reference);
+        std::fs::create_dir_all(refs.parent().unwrap_or(root)).map_err(Error::write_file)?;
+        std::fs::write(refs, &sha).map_err(Error::write_file)?;
+    }
+    std::fs::write(root.join("active"), &sha).map_err(Error::write_file)?;
+    std::fs::remove_dir_all(root.join(INCOMPLETE)).map_err(Error::write_file)
+}
+
+#[cfg(unix)]
+fn store_blob(root: &Path, file: &Path, target: &Path, relative: &Path) -> Result<(), Error> {
+    let blobs = root.join(BLOBS);
+    std::fs::create_dir_all(&blobs).map_err(Error::write_path(&blobs))?;
+    let hash = sha256_file(file)?;
+    let blob = blobs.join(&hash);
+    match blob.exists() {
+        true => std::fs::remove_file(file).map_err(Error::write_file)?,
+        false => std::fs::rename(file, &blob).map_err(Error::write_path(&blob))?,
+    }
+    // snapshots/<sha>/<relative> -> ../../blobs/<hash>
+    let up = "../".repeat(relative.components().count() + 1);
+    std::os::unix::fs::symlink(format!("{up}blobs/{hash}"), target).map_err(Error::write_file)
+}
+
+/// Like `huggingface_hub` without symlink support, files are stored in the snapshot directly.
+#[cfg(not(unix))]
+fn store_blob(_: &Path, file: &Path, target: &Path, _: &Path) -> Result<(), Error> {
+    std::fs::rename(file, target).map_err(Error::write_path(&target))
+}
+
+const LATEST: &str = "latest";
+
+fn set_active(root: &Path, name: &str) -> Result<(), Error> {
+    std::fs::write(root.join("active"), name).map_err(Error::write_file)?;
+    link_latest(root, name)
+}
+
+#[cfg(unix)]
+fn link_latest(root: &Path, name: &str) -> Result<(), Error> {
+    // swap the link atomically so readers never observe a missing `latest`
+    let tmp = root.join(format!("{LATEST}.tmp"));
+    let _ = std::fs::remove_file(&tmp);
+    std::os::unix::fs::symlink(name, &tmp).map_err(Error::write_file)?;
+    std::fs::rename(tmp, root.join(LATEST)).map_err(Error::write_file)
+}
+
+#[cfg(windows)]
+fn link_latest(root: &Path, name: &str) -> Result<(), Error> {
+    let latest = root.join(LATEST);
+    if junction::exists(&latest).unwrap_or(false) {
+        junction::delete(&latest).map_err(Error::write_file)?;
+        std::fs::remove_dir(&latest).map_err(Error::write_file)?;
+    }
+    let target = std::fs::canonicalize(root.join(name)).map_err(Error::open_file)?;
+    junction::create(target, latest).map_err(Error::write_file)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn link_latest(_: &Path, _: &str) -> Result<(), Error> {
+    Ok(())
+}
+
+/// The version installed in `dir` before the active one of `root`.
+fn previous_version(root: &Path, dir: &Path) -> Option<String> {
+    let active = read_active(root)?;
+    let versions = installed_versions(dir);
+    let active_time = versions.iter().find(|v| v.0 == active)?.1;
+    versions
+        .into_iter()
+        .find(|(name, time)| *name != active && *time <= active_time)
+        .map(|v| v.0)
+}
+
+fn read_active(root: &Path) -> Option<String> {
+    std::fs::read_to_string(root.join("active")).ok()
+}
+
+fn version_dir_name(version: &str) -> String {
+    version.replace(['/', '\\'], "_")
+}
+
+/// Installed versions of a model, newest first.
+fn installed_versions(root: &Path) -> Vec<(String, SystemTime)> {
+    let mut versions = std::fs::read_dir(root)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+                .filter(|entry| entry.file_name() != LATEST)
+                .filter_map(|entry| {
+                    let modified = std::fs::metadata(entry.path().join("version"))
+                        .and_then(|m| m.modified())
+                        .ok()?;
+                    Some((entry.file_name().to_string_lossy().to_string(), modified))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    versions.sort_by_key(|v| std::cmp::Reverse(v.1));
+    versions
+}
+
+fn prune(root: &Path, keep: usize, retain: &[String], active: Option<String>) -> Result<(), Error> {
+    for (name, _) in installed_versions(root)
+        .into_iter()
+        .filter(|v| Some(&v.0) != active.as_ref() && !retain.contains(&v.0))
+        .skip(keep)
+    {
+        std::fs::remove_dir_all(root.join(name)).map_err(Error::write_file)?;
+    }
+    Ok(())
+}
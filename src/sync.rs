@@ -0,0 +1,47 @@
+use std::future::Future;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use tokio::runtime::Handle;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::runtime::RuntimeFlavor;
+use tokio::task::JoinHandle;
+
+use crate::error::Error;
+
+/// Runs `future` for the blocking wrappers. Downloads need a Tokio runtime, callers without one
+/// get a temporary runtime on the current thread. Inside a multi thread runtime the worker hands
+/// its other tasks off while blocking, a current thread runtime would deadlock.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn block_on<F: Future>(future: F) -> Result<F::Output, Error> {
+    if let Ok(handle) = Handle::try_current() {
+        return match handle.runtime_flavor() {
+            RuntimeFlavor::MultiThread => {
+                Ok(tokio::task::block_in_place(|| handle.block_on(future)))
+            }
+            _ => Err(Error::runtime(
+                "blocking call inside a current thread runtime, use the async variant",
+            )),
+        };
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(Error::runtime)?;
+    Ok(runtime.block_on(future))
+}
+
+/// `tokio::spawn` failing instead of panicking outside of a runtime.
+pub(crate) fn spawn<F>(future: F) -> Result<JoinHandle<F::Output>, Error>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let runtime = Handle::try_current().map_err(Error::runtime)?;
+    Ok(runtime.spawn(future))
+}
+
+/// Locks `mutex` even if a thread panicked while holding it. The maps behind these mutexes are
+/// only ever changed by single inserts and removals, so they stay usable.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,123 @@
+use crate::downloader::DownloadOptions;
+use crate::error::Error;
+use crate::gguf::quantization_name;
+use crate::huggingface::list_repo_files;
+use crate::model_manager::HuggingfaceModel;
+
+const WEIGHT_FORMATS: &[&str] = &["safetensors", "onnx", "bin", "pt", "pth", "ckpt"];
+/// Markers like `model.fp16.safetensors` or `model_quantized.onnx`.
+const PRECISIONS: &[&str] = &[
+    "fp32",
+    "fp16",
+    "bf16",
+    "int8",
+    "uint8",
+    "int4",
+    "q4",
+    "q4f16",
+    "q8",
+    "quantized",
+];
+
+#[derive(Clone, Debug)]
+pub struct Variant {
+    /// Quantization like `Q4_K_M`, or format and precision like `safetensors` or `onnx.fp16`.
+    pub name: String,
+    /// Files to download for this variant, shared files like configs included.
+    pub files: Vec<String>,
+    pub size: u64,
+}
+
+impl Variant {
+    /// `links` restricted to the files of this variant.
+    pub fn apply(&self, links: HuggingfaceModel) -> HuggingfaceModel {
+        links.files(&self.files)
+    }
+}
+
+/// Alternative weights of a repo: GGUF quantizations, or formats and precisions of other
+/// weights, smallest first. Empty if the repo only has one set of weights.
+pub async fn list_variants(
+    links: &HuggingfaceModel,
+    options: &DownloadOptions,
+) -> Result<Vec<Variant>, Error> {
+    let options = options.for_model(links);
+    let mut gguf: Vec<Variant> = vec![];
+    let mut weights: Vec<Variant> = vec![];
+    let mut shared = vec![];
+    for file in list_repo_files(
+        options.endpoint(),
+        &links.repo,
+        links.reference(),
+        options.token.as_deref(),
+    )
+    .await?
+    {
+        if !links.matches_patterns(&file.path)? {
+            continue;
+        }
+        let size = file.size.unwrap_or_default();
+        let (variants, name) = match (file.path.ends_with(".gguf"), weight_name(&file.path)) {
+            (true, _) => match quantization_name(&file.path) {
+                Some(name) => (&mut gguf, name.to_string()),
+                None => continue,
+            },
+            (false, Some(name)) => (&mut weights, name),
+            (false, None) => {
+                shared.push((file.path, size));
+                continue;
+            }
+        };
+        match variants.iter_mut().find(|v| v.name == name) {
+            Some(v) => {
+                v.files.push(file.path);
+                v.size += size;
+            }
+            None => variants.push(Variant {
+                name,
+                files: vec![file.path],
+                size,
+            }),
+        }
+    }
+    // GGUF files are self-contained, other weights need the configs and tokenizers next to them
+    let mut variants = match gguf.is_empty() {
+        false => gguf,
+        true => weights
+            .into_iter()
+            .map(|mut v| {
+                v.files.extend(shared.iter().map(|f| f.0.to_string()));
+                v.size += shared.iter().map(|f| f.1).sum::<u64>();
+                v
+            })
+            .collect(),
+    };
+    if variants.len() < 2 {
+        return Ok(vec![]);
+    }
+    variants.sort_by_key(|v| v.size);
+    Ok(variants)
+}
+
+/// The variant picked without asking: `Q4_K_M` for GGUF repos, otherwise full precision
+/// safetensors, their `fp16` or the smallest.
+pub fn default_variant(variants: &[Variant]) -> Option<&Variant> {
+    ["Q4_K_M", "safetensors", "safetensors.fp16"]
+        .iter()
+        .find_map(|name| variants.iter().find(|v| v.name == *name))
+        .or(variants.first())
+}
+
+/// `safetensors`, `onnx.quantized` and the like for weight files.
+fn weight_name(path: &str) -> Option<String> {
+    let file = path.rsplit('/').next().unwrap_or(path).to_lowercase();
+    let (stem, format) = file.rsplit_once('.')?;
+    let format = WEIGHT_FORMATS.iter().find(|v| **v == format)?;
+    let precision = stem
+        .split(['.', '_', '-'])
+        .find_map(|part| PRECISIONS.iter().find(|v| **v == part));
+    Some(match precision {
+        Some(precision) => format!("{format}.{precision}"),
+        None => format.to_string(),
+    })
+}
@@ -0,0 +1,12 @@
+const COMMANDS: &[&str] = &[
+    "register",
+    "register_config",
+    "list",
+    "path",
+    "download",
+    "cancel",
+];
+
+fn main() {
+    tauri_plugin::Builder::new(COMMANDS).build();
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use model_manager::model_manager::{Capabilities, Model, ModelManager};
+use model_manager::snapshot::ChannelObserver;
+use serde::Serialize;
+use tauri::plugin::{Builder, TauriPlugin};
+use tauri::{AppHandle, Emitter, Manager, Runtime, State};
+
+/// Event the [`ProgressSnapshot`](model_manager::snapshot::ProgressSnapshot)s of downloads are
+/// emitted as.
+pub const PROGRESS_EVENT: &str = "model-manager://progress";
+
+/// The manager of the plugin, shared by every window. Downloads run on clones of it, so
+/// registering models doesn't wait for them.
+pub struct ModelManagerState(RwLock<ModelManager>);
+
+impl ModelManagerState {
+    pub fn manager(&self) -> ModelManager {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn register(&self, ident: String, model: Model) {
+        let mut manager = self.0.write().unwrap_or_else(|e| e.into_inner());
+        manager.register_models(HashMap::from([(ident, model)]));
+    }
+}
+
+/// Registers the plugin with the models of `manager`. The webview calls its commands as
+/// `plugin:model-manager|<command>` once the `model-manager:default` permission is granted:
+///
+/// - `register` with `ident` and a `model` definition like in a JSON registry
+/// - `register_config` with the `path` of a registry file
+/// - `list` of the models
+/// - `path` of an `ident` that's downloaded, `null` otherwise
+/// - `download` of an `ident`, returning its path and emitting [`PROGRESS_EVENT`]s
+/// - `cancel` of the running downloads
+pub fn init<R: Runtime>(manager: ModelManager) -> TauriPlugin<R> {
+    Builder::new("model-manager")
+        .invoke_handler(tauri::generate_handler![
+            register,
+            register_config,
+            list,
+            path,
+            download,
+            cancel
+        ])
+        .setup(move |app, _api| {
+            app.manage(ModelManagerState(RwLock::new(manager)));
+            Ok(())
+        })
+        .build()
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct ModelEntry {
+    ident: String,
+    registered: bool,
+    downloaded: bool,
+    expected_version: Option<String>,
+    installed_version: Option<String>,
+    size: u64,
+    capabilities: Option<Capabilities>,
+}
+
+#[tauri::command]
+fn register(state: State<'_, ModelManagerState>, ident: String, model: Model) {
+    let mut model = model;
+    // like in a registry, the directory defaults to the ident
+    if model.directory.as_os_str().is_empty() {
+        model.directory = PathBuf::from(&ident);
+    }
+    state.register(ident, model);
+}
+
+#[tauri::command]
+fn register_config(state: State<'_, ModelManagerState>, path: PathBuf) -> Result<(), String> {
+    let mut manager = state.0.write().unwrap_or_else(|e| e.into_inner());
+    manager.register_config(path).map_err(|e| e.report())
+}
+
+#[tauri::command]
+fn list(state: State<'_, ModelManagerState>) -> Result<Vec<ModelEntry>, String> {
+    let list = state.manager().list().map_err(|e| e.report())?;
+    Ok(list
+        .into_iter()
+        .map(|status| ModelEntry {
+            ident: status.ident,
+            registered: status.registered,
+            downloaded: status.downloaded,
+            expected_version: status.expected_version,
+            installed_version: status.installed_version,
+            size: status.size,
+            capabilities: status.capabilities,
+        })
+        .collect())
+}
+
+#[tauri::command]
+fn path(state: State<'_, ModelManagerState>, ident: String) -> Option<PathBuf> {
+    state.manager().get_model_if_cached(&ident).map(|v| v.0)
+}
+
+#[tauri::command]
+async fn download<R: Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, ModelManagerState>,
+    ident: String,
+) -> Result<PathBuf, String> {
+    let mut manager = state.manager();
+    let (observer, mut receiver) = ChannelObserver::new();
+    manager.set_progress_observer(Arc::new(observer));
+    tauri::async_runtime::spawn(async move {
+        // ends once the manager and with it the observer is dropped
+        while let Some(snapshot) = receiver.recv().await {
+            let _ = app.emit(PROGRESS_EVENT, snapshot);
+        }
+    });
+    let result = manager.get_model_async(&ident).await.map(|v| v.0);
+    result.map_err(|e| e.report())
+}
+
+#[tauri::command]
+fn cancel(state: State<'_, ModelManagerState>) {
+    state.manager().cancel_downloads();
+}